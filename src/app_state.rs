@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT_FILES: usize = 10;
+
+/// Session state restored on the next launch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppState {
+    pub last_rule_path: PathBuf,
+    pub window_width: usize,
+    pub window_height: usize,
+    #[serde(default)]
+    pub window_position: Option<(isize, isize)>,
+    pub camera_yaw: f32,
+    pub camera_pitch: f32,
+    pub camera_distance: f32,
+    pub recent_files: VecDeque<PathBuf>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self {
+            last_rule_path: PathBuf::from("rules/cherry_blossom.json"),
+            window_width: 800,
+            window_height: 600,
+            window_position: None,
+            camera_yaw: 0.0,
+            camera_pitch: -0.3,
+            camera_distance: 10.0,
+            recent_files: VecDeque::new(),
+        }
+    }
+}
+
+impl AppState {
+    /// `~/.config/rust-lsystems/app_state.json` (or the platform equivalent)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust-lsystems").join("app_state.json"))
+    }
+    
+    /// Loads the saved state, falling back to defaults if there is none or it cannot be read
+    pub fn load() -> Self {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load_from(&path).unwrap_or_else(|e| {
+                eprintln!("Warning: ignoring saved app state: {}", e);
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+    
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+    
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::default_path().ok_or("No config directory available")?;
+        self.save_to(&path)
+    }
+    
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize app state: {}", e))?;
+        fs::write(path, json)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+    
+    /// Makes `path` the last rule and moves it to the front of the recent files list
+    pub fn record_rule(&mut self, path: &Path) {
+        self.last_rule_path = path.to_path_buf();
+        self.recent_files.retain(|p| p != path);
+        self.recent_files.push_front(path.to_path_buf());
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn save_then_load_round_trips_every_field() {
+        let mut state = AppState {
+            window_width: 1280,
+            window_height: 720,
+            window_position: Some((40, -20)),
+            camera_yaw: 1.25,
+            camera_pitch: -0.5,
+            camera_distance: 37.5,
+            ..AppState::default()
+        };
+        state.record_rule(Path::new("rules/koch.json"));
+        state.record_rule(Path::new("rules/sierpinski.json"));
+        
+        let path = std::env::temp_dir().join(format!("lsystem_app_state_{}", std::process::id())).join("app_state.json");
+        state.save_to(&path).unwrap();
+        let loaded = AppState::load_from(&path);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+        
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!((loaded.camera_yaw, loaded.camera_pitch, loaded.camera_distance), (1.25, -0.5, 37.5));
+        assert_eq!(loaded.last_rule_path, PathBuf::from("rules/sierpinski.json"));
+        assert_eq!(loaded.recent_files, [PathBuf::from("rules/sierpinski.json"), PathBuf::from("rules/koch.json")]);
+    }
+}
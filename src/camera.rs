@@ -1,4 +1,34 @@
-use glam::{Mat4, Vec3, Vec2};
+use glam::{Mat4, Vec3, Vec2, Vec4};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Radians turned per frame while an arrow key is held, before `keyboard_orbit_speed`
+const KEYBOARD_ORBIT_STEP: f32 = 0.05;
+/// Time `auto_center` takes to move the target onto the geometry
+const AUTO_CENTER_SECONDS: f32 = 0.5;
+/// Room `fit_to_bounds` leaves around the geometry's bounding sphere
+const FIT_MARGIN: f32 = 1.1;
+/// Radians per second the camera turns while auto-orbiting
+const DEFAULT_ORBIT_SPEED: f32 = 0.3;
+/// Closest the camera gets to its target, whether zooming, fitting or loading a view
+const MIN_DISTANCE: f32 = 1.0;
+/// Farthest the camera backs off; the default far clip plane is at this distance too
+const MAX_DISTANCE: f32 = 1000.0;
+
+/// Saved camera bookmark: (yaw, pitch, distance, target)
+pub type NamedView = (f32, f32, f32, Vec3);
+
+/// Camera angle shipped in a rule file; angles are in degrees
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ViewPreset {
+    pub name: String,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub description: Option<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Camera {
@@ -15,9 +45,24 @@ pub struct Camera {
     pub pitch: f32,
     pub distance: f32,
     
+    /// Multiplier on the arrow-key orbit step; 1.0 turns 0.05 rad per frame
+    pub keyboard_orbit_speed: f32,
+    /// Turns the camera around the target by itself, paused while the mouse drags it
+    pub auto_orbit: bool,
+    /// Radians per second of auto-orbit
+    pub orbit_speed: f32,
+    
     // Mouse interaction
     last_mouse_pos: Option<Vec2>,
     is_rotating: bool,
+    
+    // Bookmarked views
+    pub named_views: HashMap<String, NamedView>,
+    
+    // In-progress target move: (from, to, elapsed, duration)
+    target_animation: Option<(Vec3, Vec3, f32, f32)>,
+    // Box last passed to `fit_to_bounds`, for `reset_to_fit`
+    fit_bounds: Option<(Vec3, Vec3)>,
 }
 
 impl Camera {
@@ -33,8 +78,14 @@ impl Camera {
             yaw: 0.0,
             pitch: -0.3,
             distance: 10.0,
+            keyboard_orbit_speed: 1.0,
+            auto_orbit: false,
+            orbit_speed: DEFAULT_ORBIT_SPEED,
             last_mouse_pos: None,
             is_rotating: false,
+            named_views: HashMap::new(),
+            target_animation: None,
+            fit_bounds: None,
         }
     }
     
@@ -65,29 +116,372 @@ impl Camera {
     }
     
     pub fn update_rotation(&mut self, mouse_pos: Vec2) {
-        if let Some(last_pos) = self.last_mouse_pos {
-            if self.is_rotating {
-                let delta = mouse_pos - last_pos;
-                self.yaw -= delta.x * 0.01;
-                self.pitch -= delta.y * 0.01;
-                
-                // Clamp pitch to prevent flipping
-                self.pitch = self.pitch.clamp(-std::f32::consts::FRAC_PI_2 + 0.1, 
-                                             std::f32::consts::FRAC_PI_2 - 0.1);
-                
-                self.update_from_angles();
-            }
+        if let Some(last_pos) = self.last_mouse_pos
+            && self.is_rotating {
+            let delta = mouse_pos - last_pos;
+            self.yaw -= delta.x * 0.01;
+            self.pitch -= delta.y * 0.01;
+            
+            // Clamp pitch to prevent flipping
+            self.pitch = self.pitch.clamp(-std::f32::consts::FRAC_PI_2 + 0.1, 
+                                         std::f32::consts::FRAC_PI_2 - 0.1);
+            
+            self.update_from_angles();
         }
         self.last_mouse_pos = Some(mouse_pos);
     }
     
+    /// Orbits for one frame of held arrow keys; a short tap turns about as far as
+    /// 10 pixels of mouse drag
+    pub fn keyboard_orbit(&mut self, left: bool, right: bool, up: bool, down: bool) {
+        let step = KEYBOARD_ORBIT_STEP * self.keyboard_orbit_speed;
+        let yaw_dir = right as i32 - left as i32;
+        let pitch_dir = up as i32 - down as i32;
+        if yaw_dir == 0 && pitch_dir == 0 {
+            return;
+        }
+        
+        self.yaw -= yaw_dir as f32 * step;
+        self.pitch = (self.pitch + pitch_dir as f32 * step).clamp(-std::f32::consts::FRAC_PI_2 + 0.1, 
+                                                                  std::f32::consts::FRAC_PI_2 - 0.1);
+        self.update_from_angles();
+    }
+    
+    /// Moves the orbit target to `target` over `duration_secs`, easing in and out; `update`
+    /// advances the move each frame
+    pub fn animate_to_target(&mut self, target: Vec3, duration_secs: f32) {
+        if duration_secs <= 0.0 {
+            self.target_animation = None;
+            self.target = target;
+            self.update_from_angles();
+        } else {
+            self.target_animation = Some((self.target, target, 0.0, duration_secs));
+        }
+    }
+    
+    /// Recenters the orbit on `centroid`, e.g. `Renderer::get_centroid_of_visible_geometry`
+    pub fn auto_center(&mut self, centroid: Vec3) {
+        self.animate_to_target(centroid, AUTO_CENTER_SECONDS);
+    }
+    
+    /// Orbits around the center of the `min`-`max` box at the current distance, and remembers
+    /// the box for `reset_to_fit`
+    pub fn center_on_bounds(&mut self, min: Vec3, max: Vec3) {
+        self.fit_bounds = Some((min, max));
+        self.auto_center((min + max) * 0.5);
+    }
+    
+    /// Aims at the center of the `min`-`max` box and backs off until its bounding sphere fits
+    /// the narrower of the two fields of view with a 10% margin, keeping yaw and pitch
+    pub fn fit_to_bounds(&mut self, min: Vec3, max: Vec3) {
+        self.fit_bounds = Some((min, max));
+        self.target_animation = None;
+        self.target = (min + max) * 0.5;
+        let radius = ((max - min).length() * 0.5).max(1e-3);
+        let horizontal_fov = 2.0 * ((self.fov * 0.5).tan() * self.aspect).atan();
+        let half_fov = self.fov.min(horizontal_fov) * 0.5;
+        self.distance = (radius * FIT_MARGIN / half_fov.sin()).clamp(MIN_DISTANCE, MAX_DISTANCE);
+        self.update_from_angles();
+    }
+    
+    /// Fits the view to the box last given to `fit_to_bounds` again, e.g. after zooming too far;
+    /// false if there is none yet
+    pub fn reset_to_fit(&mut self) -> bool {
+        let Some((min, max)) = self.fit_bounds else { return false };
+        self.fit_to_bounds(min, max);
+        true
+    }
+    
+    pub fn set_orbit_speed(&mut self, radians_per_second: f32) {
+        self.orbit_speed = radians_per_second;
+    }
+    
+    /// Turns `dt` seconds' worth of auto-orbit, unless it is off or the mouse is rotating the camera
+    pub fn advance_auto_orbit(&mut self, dt: f32) {
+        if !self.auto_orbit || self.is_rotating {
+            return;
+        }
+        self.yaw += self.orbit_speed * dt;
+        self.update_from_angles();
+    }
+    
+    pub fn update(&mut self, dt: f32) {
+        let Some((from, to, elapsed, duration)) = self.target_animation else { return };
+        let elapsed = elapsed + dt;
+        let t = (elapsed / duration).min(1.0);
+        self.target = from.lerp(to, t * t * (3.0 - 2.0 * t));
+        self.target_animation = (t < 1.0).then_some((from, to, elapsed, duration));
+        self.update_from_angles();
+    }
+    
+    /// Re-targets the camera on `world_point` without moving it, then orbits by the given deltas
+    pub fn orbit_around_point(&mut self, world_point: Vec3, delta_yaw: f32, delta_pitch: f32) {
+        self.target_animation = None;
+        let offset = self.position - world_point;
+        let distance = offset.length();
+        if distance > f32::EPSILON {
+            self.distance = distance;
+            self.pitch = (offset.y / distance).clamp(-1.0, 1.0).asin();
+            self.yaw = offset.z.atan2(offset.x);
+        }
+        self.target = world_point;
+        
+        self.yaw += delta_yaw;
+        self.pitch = (self.pitch + delta_pitch).clamp(-std::f32::consts::FRAC_PI_2 + 0.1, 
+                                                      std::f32::consts::FRAC_PI_2 - 0.1);
+        self.update_from_angles();
+    }
+    
+    /// World-space half-spaces `(normal, d)` of the view frustum in the order
+    /// left, right, bottom, top, near, far; a point `p` is inside when `plane.dot(p.extend(1.0)) >= 0`
+    pub fn get_frustum_planes(&self) -> [Vec4; 6] {
+        let view_proj = self.projection_matrix() * self.view_matrix();
+        let (r0, r1, r2, r3) = (view_proj.row(0), view_proj.row(1), view_proj.row(2), view_proj.row(3));
+        
+        // Gribb-Hartmann extraction; the left-handed projection maps depth to [0, 1]
+        [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|plane| plane / plane.truncate().length())
+    }
+    
     pub fn zoom(&mut self, delta: f32) {
         self.distance *= 1.0 + delta * 0.1;
-        self.distance = self.distance.clamp(1.0, 100.0);
+        self.distance = self.distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
         self.update_from_angles();
     }
     
+    /// Keeps `near` positive and `far` beyond it
+    pub fn set_clipping_planes(&mut self, near: f32, far: f32) {
+        self.near = near.max(1e-4);
+        self.far = far.max(self.near + 1e-3);
+    }
+    
+    /// Fits the clip planes tightly around `aabb` as seen from the current position, so depth
+    /// precision suits the size of the geometry
+    pub fn auto_adjust_clipping(&mut self, aabb: (Vec3, Vec3)) {
+        let (min, max) = aabb;
+        let radius = (max - min).length() * 0.5;
+        let distance = (self.position - (min + max) * 0.5).length();
+        self.set_clipping_planes((distance - radius * 1.5).max(0.01), distance + radius * 2.5);
+    }
+    
     pub fn set_aspect_ratio(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
-}
\ No newline at end of file
+    
+    pub fn screen_to_world_ray(&self, screen_pos: Vec2, screen_width: f32, screen_height: f32) -> (Vec3, Vec3) {
+        let ndc_x = screen_pos.x / screen_width * 2.0 - 1.0;
+        let ndc_y = 1.0 - screen_pos.y / screen_height * 2.0;
+        
+        let inv_view_proj = (self.projection_matrix() * self.view_matrix()).inverse();
+        let near = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+        
+        (near, (far - near).normalize_or_zero())
+    }
+    
+    /// Sets the orbit angles (radians) and distance with the same limits as mouse control;
+    /// call `update_from_angles` afterwards
+    pub fn set_yaw_pitch_distance(&mut self, yaw: f32, pitch: f32, distance: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-std::f32::consts::FRAC_PI_2 + 0.1, std::f32::consts::FRAC_PI_2 - 0.1);
+        self.distance = distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+    
+    pub fn save_named_view(&mut self, name: &str) {
+        self.named_views.insert(name.to_string(), (self.yaw, self.pitch, self.distance, self.target));
+    }
+    
+    pub fn load_named_view(&mut self, name: &str) -> bool {
+        if let Some(&(yaw, pitch, distance, target)) = self.named_views.get(name) {
+            self.target_animation = None;
+            self.yaw = yaw;
+            self.pitch = pitch;
+            self.distance = distance;
+            self.target = target;
+            self.update_from_angles();
+            true
+        } else {
+            false
+        }
+    }
+    
+    pub fn named_view_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.named_views.keys().cloned().collect();
+        names.sort();
+        names
+    }
+    
+    pub fn save_views_to_file(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&self.named_views)
+            .map_err(|e| format!("Failed to serialize camera views: {}", e))?;
+        fs::write(path, json)
+            .map_err(|e| format!("Failed to write camera views: {}", e))
+    }
+    
+    pub fn load_views_from_file(&mut self, path: &Path) -> Result<(), String> {
+        self.named_views.clear();
+        if !path.exists() {
+            return Ok(());
+        }
+        
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read camera views: {}", e))?;
+        self.named_views = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse camera views: {}", e))?;
+        Ok(())
+    }
+    
+    /// Camera views are stored next to the rule file as `<rule_basename>.views.json`
+    pub fn views_path_for_rule(rule_path: &Path) -> std::path::PathBuf {
+        let stem = rule_path.file_stem().and_then(|s| s.to_str()).unwrap_or("rule");
+        rule_path.with_file_name(format!("{}.views.json", stem))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn target_projects_to_the_ndc_origin() {
+        let mut camera = Camera::new(4.0 / 3.0);
+        camera.yaw = 0.7;
+        camera.target = Vec3::new(1.0, 2.0, -3.0);
+        camera.update_from_angles();
+        
+        let ndc = (camera.projection_matrix() * camera.view_matrix()).project_point3(camera.target);
+        assert!(ndc.x.abs() < 1e-5 && ndc.y.abs() < 1e-5, "target at {:?}", ndc);
+        assert!((0.0..=1.0).contains(&ndc.z));
+    }
+    
+    #[test]
+    fn fit_to_bounds_centers_and_frames_the_box() {
+        let mut camera = Camera::new(1.0);
+        assert!(!camera.reset_to_fit());
+        camera.fit_to_bounds(Vec3::ZERO, Vec3::splat(10.0));
+        assert_eq!(camera.target, Vec3::new(5.0, 5.0, 5.0));
+        // The bounding sphere's radius is 5√3 ≈ 8.66, and 1.1 × that / sin(22.5°) ≈ 24.9
+        assert!((24.5..25.5).contains(&camera.distance), "distance {}", camera.distance);
+        assert!((camera.position.distance(camera.target) - camera.distance).abs() < 1e-3);
+        
+        // Every corner is on screen
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        for corner in (0..8).map(|i| Vec3::new((i & 1) as f32, (i >> 1 & 1) as f32, (i >> 2) as f32) * 10.0) {
+            let ndc = view_proj.project_point3(corner);
+            assert!(ndc.x.abs() < 1.0 && ndc.y.abs() < 1.0, "{} at {}", corner, ndc);
+        }
+        
+        camera.zoom(50.0);
+        assert!(camera.reset_to_fit());
+        assert!((24.5..25.5).contains(&camera.distance));
+        
+        // Fitting keeps to the same distance limits as zooming
+        camera.fit_to_bounds(Vec3::ZERO, Vec3::splat(0.01));
+        assert_eq!(camera.distance, MIN_DISTANCE);
+        camera.fit_to_bounds(Vec3::ZERO, Vec3::splat(1e5));
+        assert_eq!(camera.distance, MAX_DISTANCE);
+    }
+    
+    #[test]
+    fn auto_orbit_turns_at_orbit_speed_unless_dragged() {
+        let mut camera = Camera::new(1.0);
+        let start_yaw = camera.yaw;
+        camera.advance_auto_orbit(1.0);
+        assert_eq!(camera.yaw, start_yaw);
+        
+        camera.auto_orbit = true;
+        camera.set_orbit_speed(std::f32::consts::FRAC_PI_2);
+        for _ in 0..60 {
+            camera.advance_auto_orbit(1.0 / 60.0);
+        }
+        assert!((camera.yaw - start_yaw - std::f32::consts::FRAC_PI_2).abs() < 1e-4, "yaw {}", camera.yaw);
+        assert!((camera.position.distance(camera.target) - camera.distance).abs() < 1e-3);
+        
+        // Dragging holds the orbit until the button is released
+        let yaw = camera.yaw;
+        camera.start_rotation(Vec2::ZERO);
+        camera.advance_auto_orbit(0.5);
+        assert_eq!(camera.yaw, yaw);
+        camera.stop_rotation();
+        camera.advance_auto_orbit(0.5);
+        assert!(camera.yaw > yaw);
+    }
+    
+    #[test]
+    fn frustum_contains_the_target_but_not_points_behind_the_camera() {
+        let camera = Camera::new(1.0);
+        let inside = |p: Vec3| camera.get_frustum_planes().iter().all(|plane| plane.dot(p.extend(1.0)) >= 0.0);
+        assert!(inside(camera.target));
+        assert!(!inside(camera.position * 2.0));
+    }
+    
+    #[test]
+    fn keyboard_orbit_clamps_pitch() {
+        let mut camera = Camera::new(1.0);
+        for _ in 0..100 {
+            camera.keyboard_orbit(false, false, true, false);
+        }
+        assert!(camera.pitch <= std::f32::consts::FRAC_PI_2 - 0.1 + 1e-6);
+        
+        let yaw = camera.yaw;
+        camera.keyboard_orbit(true, true, false, false);
+        assert_eq!(camera.yaw, yaw);
+    }
+    
+    #[test]
+    fn zoom_is_clamped() {
+        let mut camera = Camera::new(1.0);
+        camera.zoom(-100.0);
+        assert_eq!(camera.distance, MIN_DISTANCE);
+        camera.zoom(10000.0);
+        assert_eq!(camera.distance, MAX_DISTANCE);
+    }
+    
+    #[test]
+    fn named_views_restore_the_saved_angles() {
+        let mut camera = Camera::new(1.0);
+        camera.yaw = 1.0;
+        camera.save_named_view("View 1");
+        camera.yaw = 2.0;
+        assert!(camera.load_named_view("View 1"));
+        assert_eq!(camera.yaw, 1.0);
+        assert!(!camera.load_named_view("View 2"));
+    }
+    
+    #[test]
+    fn auto_center_reaches_the_centroid_after_half_a_second() {
+        let mut camera = Camera::new(1.0);
+        let centroid = Vec3::new(0.0, 5.0, 0.0);
+        camera.auto_center(centroid);
+        camera.update(0.25);
+        assert_eq!(camera.target, Vec3::new(0.0, 2.5, 0.0));
+        camera.update(0.25);
+        assert_eq!(camera.target, centroid);
+        assert!(((camera.position - camera.target).length() - camera.distance).abs() < 1e-4);
+        
+        camera.update(1.0);
+        assert_eq!(camera.target, centroid);
+    }
+    
+    #[test]
+    fn set_yaw_pitch_distance_applies_camera_limits() {
+        let mut camera = Camera::new(1.0);
+        camera.set_yaw_pitch_distance(0.5, 3.0, 5000.0);
+        assert_eq!(camera.yaw, 0.5);
+        assert_eq!(camera.pitch, std::f32::consts::FRAC_PI_2 - 0.1);
+        assert_eq!(camera.distance, MAX_DISTANCE);
+    }
+    
+    #[test]
+    fn auto_adjust_clipping_brackets_the_bounding_box() {
+        let mut camera = Camera::new(1.0);
+        camera.update_from_angles();
+        let distance = camera.position.length();
+        camera.auto_adjust_clipping((Vec3::splat(-1.0), Vec3::splat(1.0)));
+        let radius = 3.0_f32.sqrt();
+        assert!((camera.near - (distance - radius * 1.5)).abs() < 1e-4);
+        assert!((camera.far - (distance + radius * 2.5)).abs() < 1e-4);
+        
+        camera.auto_adjust_clipping((Vec3::splat(-100.0), Vec3::splat(100.0)));
+        assert_eq!(camera.near, 0.01);
+    }
+}
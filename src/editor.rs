@@ -2,6 +2,7 @@ use std::process::Command;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::Write;
+use crate::LSystemRule;
 
 pub struct Editor {
     editor_command: String,
@@ -75,6 +76,35 @@ impl Editor {
         Ok(path)
     }
     
+    /// Writes `source` to `dest_path` as a new rule file and opens it in the editor; returns
+    /// where the copy was written (see `duplicate_rule`)
+    pub fn new_from_existing(&self, source: &LSystemRule, dest_path: &Path) -> Result<PathBuf, String> {
+        let path = Self::duplicate_rule(source, dest_path)?;
+        self.edit_file(Some(&path))
+    }
+    
+    /// Saves `source` as pretty-printed JSON at `dest_path`, or at `<stem>_1.json`, `<stem>_2.json`, ...
+    /// if that file already exists
+    pub fn duplicate_rule(source: &LSystemRule, dest_path: &Path) -> Result<PathBuf, String> {
+        let path = unused_path(dest_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        source.save_to_file(&path)?;
+        Ok(path)
+    }
+    
+    /// `rules/<stem>_copy_<timestamp>.json` for a copy of the rule at `source_path`
+    pub fn copy_path_for(&self, source_path: &Path) -> PathBuf {
+        let stem = source_path.file_stem().and_then(|s| s.to_str()).unwrap_or("rule");
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.rules_directory.join(format!("{}_copy_{}.json", stem, timestamp))
+    }
+    
     fn create_new_file(&self) -> Result<PathBuf, String> {
         // Get a unique filename
         let timestamp = std::time::SystemTime::now()
@@ -119,49 +149,41 @@ impl Editor {
             
         Ok(())
     }
-    
-    pub fn edit_current_file(&self, current_file: Option<&Path>) -> Result<Option<PathBuf>, String> {
-        match current_file {
-            Some(path) => {
-                self.edit_file(Some(path))?;
-                Ok(Some(path.to_path_buf()))
-            },
-            None => {
-                // Create and edit a new file
-                let path = self.edit_file(None)?;
-                Ok(Some(path))
-            }
-        }
+}
+
+/// `path` itself if it is free, otherwise the first free `<stem>_N.<ext>` next to it
+fn unused_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
     }
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("rule");
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("json");
+    (1..)
+        .map(|n| path.with_file_name(format!("{}_{}.{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .expect("some numbered file name is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     
-    pub fn open_rules_directory(&self) -> Result<(), String> {
-        // Ensure directory exists
-        fs::create_dir_all(&self.rules_directory)
-            .map_err(|e| format!("Failed to create rules directory: {}", e))?;
+    #[test]
+    fn duplicate_rule_numbers_existing_destinations() {
+        let dir = std::env::temp_dir().join(format!("lsystem_duplicate_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rule = LSystemRule::builder().name("Copy Me").axiom("F").rule('F', "FF").angle(30.0).build().unwrap();
         
-        // Try to open the directory with system file manager
-        let status = if cfg!(target_os = "linux") {
-            Command::new("xdg-open").arg(&self.rules_directory).status()
-        } else if cfg!(target_os = "macos") {
-            Command::new("open").arg(&self.rules_directory).status()
-        } else if cfg!(target_os = "windows") {
-            Command::new("explorer").arg(&self.rules_directory).status()
-        } else {
-            return Err("Unsupported operating system".to_string());
-        };
+        let first = Editor::duplicate_rule(&rule, &dir.join("copy.json")).unwrap();
+        let second = Editor::duplicate_rule(&rule, &dir.join("copy.json")).unwrap();
+        let third = Editor::duplicate_rule(&rule, &dir.join("copy.json")).unwrap();
+        assert_eq!(first, dir.join("copy.json"));
+        assert_eq!(second, dir.join("copy_1.json"));
+        assert_eq!(third, dir.join("copy_2.json"));
         
-        match status {
-            Ok(status) if status.success() => Ok(()),
-            Ok(_) => Err("File manager exited with error".to_string()),
-            Err(e) => Err(format!("Failed to open file manager: {}", e)),
-        }
-    }
-    
-    pub fn set_editor(&mut self, editor: String) {
-        self.editor_command = editor;
+        let copied = crate::load_rule_from_file(third.to_str().unwrap()).unwrap();
+        assert_eq!(copied.name, "Copy Me");
+        assert_eq!(copied.rules, rule.rules);
+        fs::remove_dir_all(&dir).unwrap();
     }
-    
-    pub fn get_editor(&self) -> &str {
-        &self.editor_command
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use minifb::{Key, Window};
+
+#[derive(Debug, Clone)]
+pub struct GalleryEntry {
+    pub name: String,
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct GalleryCategory {
+    pub name: String,
+    pub entries: Vec<GalleryEntry>,
+    pub expanded: bool,
+}
+
+/// A visible line in the gallery: a category header or one of its entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GalleryRow {
+    Category(usize),
+    Entry(usize, usize),
+}
+
+pub struct GalleryBrowser {
+    pub categories: Vec<GalleryCategory>,
+    pub selected_index: usize,
+}
+
+impl GalleryBrowser {
+    /// Recursively finds rule files below `root`, grouped by their parent directory.
+    /// Files directly inside `root` are left to the tree selection menu.
+    pub fn scan(root: &Path) -> Self {
+        let mut files = Vec::new();
+        Self::collect_rule_files(root, &mut files);
+        
+        let mut categories: Vec<GalleryCategory> = Vec::new();
+        for path in files {
+            let parent = match path.parent() {
+                Some(parent) if parent != root => parent,
+                _ => continue,
+            };
+            
+            let category_name = parent.strip_prefix(root)
+                .unwrap_or(parent)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let name = path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .replace('_', " ");
+            let entry = GalleryEntry { name, file_path: path.clone() };
+            
+            match categories.iter_mut().find(|c| c.name == category_name) {
+                Some(category) => category.entries.push(entry),
+                None => categories.push(GalleryCategory {
+                    name: category_name,
+                    entries: vec![entry],
+                    expanded: false,
+                }),
+            }
+        }
+        
+        categories.sort_by(|a, b| a.name.cmp(&b.name));
+        for category in &mut categories {
+            category.entries.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        
+        Self {
+            categories,
+            selected_index: 0,
+        }
+    }
+    
+    fn collect_rule_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    Self::collect_rule_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "json" || ext == "toml") &&
+                          !path.to_str().is_some_and(|p| p.ends_with(".views.json")) {
+                    files.push(path);
+                }
+            }
+        }
+    }
+    
+    pub fn visible_rows(&self) -> Vec<GalleryRow> {
+        let mut rows = Vec::new();
+        for (ci, category) in self.categories.iter().enumerate() {
+            rows.push(GalleryRow::Category(ci));
+            if category.expanded {
+                for ei in 0..category.entries.len() {
+                    rows.push(GalleryRow::Entry(ci, ei));
+                }
+            }
+        }
+        rows
+    }
+    
+    pub fn handle_input(&mut self, window: &Window) -> Option<PathBuf> {
+        let rows = self.visible_rows();
+        if rows.is_empty() {
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
+            if self.selected_index > 0 {
+                self.selected_index -= 1;
+            } else {
+                self.selected_index = rows.len() - 1;
+            }
+        }
+        
+        if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
+            self.selected_index = (self.selected_index + 1) % rows.len();
+        }
+        
+        if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
+            match rows.get(self.selected_index) {
+                Some(GalleryRow::Category(ci)) => {
+                    self.categories[*ci].expanded = !self.categories[*ci].expanded;
+                }
+                Some(GalleryRow::Entry(ci, ei)) => {
+                    return Some(self.categories[*ci].entries[*ei].file_path.clone());
+                }
+                None => {}
+            }
+        }
+        
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn scan_groups_nested_rule_files_by_directory() {
+        let root = std::env::temp_dir().join(format!("lsystem_gallery_{}", std::process::id()));
+        for path in ["top.json", "trees/oak_tree.json", "trees/birch.toml", "trees/oak_tree.views.json",
+                     "trees/notes.txt", "ferns/fern.json"] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "{}").unwrap();
+        }
+        let gallery = GalleryBrowser::scan(&root);
+        fs::remove_dir_all(&root).ok();
+        
+        let names: Vec<&str> = gallery.categories.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["ferns", "trees"]);
+        let trees: Vec<&str> = gallery.categories[1].entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(trees, ["birch", "oak tree"]);
+    }
+    
+    #[test]
+    fn visible_rows_list_entries_of_expanded_categories() {
+        let entry = |name: &str| GalleryEntry { name: name.to_string(), file_path: PathBuf::from(name) };
+        let mut gallery = GalleryBrowser {
+            categories: vec![
+                GalleryCategory { name: "a".to_string(), entries: vec![entry("x"), entry("y")], expanded: false },
+                GalleryCategory { name: "b".to_string(), entries: vec![entry("z")], expanded: false },
+            ],
+            selected_index: 0,
+        };
+        assert_eq!(gallery.visible_rows(), [GalleryRow::Category(0), GalleryRow::Category(1)]);
+        
+        gallery.categories[0].expanded = true;
+        assert_eq!(gallery.visible_rows(), [GalleryRow::Category(0), GalleryRow::Entry(0, 0),
+                                            GalleryRow::Entry(0, 1), GalleryRow::Category(1)]);
+    }
+}
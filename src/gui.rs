@@ -1,139 +1,809 @@
+use glam::Vec3;
 use minifb::{Key, Window};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::LSystemRule;
+use crate::renderer::{color_to_u32, Renderer};
+use crate::turtle3d::is_turtle_command;
+use crate::wizard::key_to_char;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParameterPreset {
+    pub name: String,
+    pub angle: Option<f32>,
+    pub step_length: Option<f32>,
+    pub iterations: Option<u32>,
+}
+
+/// Seconds between two clicks on a drag value for them to count as a double-click
+const DOUBLE_CLICK_SECONDS: f32 = 0.4;
+
+/// A number that changes as the mouse is dragged across it; double-click to type a value
 #[derive(Debug, Clone)]
-pub struct Slider {
+pub struct DragValue {
     pub name: String,
     pub value: f32,
+    /// Value the control was created with, restored by `Gui::reset_to_defaults`
+    pub default_value: f32,
     pub min: f32,
     pub max: f32,
+    /// Value change per pixel dragged
+    pub speed: f32,
+    /// How the value is shown, e.g. `{:.1}` or `{:.2}x`
+    pub format: String,
+    /// Dragging scales the value instead of adding to it, for ranges spanning orders of magnitude
+    pub logarithmic: bool,
     pub step: f32,
     pub x: usize,
     pub y: usize,
     pub width: usize,
     pub height: usize,
+    pub target_value: Option<f32>,
+    /// Text typed since a double-click, applied with Enter
+    pub text_input: Option<String>,
+    dragging: bool,
+    last_click: Option<Instant>,
 }
 
-impl Slider {
-    pub fn new(name: &str, value: f32, min: f32, max: f32, x: usize, y: usize) -> Self {
+impl DragValue {
+    pub fn new(name: &str, value: f32, min: f32, max: f32, format: &str) -> Self {
         Self {
             name: name.to_string(),
             value,
+            default_value: value,
             min,
             max,
+            speed: (max - min) / 200.0,
+            format: format.to_string(),
+            logarithmic: false,
             step: (max - min) / 100.0,
-            x,
-            y,
+            x: 20,
+            y: 0,
             width: 200,
             height: 20,
+            target_value: None,
+            text_input: None,
+            dragging: false,
+            last_click: None,
         }
     }
     
-    pub fn update(&mut self, window: &Window, mouse_x: f32, mouse_y: f32, mouse_pressed: bool) -> bool {
-        if mouse_pressed &&
-           mouse_x >= self.x as f32 && mouse_x <= (self.x + self.width) as f32 &&
-           mouse_y >= self.y as f32 && mouse_y <= (self.y + self.height) as f32 {
-            
-            let relative_x = (mouse_x - self.x as f32) / self.width as f32;
-            let relative_x = relative_x.clamp(0.0, 1.0);
-            let new_value = self.min + relative_x * (self.max - self.min);
-            
-            if (new_value - self.value).abs() > self.step * 0.1 {
-                self.value = new_value;
-                return true; // Value changed
+    /// Makes `speed` the change in the value's natural log per pixel; `min` must be positive
+    pub fn logarithmic(mut self) -> Self {
+        self.logarithmic = true;
+        self.speed = (self.max / self.min).ln() / 200.0;
+        self
+    }
+    
+    /// Starts a smooth transition towards `value`, advanced by `step_animation`
+    pub fn animate_to(&mut self, value: f32) {
+        self.target_value = Some(value.clamp(self.min, self.max));
+    }
+    
+    /// Returns true if the value moved
+    pub fn step_animation(&mut self, dt: f32) -> bool {
+        let target = match self.target_value {
+            Some(target) => target,
+            None => return false,
+        };
+        
+        self.value += (target - self.value) * (dt * 10.0).min(1.0);
+        if (target - self.value).abs() <= self.step * 0.1 {
+            self.value = target;
+            self.target_value = None;
+        }
+        true
+    }
+    
+    /// Where the value sits in its range, from 0.0 to 1.0
+    fn fraction(&self) -> f32 {
+        let fraction = if self.logarithmic {
+            (self.value / self.min).ln() / (self.max / self.min).ln()
+        } else {
+            (self.value - self.min) / (self.max - self.min)
+        };
+        fraction.clamp(0.0, 1.0)
+    }
+    
+    pub fn is_dragging(&self) -> bool {
+        self.dragging
+    }
+    
+    fn contains(&self, mouse_x: f32, mouse_y: f32) -> bool {
+        mouse_x >= self.x as f32 && mouse_x <= (self.x + self.width) as f32 &&
+            mouse_y >= self.y as f32 && mouse_y <= (self.y + self.height) as f32
+    }
+    
+    /// Drags while the button is held after pressing on the value; Shift drags at a tenth of
+    /// the speed and Ctrl at ten times. Returns true if the value changed
+    pub fn update(&mut self, window: &Window, mouse_x: f32, mouse_y: f32, mouse_pressed: bool, prev_mouse_x: f32) -> bool {
+        if !mouse_pressed {
+            self.dragging = false;
+            return false;
+        }
+        
+        if !self.dragging {
+            if !self.contains(mouse_x, mouse_y) {
+                return false;
             }
+            let now = Instant::now();
+            if self.last_click.is_some_and(|last| now.duration_since(last).as_secs_f32() < DOUBLE_CLICK_SECONDS) {
+                self.text_input = Some(String::new());
+                self.last_click = None;
+            } else {
+                self.last_click = Some(now);
+            }
+            self.dragging = true;
+            return false;
         }
-        false
+        
+        let scale = if window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift) {
+            0.1
+        } else if window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl) {
+            10.0
+        } else {
+            1.0
+        };
+        self.drag(mouse_x - prev_mouse_x, scale)
     }
     
-    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize) {
-        // Draw slider background
-        self.fill_rect(buffer, width, height, self.x, self.y, self.width, self.height, 0x404040);
+    /// Moves the value by `dx` pixels' worth of `speed * scale`; returns true if it changed
+    pub fn drag(&mut self, dx: f32, scale: f32) -> bool {
+        let new_value = if self.logarithmic {
+            self.value * (dx * self.speed * scale).exp()
+        } else {
+            self.value + dx * self.speed * scale
+        }.clamp(self.min, self.max);
+        if new_value == self.value {
+            return false;
+        }
+        self.value = new_value;
+        self.target_value = None;
+        true
+    }
+    
+    /// Applies the typed text if it parses as a number; either way the text input closes
+    pub fn commit_text_input(&mut self) -> bool {
+        let parsed = self.text_input.take().and_then(|text| text.trim().parse::<f32>().ok());
+        match parsed {
+            Some(value) => {
+                self.value = value.clamp(self.min, self.max);
+                self.target_value = None;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// `value` written out through `format`, whose `{}` or `{:.N}` placeholder takes the number
+    pub fn format_value(&self) -> String {
+        let (open, close) = match (self.format.find('{'), self.format.find('}')) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => return format!("{:.2}", self.value),
+        };
+        let precision = self.format[open + 1..close].strip_prefix(":.")
+            .and_then(|digits| digits.parse::<usize>().ok())
+            .unwrap_or(2);
+        format!("{}{:.*}{}", &self.format[..open], precision, self.value, &self.format[close + 1..])
+    }
+    
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize, y_offset: i32, alpha: f32, focused: bool) {
+        let y = (self.y as i32 + y_offset).max(0) as usize;
         
-        // Draw slider track
-        let track_y = self.y + self.height / 2 - 2;
-        self.fill_rect(buffer, width, height, self.x + 5, track_y, self.width - 10, 4, 0x606060);
+        // Dark background with the value's position in its range filled in from the left
+        self.fill_rect(buffer, width, height, (self.x, y, self.width, self.height), 0x303030, alpha);
+        let fill_width = (self.fraction() * self.width as f32) as usize;
+        self.fill_rect(buffer, width, height, (self.x, y, fill_width, self.height), 0x2F4A34, alpha);
         
-        // Draw slider handle
-        let handle_pos = ((self.value - self.min) / (self.max - self.min) * (self.width - 20) as f32) as usize;
-        let handle_x = self.x + 10 + handle_pos;
-        let handle_y = self.y + 2;
-        self.fill_rect(buffer, width, height, handle_x - 5, handle_y, 10, self.height - 4, 0x00FF00);
+        let (text, color) = match &self.text_input {
+            Some(text) => (format!("{}: {}_", self.name, text), 0xFFFF00),
+            None => (format!("{}: {}", self.name, self.format_value()), 0xFFFFFF),
+        };
+        Renderer::render_text_at_screen(buffer, width, height, (self.x + 6, y + (self.height - 8) / 2), &text, color, 1);
         
-        // Draw label
-        self.draw_text(buffer, width, height, self.x, self.y - 15, 
-                      &format!("{}: {:.2}", self.name, self.value), 0xFFFFFF);
+        if focused {
+            self.fill_rect(buffer, width, height, (self.x, y, self.width, 1), FOCUS_BORDER_COLOR, alpha);
+            self.fill_rect(buffer, width, height, (self.x, y + self.height - 1, self.width, 1), FOCUS_BORDER_COLOR, alpha);
+            self.fill_rect(buffer, width, height, (self.x, y, 1, self.height), FOCUS_BORDER_COLOR, alpha);
+            self.fill_rect(buffer, width, height, (self.x + self.width - 1, y, 1, self.height), FOCUS_BORDER_COLOR, alpha);
+        }
     }
     
     fn fill_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize, 
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
-        for dy in 0..h {
-            for dx in 0..w {
-                let px = x + dx;
-                let py = y + dy;
-                if px < buf_width && py < buf_height {
-                    buffer[py * buf_width + px] = color;
-                }
-            }
+                (x, y, w, h): (usize, usize, usize, usize), color: u32, alpha: f32) {
+        fill_rect(buffer, buf_width, buf_height, (x, y, w, h), color, alpha);
+    }
+}
+
+/// Height of a waveform's label row, above its graph
+const WAVEFORM_HEADER: usize = 12;
+/// Pixels of drag one Left/Right press on a focused control is worth
+const KEY_NUDGE_PIXELS: f32 = 5.0;
+const FOCUS_BORDER_COLOR: u32 = 0xFFFFFF;
+
+/// The GUI widget that receives keyboard input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusTarget {
+    None,
+    /// Left/Right nudge the value, Enter starts typing one, Tab moves to the next control
+    Control(usize),
+    /// The control at this index is taking typed text
+    TextInput(usize),
+    /// The rule editor's row at this index is taking a new replacement string
+    RuleText(usize),
+}
+
+/// A change made in the rule editor, for the caller to apply to its L-system
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleEdit {
+    Replace(char, String),
+    Remove(char),
+}
+
+// Rule editor box, to the right of the parameter panel
+const RULE_EDITOR_X: usize = PANEL_X + PANEL_WIDTH + 10;
+const RULE_EDITOR_WIDTH: usize = 300;
+const RULE_ROW_HEIGHT: usize = 18;
+const RULE_ROWS_TOP: usize = 30;
+const RULE_BUTTON_SIZE: usize = 14;
+
+/// Scrollable list of `symbol -> replacement` rows; clicking a row types a new replacement,
+/// and the + and - buttons add a rule or remove the selected one
+pub struct RuleEditor {
+    pub rows: Vec<(char, String)>,
+    /// Index of the first visible row
+    pub scroll: usize,
+    pub selected: Option<usize>,
+    /// Replacement being typed into the focused row
+    pub text_input: Option<String>,
+    // Symbol of a row added with + that has no rule until Enter is pressed
+    uncommitted: Option<char>,
+}
+
+impl RuleEditor {
+    pub fn new() -> Self {
+        Self { rows: Vec::new(), scroll: 0, selected: None, text_input: None, uncommitted: None }
+    }
+    
+    /// Shows `rules` sorted by symbol
+    pub fn load(&mut self, rules: &HashMap<char, String>) {
+        self.rows = rules.iter().map(|(&symbol, replacement)| (symbol, replacement.clone())).collect();
+        self.rows.sort_by_key(|&(symbol, _)| symbol);
+        self.scroll = self.scroll.min(self.rows.len().saturating_sub(1));
+        self.selected = self.selected.filter(|&i| i < self.rows.len());
+        self.uncommitted = None;
+    }
+    
+    fn visible_rows() -> usize {
+        (PANEL_HEIGHT - RULE_ROWS_TOP - 8) / RULE_ROW_HEIGHT
+    }
+    
+    /// Adds a row for the first capital letter that is neither a rule nor a turtle command
+    pub fn add_row(&mut self) -> Option<usize> {
+        let symbol = ('A'..='Z').find(|&c| !is_turtle_command(c) && self.rows.iter().all(|&(s, _)| s != c))?;
+        self.rows.push((symbol, String::new()));
+        self.uncommitted = Some(symbol);
+        let index = self.rows.len() - 1;
+        self.scroll = self.scroll.max((index + 1).saturating_sub(Self::visible_rows()));
+        Some(index)
+    }
+    
+    /// Drops the row if it was added with + and never committed
+    fn discard_uncommitted(&mut self, index: usize) {
+        if self.uncommitted.take().is_some_and(|symbol| self.rows.get(index).is_some_and(|&(s, _)| s == symbol)) {
+            self.rows.remove(index);
+            self.selected = None;
+        }
+    }
+    
+    pub fn contains(mouse_x: f32, mouse_y: f32) -> bool {
+        mouse_x >= RULE_EDITOR_X as f32 && mouse_x <= (RULE_EDITOR_X + RULE_EDITOR_WIDTH) as f32 &&
+            mouse_y >= PANEL_Y as f32 && mouse_y <= (PANEL_Y + PANEL_HEIGHT) as f32
+    }
+    
+    fn button_x(minus: bool) -> usize {
+        RULE_EDITOR_X + RULE_EDITOR_WIDTH - if minus { 2 } else { 3 } * (RULE_BUTTON_SIZE + 4)
+    }
+    
+    /// `Some(true)` over the - button, `Some(false)` over +
+    fn button_at(mouse_x: f32, mouse_y: f32) -> Option<bool> {
+        let top = (PANEL_Y + 8) as f32;
+        if mouse_y < top || mouse_y >= top + RULE_BUTTON_SIZE as f32 {
+            return None;
         }
+        [false, true].into_iter().find(|&minus| {
+            let x = Self::button_x(minus) as f32;
+            mouse_x >= x && mouse_x < x + RULE_BUTTON_SIZE as f32
+        })
     }
     
-    fn draw_text(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize,
-                x: usize, y: usize, text: &str, color: u32) {
-        // Simple bitmap font rendering
-        let char_width = 6;
-        let char_height = 8;
+    fn row_at(&self, mouse_x: f32, mouse_y: f32) -> Option<usize> {
+        let top = (PANEL_Y + RULE_ROWS_TOP) as f32;
+        if !Self::contains(mouse_x, mouse_y) || mouse_y < top {
+            return None;
+        }
+        let visible = ((mouse_y - top) / RULE_ROW_HEIGHT as f32) as usize;
+        let index = self.scroll + visible;
+        (visible < Self::visible_rows() && index < self.rows.len()).then_some(index)
+    }
+    
+    fn render(&self, buffer: &mut [u32], width: usize, height: usize, y_offset: usize, alpha: f32) {
+        let y = PANEL_Y + y_offset;
+        fill_rect(buffer, width, height, (RULE_EDITOR_X, y, RULE_EDITOR_WIDTH, PANEL_HEIGHT), 0x202020, alpha);
+        Renderer::render_text_at_screen(buffer, width, height, (RULE_EDITOR_X + 10, y + 12), "Rules", 0xFFFFFF, 1);
+        for (minus, label) in [(false, "+"), (true, "-")] {
+            let x = Self::button_x(minus);
+            fill_rect(buffer, width, height, (x, y + 8, RULE_BUTTON_SIZE, RULE_BUTTON_SIZE), 0x404040, alpha);
+            Renderer::render_text_at_screen(buffer, width, height, (x + 4, y + 11), label, 0xFFFFFF, 1);
+        }
         
-        for (i, _c) in text.chars().enumerate() {
-            let char_x = x + i * char_width;
+        let text_x = RULE_EDITOR_X + 40;
+        let text_width = RULE_EDITOR_WIDTH - 50;
+        let max_chars = (text_width - 8) / 6;
+        for (visible, (index, (symbol, replacement))) in self.rows.iter().enumerate().skip(self.scroll)
+            .take(Self::visible_rows()).enumerate() {
+            let row_y = y + RULE_ROWS_TOP + visible * RULE_ROW_HEIGHT;
+            let editing = self.selected == Some(index) && self.text_input.is_some();
+            let label_color = if self.selected == Some(index) { 0x00FF00 } else { 0xCCCCCC };
+            Renderer::render_text_at_screen(buffer, width, height, (RULE_EDITOR_X + 10, row_y + 4),
+                                            &format!("{} ->", symbol), label_color, 1);
             
-            // Draw a simple rectangle pattern for each character
-            for dy in 0..char_height {
-                for dx in 0..char_width {
-                    let px = char_x + dx;
-                    let py = y + dy;
-                    
-                    if px < buf_width && py < buf_height {
-                        // Simple pattern to make text visible
-                        if (dy == 1 || dy == char_height - 2) && dx > 0 && dx < char_width - 1 {
-                            buffer[py * buf_width + px] = color;
-                        }
-                        if (dx == 1 || dx == char_width - 2) && dy > 1 && dy < char_height - 2 {
-                            buffer[py * buf_width + px] = color;
-                        }
-                    }
-                }
-            }
+            let box_color = if editing { 0x404060 } else { 0x303030 };
+            fill_rect(buffer, width, height, (text_x, row_y, text_width, RULE_ROW_HEIGHT - 2), box_color, alpha);
+            // While typing, show the end of the text where the cursor is
+            let text = match (&self.text_input, editing) {
+                (Some(input), true) => format!("{}_", input),
+                _ => replacement.clone(),
+            };
+            let skip = if editing { text.chars().count().saturating_sub(max_chars) } else { 0 };
+            let shown: String = text.chars().skip(skip).take(max_chars).collect();
+            Renderer::render_text_at_screen(buffer, width, height, (text_x + 4, row_y + 4), &shown, 0xFFFFFF, 1);
         }
     }
 }
 
-pub struct GUI {
-    pub sliders: Vec<Slider>,
+// Panel layout; the controls scroll between CONTENT_TOP and CONTENT_BOTTOM
+const PANEL_X: usize = 10;
+const PANEL_Y: usize = 10;
+const PANEL_WIDTH: usize = 250;
+const PANEL_HEIGHT: usize = 300;
+const CONTENT_TOP: usize = 35;
+const CONTENT_BOTTOM: usize = 270;
+// Controls are stacked below the title with this much space between them
+const TITLE_HEIGHT: usize = 40;
+const CONTROL_PADDING: usize = 10;
+/// Oldest snapshots are dropped beyond this many
+const MAX_HISTORY: usize = 50;
+
+pub struct Gui {
+    pub controls: Vec<DragValue>,
     pub visible: bool,
     pub mouse_pressed: bool,
     pub last_mouse_pos: (f32, f32),
+    pub animation_progress: f32,
+    pub animating: bool,
+    pub scroll_offset: i32,
+    pub presets: Vec<(String, HashMap<String, f32>)>,
+    pub palette: Vec<Vec3>,
+    /// One line about the generated string, shown at the bottom of the panel
+    pub stats_summary: String,
+    /// Set by clicking a control or cycling with Tab; cleared by Escape or clicking elsewhere
+    pub focused: FocusTarget,
+    pub rule_editor: RuleEditor,
+    // Rule editor changes waiting for `take_rule_edits`
+    rule_edits: Vec<RuleEdit>,
+    values_changed: bool,
+    /// Snapshots of every control's value for undo and redo, oldest first
+    history: Vec<Vec<f32>>,
+    /// Index in `history` of the snapshot the controls currently show
+    history_cursor: usize,
 }
 
-impl GUI {
+impl Gui {
     pub fn new() -> Self {
-        let mut sliders = Vec::new();
-        
-        // Create parameter sliders
-        sliders.push(Slider::new("Angle", 25.0, 5.0, 90.0, 20, 50));
-        sliders.push(Slider::new("Step Length", 1.0, 0.1, 3.0, 20, 100));
-        sliders.push(Slider::new("Trunk Width", 5.0, 1.0, 20.0, 20, 150));
-        sliders.push(Slider::new("Branch Taper", 0.8, 0.3, 1.0, 20, 200));
-        
-        Self {
-            sliders,
+        let mut gui = Self {
+            controls: Vec::new(),
             visible: false,
             mouse_pressed: false,
             last_mouse_pos: (0.0, 0.0),
+            animation_progress: 0.0,
+            animating: false,
+            scroll_offset: 0,
+            presets: Vec::new(),
+            palette: Vec::new(),
+            stats_summary: String::new(),
+            focused: FocusTarget::None,
+            rule_editor: RuleEditor::new(),
+            rule_edits: Vec::new(),
+            values_changed: false,
+            history: Vec::new(),
+            history_cursor: 0,
+        };
+        // Rules with a taper also get Trunk Width and Branch Taper sliders, see `load_rule`
+        for slider in [
+            DragValue::new("Angle", 25.0, 5.0, 90.0, "{:.1}"),
+            DragValue::new("Step Length", 1.0, 0.1, 3.0, "{:.2}"),
+            DragValue::new("Iterations", 5.0, 1.0, 8.0, "{:.0}"),
+            DragValue::new("Line Width Scale", 1.0, 0.25, 3.0, "{:.2}x"),
+            DragValue::new("AO Strength", 0.0, 0.0, 1.0, "{:.2}"),
+            DragValue::new("Near Clip", 0.1, 0.001, 1.0, "{:.3}").logarithmic(),
+            DragValue::new("Far Clip", 1000.0, 10.0, 10000.0, "{:.0}").logarithmic(),
+            DragValue::new("Focus Distance", 10.0, 0.1, 1000.0, "{:.1}").logarithmic(),
+            DragValue::new("Orbit Speed", 0.3, 0.0, 2.0, "{:.2} rad/s"),
+            // Post-processing: 0 turns each pass off; tone mapping 1 is Reinhard and 2 is ACES.
+            // Fog mode 0 fades line colors per vertex and 1 fades the finished frame by depth
+            DragValue::new("Fog Density", 0.0, 0.0, 1.0, "{:.2}"),
+            DragValue::new("Fog Mode", 0.0, 0.0, 1.0, "{:.0}"),
+            DragValue::new("Bloom", 0.0, 0.0, 2.0, "{:.2}"),
+            DragValue::new("Tone Mapping", 0.0, 0.0, 2.0, "{:.0}"),
+        ] {
+            gui.add_slider(slider);
+        }
+        gui.history.push(gui.snapshot());
+        gui
+    }
+    
+    /// Stacks the controls below the panel title in list order, ignoring their current `y`
+    pub fn layout(&mut self) {
+        let mut y = PANEL_Y + TITLE_HEIGHT;
+        for control in &mut self.controls {
+            control.y = y;
+            y += control.height + CONTROL_PADDING;
+        }
+        self.scroll_offset = self.scroll_offset.clamp(0, self.max_scroll());
+    }
+    
+    pub fn add_slider(&mut self, slider: DragValue) {
+        self.insert_slider_at(self.controls.len(), slider);
+    }
+    
+    /// Inserts before the control at `index`, or appends if `index` is past the end
+    pub fn insert_slider_at(&mut self, index: usize, slider: DragValue) {
+        let index = index.min(self.controls.len());
+        // Undo snapshots hold one value per control, so they gain the slider's value too
+        for snapshot in &mut self.history {
+            snapshot.insert(index, slider.value);
+        }
+        self.controls.insert(index, slider);
+        // Indices shift, so the focused index would point at the wrong control
+        self.focused = FocusTarget::None;
+        self.layout();
+    }
+    
+    pub fn remove_slider(&mut self, name: &str) -> Option<DragValue> {
+        let index = self.controls.iter().position(|c| c.name == name)?;
+        for snapshot in &mut self.history {
+            snapshot.remove(index);
+        }
+        let slider = self.controls.remove(index);
+        self.focused = FocusTarget::None;
+        self.layout();
+        Some(slider)
+    }
+    
+    /// True while a control has keyboard focus; other key handlers should stand aside
+    pub fn is_any_widget_focused(&self) -> bool {
+        self.visible && self.focused != FocusTarget::None
+    }
+    
+    fn snapshot(&self) -> Vec<f32> {
+        self.controls.iter().map(|c| c.value).collect()
+    }
+    
+    /// Pushes the current values after the cursor, dropping any redo history, unless they are
+    /// already the cursor's snapshot
+    fn record_snapshot(&mut self) {
+        let snapshot = self.snapshot();
+        if self.history.get(self.history_cursor) == Some(&snapshot) {
+            return;
+        }
+        self.history.truncate(self.history_cursor + 1);
+        self.history.push(snapshot);
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history_cursor = self.history.len() - 1;
+    }
+    
+    fn restore_snapshot(&mut self, index: usize) {
+        self.history_cursor = index;
+        for (control, &value) in self.controls.iter_mut().zip(&self.history[index]) {
+            control.value = value.clamp(control.min, control.max);
+            control.target_value = None;
+        }
+    }
+    
+    /// Steps the controls back to their previous values; returns false if there are none
+    pub fn undo(&mut self) -> bool {
+        // Changes made outside handle_input, such as loading a rule, become undoable first
+        self.record_snapshot();
+        if self.history_cursor == 0 {
+            return false;
+        }
+        self.restore_snapshot(self.history_cursor - 1);
+        true
+    }
+    
+    /// Steps forward again after `undo`; returns false if nothing was undone
+    pub fn redo(&mut self) -> bool {
+        if self.history_cursor + 1 >= self.history.len() || self.history[self.history_cursor] != self.snapshot() {
+            return false;
+        }
+        self.restore_snapshot(self.history_cursor + 1);
+        true
+    }
+    
+    /// Puts every control back to the value it was created with, as one undoable change
+    pub fn reset_to_defaults(&mut self) {
+        self.record_snapshot();
+        for control in &mut self.controls {
+            control.value = control.default_value;
+            control.target_value = None;
+        }
+        self.record_snapshot();
+    }
+    
+    /// Rule changes made in the editor since the last call, oldest first
+    pub fn take_rule_edits(&mut self) -> Vec<RuleEdit> {
+        std::mem::take(&mut self.rule_edits)
+    }
+    
+    /// Starts typing a new replacement for the rule editor's row `index`
+    fn edit_rule_row(&mut self, index: usize) {
+        let editor = &mut self.rule_editor;
+        editor.selected = Some(index);
+        editor.text_input = editor.rows.get(index).map(|(_, replacement)| replacement.clone());
+        self.focused = FocusTarget::RuleText(index);
+    }
+    
+    /// Removes the selected rule editor row, queueing a `RuleEdit::Remove` unless it was never committed
+    fn remove_selected_rule(&mut self) {
+        let editor = &mut self.rule_editor;
+        let Some(index) = editor.selected.filter(|&i| i < editor.rows.len()) else { return };
+        let (symbol, _) = editor.rows.remove(index);
+        if editor.uncommitted.take() != Some(symbol) {
+            self.rule_edits.push(RuleEdit::Remove(symbol));
+        }
+        editor.selected = None;
+        editor.text_input = None;
+        self.focused = FocusTarget::None;
+    }
+    
+    /// Applies one key press to the focused control; returns true if a value changed
+    pub fn handle_focus_key(&mut self, key: Key, shift: bool, ctrl: bool) -> bool {
+        match self.focused {
+            FocusTarget::None => false,
+            FocusTarget::RuleText(index) => {
+                let editor = &mut self.rule_editor;
+                match key {
+                    Key::Enter | Key::NumPadEnter => {
+                        let text = editor.text_input.take().unwrap_or_default();
+                        if let Some((symbol, replacement)) = editor.rows.get_mut(index) {
+                            replacement.clone_from(&text);
+                            self.rule_edits.push(RuleEdit::Replace(*symbol, text));
+                        }
+                        editor.uncommitted = None;
+                        self.focused = FocusTarget::None;
+                    }
+                    Key::Escape => {
+                        editor.text_input = None;
+                        editor.discard_uncommitted(index);
+                        self.focused = FocusTarget::None;
+                    }
+                    Key::Backspace => {
+                        if let Some(text) = editor.text_input.as_mut() {
+                            text.pop();
+                        }
+                    }
+                    _ => {
+                        if let (Some(c), Some(text)) = (key_to_char(key, shift), editor.text_input.as_mut()) {
+                            text.push(c);
+                        }
+                    }
+                }
+                false
+            }
+            FocusTarget::TextInput(index) => {
+                let Some(control) = self.controls.get_mut(index) else { return false };
+                match key {
+                    Key::Enter | Key::NumPadEnter => {
+                        self.focused = FocusTarget::Control(index);
+                        return control.commit_text_input();
+                    }
+                    Key::Escape => {
+                        control.text_input = None;
+                        self.focused = FocusTarget::Control(index);
+                    }
+                    Key::Backspace => {
+                        if let Some(text) = control.text_input.as_mut() {
+                            text.pop();
+                        }
+                    }
+                    _ => {
+                        let typed = key_to_char(key, shift).filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-');
+                        if let (Some(c), Some(text)) = (typed, control.text_input.as_mut()) {
+                            text.push(c);
+                        }
+                    }
+                }
+                false
+            }
+            FocusTarget::Control(index) => {
+                let count = self.controls.len();
+                let scale = if shift { 0.1 } else if ctrl { 10.0 } else { 1.0 };
+                match key {
+                    Key::Left => return self.controls[index].drag(-KEY_NUDGE_PIXELS, scale),
+                    Key::Right => return self.controls[index].drag(KEY_NUDGE_PIXELS, scale),
+                    Key::Enter | Key::NumPadEnter => {
+                        self.controls[index].text_input = Some(String::new());
+                        self.focused = FocusTarget::TextInput(index);
+                    }
+                    Key::Tab if shift => self.focused = FocusTarget::Control((index + count - 1) % count),
+                    Key::Tab => self.focused = FocusTarget::Control((index + 1) % count),
+                    Key::Escape => self.focused = FocusTarget::None,
+                    _ => {}
+                }
+                false
+            }
+        }
+    }
+    
+    /// Named control values for each preset in the rule, keyed by control name
+    pub fn preset_parameter_sets(rule: &LSystemRule) -> Vec<(&str, HashMap<&str, f32>)> {
+        rule.parameter_presets.iter().flatten().map(|preset| {
+            let mut values = HashMap::new();
+            if let Some(angle) = preset.angle {
+                values.insert("Angle", angle);
+            }
+            if let Some(step_length) = preset.step_length {
+                values.insert("Step Length", step_length);
+            }
+            if let Some(iterations) = preset.iterations {
+                values.insert("Iterations", iterations as f32);
+            }
+            (preset.name.as_str(), values)
+        }).collect()
+    }
+    
+    /// Syncs the controls and preset list with a newly loaded rule
+    pub fn load_rule(&mut self, rule: &LSystemRule) {
+        // The taper sliders only mean something for rules that taper by depth
+        match rule.taper {
+            Some(taper) if self.get_parameter("Trunk Width").is_none() => {
+                let index = self.controls.iter().position(|c| c.name == "Step Length").map_or(0, |i| i + 1);
+                self.insert_slider_at(index, DragValue::new("Trunk Width", taper.base, 1.0, 20.0, "{:.1}"));
+                self.insert_slider_at(index + 1, DragValue::new("Branch Taper", taper.decay, 0.3, 1.0, "{:.2}"));
+            }
+            Some(_) => {}
+            None => {
+                self.remove_slider("Trunk Width");
+                self.remove_slider("Branch Taper");
+            }
+        }
+        
+        for control in &mut self.controls {
+            let value = match control.name.as_str() {
+                "Angle" => Some(rule.angle),
+                "Step Length" => rule.step_length,
+                "Iterations" => Some(rule.iterations as f32),
+                "Trunk Width" => rule.taper.map(|taper| taper.base),
+                "Branch Taper" => rule.taper.map(|taper| taper.decay),
+                _ => None,
+            };
+            if let Some(value) = value {
+                control.value = value.clamp(control.min, control.max);
+                control.target_value = None;
+            }
+        }
+        
+        // Keep a replacement being typed; the rows refresh once it is committed
+        if self.rule_editor.text_input.is_none() {
+            self.rule_editor.load(&rule.rules);
+        }
+        
+        self.palette = rule.colors.as_ref()
+            .and_then(|colors| colors.palette.as_ref())
+            .map(|palette| palette.iter().map(|&c| Vec3::from(c)).collect())
+            .unwrap_or_default();
+        
+        self.presets = Self::preset_parameter_sets(rule).into_iter()
+            .map(|(name, values)| {
+                let values = values.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+                (name.to_string(), values)
+            })
+            .collect();
+    }
+    
+    /// Sets the controls a rule's render hints cover to the hinted values
+    pub fn load_hints_from_rule(&mut self, rule: &LSystemRule) {
+        let Some(hints) = rule.render_hints.as_ref() else { return };
+        if let Some(scale) = hints.line_width_scale {
+            self.set_parameter("Line Width Scale", scale);
+        }
+        if let Some(density) = hints.fog_density {
+            self.set_parameter("Fog Density", density);
+        }
+    }
+    
+    pub fn apply_preset(&mut self, index: usize) {
+        if let Some((_, values)) = self.presets.get(index) {
+            for control in &mut self.controls {
+                if let Some(&value) = values.get(&control.name) {
+                    control.animate_to(value);
+                }
+            }
+        }
+    }
+    
+    /// Name of the preset the controls currently match, if any
+    pub fn active_preset(&self) -> Option<&str> {
+        self.presets.iter()
+            .find(|(_, values)| values.iter().all(|(name, &value)| {
+                self.controls.iter()
+                    .find(|c| &c.name == name)
+                    .is_none_or(|c| (c.value - value).abs() <= c.step)
+            }))
+            .map(|(name, _)| name.as_str())
+    }
+    
+    /// The panel has keyboard focus while the mouse hovers over it or the rule editor
+    pub fn has_focus(&self, window: &Window) -> bool {
+        window.get_mouse_pos(minifb::MouseMode::Clamp)
+            .is_some_and(|(x, y)| self.is_mouse_over_panel(x, y))
+    }
+    
+    /// Total height needed by all controls plus padding
+    pub fn content_height(&self) -> usize {
+        let top = self.controls.iter().map(|c| c.y).min();
+        let bottom = self.controls.iter().map(|c| c.y + c.height).max();
+        match (top, bottom) {
+            (Some(top), Some(bottom)) => bottom - top + 20,
+            _ => 0,
         }
     }
     
+    fn max_scroll(&self) -> i32 {
+        self.content_height().saturating_sub(CONTENT_BOTTOM - CONTENT_TOP) as i32
+    }
+    
+    pub fn is_mouse_over_panel(&self, mouse_x: f32, mouse_y: f32) -> bool {
+        let over_parameters = mouse_x >= PANEL_X as f32 && mouse_x <= (PANEL_X + PANEL_WIDTH) as f32 &&
+            mouse_y >= PANEL_Y as f32 && mouse_y <= (PANEL_Y + PANEL_HEIGHT) as f32;
+        self.visible && (over_parameters || RuleEditor::contains(mouse_x, mouse_y))
+    }
+    
+    fn is_control_in_view(&self, control: &DragValue) -> bool {
+        let y = control.y as i32 - self.scroll_offset;
+        y >= CONTENT_TOP as i32 && y + control.height as i32 <= CONTENT_BOTTOM as i32
+    }
+    
     pub fn toggle(&mut self) {
         self.visible = !self.visible;
+        if let FocusTarget::RuleText(index) = self.focused {
+            self.rule_editor.discard_uncommitted(index);
+        }
+        self.focused = FocusTarget::None;
+        self.rule_editor.text_input = None;
+        for control in &mut self.controls {
+            control.text_input = None;
+        }
+    }
+    
+    pub fn update_animation(&mut self, dt: f32) {
+        let target = if self.visible { 1.0 } else { 0.0 };
+        if self.animation_progress < target {
+            self.animation_progress = (self.animation_progress + dt * 5.0).min(1.0);
+        } else if self.animation_progress > target {
+            self.animation_progress = (self.animation_progress - dt * 5.0).max(0.0);
+        }
+        self.animating = self.animation_progress != target;
+        
+        for control in &mut self.controls {
+            if control.step_animation(dt) {
+                self.values_changed = true;
+            }
+        }
     }
     
     pub fn handle_input(&mut self, window: &Window) -> bool {
@@ -141,76 +811,270 @@ impl GUI {
             return false;
         }
         
-        let mut changed = false;
+        let mut changed = std::mem::take(&mut self.values_changed);
+        
+        // Only the focused control sees key presses
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let ctrl_down = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        if self.focused != FocusTarget::None {
+            for key in window.get_keys_pressed(minifb::KeyRepeat::Yes) {
+                changed |= self.handle_focus_key(key, shift, ctrl_down);
+            }
+        }
+        
+        // Ctrl+1-5 selects a preset while the panel has focus
+        if ctrl_down && self.has_focus(window) {
+            let preset_keys = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5];
+            for (i, key) in preset_keys.iter().enumerate() {
+                if window.is_key_pressed(*key, minifb::KeyRepeat::No) {
+                    self.apply_preset(i);
+                }
+            }
+        }
         
         // Handle mouse input
         if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
             let mouse_pressed = window.get_mouse_down(minifb::MouseButton::Left);
             
-            for slider in &mut self.sliders {
-                if slider.update(window, mouse_pos.0, mouse_pos.1, mouse_pressed) {
+            // Scroll the panel contents with the wheel while hovering over it
+            if let Some(scroll) = window.get_scroll_wheel() {
+                if RuleEditor::contains(mouse_pos.0, mouse_pos.1) {
+                    let max_scroll = self.rule_editor.rows.len().saturating_sub(RuleEditor::visible_rows());
+                    let rows = if scroll.1 > 0.0 { -1 } else { 1 };
+                    self.rule_editor.scroll = self.rule_editor.scroll.saturating_add_signed(rows).min(max_scroll);
+                } else if self.is_mouse_over_panel(mouse_pos.0, mouse_pos.1) {
+                    self.scroll_offset = (self.scroll_offset - (scroll.1 * 20.0) as i32).clamp(0, self.max_scroll());
+                }
+            }
+            
+            // Controls are laid out in content space, so undo the scroll for hit testing
+            let content_mouse_y = mouse_pos.1 + self.scroll_offset as f32;
+            let in_content = mouse_pos.1 >= CONTENT_TOP as f32 && mouse_pos.1 <= CONTENT_BOTTOM as f32;
+            
+            // A click focuses the control or rule under it, or clears focus anywhere else
+            if mouse_pressed && !self.mouse_pressed {
+                let clicked = self.controls.iter()
+                    .position(|c| in_content && self.is_control_in_view(c) && c.contains(mouse_pos.0, content_mouse_y));
+                match self.focused {
+                    FocusTarget::TextInput(index) => self.controls[index].text_input = None,
+                    FocusTarget::RuleText(index) => {
+                        self.rule_editor.text_input = None;
+                        self.rule_editor.discard_uncommitted(index);
+                    }
+                    _ => {}
+                }
+                self.focused = clicked.map_or(FocusTarget::None, FocusTarget::Control);
+                
+                match RuleEditor::button_at(mouse_pos.0, mouse_pos.1) {
+                    Some(true) => self.remove_selected_rule(),
+                    Some(false) => {
+                        if let Some(index) = self.rule_editor.add_row() {
+                            self.edit_rule_row(index);
+                        }
+                    }
+                    None => {
+                        if let Some(index) = self.rule_editor.row_at(mouse_pos.0, mouse_pos.1) {
+                            self.edit_rule_row(index);
+                        }
+                    }
+                }
+            }
+            
+            for i in 0..self.controls.len() {
+                // A drag keeps going when the mouse leaves the control
+                let dragging = self.controls[i].is_dragging();
+                if !dragging && (!in_content || !self.is_control_in_view(&self.controls[i])) {
+                    continue;
+                }
+                let prev_mouse_x = self.last_mouse_pos.0;
+                if self.controls[i].update(window, mouse_pos.0, content_mouse_y, mouse_pressed, prev_mouse_x) {
                     changed = true;
                 }
+                // A double-click switched the control to typing
+                if self.controls[i].text_input.is_some() {
+                    self.focused = FocusTarget::TextInput(i);
+                }
             }
             
             self.last_mouse_pos = mouse_pos;
             self.mouse_pressed = mouse_pressed;
         }
         
+        // A drag or preset transition is one change, recorded once it settles
+        if !self.controls.iter().any(|c| c.is_dragging() || c.target_value.is_some()) {
+            self.record_snapshot();
+        }
+        
         changed
     }
     
     pub fn render(&self, buffer: &mut [u32], width: usize, height: usize) {
-        if !self.visible {
+        if self.animation_progress <= 0.0 {
             return;
         }
         
+        // Slide in from the bottom edge of the screen
+        let offset = ((1.0 - self.animation_progress) * height as f32) as usize;
+        
         // Draw GUI background panel
-        self.fill_rect(buffer, width, height, 10, 10, 250, 300, 0x202020);
-        self.draw_rect(buffer, width, height, 10, 10, 250, 300, 0x606060);
+        self.fill_rect(buffer, width, height, (PANEL_X, PANEL_Y + offset, PANEL_WIDTH, PANEL_HEIGHT), 0x202020);
+        self.draw_rect(buffer, width, height, (PANEL_X, PANEL_Y + offset, PANEL_WIDTH, PANEL_HEIGHT), 0x606060);
         
-        // Draw title
-        self.draw_text(buffer, width, height, 20, 25, "L-System Parameters", 0xFFFFFF);
+        // Draw title, followed by the rule's palette
+        Renderer::render_text_at_screen(buffer, width, height, (20, 25 + offset), "L-System Parameters", 0xFFFFFF, 1);
+        for (i, &color) in self.palette.iter().take(5).enumerate() {
+            Self::render_color_swatch(buffer, width, height, (150 + i * 20, 22 + offset, 16, 12), color);
+        }
         
-        // Render all sliders
-        for slider in &self.sliders {
-            slider.render(buffer, width, height);
+        // Render the controls that fit in the scrolled content area
+        for (i, control) in self.controls.iter().enumerate().filter(|(_, c)| self.is_control_in_view(c)) {
+            let focused = matches!(self.focused, FocusTarget::Control(index) | FocusTarget::TextInput(index) if index == i);
+            control.render(buffer, width, height, offset as i32 - self.scroll_offset, self.animation_progress, focused);
+        }
+        
+        // Draw scrollbar when the content overflows
+        let max_scroll = self.max_scroll();
+        if max_scroll > 0 {
+            let track_height = CONTENT_BOTTOM - CONTENT_TOP;
+            let thumb_height = (track_height * track_height / self.content_height()).max(10);
+            let thumb_y = CONTENT_TOP + ((track_height - thumb_height) as f32 * self.scroll_offset as f32 / max_scroll as f32) as usize;
+            let bar_x = PANEL_X + PANEL_WIDTH - 8;
+            self.fill_rect(buffer, width, height, (bar_x, CONTENT_TOP + offset, 4, track_height), 0x303030);
+            self.fill_rect(buffer, width, height, (bar_x, thumb_y + offset, 4, thumb_height), 0x808080);
         }
         
         // Draw instructions
-        self.draw_text(buffer, width, height, 20, 280, "G: Toggle GUI | Click sliders to adjust", 0xCCCCCC);
-    }
-    
-    pub fn get_parameter(&self, name: &str) -> Option<f32> {
-        self.sliders.iter()
-            .find(|s| s.name == name)
-            .map(|s| s.value)
+        Renderer::render_text_at_screen(buffer, width, height, (20, 280 + offset), "Drag to adjust, double-click to type", 0xCCCCCC, 1);
+        Renderer::render_text_at_screen(buffer, width, height, (20, 294 + offset), &self.stats_summary, 0x88CCFF, 1);
+        
+        self.rule_editor.render(buffer, width, height, offset, self.animation_progress);
+        
+        // Draw preset list below the panel
+        if !self.presets.is_empty() {
+            let box_y = PANEL_Y + PANEL_HEIGHT + 10 + offset;
+            let box_height = 40 + self.presets.len() * 15;
+            self.fill_rect(buffer, width, height, (PANEL_X, box_y, PANEL_WIDTH, box_height), 0x202020);
+            self.draw_rect(buffer, width, height, (PANEL_X, box_y, PANEL_WIDTH, box_height), 0x606060);
+            
+            let active = self.active_preset();
+            Renderer::render_text_at_screen(buffer, width, height, (20, box_y + 8), 
+                          &format!("Preset: {}", active.unwrap_or("(Custom)")), 0xFFFFFF, 1);
+            for (i, (name, _)) in self.presets.iter().enumerate() {
+                let color = if Some(name.as_str()) == active { 0x00FF00 } else { 0xCCCCCC };
+                Renderer::render_text_at_screen(buffer, width, height, (20, box_y + 25 + i * 15), 
+                              &format!("Ctrl+{}: {}", i + 1, name), color, 1);
+            }
+        }
     }
     
-    fn fill_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize, 
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+    /// Fills a rectangle with `color` inside a border in the complementary color; swatches
+    /// wide enough for it also show the `#RRGGBB` code in black or white, whichever contrasts
+    pub fn render_color_swatch(buffer: &mut [u32], width: usize, height: usize,
+                               (x, y, w, h): (usize, usize, usize, usize), color: Vec3) {
+        let fill = color_to_u32(color);
+        let border = !fill & 0xFFFFFF;
+        
         for dy in 0..h {
             for dx in 0..w {
-                let px = x + dx;
-                let py = y + dy;
-                if px < buf_width && py < buf_height {
-                    buffer[py * buf_width + px] = color;
+                let (px, py) = (x + dx, y + dy);
+                if px < width && py < height {
+                    let edge = dx == 0 || dy == 0 || dx == w - 1 || dy == h - 1;
+                    buffer[py * width + px] = if edge { border } else { fill };
                 }
             }
         }
+        
+        let hex = format!("#{:06X}", fill);
+        if w >= Renderer::measure_text(&hex, 1).0 + 4 && h >= 12 {
+            let luminance = color.dot(Vec3::new(0.299, 0.587, 0.114));
+            let text_color = if luminance > 0.5 { 0x000000 } else { 0xFFFFFF };
+            Renderer::render_text_at_screen(buffer, width, height, (x + 2, y + (h - 8) / 2), &hex, text_color, 1);
+        }
+    }
+    
+    pub fn get_parameter(&self, name: &str) -> Option<f32> {
+        self.controls.iter()
+            .find(|c| c.name == name)
+            .map(|c| c.value)
+    }
+    
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        if let Some(control) = self.controls.iter_mut().find(|c| c.name == name) {
+            control.value = value.clamp(control.min, control.max);
+            control.target_value = None;
+        }
+    }
+    
+    /// Moves a control's upper limit, e.g. to follow the camera's far plane
+    pub fn set_parameter_max(&mut self, name: &str, max: f32) {
+        if let Some(control) = self.controls.iter_mut().find(|c| c.name == name) {
+            control.max = max.max(control.min);
+            control.value = control.value.min(control.max);
+            if control.logarithmic {
+                control.speed = (control.max / control.min).ln() / 200.0;
+            }
+        }
+    }
+    
+    fn fill_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize, 
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
+        fill_rect(buffer, buf_width, buf_height, (x, y, w, h), color, self.animation_progress);
+    }
+    
+    /// Sparkline of `data` (oldest first) in a `w` x `h` box: `label` and the latest value along the top,
+    /// the min and max at the left of a graph scaled to fit, and gridlines at 25%, 50% and 75%.
+    /// Drawn whether or not the panel is visible, so other overlays can use it.
+    pub fn render_waveform(buffer: &mut [u32], buf_width: usize, buf_height: usize,
+                           (x, y, w, h): (usize, usize, usize, usize), data: &[f32], label: &str, color: u32) {
+        fill_rect(buffer, buf_width, buf_height, (x, y, w, h), 0x101018, 0.9);
+        Renderer::render_text_at_screen(buffer, buf_width, buf_height, (x + 2, y + 2), label, 0xCCCCCC, 1);
+        let Some(&latest) = data.last() else { return };
+        let current = format!("{:.1}", latest);
+        let current_x = (x + w).saturating_sub(Renderer::measure_text(&current, 1).0 + 2);
+        Renderer::render_text_at_screen(buffer, buf_width, buf_height, (current_x, y + 2), &current, color, 1);
+        
+        let (graph_x, graph_y) = (x + 2, y + WAVEFORM_HEADER);
+        let (graph_w, graph_h) = (w.saturating_sub(4), h.saturating_sub(WAVEFORM_HEADER + 2));
+        if graph_w < 2 || graph_h < 2 {
+            return;
+        }
+        for quarter in 1..4 {
+            fill_rect(buffer, buf_width, buf_height, (graph_x, graph_y + graph_h * quarter / 4, graph_w, 1), 0x303040, 1.0);
+        }
+        
+        let min = data.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = data.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let range = max - min;
+        let point = |i: usize, value: f32| {
+            let t = if data.len() > 1 { i as f32 / (data.len() - 1) as f32 } else { 1.0 };
+            let level = if range > 0.0 { (value - min) / range } else { 0.5 };
+            (graph_x as f32 + t * (graph_w - 1) as f32, graph_y as f32 + (1.0 - level) * (graph_h - 1) as f32)
+        };
+        let mut previous = point(0, data[0]);
+        for (i, &value) in data.iter().enumerate().skip(1) {
+            let next = point(i, value);
+            draw_line(buffer, buf_width, buf_height, previous, next, color);
+            previous = next;
+        }
+        
+        Renderer::render_text_at_screen(buffer, buf_width, buf_height, (graph_x, graph_y), &format!("{:.1}", max), 0x808080, 1);
+        let min_y = (graph_y + graph_h).saturating_sub(8);
+        Renderer::render_text_at_screen(buffer, buf_width, buf_height, (graph_x, min_y), &format!("{:.1}", min), 0x808080, 1);
     }
     
     fn draw_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize,
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
         // Top and bottom borders
         for dx in 0..w {
             let px = x + dx;
             if px < buf_width {
                 if y < buf_height {
-                    buffer[y * buf_width + px] = color;
+                    let idx = y * buf_width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if y + h - 1 < buf_height {
-                    buffer[(y + h - 1) * buf_width + px] = color;
+                    let idx = (y + h - 1) * buf_width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
@@ -220,38 +1084,258 @@ impl GUI {
             let py = y + dy;
             if py < buf_height {
                 if x < buf_width {
-                    buffer[py * buf_width + x] = color;
+                    let idx = py * buf_width + x;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if x + w - 1 < buf_width {
-                    buffer[py * buf_width + (x + w - 1)] = color;
+                    let idx = py * buf_width + (x + w - 1);
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
     }
+}
+
+/// Blends `color` over a rectangle, clipped to the buffer
+fn fill_rect(buffer: &mut [u32], buf_width: usize, buf_height: usize,
+             (x, y, w, h): (usize, usize, usize, usize), color: u32, alpha: f32) {
+    for dy in 0..h {
+        for dx in 0..w {
+            let px = x + dx;
+            let py = y + dy;
+            if px < buf_width && py < buf_height {
+                let idx = py * buf_width + px;
+                buffer[idx] = lerp_color(buffer[idx], color, alpha);
+            }
+        }
+    }
+}
+
+/// One-pixel line from `from` to `to`, stepping along the longer axis
+fn draw_line(buffer: &mut [u32], buf_width: usize, buf_height: usize, from: (f32, f32), to: (f32, f32), color: u32) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let (px, py) = ((from.0 + (to.0 - from.0) * t).round(), (from.1 + (to.1 - from.1) * t).round());
+        if px >= 0.0 && py >= 0.0 && (px as usize) < buf_width && (py as usize) < buf_height {
+            buffer[py as usize * buf_width + px as usize] = color;
+        }
+    }
+}
+
+fn lerp_color(existing: u32, color: u32, alpha: f32) -> u32 {
+    let alpha = alpha.clamp(0.0, 1.0);
+    let blend = |shift: u32| {
+        let a = ((existing >> shift) & 0xFF) as f32;
+        let b = ((color >> shift) & 0xFF) as f32;
+        ((a + (b - a) * alpha) as u32) << shift
+    };
+    blend(16) | blend(8) | blend(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn control_ys(gui: &Gui) -> Vec<usize> {
+        gui.controls.iter().map(|c| c.y).collect()
+    }
     
-    fn draw_text(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize,
-                x: usize, y: usize, text: &str, color: u32) {
-        let char_width = 6;
-        let char_height = 8;
+    #[test]
+    fn new_stacks_controls_below_the_title() {
+        let gui = Gui::new();
+        assert_eq!(control_ys(&gui), vec![50, 80, 110, 140, 170, 200, 230, 260, 290, 320, 350, 380, 410]);
+    }
+    
+    #[test]
+    fn inserting_and_removing_relayouts_the_rest() {
+        let mut gui = Gui::new();
+        gui.insert_slider_at(1, DragValue::new("Roll", 0.0, 0.0, 1.0, "{:.2}"));
+        assert_eq!(gui.controls[1].name, "Roll");
+        assert_eq!(gui.controls[1].y, 80);
+        assert_eq!(gui.controls[2].name, "Step Length");
+        assert_eq!(gui.controls[2].y, 110);
         
-        for (i, _c) in text.chars().enumerate() {
-            let char_x = x + i * char_width;
-            
-            for dy in 0..char_height {
-                for dx in 0..char_width {
-                    let px = char_x + dx;
-                    let py = y + dy;
-                    
-                    if px < buf_width && py < buf_height {
-                        if (dy == 1 || dy == char_height - 2) && dx > 0 && dx < char_width - 1 {
-                            buffer[py * buf_width + px] = color;
-                        }
-                        if (dx == 1 || dx == char_width - 2) && dy > 1 && dy < char_height - 2 {
-                            buffer[py * buf_width + px] = color;
-                        }
-                    }
-                }
-            }
+        let removed = gui.remove_slider("Angle").unwrap();
+        assert_eq!(removed.name, "Angle");
+        assert_eq!(gui.controls[0].name, "Roll");
+        assert_eq!(gui.controls[0].y, 50);
+        assert!(gui.remove_slider("Angle").is_none());
+        
+        gui.add_slider(DragValue::new("Last", 0.0, 0.0, 1.0, "{:.2}"));
+        assert_eq!(gui.controls.last().map(|c| c.y), Some(50 + 13 * 30));
+    }
+    
+    #[test]
+    fn taper_sliders_follow_the_rule() {
+        let plain = LSystemRule::builder().name("Plain").axiom("F").rule('F', "F[+F]F").angle(25.0).iterations(3).build().unwrap();
+        let tapered = LSystemRule {
+            taper: Some(crate::turtle3d::TaperConfig { base: 6.0, decay: 0.65, min: 0.4 }),
+            ..plain.clone()
+        };
+        let mut gui = Gui::new();
+        gui.set_parameter("Angle", 40.0);
+        
+        gui.load_rule(&tapered);
+        assert_eq!(gui.controls[2].name, "Trunk Width");
+        assert_eq!(gui.get_parameter("Trunk Width"), Some(6.0));
+        assert_eq!(gui.get_parameter("Branch Taper"), Some(0.65));
+        // Undo snapshots stay one value per control
+        assert!(gui.undo());
+        assert_eq!(gui.get_parameter("Branch Taper"), Some(0.65));
+        
+        gui.load_rule(&plain);
+        assert_eq!(gui.get_parameter("Trunk Width"), None);
+        assert_eq!(gui.get_parameter("Branch Taper"), None);
+        assert_eq!(gui.controls[2].name, "Iterations");
+    }
+    
+    #[test]
+    fn undo_and_redo_step_through_slider_snapshots() {
+        let mut gui = Gui::new();
+        for angle in [30.0, 40.0, 50.0] {
+            gui.set_parameter("Angle", angle);
+            gui.set_parameter("Step Length", angle / 20.0);
+            gui.record_snapshot();
+        }
+        
+        assert!(gui.undo());
+        assert!(gui.undo());
+        assert_eq!(gui.get_parameter("Angle"), Some(30.0));
+        assert_eq!(gui.get_parameter("Step Length"), Some(1.5));
+        assert!(gui.redo());
+        assert_eq!(gui.get_parameter("Angle"), Some(40.0));
+        assert_eq!(gui.get_parameter("Step Length"), Some(2.0));
+        
+        // A new change drops what could have been redone
+        gui.set_parameter("Angle", 60.0);
+        gui.record_snapshot();
+        assert!(!gui.redo());
+        
+        gui.reset_to_defaults();
+        assert_eq!(gui.get_parameter("Angle"), Some(25.0));
+        assert!(gui.undo());
+        assert_eq!(gui.get_parameter("Angle"), Some(60.0));
+        
+        for angle in 0..2 * MAX_HISTORY {
+            gui.set_parameter("Angle", 5.0 + angle as f32 * 0.5);
+            gui.record_snapshot();
         }
+        assert_eq!(gui.history.len(), MAX_HISTORY);
+        while gui.undo() {}
+        assert_eq!(gui.get_parameter("Angle"), Some(5.0 + MAX_HISTORY as f32 * 0.5));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn set_parameter_clamps_to_the_control_range() {
+        let mut gui = Gui::new();
+        gui.set_parameter("Angle", 500.0);
+        assert_eq!(gui.get_parameter("Angle"), Some(90.0));
+        assert_eq!(gui.get_parameter("Missing"), None);
+    }
+    
+    #[test]
+    fn drag_value_moves_by_speed_per_pixel_and_parses_typed_text() {
+        let mut angle = DragValue::new("Angle", 25.0, 5.0, 85.0, "{:.1} deg");
+        assert_eq!(angle.speed, 0.4);
+        assert!(angle.drag(10.0, 1.0));
+        assert!((angle.value - 29.0).abs() < 1e-4);
+        assert!(angle.drag(10.0, 0.1));
+        assert!((angle.value - 29.4).abs() < 1e-4);
+        assert!(angle.drag(1000.0, 10.0));
+        assert_eq!(angle.value, 85.0);
+        assert!(!angle.drag(5.0, 1.0));
+        assert_eq!(angle.format_value(), "85.0 deg");
+        
+        angle.text_input = Some("22.5".to_string());
+        assert!(angle.commit_text_input());
+        assert_eq!(angle.value, 22.5);
+        angle.text_input = Some("-".to_string());
+        assert!(!angle.commit_text_input());
+        assert!(angle.text_input.is_none());
+    }
+    
+    #[test]
+    fn focused_control_takes_keys_and_tab_cycles_focus() {
+        let mut gui = Gui::new();
+        gui.visible = true;
+        assert!(!gui.is_any_widget_focused());
+        assert!(!gui.handle_focus_key(Key::Right, false, false));
+        
+        gui.focused = FocusTarget::Control(0);
+        assert!(gui.handle_focus_key(Key::Right, false, false));
+        assert!((gui.get_parameter("Angle").unwrap() - 27.125).abs() < 1e-4);
+        
+        gui.handle_focus_key(Key::Enter, false, false);
+        assert_eq!(gui.focused, FocusTarget::TextInput(0));
+        gui.handle_focus_key(Key::Key3, false, false);
+        gui.handle_focus_key(Key::Key0, false, false);
+        assert!(gui.handle_focus_key(Key::Enter, false, false));
+        assert_eq!(gui.get_parameter("Angle"), Some(30.0));
+        assert_eq!(gui.focused, FocusTarget::Control(0));
+        
+        gui.handle_focus_key(Key::Tab, false, false);
+        assert_eq!(gui.focused, FocusTarget::Control(1));
+        assert_eq!(gui.controls[1].name, "Step Length");
+        gui.handle_focus_key(Key::Tab, true, false);
+        gui.handle_focus_key(Key::Tab, true, false);
+        assert_eq!(gui.focused, FocusTarget::Control(gui.controls.len() - 1));
+        assert_eq!(gui.controls.last().unwrap().name, "Tone Mapping");
+        gui.handle_focus_key(Key::Escape, false, false);
+        assert!(!gui.is_any_widget_focused());
+    }
+    
+    #[test]
+    fn waveform_draws_its_background_and_graph_inside_the_box() {
+        let (width, height) = (40, 40);
+        let mut buffer = vec![0u32; width * height];
+        Gui::render_waveform(&mut buffer, width, height, (5, 5, 30, 30), &[0.0, 1.0, 0.5], "", 0xFF0000);
+        assert!(buffer.contains(&0xFF0000));
+        for (i, &pixel) in buffer.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let inside = (5..35).contains(&x) && (5..35).contains(&y);
+            assert_eq!(pixel != 0, inside, "pixel ({}, {})", x, y);
+        }
+    }
+    
+    #[test]
+    fn rule_editor_queues_replacements_and_removals() {
+        let mut gui = Gui::new();
+        gui.visible = true;
+        gui.rule_editor.load(&HashMap::from([('X', "F[+X]".to_string()), ('A', "B".to_string())]));
+        assert_eq!(gui.rule_editor.rows[0], ('A', "B".to_string()));
+        
+        gui.edit_rule_row(1);
+        assert!(gui.is_any_widget_focused());
+        for _ in 0..3 {
+            gui.handle_focus_key(Key::Backspace, false, false);
+        }
+        gui.handle_focus_key(Key::Minus, false, false);
+        gui.handle_focus_key(Key::X, true, false);
+        gui.handle_focus_key(Key::RightBracket, false, false);
+        gui.handle_focus_key(Key::Enter, false, false);
+        assert_eq!(gui.take_rule_edits(), vec![RuleEdit::Replace('X', "F[-X]".to_string())]);
+        assert!(!gui.is_any_widget_focused());
+        
+        // A row added with + and cancelled never reaches the L-system
+        let index = gui.rule_editor.add_row().unwrap();
+        assert_eq!(gui.rule_editor.rows[index].0, 'B');
+        gui.edit_rule_row(index);
+        gui.handle_focus_key(Key::Escape, false, false);
+        assert_eq!(gui.rule_editor.rows.len(), 2);
+        
+        gui.rule_editor.selected = Some(0);
+        gui.remove_selected_rule();
+        assert_eq!(gui.take_rule_edits(), vec![RuleEdit::Remove('A')]);
+    }
+    
+    #[test]
+    fn logarithmic_drag_value_covers_its_range_in_200_pixels() {
+        let mut near = DragValue::new("Near Clip", 0.001, 0.001, 1.0, "{:.3}").logarithmic();
+        assert!(near.drag(100.0, 1.0));
+        assert!((near.value - 0.001_f32.sqrt()).abs() < 1e-4);
+        assert!((near.fraction() - 0.5).abs() < 1e-3);
+        near.drag(100.0, 1.0);
+        assert!((near.value - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::font;
+use crate::gui::Gui;
+use crate::renderer::Renderer;
+use crate::{LSystemRule, SymbolStatus};
+
+/// Bottom-left panel showing the current rule's name, description and reference link
+pub struct InfoPanel {
+    pub visible: bool,
+}
+
+const LINE_CHARS: usize = 60;
+const LINE_HEIGHT: usize = 16;
+const BADGE_WIDTH: usize = font::CELL_WIDTH + 6;
+const BADGE_SPACING: usize = BADGE_WIDTH + 3;
+
+impl InfoPanel {
+    pub fn new() -> Self {
+        Self { visible: false }
+    }
+    
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+    
+    /// Shows `rule`'s name, description, link and primary color; `symbols` is drawn as a row of
+    /// badges colored by each symbol's role in the rule
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize, rule: &LSystemRule,
+                  symbols: &[(char, SymbolStatus)]) {
+        if !self.visible {
+            return;
+        }
+        
+        let (name, description, url, color) = (&rule.name, rule.description.as_deref(),
+                                               rule.description_url.as_deref(), rule.primary_color());
+        let mut lines = vec![(name.to_string(), 0xFFFFFF)];
+        if let Some(description) = description {
+            lines.extend(wrap(description, LINE_CHARS).into_iter().map(|line| (line, 0xCCCCCC)));
+        }
+        let color_line = color.map(|_| lines.len());
+        if color.is_some() {
+            lines.push(("Current color:".to_string(), 0xCCCCCC));
+        }
+        let symbols_line = (!symbols.is_empty()).then_some(lines.len());
+        if symbols_line.is_some() {
+            lines.push(("Symbols:".to_string(), 0xCCCCCC));
+        }
+        if url.is_some() {
+            lines.push(("[U] Open in browser".to_string(), 0x00FFFF));
+        }
+        
+        let panel_width = Renderer::measure_text(" ", 1).0 * LINE_CHARS + 20;
+        // Badges start after the "Symbols:" label and wrap onto extra rows
+        let badges_x = 90;
+        let badges_per_row = ((panel_width - badges_x - 10) / BADGE_SPACING).max(1);
+        let badge_rows = symbols.len().div_ceil(badges_per_row);
+        if let Some(line) = symbols_line {
+            for _ in 1..badge_rows {
+                lines.insert(line + 1, (String::new(), 0));
+            }
+        }
+        let panel_height = lines.len() * LINE_HEIGHT + 16;
+        let panel_x = 10;
+        let panel_y = height.saturating_sub(panel_height + 10);
+        
+        self.fill_rect(buffer, width, height, (panel_x, panel_y, panel_width, panel_height), 0x202030);
+        for (i, (line, color)) in lines.iter().enumerate() {
+            Renderer::render_text_at_screen(buffer, width, height, (panel_x + 10, panel_y + 8 + i * LINE_HEIGHT), line, *color, 1);
+        }
+        if let (Some(line), Some(color)) = (color_line, color) {
+            Gui::render_color_swatch(buffer, width, height, (panel_x + 130, panel_y + 6 + line * LINE_HEIGHT, 70, 14), color);
+        }
+        if let Some(line) = symbols_line {
+            for (i, &(symbol, status)) in symbols.iter().enumerate() {
+                let x = panel_x + badges_x + (i % badges_per_row) * BADGE_SPACING;
+                let y = panel_y + 6 + (line + i / badges_per_row) * LINE_HEIGHT;
+                self.fill_rect(buffer, width, height, (x, y, BADGE_WIDTH, 12), badge_color(status));
+                Renderer::render_text_at_screen(buffer, width, height, (x + 3, y + 2), &symbol.to_string(), 0x000000, 1);
+            }
+        }
+    }
+    
+    fn fill_rect(&self, buffer: &mut [u32], width: usize, height: usize,
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
+        for dy in 0..h {
+            for dx in 0..w {
+                let px = x + dx;
+                let py = y + dy;
+                if px < width && py < height {
+                    buffer[py * width + px] = color;
+                }
+            }
+        }
+    }
+}
+
+/// Green for turtle commands, blue for rules, yellow for undefined symbols, gray for the rest
+fn badge_color(status: SymbolStatus) -> u32 {
+    match status {
+        SymbolStatus::TurtleCommand => 0x40C040,
+        SymbolStatus::DefinedRule => 0x4080FF,
+        SymbolStatus::UndefinedInRHS => 0xE0C030,
+        SymbolStatus::AppearanceInAxiomOnly | SymbolStatus::Unreachable => 0x808080,
+    }
+}
+
+/// Greedy word wrap to at most `max_chars` per line
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.len() + 1 + word.len() > max_chars {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_breaks_between_words() {
+        assert_eq!(wrap("a bb ccc dddd", 6), vec!["a bb", "ccc", "dddd"]);
+        assert_eq!(wrap("   ", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn wrap_keeps_long_words_whole() {
+        assert_eq!(wrap("abcdefghij xy", 4), vec!["abcdefghij", "xy"]);
+    }
+}
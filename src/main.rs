@@ -1,9 +1,11 @@
 use clap::{Arg, Command};
 use minifb::{Key, Window, WindowOptions};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use glam::Vec2;
+use glam::{Vec2, Vec3};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 
 mod camera;
 mod renderer;
@@ -12,20 +14,38 @@ mod menu;
 mod editor;
 mod gui;
 mod main_menu;
+mod toast;
+mod gallery;
+mod app_state;
+mod wizard;
+mod info_panel;
+mod stats_overlay;
+mod font;
+mod post_process;
+mod parametric;
 
-use camera::Camera;
-use renderer::Renderer;
-use turtle3d::Turtle3D;
+use camera::{Camera, ViewPreset};
+use renderer::{Line, RenderHints, RenderMode, Renderer, SkyboxConfig};
+use turtle3d::{GradientStop, TaperConfig, TaperMode, Turtle3D};
 use menu::Menu;
 use editor::Editor;
-use gui::GUI;
-use main_menu::{MainMenu, MenuAction};
+use gui::{Gui, ParameterPreset, RuleEdit};
+use main_menu::{MainMenu, MainMenuItem, MenuAction};
+use toast::Toast;
+use app_state::AppState;
+use info_panel::InfoPanel;
+use stats_overlay::{FrameStats, StatsOverlay};
+use post_process::{BloomPass, FogPass, PostProcessPipeline, ToneMappingMode, ToneMappingPass};
+use parametric::Predecessor;
 
 const WIDTH: usize = 800;
 const HEIGHT: usize = 600;
 
+const DEFAULT_RULE_PATH: &str = "rules/cherry_blossom.json";
+const EMBEDDED_DEFAULT_RULE: &str = include_str!("../rules/cherry_blossom.json");
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct LSystemRule {
+pub(crate) struct LSystemRule {
     name: String,
     axiom: String,
     angle: f32,
@@ -36,303 +56,5059 @@ struct LSystemRule {
     start_direction: Option<[f32; 3]>,
     colors: Option<ColorConfig>,
     description: Option<String>,
+    description_url: Option<String>,
+    skybox: Option<SkyboxConfig>,
+    #[serde(default)]
+    axioms: Vec<String>,
+    start_positions: Option<Vec<[f32; 3]>>,
+    alternating_rules: Option<HashMap<char, [String; 2]>>,
+    parameter_presets: Option<Vec<ParameterPreset>>,
+    taper: Option<TaperConfig>,
+    iteration_limits: Option<HashMap<char, u32>>,
+    time_varying_params: Option<Vec<TimeKeyframe>>,
+    /// Y of a ground plane that branches bounce off instead of passing through
+    ground_collision: Option<f32>,
+    /// Camera angles for the View Presets menu; the first is applied when the rule is opened
+    view_presets: Option<Vec<ViewPreset>>,
+    /// Generation stops early once the string grows past this many megabytes (default 100)
+    max_memory_mb: Option<u32>,
+    /// Viewing settings such as line width and sky colors, applied when the rule is opened
+    render_hints: Option<RenderHints>,
+    /// Gravity, wind and height limits that shape the tree as it is drawn
+    environment: Option<EnvironmentConfig>,
+    /// Weighted alternative productions; each occurrence of the symbol picks one at random
+    stochastic_rules: Option<HashMap<char, Vec<StochasticProduction>>>,
+    /// Seed the stochastic productions were generated with, so the same tree can be regrown
+    seed: Option<u64>,
+    /// Multi-character symbols such as `FA`; wherever a name appears in the axiom or a production
+    /// it is one symbol (longest name first) with no turtle meaning
+    modules: Option<Vec<String>>,
+    /// Productions for the names in `modules`
+    module_rules: Option<HashMap<String, String>>,
+    /// Symbols may carry numeric arguments, as in `F(1.5)` (move 1.5 steps) or `+(30)` (turn 30 degrees)
+    parametric: Option<bool>,
+    /// Productions keyed by a predecessor with named parameters, such as `"F(x)": "F(x*0.9)+F(x*0.9)"`;
+    /// their successors' arguments are expressions of those parameters. Needs `parametric`
+    parametric_rules: Option<HashMap<String, String>>,
+    /// Productions that only apply next to given neighbors; they take priority over every other rule
+    context_rules: Option<Vec<ContextRule>>,
+    /// Color to switch to whenever a symbol is drawn, e.g. `{ "L": [0.2, 0.7, 0.2] }`; overrides depth colors
+    symbol_colors: Option<HashMap<char, [f32; 3]>>,
+}
+
+/// A 2L-system production: `symbol` becomes `replacement` when its neighbors match the contexts
+/// that are given. Neighbors are found as in The Algorithmic Beauty of Plants: whole `[...]`
+/// branches are skipped, the left neighbor of a branch's first symbol is the one before its `[`,
+/// and the last symbol of a branch has no right neighbor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct ContextRule {
+    left_context: Option<char>,
+    symbol: char,
+    right_context: Option<char>,
+    replacement: String,
+}
+
+impl ContextRule {
+    fn matches(&self, symbol: char, left: Option<char>, right: Option<char>) -> bool {
+        self.symbol == symbol &&
+            self.left_context.is_none_or(|c| left == Some(c)) &&
+            self.right_context.is_none_or(|c| right == Some(c))
+    }
+
+    /// Number of contexts given; the most specific matching rule wins
+    fn specificity(&self) -> usize {
+        self.left_context.is_some() as usize + self.right_context.is_some() as usize
+    }
+}
+
+/// One alternative of a stochastic rule, chosen with probability `weight` over the sum of its
+/// symbol's weights. Read from either `{ "successor": ..., "weight": ... }` or `[successor, weight]`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "StochasticProductionJson")]
+pub(crate) struct StochasticProduction {
+    successor: String,
+    weight: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StochasticProductionJson {
+    Named { successor: String, weight: f32 },
+    Pair(String, f32),
+}
+
+impl From<StochasticProductionJson> for StochasticProduction {
+    fn from(json: StochasticProductionJson) -> Self {
+        let (successor, weight) = match json {
+            StochasticProductionJson::Named { successor, weight } => (successor, weight),
+            StochasticProductionJson::Pair(successor, weight) => (successor, weight),
+        };
+        StochasticProduction { successor, weight }
+    }
+}
+
+/// Parameter values at one point of a rule's animation; unset values keep the rule's own
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub(crate) struct TimeKeyframe {
+    time_secs: f32,
+    angle: Option<f32>,
+    step_length: Option<f32>,
+}
+
+/// Surroundings a rule grows in, so one rule can be drawn as a desert or an alpine tree
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub(crate) struct EnvironmentConfig {
+    /// Pull on every branch; longer vectors bend harder
+    gravity: Option<[f32; 3]>,
+    /// Added to `gravity` to lean the tree one way
+    wind: Option<[f32; 3]>,
+    /// Y branches turn back down at
+    max_height: Option<f32>,
+    /// Y of the ground plane; takes precedence over the rule's `ground_collision`
+    ground_y: Option<f32>,
+    /// How far each step bends towards gravity plus wind (default 0.2)
+    tropism_weight: Option<f32>,
+}
+
+impl EnvironmentConfig {
+    /// Gravity plus wind, or `None` when neither is set
+    fn tropism(&self) -> Option<Vec3> {
+        if self.gravity.is_none() && self.wind.is_none() {
+            return None;
+        }
+        Some(Vec3::from(self.gravity.unwrap_or_default()) + Vec3::from(self.wind.unwrap_or_default()))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ColorConfig {
     depth_based: Option<bool>,
     palette: Option<Vec<[f32; 3]>>,
+    /// Colors by height from ground to tips, blending along each branch; overrides the other colors
+    gradient: Option<Vec<GradientStop>>,
 }
 
-struct LSystem {
-    rule: LSystemRule,
-    current_string: String,
-}
+impl LSystemRule {
+    fn builder() -> LSystemRuleBuilder {
+        LSystemRuleBuilder::default()
+    }
 
-impl LSystem {
-    fn new(rule: LSystemRule) -> Self {
-        LSystem {
-            current_string: rule.axiom.clone(),
-            rule,
+    fn with_angle(mut self, degrees: f32) -> LSystemRule {
+        self.angle = degrees;
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Rule name must not be empty".to_string());
+        }
+        if self.axiom.is_empty() {
+            return Err(format!("Rule '{}' has an empty axiom", self.name));
+        }
+        if !self.angle.is_finite() {
+            return Err(format!("Rule '{}' has an invalid angle: {}", self.name, self.angle));
+        }
+
+        check_brackets(&self.axiom).map_err(|e| format!("Axiom: {}", e))?;
+        for axiom in &self.axioms {
+            check_brackets(axiom).map_err(|e| format!("Axiom '{}': {}", axiom, e))?;
+        }
+        for (symbol, replacement) in &self.rules {
+            check_brackets(replacement).map_err(|e| format!("Rule '{}': {}", symbol, e))?;
+        }
+        if let Some(alternating) = &self.alternating_rules {
+            for (symbol, pair) in alternating {
+                for replacement in pair {
+                    check_brackets(replacement).map_err(|e| format!("Alternating rule '{}': {}", symbol, e))?;
+                    if let Some(c) = replacement.chars().find(|c| !self.is_defined_symbol(*c)) {
+                        return Err(format!("Alternating rule '{}' uses undefined symbol '{}'", symbol, c));
+                    }
+                }
+            }
+        }
+        if let Some(stochastic) = &self.stochastic_rules {
+            for (symbol, productions) in stochastic {
+                if productions.is_empty() {
+                    return Err(format!("Stochastic rule '{}' has no productions", symbol));
+                }
+                for production in productions {
+                    check_brackets(&production.successor).map_err(|e| format!("Stochastic rule '{}': {}", symbol, e))?;
+                    if !(production.weight.is_finite() && production.weight > 0.0) {
+                        return Err(format!("Stochastic rule '{}' has an invalid weight: {}", symbol, production.weight));
+                    }
+                }
+            }
         }
+        for rule in self.context_rules.iter().flatten() {
+            if [rule.symbol].iter().chain(&rule.left_context).chain(&rule.right_context).any(|&c| matches!(c, '[' | ']')) {
+                return Err(format!("Context rule for '{}' matches a bracket, but brackets are never context", rule.symbol));
+            }
+            check_brackets(&rule.replacement).map_err(|e| format!("Context rule for '{}': {}", rule.symbol, e))?;
+        }
+        self.check_modules()?;
+        self.check_parametric()?;
+        self.check_iteration_limits()?;
+
+        Ok(())
     }
 
-    fn iterate(&mut self) {
-        let mut new_string = String::new();
-        
-        for ch in self.current_string.chars() {
-            if let Some(replacement) = self.rule.rules.get(&ch) {
-                new_string.push_str(replacement);
-            } else {
-                new_string.push(ch);
+    /// Module names must be non-empty, and every module rule must be for a declared module
+    fn check_modules(&self) -> Result<(), String> {
+        let modules = self.modules.as_deref().unwrap_or_default();
+        if let Some(name) = modules.iter().find(|name| name.is_empty() || name.contains(turtle3d::MODULE_DELIMITER)) {
+            return Err(format!("Invalid module name {:?}", name));
+        }
+        for (name, successor) in self.module_rules.iter().flatten() {
+            if !modules.contains(name) {
+                return Err(format!("Module rule '{}' is not listed in modules", name));
             }
+            check_brackets(successor).map_err(|e| format!("Module rule '{}': {}", name, e))?;
         }
-        
-        self.current_string = new_string;
+        Ok(())
     }
 
-    fn generate(&mut self) {
-        for _ in 0..self.rule.iterations {
-            self.iterate();
+    /// Parametric rules need `parametric`, a well-formed predecessor and successors whose
+    /// expressions only use that predecessor's parameters
+    fn check_parametric(&self) -> Result<(), String> {
+        if !self.is_parametric() {
+            return match &self.parametric_rules {
+                Some(rules) if !rules.is_empty() => Err("parametric_rules need \"parametric\": true".to_string()),
+                _ => Ok(()),
+            };
+        }
+        for (predecessor, successor) in self.parametric_rules.iter().flatten() {
+            let parsed = Predecessor::parse(predecessor)?;
+            check_brackets(successor).map_err(|e| format!("Parametric rule '{}': {}", predecessor, e))?;
+            parametric::instantiate(successor, &parsed.params, &vec![1.0; parsed.params.len()])
+                .map_err(|e| format!("Parametric rule '{}': {}", predecessor, e))?;
         }
+        let plain_successors = self.rules.values()
+            .chain(self.alternating_rules.iter().flat_map(|alt| alt.values().flatten()))
+            .chain(self.stochastic_rules.iter().flat_map(|stochastic| stochastic.values().flatten().map(|p| &p.successor)))
+            .chain(self.context_rules.iter().flatten().map(|rule| &rule.replacement));
+        for text in std::iter::once(&self.axiom).chain(&self.axioms).chain(plain_successors) {
+            parametric::instantiate(text, &[], &[])?;
+        }
+        Ok(())
     }
 
-    fn draw_3d(&self, turtle: &mut Turtle3D, renderer: &mut Renderer) {
-        turtle.reset();
-        
-        if let Some(step_length) = self.rule.step_length {
-            turtle.set_step_length(step_length);
+    fn is_parametric(&self) -> bool {
+        self.parametric.unwrap_or(false)
+    }
+
+    /// Each parametric rule with its parsed predecessor; rules that fail to parse are skipped,
+    /// as `validate` reports them
+    fn parametric_productions(&self) -> Vec<(Predecessor, &str)> {
+        if !self.is_parametric() {
+            return Vec::new();
         }
-        
-        turtle.set_angle(self.rule.angle);
-        
-        if let Some(colors) = &self.rule.colors {
-            if let Some(depth_based) = colors.depth_based {
-                turtle.set_depth_colors(depth_based);
+        self.parametric_rules.iter().flatten()
+            .filter_map(|(predecessor, successor)| Some((Predecessor::parse(predecessor).ok()?, successor.as_str())))
+            .collect()
+    }
+
+    /// `s` with every module name wrapped in `MODULE_DELIMITER`s, so rewriting and the turtle
+    /// can tell `FA` the module from `F` followed by `A`
+    fn encode_modules<'a>(&self, s: &'a str) -> std::borrow::Cow<'a, str> {
+        let modules = match &self.modules {
+            Some(modules) if !modules.is_empty() => modules,
+            _ => return std::borrow::Cow::Borrowed(s),
+        };
+        let mut encoded = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            match modules.iter().filter(|name| rest.starts_with(name.as_str())).max_by_key(|name| name.len()) {
+                Some(name) => {
+                    encoded.push(turtle3d::MODULE_DELIMITER);
+                    encoded.push_str(name);
+                    encoded.push(turtle3d::MODULE_DELIMITER);
+                    rest = &rest[name.len()..];
+                }
+                None => {
+                    encoded.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
             }
         }
-        
-        turtle.interpret(&self.current_string, renderer, Some(&self.rule.rules));
+        std::borrow::Cow::Owned(encoded)
     }
-}
 
-fn load_rule_from_file(path: &str) -> Result<LSystemRule, Box<dyn std::error::Error>> {
-    let contents = fs::read_to_string(path)?;
-    let rule: LSystemRule = serde_json::from_str(&contents)?;
-    Ok(rule)
-}
+    /// Every symbol given an iteration limit must have a rule to stop applying
+    fn check_iteration_limits(&self) -> Result<(), String> {
+        let Some(limits) = &self.iteration_limits else {
+            return Ok(());
+        };
+        let parametric_productions = self.parametric_productions();
+        let has_rule = |c: &char| self.rules.contains_key(c) ||
+            self.alternating_rules.as_ref().is_some_and(|alt| alt.contains_key(c)) ||
+            self.stochastic_rules.as_ref().is_some_and(|stochastic| stochastic.contains_key(c)) ||
+            self.context_rules.iter().flatten().any(|rule| rule.symbol == *c) ||
+            parametric_productions.iter().any(|(predecessor, _)| predecessor.symbol == *c);
+        match limits.keys().find(|c| !has_rule(c)) {
+            Some(c) => Err(format!("Iteration limit for '{}', which has no rule", c)),
+            None => Ok(()),
+        }
+    }
 
-fn main() {
-    let matches = Command::new("RustL-System")
-        .version("0.1.0")
-        .author("Christian")
-        .about("3D L-System generator with interactive menu and vim integration")
-        .arg(
-            Arg::new("rule-file")
-                .short('r')
-                .long("rule")
-                .value_name("FILE")
-                .help("JSON file containing L-System rules")
-                .default_value("rules/cherry_blossom.json"),
-        )
-        .get_matches();
+    /// Angle and step length `elapsed_secs` into the rule's keyframe animation, which loops
+    /// at the last keyframe's time; `None` for rules without keyframes
+    fn animated_params(&self, elapsed_secs: f32) -> Option<(f32, Option<f32>)> {
+        let keyframes = self.time_varying_params.as_ref()?;
+        let duration = keyframes.last()?.time_secs;
+        let t = if duration > 0.0 { elapsed_secs % duration } else { 0.0 };
+        let angle = interpolate_keyframes(keyframes, t, |k| k.angle).unwrap_or(self.angle);
+        let step_length = interpolate_keyframes(keyframes, t, |k| k.step_length).or(self.step_length);
+        Some((angle, step_length))
+    }
 
-    let rule_file = matches.get_one::<String>("rule-file").unwrap();
-    
-    let mut current_rule = match load_rule_from_file(rule_file) {
-        Ok(rule) => rule,
-        Err(e) => {
-            eprintln!("Error loading rule file {}: {}", rule_file, e);
-            std::process::exit(1);
+    /// First palette entry, if the rule defines a palette
+    fn primary_color(&self) -> Option<Vec3> {
+        self.colors.as_ref()
+            .and_then(|colors| colors.palette.as_ref())
+            .and_then(|palette| palette.first())
+            .map(|&c| Vec3::from(c))
+    }
+
+    /// The rule's skybox, with the zenith and horizon from its `sky_gradient` hint if it has one
+    fn skybox_config(&self) -> SkyboxConfig {
+        let mut skybox = self.skybox.clone().unwrap_or_default();
+        if let Some([zenith, horizon]) = self.render_hints.as_ref().and_then(|hints| hints.sky_gradient) {
+            skybox.zenith = Vec3::from(zenith);
+            skybox.horizon = Vec3::from(horizon);
         }
-    };
+        skybox
+    }
 
-    println!("3D L-System Viewer Started");
-    println!("Controls:");
-    println!("  Mouse + Drag: Rotate camera");
-    println!("  Mouse Wheel: Zoom in/out");
-    println!("  F1: Toggle main menu");
-    println!("  Tab: Toggle tree selection menu");
-    println!("  1-9: Load tree species (1=Sierpinski, 2=Plant, 3=Oak, 4=Pine, 5=Cherry, 6=Maple, 7=Willow, 8=Baobab, 9=Eucalyptus)");
-    println!("  G: Toggle GUI parameter controls");
-    println!("  E: Edit current L-system in vim");
-    println!("  R: Reload current L-system");
-    println!("  Escape: Exit");
+    /// The environment's ground, or else `ground_collision`
+    fn ground_level(&self) -> Option<f32> {
+        self.environment.and_then(|environment| environment.ground_y).or(self.ground_collision)
+    }
 
-    let mut window = Window::new(
-        "3D L-System Viewer - Interactive",
-        WIDTH,
-        HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+    fn is_defined_symbol(&self, c: char) -> bool {
+        turtle3d::is_turtle_command(c) ||
+            self.rules.contains_key(&c) ||
+            self.alternating_rules.as_ref().is_some_and(|alt| alt.contains_key(&c)) ||
+            self.stochastic_rules.as_ref().is_some_and(|stochastic| stochastic.contains_key(&c)) ||
+            self.context_rules.iter().flatten().any(|rule| rule.symbol == c) ||
+            self.parametric_productions().iter().any(|(predecessor, _)| predecessor.symbol == c)
+    }
 
-    window.set_target_fps(60);
+    fn is_stochastic(&self) -> bool {
+        self.stochastic_rules.as_ref().is_some_and(|stochastic| !stochastic.is_empty())
+    }
 
-    let mut camera = Camera::new(WIDTH as f32 / HEIGHT as f32);
-    let mut renderer = Renderer::new(WIDTH, HEIGHT);
-    let mut turtle = Turtle3D::new();
-    let mut menu = Menu::new();
-    let mut main_menu = MainMenu::new();
-    let editor = Editor::new();
-    let mut gui = GUI::new();
-    
-    let mut current_file_path = std::path::PathBuf::from(rule_file);
-    let mut needs_regeneration = true;
-    let mut lsystem = LSystem::new(current_rule.clone());
-    
-    let mut mouse_pressed = false;
+    /// Rewrites `input` once; `iteration` selects the even/odd alternating productions.
+    /// Stochastic rules always take their first production, as there is no RNG to choose with
+    fn expand_into(&self, input: &str, iteration: u32, output: &mut String) {
+        self.expand_with(input, iteration, None, output);
+    }
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // Handle main menu input - use F1 key (Menu)
-        if window.is_key_pressed(Key::F1, minifb::KeyRepeat::No) {
-            main_menu.toggle();
-        }
-        
-        // Handle main menu actions
-        if let Some(action) = main_menu.handle_input(&window) {
-            match action {
-                MenuAction::ShowTreeSelection => {
-                    main_menu.hide();
-                    if !menu.visible {
-                        menu.toggle();
-                    }
-                },
-                MenuAction::ShowParameters => {
-                    main_menu.hide();
-                    if !gui.visible {
-                        gui.toggle();
-                    }
-                },
-                MenuAction::EditLSystem => {
-                    main_menu.hide();
-                    match editor.edit_file(Some(&current_file_path)) {
-                        Ok(_) => {
-                            println!("File edited, reloading...");
-                            match load_rule_from_file(current_file_path.to_str().unwrap()) {
-                                Ok(new_rule) => {
-                                    current_rule = new_rule;
-                                    lsystem = LSystem::new(current_rule.clone());
-                                    needs_regeneration = true;
-                                }
-                                Err(e) => eprintln!("Error reloading file: {}", e),
-                            }
-                        }
-                        Err(e) => eprintln!("Error editing file: {}", e),
-                    }
-                },
-                MenuAction::ReloadLSystem => {
-                    main_menu.hide();
-                    match load_rule_from_file(current_file_path.to_str().unwrap()) {
-                        Ok(new_rule) => {
-                            current_rule = new_rule;
-                            lsystem = LSystem::new(current_rule.clone());
-                            needs_regeneration = true;
-                            println!("L-system reloaded");
-                        }
-                        Err(e) => eprintln!("Error reloading file: {}", e),
-                    }
-                },
-                MenuAction::Exit => {
-                    break;
+    /// `expand_into`, choosing each stochastic production with `rng`
+    fn expand_stochastic_into(&self, input: &str, iteration: u32, rng: &mut SmallRng, output: &mut String) {
+        self.expand_with(input, iteration, Some(rng), output);
+    }
+
+    fn expand_with(&self, input: &str, iteration: u32, mut rng: Option<&mut SmallRng>, output: &mut String) {
+        let parity = (iteration % 2) as usize;
+        let parametric_productions = self.parametric_productions();
+        let neighbors = match &self.context_rules {
+            Some(rules) if !rules.is_empty() => neighbor_symbols(input, self.is_parametric()),
+            _ => Vec::new(),
+        };
+        for (index, token) in symbol_tokens(input, self.is_parametric()).enumerate() {
+            if let Some(name) = module_name(token) {
+                match self.module_rules.as_ref().and_then(|rules| rules.get(name)) {
+                    Some(successor) => output.push_str(&self.encode_modules(successor)),
+                    None => output.push_str(token),
+                }
+                continue;
+            }
+            let ch = token.chars().next().expect("tokens are never empty");
+            // Symbols past their iteration limit are copied through as constants
+            let frozen = self.iteration_limits.as_ref()
+                .and_then(|limits| limits.get(&ch))
+                .is_some_and(|&limit| iteration >= limit);
+            if frozen {
+                output.push_str(token);
+                continue;
+            }
+            if let Some(&(left, right)) = neighbors.get(index)
+                && let Some(rule) = self.context_rule_for(ch, left, right) {
+                self.push_successor(&rule.replacement, &[], &[], output);
+                continue;
+            }
+            if !parametric_productions.is_empty() {
+                // A parametric rule applies when its symbol and parameter count both match
+                let args = parametric::literal_arguments(token);
+                let production = parametric_productions.iter()
+                    .find(|(predecessor, _)| predecessor.symbol == ch && predecessor.params.len() == args.len());
+                if let Some((predecessor, successor)) = production {
+                    self.push_successor(successor, &predecessor.params, &args, output);
+                    continue;
                 }
             }
+            if let Some(pair) = self.alternating_rules.as_ref().and_then(|alt| alt.get(&ch)) {
+                self.push_successor(&pair[parity], &[], &[], output);
+            } else if let Some(productions) = self.stochastic_rules.as_ref().and_then(|stochastic| stochastic.get(&ch)) {
+                self.push_successor(&choose_production(productions, rng.as_deref_mut()).successor, &[], &[], output);
+            } else if let Some(replacement) = self.rules.get(&ch) {
+                self.push_successor(replacement, &[], &[], output);
+            } else {
+                output.push_str(token);
+            }
         }
-        
-        // Handle input
-        if window.is_key_pressed(Key::Tab, minifb::KeyRepeat::No) {
-            println!("Tab key detected");
-            menu.toggle();
+    }
+
+    /// The most specific context rule for `symbol` between `left` and `right`, the first listed on a tie
+    fn context_rule_for(&self, symbol: char, left: Option<char>, right: Option<char>) -> Option<&ContextRule> {
+        self.context_rules.iter().flatten()
+            .filter(|rule| rule.matches(symbol, left, right))
+            .min_by_key(|rule| std::cmp::Reverse(rule.specificity()))
+    }
+
+    /// Appends `successor` with its modules encoded and, for parametric rules, its argument
+    /// expressions evaluated with `params` bound to `args`
+    fn push_successor(&self, successor: &str, params: &[String], args: &[f32], output: &mut String) {
+        let successor = self.encode_modules(successor);
+        if !self.is_parametric() {
+            output.push_str(&successor);
+            return;
         }
-        
-        if window.is_key_pressed(Key::G, minifb::KeyRepeat::No) {
-            gui.toggle();
+        // `validate` rejects expressions that fail to evaluate, so this copies nothing unevaluated
+        match parametric::instantiate(&successor, params, args) {
+            Ok(instantiated) => output.push_str(&instantiated),
+            Err(_) => output.push_str(&successor),
         }
-        
-        if window.is_key_pressed(Key::E, minifb::KeyRepeat::No) && !menu.visible {
-            match editor.edit_file(Some(&current_file_path)) {
-                Ok(_) => {
-                    println!("File edited, reloading...");
-                    match load_rule_from_file(current_file_path.to_str().unwrap()) {
-                        Ok(new_rule) => {
-                            current_rule = new_rule;
-                            lsystem = LSystem::new(current_rule.clone());
-                            needs_regeneration = true;
-                        }
-                        Err(e) => eprintln!("Error reloading file: {}", e),
-                    }
+    }
+
+    /// Length of the string after `iterations` rewrites, without building it: a count per symbol
+    /// is pushed through the production matrix once per iteration (saturating at `u64::MAX`).
+    /// Context rules only count where they need no neighbors, as each symbol is expanded alone
+    fn estimate_string_length(&self, iterations: u32) -> u64 {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for symbol in symbol_tokens(&LSystem::initial_string(self).0, self.is_parametric()) {
+            *counts.entry(symbol.to_string()).or_insert(0) += 1;
+        }
+
+        for iteration in 0..iterations {
+            let mut next: HashMap<String, u64> = HashMap::new();
+            for (symbol, &count) in &counts {
+                let mut replacement = String::new();
+                self.expand_into(symbol, iteration, &mut replacement);
+                for c in symbol_tokens(&replacement, self.is_parametric()) {
+                    let entry = next.entry(c.to_string()).or_insert(0);
+                    *entry = entry.saturating_add(count);
                 }
-                Err(e) => eprintln!("Error editing file: {}", e),
             }
+            counts = next;
         }
-        
-        if window.is_key_pressed(Key::R, minifb::KeyRepeat::No) && !menu.visible {
-            match load_rule_from_file(current_file_path.to_str().unwrap()) {
-                Ok(new_rule) => {
-                    current_rule = new_rule;
-                    lsystem = LSystem::new(current_rule.clone());
-                    needs_regeneration = true;
-                    println!("L-system reloaded");
-                }
-                Err(e) => eprintln!("Error reloading file: {}", e),
+
+        // A module takes its name's length plus two delimiters, and a parametric symbol its arguments
+        counts.iter().fold(0u64, |total, (symbol, &count)| total.saturating_add(count.saturating_mul(symbol.len() as u64)))
+    }
+
+    /// FNV-1a hash of everything that shapes the generated string: axioms, sorted rules, angle and iterations
+    fn checksum(&self) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, self.axiom.as_bytes());
+        for axiom in &self.axioms {
+            hash = fnv1a(hash, &[0]);
+            hash = fnv1a(hash, axiom.as_bytes());
+        }
+
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort();
+        for (symbol, replacement) in rules {
+            hash = fnv1a(hash, symbol.to_string().as_bytes());
+            hash = fnv1a(hash, replacement.as_bytes());
+            hash = fnv1a(hash, &[0]);
+        }
+
+        if let Some(alternating) = &self.alternating_rules {
+            let mut alternating: Vec<_> = alternating.iter().collect();
+            alternating.sort();
+            for (symbol, [even, odd]) in alternating {
+                hash = fnv1a(hash, symbol.to_string().as_bytes());
+                hash = fnv1a(hash, even.as_bytes());
+                hash = fnv1a(hash, &[0]);
+                hash = fnv1a(hash, odd.as_bytes());
+                hash = fnv1a(hash, &[0]);
             }
         }
-        
-        // Handle menu input
-        if let Some(selected_file) = menu.handle_input(&window) {
-            match load_rule_from_file(selected_file.to_str().unwrap()) {
-                Ok(new_rule) => {
-                    current_rule = new_rule;
-                    current_file_path = selected_file;
-                    lsystem = LSystem::new(current_rule.clone());
-                    needs_regeneration = true;
-                    println!("Loaded L-system: {}", current_rule.name);
+
+        if let Some(stochastic) = &self.stochastic_rules {
+            let mut stochastic: Vec<_> = stochastic.iter().collect();
+            stochastic.sort_by_key(|&(symbol, _)| symbol);
+            for (symbol, productions) in stochastic {
+                hash = fnv1a(hash, symbol.to_string().as_bytes());
+                for production in productions {
+                    hash = fnv1a(hash, production.successor.as_bytes());
+                    hash = fnv1a(hash, &production.weight.to_bits().to_le_bytes());
                 }
-                Err(e) => eprintln!("Error loading file: {}", e),
+                hash = fnv1a(hash, &[0]);
             }
         }
-        
-        // Handle mouse input for camera control
-        if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
-            let mouse_vec = Vec2::new(mouse_pos.0, mouse_pos.1);
-            
-            if window.get_mouse_down(minifb::MouseButton::Left) {
-                if !mouse_pressed {
-                    camera.start_rotation(mouse_vec);
-                    mouse_pressed = true;
-                } else {
-                    camera.update_rotation(mouse_vec);
-                }
-            } else if mouse_pressed {
-                camera.stop_rotation();
-                mouse_pressed = false;
+        // The seed picks which stochastic productions are drawn
+        if self.is_stochastic() {
+            hash = fnv1a(hash, &self.seed.map_or([0; 8], u64::to_le_bytes));
+        }
+
+        if self.is_parametric() {
+            hash = fnv1a(hash, b"parametric");
+            let mut parametric_rules: Vec<_> = self.parametric_rules.iter().flatten().collect();
+            parametric_rules.sort();
+            for (predecessor, successor) in parametric_rules {
+                hash = fnv1a(hash, predecessor.as_bytes());
+                hash = fnv1a(hash, successor.as_bytes());
+                hash = fnv1a(hash, &[0]);
             }
         }
-        
-        // Handle mouse wheel for zoom
-        if let Some(scroll) = window.get_scroll_wheel() {
-            camera.zoom(-scroll.1 * 0.1);
+
+        for rule in self.context_rules.iter().flatten() {
+            let contexts = [rule.left_context, Some(rule.symbol), rule.right_context];
+            for context in contexts {
+                hash = fnv1a(hash, context.map(String::from).unwrap_or_default().as_bytes());
+                hash = fnv1a(hash, &[0]);
+            }
+            hash = fnv1a(hash, rule.replacement.as_bytes());
+            hash = fnv1a(hash, &[0]);
         }
-        
-        // Handle GUI input and parameter changes
-        if gui.handle_input(&window) {
-            // Apply GUI parameters to turtle
-            if let Some(angle) = gui.get_parameter("Angle") {
-                turtle.set_angle(angle);
+
+        for module in self.modules.iter().flatten() {
+            hash = fnv1a(hash, module.as_bytes());
+            hash = fnv1a(hash, &[0]);
+        }
+        if let Some(module_rules) = &self.module_rules {
+            let mut module_rules: Vec<_> = module_rules.iter().collect();
+            module_rules.sort();
+            for (module, replacement) in module_rules {
+                hash = fnv1a(hash, module.as_bytes());
+                hash = fnv1a(hash, replacement.as_bytes());
+                hash = fnv1a(hash, &[0]);
             }
-            if let Some(step_length) = gui.get_parameter("Step Length") {
-                turtle.set_step_length(step_length);
+        }
+
+        if let Some(limits) = &self.iteration_limits {
+            let mut limits: Vec<_> = limits.iter().collect();
+            limits.sort();
+            for (symbol, limit) in limits {
+                hash = fnv1a(hash, symbol.to_string().as_bytes());
+                hash = fnv1a(hash, &limit.to_le_bytes());
             }
-            needs_regeneration = true;
         }
-        
-        // Regenerate L-system if needed
-        if needs_regeneration {
-            lsystem.generate();
-            println!("Generated {}: {} characters", current_rule.name, lsystem.current_string.len());
-            needs_regeneration = false;
+
+        hash = fnv1a(hash, &self.angle.to_bits().to_le_bytes());
+        fnv1a(hash, &self.iterations.to_le_bytes())
+    }
+
+    /// Writes the rule as TOML if `path` ends in `.toml`, otherwise as JSON
+    fn save_to_file(&self, path: &std::path::Path) -> Result<(), String> {
+        let contents = RuleFormat::from_path(path).serialize(self)?;
+        fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Linear interpolation of one keyframed value at time `t`, holding the first and last values
+/// outside the keyframed range; keyframes that leave the value unset are skipped
+fn interpolate_keyframes(keyframes: &[TimeKeyframe], t: f32, value: impl Fn(&TimeKeyframe) -> Option<f32>) -> Option<f32> {
+    let mut previous: Option<(f32, f32)> = None;
+    for keyframe in keyframes {
+        let Some(v) = value(keyframe) else { continue };
+        if keyframe.time_secs >= t {
+            return Some(match previous {
+                Some((pt, pv)) if keyframe.time_secs > pt => pv + (v - pv) * (t - pt) / (keyframe.time_secs - pt),
+                _ => v,
+            });
         }
-        
-        // Render
-        renderer.clear();
-        lsystem.draw_3d(&mut turtle, &mut renderer);
-        renderer.render(&camera);
-        
-        // Get buffer from renderer
-        let buffer = renderer.get_buffer();
-        let mut display_buffer = buffer.to_vec();
-        
-        // Render menu overlay
-        menu.render_to_buffer(&mut display_buffer, WIDTH, HEIGHT);
-        
-        // Render GUI overlay
-        gui.render(&mut display_buffer, WIDTH, HEIGHT);
-        
-        // Render main menu overlay (on top of everything)
-        main_menu.render(&mut display_buffer, WIDTH, HEIGHT, &current_rule.name);
-        
-        window.update_with_buffer(&display_buffer, WIDTH, HEIGHT).unwrap();
+        previous = Some((keyframe.time_secs, v));
+    }
+    previous.map(|(_, v)| v)
+}
+
+/// The symbols of a generated string in order: single characters, and modules as whole
+/// `MODULE_DELIMITER`-wrapped tokens. With `parametric` set, a symbol's `(...)` arguments
+/// are part of its token
+fn symbol_tokens(s: &str, parametric: bool) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        let len = if c == turtle3d::MODULE_DELIMITER {
+            rest[1..].find(turtle3d::MODULE_DELIMITER).map_or(rest.len(), |end| end + 2)
+        } else if parametric {
+            c.len_utf8() + parametric::argument_group_len(&rest[c.len_utf8()..]).unwrap_or(0)
+        } else {
+            c.len_utf8()
+        };
+        let (token, tail) = rest.split_at(len);
+        rest = tail;
+        Some(token)
+    })
+}
+
+/// Each token's nearest left and right neighbors for context rules, skipping over whole `[...]`
+/// branches; `None` where there is no neighbor on that side of the branch, or it is a module
+fn neighbor_symbols(s: &str, parametric: bool) -> Vec<(Option<char>, Option<char>)> {
+    let symbols: Vec<Option<char>> = symbol_tokens(s, parametric)
+        .map(|token| if module_name(token).is_some() { None } else { token.chars().next() })
+        .collect();
+    let mut neighbors = vec![(None, None); symbols.len()];
+
+    // The stacks keep the neighbor from outside each branch, to restore once it is passed
+    let mut branches = Vec::new();
+    let mut left = None;
+    for (i, &symbol) in symbols.iter().enumerate() {
+        match symbol {
+            Some('[') => branches.push(left),
+            Some(']') => left = branches.pop().flatten(),
+            _ => {
+                neighbors[i].0 = left;
+                left = symbol;
+            }
+        }
+    }
+
+    branches.clear();
+    let mut right = None;
+    for (i, &symbol) in symbols.iter().enumerate().rev() {
+        match symbol {
+            Some(']') => {
+                branches.push(right);
+                right = None;
+            }
+            Some('[') => right = branches.pop().flatten(),
+            _ => {
+                neighbors[i].1 = right;
+                right = symbol;
+            }
+        }
+    }
+    neighbors
+}
+
+/// The module name inside a token from `symbol_tokens`, or `None` for a single character
+fn module_name(token: &str) -> Option<&str> {
+    token.strip_prefix(turtle3d::MODULE_DELIMITER)?.strip_suffix(turtle3d::MODULE_DELIMITER)
+}
+
+/// A production picked with probability proportional to its weight, or the first without an RNG.
+/// `productions` must not be empty
+fn choose_production<'a>(productions: &'a [StochasticProduction], rng: Option<&mut SmallRng>) -> &'a StochasticProduction {
+    let Some(rng) = rng else { return &productions[0] };
+    let total: f32 = productions.iter().map(|production| production.weight).sum();
+    let mut roll = rng.gen_range(0.0..total);
+    for production in productions {
+        if roll < production.weight {
+            return production;
+        }
+        roll -= production.weight;
+    }
+    &productions[productions.len() - 1]
+}
+
+fn check_brackets(s: &str) -> Result<(), String> {
+    let mut depth = 0i32;
+    for (i, ch) in s.chars().enumerate() {
+        match ch {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth < 0 {
+                    return Err(format!("unmatched ']' at position {}", i));
+                }
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("{} unclosed '['", depth));
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct LSystemRuleBuilder {
+    name: Option<String>,
+    axiom: Option<String>,
+    angle: Option<f32>,
+    iterations: Option<u32>,
+    rules: HashMap<char, String>,
+    step_length: Option<f32>,
+    depth_colors: Option<bool>,
+    palette: Option<Vec<[f32; 3]>>,
+    description: Option<String>,
+}
+
+impl LSystemRuleBuilder {
+    fn name(&mut self, s: &str) -> &mut Self {
+        self.name = Some(s.to_string());
+        self
+    }
+
+    fn axiom(&mut self, s: &str) -> &mut Self {
+        self.axiom = Some(s.to_string());
+        self
+    }
+
+    fn rule(&mut self, from: char, to: &str) -> &mut Self {
+        self.rules.insert(from, to.to_string());
+        self
+    }
+
+    fn angle(&mut self, degrees: f32) -> &mut Self {
+        self.angle = Some(degrees);
+        self
+    }
+
+    fn iterations(&mut self, n: u32) -> &mut Self {
+        self.iterations = Some(n);
+        self
+    }
+
+    fn step_length(&mut self, l: f32) -> &mut Self {
+        self.step_length = Some(l);
+        self
+    }
+
+    fn depth_colors(&mut self, enabled: bool) -> &mut Self {
+        self.depth_colors = Some(enabled);
+        self
+    }
+
+    fn palette(&mut self, colors: Vec<[f32; 3]>) -> &mut Self {
+        self.palette = Some(colors);
+        self
+    }
+
+    fn description(&mut self, s: &str) -> &mut Self {
+        self.description = Some(s.to_string());
+        self
+    }
+
+    fn build(&self) -> Result<LSystemRule, String> {
+        let name = self.name.clone().ok_or("Rule name is required")?;
+        let axiom = self.axiom.clone().ok_or("Rule axiom is required")?;
+        let angle = self.angle.ok_or("Rule angle is required")?;
+
+        let colors = if self.depth_colors.is_some() || self.palette.is_some() {
+            Some(ColorConfig {
+                depth_based: self.depth_colors,
+                palette: self.palette.clone(),
+                gradient: None,
+            })
+        } else {
+            None
+        };
+
+        let rule = LSystemRule {
+            name,
+            axiom,
+            angle,
+            iterations: self.iterations.unwrap_or(1),
+            rules: self.rules.clone(),
+            step_length: self.step_length,
+            start_position: None,
+            start_direction: None,
+            colors,
+            description: self.description.clone(),
+            description_url: None,
+            skybox: None,
+            axioms: Vec::new(),
+            start_positions: None,
+            alternating_rules: None,
+            parameter_presets: None,
+            taper: None,
+            iteration_limits: None,
+            time_varying_params: None,
+            ground_collision: None,
+            view_presets: None,
+            max_memory_mb: None,
+            render_hints: None,
+            environment: None,
+            stochastic_rules: None,
+            seed: None,
+            modules: None,
+            module_rules: None,
+            parametric: None,
+            parametric_rules: None,
+            context_rules: None,
+            symbol_colors: None,
+        };
+        rule.validate()?;
+        Ok(rule)
+    }
+}
+
+/// Role of one symbol within a rule, as reported by `LSystem::validate_symbols`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SymbolStatus {
+    /// Built-in turtle command without a rule of its own
+    TurtleCommand,
+    /// Has a rule that the axiom eventually triggers
+    DefinedRule,
+    /// Produced by a rule but has neither a rule nor a turtle meaning, so it is copied unchanged
+    UndefinedInRHS,
+    /// Neither a rule nor a turtle command, and only written in the axiom
+    AppearanceInAxiomOnly,
+    /// Has a rule, but no chain of rewrites from the axiom produces it
+    Unreachable,
+}
+
+/// A rule together with the string it generated, so the string can be reloaded without
+/// regenerating it. Borrowed when writing, owned when reading
+#[derive(Serialize, Deserialize)]
+struct LSystemSnapshot<'a> {
+    rule: std::borrow::Cow<'a, LSystemRule>,
+    generated_string: std::borrow::Cow<'a, str>,
+    /// Unix seconds when the snapshot was written
+    generation_timestamp: u64,
+    string_length: usize,
+    max_bracket_depth: usize,
+    /// `generated_string` is run-length encoded as by `fold_string`
+    #[serde(default)]
+    compressed: bool,
+}
+
+/// Counts describing the current string, from `LSystem::statistics`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LSystemStats {
+    total_symbols: usize,
+    unique_symbols: usize,
+    symbol_counts: HashMap<char, usize>,
+    max_stack_depth: usize,
+    /// Forward moves outside module names, whether they draw or not
+    forward_move_count: usize,
+    /// Number of `[`
+    branch_count: usize,
+}
+
+impl LSystemStats {
+    /// One line for the GUI panel
+    fn summary(&self) -> String {
+        format!("Symbols: {}  Branches: {}  Depth: {}", self.total_symbols, self.branch_count, self.max_stack_depth)
+    }
+
+    /// Every count, with symbols from most to least frequent
+    fn report(&self) -> String {
+        let mut counts: Vec<(char, usize)> = self.symbol_counts.iter().map(|(&c, &n)| (c, n)).collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut report = format!("Symbols: {} ({} unique)\nForward moves: {}\nBranches: {}\nMax stack depth: {}\n",
+                                 self.total_symbols, self.unique_symbols, self.forward_move_count,
+                                 self.branch_count, self.max_stack_depth);
+        for (c, n) in counts {
+            let share = n as f64 * 100.0 / self.total_symbols as f64;
+            report.push_str(&format!("  {:?}: {} ({:.1}%)\n", c, n, share));
+        }
+        report
+    }
+}
+
+#[derive(Clone)]
+struct LSystem {
+    rule: LSystemRule,
+    current_string: String,
+    current_iteration: u32,
+    // End offset of each axiom's expansion within current_string
+    axiom_boundaries: Vec<usize>,
+    max_bytes: usize,
+    /// Set when generation stopped early because the string passed `max_bytes`
+    truncated: bool,
+    /// Seed `rng` was last seeded with, for rules with stochastic productions
+    last_seed: Option<u64>,
+    /// Picks stochastic productions; reseeded by every `reset`, so regenerating repeats the tree
+    rng: SmallRng,
+}
+
+impl LSystem {
+    fn new(rule: LSystemRule) -> Self {
+        let (current_string, axiom_boundaries) = Self::initial_string(&rule);
+        let mut lsystem = LSystem {
+            current_string,
+            current_iteration: 0,
+            axiom_boundaries,
+            max_bytes: Self::max_bytes_for(&rule),
+            truncated: false,
+            last_seed: None,
+            rng: SmallRng::seed_from_u64(0),
+            rule,
+        };
+        lsystem.seed_rng();
+        lsystem
+    }
+
+    /// Seeds `rng` from the rule's seed, else the previous seed, else the OS's entropy
+    fn seed_rng(&mut self) {
+        let seed = self.rule.seed.or(self.last_seed).unwrap_or_else(|| rand::thread_rng().r#gen());
+        self.rng = SmallRng::seed_from_u64(seed);
+        self.last_seed = self.rule.is_stochastic().then_some(seed);
+    }
+
+    fn max_bytes_for(rule: &LSystemRule) -> usize {
+        rule.max_memory_mb.map_or(DEFAULT_MAX_STRING_BYTES, |mb| mb as usize * 1_000_000)
+    }
+
+    fn initial_string(rule: &LSystemRule) -> (String, Vec<usize>) {
+        if rule.axioms.len() > 1 {
+            let mut boundaries = Vec::with_capacity(rule.axioms.len());
+            let mut joined = String::new();
+            for axiom in &rule.axioms {
+                rule.push_successor(axiom, &[], &[], &mut joined);
+                boundaries.push(joined.len());
+            }
+            (joined, boundaries)
+        } else {
+            let mut axiom = String::new();
+            rule.push_successor(rule.axioms.first().unwrap_or(&rule.axiom), &[], &[], &mut axiom);
+            let len = axiom.len();
+            (axiom, vec![len])
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let (current_string, axiom_boundaries) = Self::initial_string(&self.rule);
+        self.current_string = current_string;
+        self.axiom_boundaries = axiom_boundaries;
+        self.current_iteration = 0;
+        self.truncated = false;
+        self.seed_rng();
+    }
+
+    /// Sets the production for `symbol` and goes back to the axiom, so the next `generate` uses it
+    fn replace_rule(&mut self, symbol: char, new_replacement: &str) -> Result<(), String> {
+        if new_replacement.contains(['\n', '\r']) {
+            return Err(format!("Rule '{}' must fit on one line", symbol));
+        }
+        check_brackets(new_replacement).map_err(|e| format!("Rule '{}': {}", symbol, e))?;
+        self.rule.rules.insert(symbol, new_replacement.to_string());
+        self.reset();
+        Ok(())
+    }
+
+    /// Drops the production for `symbol`, which is copied unchanged from then on
+    fn remove_rule(&mut self, symbol: char) -> Result<(), String> {
+        self.rule.rules.remove(&symbol).ok_or_else(|| format!("There is no rule for '{}'", symbol))?;
+        self.reset();
+        Ok(())
+    }
+
+    /// The expanded string of each axiom, in order
+    fn axiom_segments(&self) -> Vec<&str> {
+        let mut start = 0;
+        self.axiom_boundaries.iter().map(|&end| {
+            let segment = &self.current_string[start..end];
+            start = end;
+            segment
+        }).collect()
+    }
+
+    pub fn current_iteration(&self) -> u32 {
+        self.current_iteration
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.current_iteration >= self.rule.iterations
+    }
+
+    /// One rewrite; stochastic productions are drawn from the system's seeded RNG
+    pub fn iterate(&mut self) {
+        if self.rule.is_stochastic() {
+            let mut rng = self.rng.clone();
+            self.iterate_stochastic(&mut rng);
+            self.rng = rng;
+        } else {
+            self.iterate_with(None);
+        }
+    }
+
+    /// One rewrite, choosing every stochastic production with `rng`
+    fn iterate_stochastic(&mut self, rng: &mut SmallRng) {
+        self.iterate_with(Some(rng));
+    }
+
+    fn iterate_with(&mut self, mut rng: Option<&mut SmallRng>) {
+        let mut new_string = String::new();
+        let mut new_boundaries = Vec::with_capacity(self.axiom_boundaries.len());
+        
+        for segment in self.axiom_segments() {
+            match rng.as_deref_mut() {
+                Some(rng) => self.rule.expand_stochastic_into(segment, self.current_iteration, rng, &mut new_string),
+                None => self.expand_into(segment, &mut new_string),
+            }
+            new_boundaries.push(new_string.len());
+        }
+        
+        self.current_string = new_string;
+        self.axiom_boundaries = new_boundaries;
+        self.current_iteration += 1;
+        
+        if self.string_size_bytes() > self.max_bytes {
+            eprintln!("Warning: L-system string exceeded {} MB at iteration {}, stopping early",
+                      self.max_bytes / 1_000_000, self.current_iteration);
+            self.truncated = true;
+        }
+    }
+
+    /// Memory held by the generated string; every symbol is ASCII, so this is also its length
+    pub fn string_size_bytes(&self) -> usize {
+        self.current_string.len()
+    }
+
+    fn expand_into(&self, input: &str, output: &mut String) {
+        self.rule.expand_into(input, self.current_iteration, output);
+    }
+
+    /// The fully expanded string for `rule`, without keeping any generator state around
+    fn generate_to_string(rule: &LSystemRule) -> String {
+        Self::generate_to_string_at(rule, rule.iterations)
+    }
+
+    /// The string after `iterations` rewrites of `rule`'s axioms. It is grown by a throwaway
+    /// `LSystem`, so seeds, axiom segments and the memory limit work as they do in the viewer
+    fn generate_to_string_at(rule: &LSystemRule, iterations: u32) -> String {
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate_n(iterations);
+        lsystem.current_string
+    }
+
+    /// Yields the string after each iteration, starting with the first expansion of the axiom.
+    /// Stochastic productions are drawn from this system's seed, so the last string is `generate`'s
+    fn generate_lazy(&self) -> impl Iterator<Item = String> + Send + use<> {
+        let (current, boundaries) = Self::initial_string(&self.rule);
+        LSystemIterator {
+            max_bytes: self.max_bytes,
+            rng: self.last_seed.map(SmallRng::seed_from_u64),
+            rule: self.rule.clone(),
+            current,
+            boundaries,
+            step: 0,
+        }
+    }
+
+    /// Returns a randomly perturbed copy of the rule for evolutionary exploration.
+    /// Falls back to an unmodified copy if no valid mutation is found in five attempts.
+    fn mutate(&self, mutation_rate: f32, rng: &mut impl Rng) -> LSystemRule {
+        const ALPHABET: [char; 10] = ['F', 'G', '+', '-', '&', '^', '\\', '/', '[', ']'];
+        const MAX_RULE_LENGTH: usize = 50;
+
+        for _ in 0..5 {
+            let mut rule = self.rule.clone();
+
+            for replacement in rule.rules.values_mut() {
+                let mut mutated = String::new();
+                for ch in replacement.chars() {
+                    let roll: f32 = rng.r#gen();
+                    if roll < mutation_rate {
+                        mutated.push(ALPHABET[rng.gen_range(0..ALPHABET.len())]);
+                    } else if roll < mutation_rate * 1.5 {
+                        // Deleted
+                    } else if roll < mutation_rate * 2.0 {
+                        mutated.push(ch);
+                        mutated.push(ALPHABET[rng.gen_range(0..ALPHABET.len())]);
+                    } else {
+                        mutated.push(ch);
+                    }
+                }
+                *replacement = mutated.chars().take(MAX_RULE_LENGTH).collect();
+            }
+
+            let noise_scale = mutation_rate * 10.0;
+            rule.angle += gaussian(rng) * noise_scale;
+            if let Some(step_length) = rule.step_length {
+                rule.step_length = Some((step_length + gaussian(rng) * noise_scale).max(0.01));
+            }
+
+            if rule.validate().is_ok() && rule.angle > 0.0 && rule.angle <= 180.0 {
+                return rule;
+            }
+        }
+
+        self.rule.clone()
+    }
+
+    fn string_checksum(&self) -> u64 {
+        fnv1a(FNV_OFFSET_BASIS, self.current_string.as_bytes())
+    }
+
+    /// Run-length encoded form of the current string, cut to roughly `max_segments * 10` characters
+    fn fold_string(&self, max_segments: u32) -> String {
+        let mut folded = String::new();
+        let _ = fold_segment(&self.current_string, &mut folded);
+
+        let limit = max_segments as usize * 10;
+        if folded.len() <= limit {
+            return folded;
+        }
+        let mut cut = limit;
+        while !folded.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let remaining = folded.len() - cut;
+        folded.truncate(cut);
+        folded.push_str(&format!("... ({} more)", remaining));
+        folded
+    }
+
+    /// Length of `fold_string` without the truncation, computed without building the string
+    fn compressed_length(&self) -> usize {
+        let mut counter = LengthCounter(0);
+        let _ = fold_segment(&self.current_string, &mut counter);
+        counter.0
+    }
+
+    fn max_bracket_depth(&self) -> usize {
+        let mut depth = 0usize;
+        let mut max_depth = 0;
+        for c in self.current_string.chars() {
+            match c {
+                '[' => {
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                ']' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        max_depth
+    }
+
+    /// Symbol counts, branching and nesting of the current string
+    fn statistics(&self) -> LSystemStats {
+        let mut symbol_counts = HashMap::new();
+        let mut forward_move_count = 0;
+        let mut in_module = false;
+        for c in self.current_string.chars() {
+            *symbol_counts.entry(c).or_insert(0) += 1;
+            if c == turtle3d::MODULE_DELIMITER {
+                in_module = !in_module;
+            } else if !in_module && turtle3d::is_forward_command(c) {
+                forward_move_count += 1;
+            }
+        }
+        LSystemStats {
+            total_symbols: self.current_string.chars().count(),
+            unique_symbols: symbol_counts.len(),
+            branch_count: symbol_counts.get(&'[').copied().unwrap_or(0),
+            symbol_counts,
+            max_stack_depth: self.max_bracket_depth(),
+            forward_move_count,
+        }
+    }
+
+    /// Writes the rule and the current string to `path` as an `LSystemSnapshot`. With `compress`
+    /// the string is run-length encoded, unless it can't be decoded exactly (e.g. it contains digits)
+    fn export_to_json_with_generated_string(&self, path: &std::path::Path, compress: bool) -> Result<(), String> {
+        let mut folded = None;
+        if compress {
+            let mut encoded = String::new();
+            let _ = fold_segment(&self.current_string, &mut encoded);
+            if unfold_string(&encoded) == self.current_string {
+                folded = Some(encoded);
+            } else {
+                eprintln!("Warning: {} does not survive run-length encoding; writing it uncompressed", self.rule.name);
+            }
+        }
+
+        let snapshot = LSystemSnapshot {
+            rule: std::borrow::Cow::Borrowed(&self.rule),
+            compressed: folded.is_some(),
+            generated_string: folded.map_or(std::borrow::Cow::Borrowed(self.current_string.as_str()), std::borrow::Cow::Owned),
+            generation_timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            string_length: self.current_string.len(),
+            max_bracket_depth: self.max_bracket_depth(),
+        };
+        let file = fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &snapshot).map_err(|e| e.to_string())
+    }
+
+    /// Loads a snapshot written by `export_to_json_with_generated_string`, taking its string as
+    /// fully generated
+    fn from_snapshot(path: &std::path::Path) -> Result<LSystem, String> {
+        let file = fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let snapshot: LSystemSnapshot = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))?;
+        let current_string = if snapshot.compressed {
+            unfold_string(&snapshot.generated_string)
+        } else {
+            snapshot.generated_string.into_owned()
+        };
+        if current_string.len() != snapshot.string_length {
+            return Err(format!("Snapshot {} holds {} characters but records {}",
+                               path.display(), current_string.len(), snapshot.string_length));
+        }
+
+        let mut lsystem = LSystem::new(snapshot.rule.into_owned());
+        lsystem.current_iteration = lsystem.rule.iterations;
+        lsystem.axiom_boundaries = vec![current_string.len()];
+        lsystem.current_string = current_string;
+        Ok(lsystem)
+    }
+
+    fn generate(&mut self) {
+        while !self.is_complete() && !self.truncated {
+            self.iterate();
+        }
+    }
+
+    /// Regrows the system from the axiom and writes each iteration, 0 through `rule.iterations`, as
+    /// one frame of a looping GIF seen through `camera`. Frames are drawn the way the viewer draws
+    /// them, so they match the screen. The string grows exponentially with depth, so high iteration
+    /// counts take a long time to generate and quantize.
+    fn render_growth_gif(&mut self, renderer: &mut Renderer, turtle: &mut Turtle3D, camera: &Camera,
+                         path: &std::path::Path, frame_delay_ms: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height) = renderer.size();
+        let (width, height) = (u16::try_from(width)?, u16::try_from(height)?);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut encoder = gif::Encoder::new(std::io::BufWriter::new(fs::File::create(path)?), width, height, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        self.reset();
+        loop {
+            renderer.clear();
+            turtle.set_view_proj(Some(camera.projection_matrix() * camera.view_matrix()));
+            self.draw_3d(turtle, renderer);
+            renderer.render(camera);
+
+            let mut frame = gif::Frame::from_rgba_speed(width, height, &mut renderer.rgba_bytes(), GIF_QUANTIZE_SPEED);
+            // GIF delays are in hundredths of a second
+            frame.delay = u16::try_from(frame_delay_ms / 10).unwrap_or(u16::MAX);
+            encoder.write_frame(&frame)?;
+
+            if self.is_complete() || self.truncated {
+                return Ok(());
+            }
+            self.iterate();
+        }
+    }
+
+    /// Regrows the system from the axiom and collects the lines of up to `frame_count` growth stages,
+    /// one iteration apart, as the frames of an animated SVG. Stops early once the system is complete
+    /// or truncated. `limiter` paces headless exports
+    fn growth_line_frames(&mut self, renderer: &mut Renderer, turtle: &mut Turtle3D, frame_count: u32,
+                          limiter: &FrameLimiter) -> Vec<Vec<Line>> {
+        self.reset();
+        let mut frames = Vec::new();
+        for frame in 0..frame_count {
+            let frame_start = std::time::Instant::now();
+            renderer.clear();
+            self.draw_3d(turtle, renderer);
+            frames.push(renderer.lines().to_vec());
+            limiter.wait(frame_start);
+            if frame + 1 == frame_count || self.is_complete() || self.truncated {
+                break;
+            }
+            self.iterate();
+        }
+        frames
+    }
+
+    /// Goes back to the axiom and applies exactly `n` iterations, whatever `rule.iterations` says,
+    /// unless the memory limit stops it first
+    fn generate_n(&mut self, n: u32) {
+        self.reset();
+        while self.current_iteration < n && !self.truncated {
+            self.iterate();
+        }
+    }
+
+    /// Regenerates from the axiom with stochastic productions drawn from `seed`, so the same
+    /// seed always grows the same string. The seed is kept in the rule for `save_to_file`
+    fn generate_with_seed(&mut self, seed: u64) {
+        self.rule.seed = Some(seed);
+        self.reset();
+        self.generate();
+    }
+
+    /// `generate_with_seed` with a seed from the OS's entropy; returns the string and the seed
+    fn generate_random(&mut self) -> (String, u64) {
+        let seed = rand::thread_rng().r#gen();
+        self.generate_with_seed(seed);
+        (self.current_string.clone(), seed)
+    }
+
+    /// Like `generate`, but stops before an iteration that would take the string past
+    /// `target_length` characters. Returns false if it stopped short of `rule.iterations`;
+    /// the partial string still draws as a complete, simpler tree.
+    fn generate_incremental(&mut self, target_length: usize) -> bool {
+        while !self.is_complete() && !self.truncated {
+            let next_length = self.rule.estimate_string_length(self.current_iteration + 1);
+            if self.current_string.len() >= target_length || next_length > target_length as u64 {
+                return false;
+            }
+            self.iterate();
+        }
+        true
+    }
+
+    /// Every production's predecessor and successor, whichever map it comes from: plain,
+    /// alternating, stochastic, context-sensitive and parametric. Module rules are `module_productions`
+    fn productions(&self) -> Vec<(char, &str)> {
+        let rule = &self.rule;
+        rule.rules.iter().map(|(&symbol, successor)| (symbol, successor.as_str()))
+            .chain(rule.alternating_rules.iter().flatten()
+                .flat_map(|(&symbol, pair)| pair.iter().map(move |successor| (symbol, successor.as_str()))))
+            .chain(rule.stochastic_rules.iter().flatten()
+                .flat_map(|(&symbol, productions)| productions.iter().map(move |p| (symbol, p.successor.as_str()))))
+            .chain(rule.context_rules.iter().flatten().map(|context| (context.symbol, context.replacement.as_str())))
+            .chain(rule.parametric_productions().into_iter().map(|(predecessor, successor)| (predecessor.symbol, successor)))
+            .collect()
+    }
+
+    /// Module rules keyed by `module_node`, so they fit in the same graph as single symbols
+    fn module_productions(&self) -> Vec<(char, &str)> {
+        self.rule.module_rules.iter().flatten()
+            .filter_map(|(name, successor)| Some((self.module_node(name)?, successor.as_str())))
+            .collect()
+    }
+
+    /// Private-use character standing for a declared module in the rules graph
+    fn module_node(&self, name: &str) -> Option<char> {
+        let index = self.rule.modules.iter().flatten().position(|module| module == name)?;
+        char::from_u32(MODULE_NODE_BASE + index as u32)
+    }
+
+    /// Graph nodes of the symbols in `s`, with each module as its `module_node`
+    fn symbol_nodes(&self, s: &str) -> Vec<char> {
+        symbol_tokens(&self.rule.encode_modules(s), self.rule.is_parametric())
+            .filter_map(|token| match module_name(token) {
+                Some(name) => self.module_node(name),
+                None => token.chars().next(),
+            })
+            .collect()
+    }
+
+    /// Each rewritten symbol mapped to the distinct symbols its productions produce, from every
+    /// production map. Turtle commands and brackets are left out unless they are rewritten
+    /// themselves, as `F` often is. Modules appear as their `module_node`
+    fn rules_graph(&self) -> HashMap<char, Vec<char>> {
+        let productions: Vec<(char, &str)> = self.productions().into_iter().chain(self.module_productions()).collect();
+        let rewritten: HashSet<char> = productions.iter().map(|&(symbol, _)| symbol).collect();
+        let mut graph: HashMap<char, Vec<char>> = HashMap::new();
+        for (symbol, successor) in productions {
+            let nodes = self.symbol_nodes(successor);
+            let edges = graph.entry(symbol).or_default();
+            for c in nodes {
+                if (rewritten.contains(&c) || !turtle3d::is_turtle_command(c)) && !edges.contains(&c) {
+                    edges.push(c);
+                }
+            }
+        }
+        graph
+    }
+
+    /// True if some symbol can eventually produce itself, so the string grows without bound
+    fn has_cycle(&self) -> bool {
+        let graph = self.rules_graph();
+        let mut on_path = HashSet::new();
+        let mut finished = HashSet::new();
+        graph.keys().any(|&symbol| has_back_edge(&graph, symbol, &mut on_path, &mut finished))
+    }
+
+    /// Symbols on the longest production chain, e.g. 3 for `A -> B -> C`; `usize::MAX` if `has_cycle`
+    fn longest_chain_length(&self) -> usize {
+        if self.has_cycle() {
+            return usize::MAX;
+        }
+        let graph = self.rules_graph();
+        let mut lengths = HashMap::new();
+        graph.keys().map(|&symbol| chain_length_from(&graph, symbol, &mut lengths)).max().unwrap_or(0)
+    }
+
+    /// Everything the axiom can grow into, following every production once, whichever map it is in
+    fn reachable_symbols_from_axiom(&self) -> HashSet<char> {
+        let productions: Vec<(char, &str)> = self.productions().into_iter().chain(self.module_productions()).collect();
+        let mut reachable = HashSet::new();
+        let axiom = if self.rule.axioms.is_empty() { self.rule.axiom.clone() } else { self.rule.axioms.join("") };
+        let mut pending: VecDeque<char> = self.symbol_nodes(&axiom).into();
+        while let Some(c) = pending.pop_front() {
+            if reachable.insert(c) {
+                for &(_, successor) in productions.iter().filter(|&&(symbol, _)| symbol == c) {
+                    pending.extend(self.symbol_nodes(successor));
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Classifies every symbol used in the axiom or rules
+    fn validate_symbols(&self) -> HashMap<char, SymbolStatus> {
+        let axiom = if self.rule.axioms.is_empty() { self.rule.axiom.clone() } else { self.rule.axioms.join("") };
+        let productions = self.productions();
+        let has_rule = |c: char| productions.iter().any(|&(symbol, _)| symbol == c);
+        let reachable = self.reachable_symbols_from_axiom();
+        
+        // Modules are named by the rule file, so only single-character symbols are classified
+        let single_symbols = |s: &str| -> Vec<char> {
+            symbol_tokens(&self.rule.encode_modules(s), self.rule.is_parametric())
+                .filter(|token| module_name(token).is_none())
+                .filter_map(|token| token.chars().next())
+                .collect()
+        };
+        let in_rhs: Vec<char> = productions.iter().chain(&self.module_productions())
+            .flat_map(|&(_, successor)| single_symbols(successor))
+            .collect();
+        let lhs = productions.iter().map(|&(symbol, _)| symbol);
+        
+        single_symbols(&axiom).into_iter().chain(in_rhs.iter().copied()).chain(lhs)
+            .map(|c| {
+                let status = if has_rule(c) {
+                    if reachable.contains(&c) { SymbolStatus::DefinedRule } else { SymbolStatus::Unreachable }
+                } else if turtle3d::is_turtle_command(c) {
+                    SymbolStatus::TurtleCommand
+                } else if in_rhs.contains(&c) {
+                    SymbolStatus::UndefinedInRHS
+                } else {
+                    SymbolStatus::AppearanceInAxiomOnly
+                };
+                (c, status)
+            })
+            .collect()
+    }
+    
+    /// Warnings about symbols that are copied unchanged or rules that never apply
+    fn validate(&self) -> Vec<String> {
+        let mut symbols: Vec<(char, SymbolStatus)> = self.validate_symbols().into_iter().collect();
+        symbols.sort_by_key(|&(c, _)| c);
+        symbols.into_iter()
+            .filter_map(|(c, status)| match status {
+                SymbolStatus::UndefinedInRHS => Some(format!("'{}' has no rule or turtle meaning and is copied unchanged", c)),
+                SymbolStatus::Unreachable => Some(format!("the rule for '{}' is never reached from the axiom", c)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Length the string will have once all `rule.iterations` have been applied
+    fn current_estimated_full_length(&self) -> u64 {
+        self.rule.estimate_string_length(self.rule.iterations)
+    }
+
+    /// Applies the rule's step length, angle, taper and coloring to `turtle` and resets it
+    fn configure_turtle(&self, turtle: &mut Turtle3D) {
+        turtle.reset();
+        
+        if let Some(step_length) = self.rule.step_length {
+            turtle.set_step_length(step_length);
+        }
+        
+        turtle.set_angle(self.rule.angle);
+        turtle.set_parametric(self.rule.is_parametric());
+        match self.rule.ground_level() {
+            Some(ground) => turtle.set_ground_level(ground),
+            None => turtle.clear_ground_level(),
+        }
+        let environment = self.rule.environment.unwrap_or_default();
+        match environment.max_height {
+            Some(max_height) => turtle.set_max_height(max_height),
+            None => turtle.clear_max_height(),
+        }
+        match environment.tropism() {
+            Some(tropism) => turtle.set_tropism(tropism, environment.tropism_weight.unwrap_or(DEFAULT_TROPISM_WEIGHT)),
+            None => turtle.clear_tropism(),
+        }
+        turtle.set_taper_mode(match self.rule.taper {
+            Some(taper) => TaperMode::ByDepth { base: taper.base, decay: taper.decay, min: taper.min },
+            None => TaperMode::FromCommands,
+        });
+        
+        if let Some(colors) = &self.rule.colors
+            && let Some(depth_based) = colors.depth_based {
+            turtle.set_depth_colors(depth_based);
+        }
+        turtle.set_gradient(self.rule.colors.as_ref().and_then(|colors| colors.gradient.clone()));
+        turtle.set_symbol_colors(self.rule.symbol_colors.iter().flatten().map(|(&c, &color)| (c, Vec3::from(color))).collect());
+    }
+
+    /// Bounds of everything `draw_3d` would draw, found with a turtle dry run, or the origin if
+    /// nothing is drawn
+    fn compute_bounding_box(&self, turtle: &mut Turtle3D) -> (Vec3, Vec3) {
+        self.drawn_bounds(turtle).unwrap_or((Vec3::ZERO, Vec3::ZERO))
+    }
+
+    /// `compute_bounding_box` with a turtle of its own, so tools without a renderer or window can
+    /// frame the tree; `None` if the string is empty or never draws a line
+    fn compute_bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.drawn_bounds(&mut Turtle3D::new())
+    }
+
+    /// Middle of `compute_bounds`, or the origin if nothing is drawn
+    fn center(&self) -> Vec3 {
+        self.compute_bounds().map_or(Vec3::ZERO, |(min, max)| (min + max) * 0.5)
+    }
+
+    /// Length of the diagonal of `compute_bounds`, or 0 if nothing is drawn
+    fn diagonal_length(&self) -> f32 {
+        self.compute_bounds().map_or(0.0, |(min, max)| (max - min).length())
+    }
+
+    /// Union of the dry-run bounds of each axiom's string that draws at least one line
+    fn drawn_bounds(&self, turtle: &mut Turtle3D) -> Option<(Vec3, Vec3)> {
+        self.configure_turtle(turtle);
+        
+        let runs: Vec<(Option<Vec3>, &str)> = match &self.rule.start_positions {
+            Some(start_positions) if !start_positions.is_empty() => {
+                self.axiom_segments().into_iter().enumerate()
+                    .map(|(i, segment)| (Some(Vec3::from(start_positions[i.min(start_positions.len() - 1)])), segment))
+                    .collect()
+            }
+            _ => vec![(None, self.current_string.as_str())],
+        };
+        let mut bounds: Option<(Vec3, Vec3)> = None;
+        for (position, commands) in runs {
+            if let Some(position) = position {
+                turtle.reset();
+                turtle.set_position(position);
+            }
+            let metrics = turtle.dry_run(commands, None);
+            if metrics.total_segments > 0 {
+                let (min, max) = metrics.bounding_box;
+                bounds = Some(bounds.map_or((min, max), |(lo, hi)| (lo.min(min), hi.max(max))));
+            }
+        }
+        bounds
+    }
+
+    /// Box-counting dimension of the current string, with box sizes from a quarter of the
+    /// tree's largest extent down through four halvings
+    fn fractal_dimension(&self, turtle: &mut Turtle3D) -> f32 {
+        let (min, max) = self.compute_bounding_box(turtle);
+        let max_scale = (max - min).max_element() * 0.25;
+        self.configure_turtle(turtle);
+        turtle.compute_fractal_dimension(&self.current_string, max_scale / 16.0, max_scale, 4)
+    }
+
+    fn draw_3d(&self, turtle: &mut Turtle3D, renderer: &mut Renderer) {
+        self.configure_turtle(turtle);
+        
+        match &self.rule.start_positions {
+            Some(start_positions) if !start_positions.is_empty() => {
+                // Each axiom grows from its own start position
+                for (i, segment) in self.axiom_segments().into_iter().enumerate() {
+                    let position = start_positions[i.min(start_positions.len() - 1)];
+                    turtle.reset();
+                    turtle.set_position(Vec3::from(position));
+                    turtle.interpret(segment, renderer, Some(&self.rule.rules));
+                }
+            }
+            _ => turtle.interpret(&self.current_string, renderer, Some(&self.rule.rules)),
+        }
+    }
+}
+
+/// Standard normal sample via the Box-Muller transform
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let u2: f32 = rng.r#gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+// FNV-1a: no extra dependency and good enough for change detection, but not collision resistant
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Continues an FNV-1a hash from `hash` over `bytes`
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Written by `fold_segment` between a group and the repeat count of the symbol after it
+const FOLD_COUNT_SEPARATOR: char = ' ';
+
+/// Writes `s` with runs collapsed: `FFFFF` becomes `5F` and `[X][X][X]` becomes `[X]3`. A group's
+/// count comes after it and a symbol's before it, so a space keeps a symbol's count from reading
+/// as the group's: `[X][X]FFF` becomes `[X]2 3F` and `[X]FF` becomes `[X] 2F`
+fn fold_segment(s: &str, out: &mut impl std::fmt::Write) -> std::fmt::Result {
+    let mut rest = s;
+    let mut after_group = false;
+    while !rest.is_empty() {
+        let token = next_fold_token(rest);
+        let mut count = 1;
+        rest = &rest[token.len()..];
+        while rest.starts_with(token) {
+            count += 1;
+            rest = &rest[token.len()..];
+        }
+
+        if token.starts_with('[') && token.len() > 1 {
+            out.write_char('[')?;
+            fold_segment(&token[1..token.len() - 1], out)?;
+            out.write_char(']')?;
+            if count > 1 {
+                write!(out, "{}", count)?;
+            }
+            after_group = true;
+        } else if count > 1 {
+            if after_group {
+                out.write_char(FOLD_COUNT_SEPARATOR)?;
+            }
+            write!(out, "{}{}", count, token)?;
+            after_group = false;
+        } else {
+            out.write_str(token)?;
+            after_group = false;
+        }
+    }
+    Ok(())
+}
+
+/// A single character, or a whole balanced `[...]` group
+fn next_fold_token(s: &str) -> &str {
+    if s.starts_with('[') {
+        let mut depth = 0;
+        for (i, ch) in s.char_indices() {
+            match ch {
+                '[' => depth += 1,
+                ']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return &s[..=i];
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    let len = s.chars().next().map_or(0, |c| c.len_utf8());
+    &s[..len]
+}
+
+/// Inverse of `fold_segment`: `3F` repeats a symbol, `[+F]2` a group, and a space between a
+/// group and a symbol's count is dropped. Strings whose own symbols include digits don't
+/// survive folding, so callers check the round trip
+fn unfold_string(folded: &str) -> String {
+    let mut out = String::with_capacity(folded.len());
+    unfold_segment(&mut folded.chars().peekable(), &mut out, false);
+    out
+}
+
+fn unfold_segment(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String, nested: bool) {
+    let read_count = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        let mut count = None;
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            count = Some(count.unwrap_or(0) * 10 + digit as usize);
+            chars.next();
+        }
+        count
+    };
+    
+    while let Some(&c) = chars.peek() {
+        if c == ']' && nested {
+            return;
+        }
+        if let Some(count) = read_count(chars) {
+            let Some(symbol) = chars.next() else { return };
+            out.extend(std::iter::repeat_n(symbol, count));
+            continue;
+        }
+        chars.next();
+        if c != '[' {
+            out.push(c);
+            continue;
+        }
+        
+        let mut group = String::from("[");
+        unfold_segment(chars, &mut group, true);
+        if chars.next_if_eq(&']').is_some() {
+            group.push(']');
+            for _ in 0..read_count(chars).unwrap_or(1) {
+                out.push_str(&group);
+            }
+            let mut ahead = chars.clone();
+            if ahead.next() == Some(FOLD_COUNT_SEPARATOR) && ahead.peek().is_some_and(char::is_ascii_digit) {
+                chars.next();
+            }
+        } else {
+            out.push_str(&group); // Unbalanced `[` at the end of the string
+        }
+    }
+}
+
+/// Depth-first search from `symbol` for an edge back to a symbol still on the current path
+fn has_back_edge(graph: &HashMap<char, Vec<char>>, symbol: char,
+                 on_path: &mut HashSet<char>, finished: &mut HashSet<char>) -> bool {
+    if finished.contains(&symbol) {
+        return false;
+    }
+    if !on_path.insert(symbol) {
+        return true;
+    }
+    let found = graph.get(&symbol).into_iter().flatten()
+        .any(|&next| has_back_edge(graph, next, on_path, finished));
+    on_path.remove(&symbol);
+    finished.insert(symbol);
+    found
+}
+
+/// Symbols on the longest path starting at `symbol`; `graph` must be acyclic
+fn chain_length_from(graph: &HashMap<char, Vec<char>>, symbol: char, lengths: &mut HashMap<char, usize>) -> usize {
+    if let Some(&length) = lengths.get(&symbol) {
+        return length;
+    }
+    let length = 1 + graph.get(&symbol).into_iter().flatten()
+        .map(|&next| chain_length_from(graph, next, lengths))
+        .max()
+        .unwrap_or(0);
+    lengths.insert(symbol, length);
+    length
+}
+
+/// The `len` most recent entries of a ring buffer whose next write goes to `next`, oldest first
+fn history_in_order(history: &[f32], next: usize, len: usize) -> Vec<f32> {
+    let start = (next + history.len() - len) % history.len();
+    (0..len).map(|i| history[(start + i) % history.len()]).collect()
+}
+
+/// `fmt::Write` sink that only counts bytes
+struct LengthCounter(usize);
+
+impl std::fmt::Write for LengthCounter {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+struct LSystemIterator {
+    rule: LSystemRule,
+    current: String,
+    step: u32,
+    // End offset of each axiom's expansion within `current`
+    boundaries: Vec<usize>,
+    rng: Option<SmallRng>,
+    max_bytes: usize,
+}
+
+impl Iterator for LSystemIterator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        // Like `generate`, stop after the string passes the memory limit
+        if self.step >= self.rule.iterations || self.current.len() > self.max_bytes {
+            return None;
+        }
+
+        let mut next = String::new();
+        let mut boundaries = Vec::with_capacity(self.boundaries.len());
+        let mut start = 0;
+        for &end in &self.boundaries {
+            self.rule.expand_with(&self.current[start..end], self.step, self.rng.as_mut(), &mut next);
+            boundaries.push(next.len());
+            start = end;
+        }
+        self.current = next;
+        self.boundaries = boundaries;
+        self.step += 1;
+        Some(self.current.clone())
+    }
+}
+
+/// Syntax a rule file is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleFormat {
+    Json,
+    Toml,
+}
+
+impl RuleFormat {
+    /// TOML for `.toml` files, JSON for everything else
+    fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => RuleFormat::Toml,
+            _ => RuleFormat::Json,
+        }
+    }
+
+    fn parse(self, contents: &str) -> Result<LSystemRule, Box<dyn std::error::Error>> {
+        Ok(match self {
+            RuleFormat::Json => serde_json::from_str(contents)?,
+            RuleFormat::Toml => toml::from_str(contents)?,
+        })
+    }
+
+    fn serialize(self, rule: &LSystemRule) -> Result<String, String> {
+        match self {
+            RuleFormat::Json => serde_json::to_string_pretty(rule).map_err(|e| e.to_string()),
+            // TOML keys must be strings, so the rule's char-keyed maps go through JSON's string keys
+            RuleFormat::Toml => serde_json::to_value(rule).map_err(|e| e.to_string())
+                .and_then(|value| toml::to_string_pretty(&without_nulls(value)).map_err(|e| e.to_string())),
+        }
+    }
+}
+
+/// `value` with every null object field removed, since TOML has no null; unset optional
+/// fields read back as `None` either way
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map.into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect(),
+        serde_json::Value::Array(values) => values.into_iter().map(without_nulls).collect(),
+        value => value,
+    }
+}
+
+/// Iteration counts above this are allowed but slow to expand
+const ITERATION_WARNING_THRESHOLD: u32 = 10;
+const MAX_RULE_ITERATIONS: u32 = 20;
+
+/// Checks a loaded rule's values, returning every problem found as `[ERROR] ...` or `[WARN] ...`;
+/// only errors stop the rule from loading
+fn validate_rule(rule: &LSystemRule) -> Result<(), Vec<String>> {
+    let mut messages = Vec::new();
+    if rule.axiom.is_empty() {
+        messages.push("[ERROR] axiom is empty".to_string());
+    } else if let Err(e) = rule.validate() {
+        // Structural problems such as unbalanced brackets or undeclared modules
+        messages.push(format!("[ERROR] {}", e));
+    }
+    let undefined: BTreeSet<char> = symbol_tokens(&rule.encode_modules(&rule.axiom), rule.is_parametric())
+        .filter(|token| module_name(token).is_none())
+        .filter_map(|token| token.chars().next())
+        .filter(|&c| !rule.is_defined_symbol(c))
+        .collect();
+    for c in undefined {
+        messages.push(format!("[WARN] axiom symbol '{}' has no rule and is not a turtle command", c));
+    }
+    if !(rule.angle > 0.0 && rule.angle < 360.0) {
+        messages.push(format!("[WARN] angle {} is outside (0, 360)", rule.angle));
+    }
+    if !(1..=MAX_RULE_ITERATIONS).contains(&rule.iterations) {
+        messages.push(format!("[WARN] iterations {} is outside [1, {}]", rule.iterations, MAX_RULE_ITERATIONS));
+    } else if rule.iterations > ITERATION_WARNING_THRESHOLD {
+        messages.push(format!("[WARN] iterations {} may take a long time to expand", rule.iterations));
+    }
+    if let Some(step_length) = rule.step_length.filter(|&step| step.is_nan() || step <= 0.0) {
+        messages.push(format!("[WARN] step_length {} is not positive", step_length));
+    }
+    let palette = rule.colors.as_ref().and_then(|colors| colors.palette.as_deref()).unwrap_or_default();
+    for (i, color) in palette.iter().enumerate() {
+        if color.iter().any(|v| !(0.0..=1.0).contains(v)) {
+            messages.push(format!("[WARN] colors.palette[{}] {:?} has values outside [0.0, 1.0]", i, color));
+        }
+    }
+
+    if messages.is_empty() { Ok(()) } else { Err(messages) }
+}
+
+/// Loads a rule in the format its extension names
+fn load_rule_from_file(path: &str) -> Result<LSystemRule, Box<dyn std::error::Error>> {
+    load_rule_from_file_as(path, RuleFormat::from_path(std::path::Path::new(path)))
+}
+
+fn load_rule_from_file_toml(path: &str) -> Result<LSystemRule, Box<dyn std::error::Error>> {
+    load_rule_from_file_as(path, RuleFormat::Toml)
+}
+
+/// Loads a rule written in `format`, whatever its extension; keyframes and gradient stops are
+/// sorted and validation warnings printed
+fn load_rule_from_file_as(path: &str, format: RuleFormat) -> Result<LSystemRule, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    let mut rule = format.parse(&contents)?;
+    if let Err(messages) = validate_rule(&rule) {
+        for message in &messages {
+            let (severity, text) = message.split_once(' ').unwrap_or(("", message));
+            eprintln!("{} {}: {}", severity, path, text);
+        }
+        if messages.iter().any(|message| message.starts_with("[ERROR]")) {
+            return Err(format!("{} is not a valid rule", path).into());
+        }
+    }
+    if let Some(keyframes) = &mut rule.time_varying_params {
+        keyframes.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+    }
+    if let Some(gradient) = rule.colors.as_mut().and_then(|colors| colors.gradient.as_mut()) {
+        gradient.sort_by(|a, b| a.t.total_cmp(&b.t));
+    }
+    for warning in LSystem::new(rule.clone()).validate() {
+        eprintln!("[WARN] {}: {}", path, warning);
+    }
+    Ok(rule)
+}
+
+/// Where and at what size a menu export is written
+struct ExportOptions {
+    width: usize,
+    height: usize,
+    path: std::path::PathBuf,
+}
+
+impl ExportOptions {
+    /// `exports/<rule name>_<unix seconds>.<extension>`, creating `exports/` if needed
+    fn new(rule_name: &str, extension: &str, width: usize, height: usize) -> Self {
+        Self::in_dir("exports", rule_name, extension, width, height)
+    }
+
+    /// Like [`ExportOptions::new`] but under `dir`
+    fn in_dir(dir: &str, rule_name: &str, extension: &str, width: usize, height: usize) -> Self {
+        let stem: String = rule_name.trim().to_lowercase().chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let dir = std::path::Path::new(dir);
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Could not create {}: {}", dir.display(), e);
+        }
+        let file_name = if extension.is_empty() {
+            format!("{}_{}", stem, timestamp)
+        } else {
+            format!("{}_{}.{}", stem, timestamp, extension)
+        };
+        Self { width, height, path: dir.join(file_name) }
+    }
+}
+
+/// Caps the frame rate by sleeping out the rest of each frame, so a static scene doesn't keep a core busy
+struct FrameLimiter {
+    /// `None` runs unlimited
+    frame_duration: Option<std::time::Duration>,
+}
+
+impl FrameLimiter {
+    /// Sleep can overshoot by about this much, so the end of each frame is spun instead
+    const SLEEP_OVERHEAD: std::time::Duration = std::time::Duration::from_millis(1);
+
+    /// `target_fps` of 0 disables limiting
+    fn new(target_fps: u32) -> Self {
+        FrameLimiter {
+            frame_duration: (target_fps > 0).then(|| std::time::Duration::from_nanos(1_000_000_000 / target_fps as u64)),
+        }
+    }
+
+    /// Returns once a whole frame has passed since `frame_start`
+    fn wait(&self, frame_start: std::time::Instant) {
+        let Some(frame_duration) = self.frame_duration else { return };
+        let elapsed = frame_start.elapsed();
+        if elapsed >= frame_duration {
+            return;
+        }
+        if let Some(sleep) = (frame_duration - elapsed).checked_sub(Self::SLEEP_OVERHEAD) {
+            std::thread::sleep(sleep);
+        }
+        while frame_start.elapsed() < frame_duration {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Quiet time after the last change before a watched rule is reloaded, so half-written files are skipped
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Watches one rule file and reports when it has been created or modified; dropping it stops watching
+struct RuleWatcher {
+    reloads: std::sync::mpsc::Receiver<()>,
+    // Kept alive for as long as events should arrive
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl RuleWatcher {
+    /// Watches the file's directory, since editors often save by replacing the file
+    fn new(path: &std::path::Path) -> notify::Result<Self> {
+        use notify::Watcher;
+
+        let (event_sender, events) = std::sync::mpsc::channel();
+        let (reload_sender, reloads) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(event_sender)?;
+        let directory = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+        watcher.watch(directory, notify::RecursiveMode::NonRecursive)?;
+        let watched = path.to_path_buf();
+        std::thread::spawn(move || forward_debounced_changes(events, &watched, reload_sender, WATCH_DEBOUNCE));
+        Ok(RuleWatcher { reloads, _watcher: watcher })
+    }
+
+    /// True once per burst of changes since the last call
+    fn changed(&self) -> bool {
+        self.reloads.try_iter().count() > 0
+    }
+}
+
+/// A `RuleWatcher` that moves to whichever rule file is open
+#[derive(Default)]
+struct OpenFileWatcher {
+    watcher: Option<RuleWatcher>,
+    path: std::path::PathBuf,
+}
+
+impl OpenFileWatcher {
+    /// True once per burst of changes to `path`, which is watched from the first call naming it
+    fn changed(&mut self, path: &std::path::Path) -> bool {
+        if self.path != path {
+            self.path = path.to_path_buf();
+            self.watcher = RuleWatcher::new(path)
+                .map_err(|e| eprintln!("Error watching {}: {}", path.display(), e))
+                .ok();
+        }
+        let changed = self.watcher.as_ref().is_some_and(RuleWatcher::changed);
+        if changed {
+            println!("[watcher] File changed, reloading...");
+        }
+        changed
+    }
+}
+
+/// Sends one message on `reloads` for each burst of create or modify events on `path`, once
+/// `debounce` has passed without another; returns when either channel closes
+fn forward_debounced_changes(events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>, path: &std::path::Path,
+                             reloads: std::sync::mpsc::Sender<()>, debounce: std::time::Duration) {
+    let file_name = path.file_name();
+    let is_change = |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Modify(_)) &&
+            event.paths.iter().any(|changed| changed.file_name() == file_name),
+        Err(_) => false,
+    };
+    while let Ok(event) = events.recv() {
+        if !is_change(&event) {
+            continue;
+        }
+        // Keep waiting while the file is still being written
+        loop {
+            match events.recv_timeout(debounce) {
+                Ok(_) => continue,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        if reloads.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Grows `rule` fully and saves one frame of it to the PNG at `path`, framed like a new tree in the
+/// window but seen from `yaw` and `pitch` (radians); no window is opened
+fn render_rule_to_png(rule: &LSystemRule, path: &std::path::Path, width: usize, height: usize,
+                      yaw: f32, pitch: f32) -> Result<(), Box<dyn std::error::Error>> {
+    let mut lsystem = LSystem::new(rule.clone());
+    lsystem.generate();
+    let mut turtle = Turtle3D::new();
+    register_custom_commands(&mut turtle);
+    let mut camera = Camera::new(width as f32 / height as f32);
+    camera.set_yaw_pitch_distance(yaw, pitch, camera.distance);
+    if let Some((min, max)) = lsystem.compute_bounds() {
+        camera.fit_to_bounds(min, max);
+    }
+
+    let mut renderer = headless_renderer(rule, width, height);
+    renderer.clear();
+    renderer.render_skybox(&camera);
+    turtle.set_view_proj(Some(camera.projection_matrix() * camera.view_matrix()));
+    lsystem.draw_3d(&mut turtle, &mut renderer);
+    if let Some(bounds) = renderer.bounding_box() {
+        camera.auto_adjust_clipping(bounds);
+    }
+    renderer.render(&camera);
+    renderer.save_png(path)
+}
+
+/// Adds the turtle commands the viewer understands beyond the built-in ones: `$` rolls the turtle
+/// so its left vector is horizontal
+fn register_custom_commands(turtle: &mut Turtle3D) {
+    turtle.set_custom_command('$', Box::new(|state, _renderer| state.make_up_horizontal()));
+}
+
+/// Copy of `turtle` for exports, with the custom commands registered again since they can't be cloned
+fn export_turtle(turtle: &Turtle3D) -> Turtle3D {
+    let mut copy = turtle.clone_without_custom_commands();
+    register_custom_commands(&mut copy);
+    copy
+}
+
+/// Renderer for drawing `rule` without a window, with the rule's skybox, ground shadow and
+/// render hints applied
+fn headless_renderer(rule: &LSystemRule, width: usize, height: usize) -> Renderer {
+    let mut renderer = Renderer::new(width, height);
+    renderer.set_skybox(rule.skybox_config());
+    renderer.set_ground_shadow(rule.ground_level());
+    // The hints' GUI values and axes only matter in the window
+    apply_render_hints(rule, &mut renderer, &mut Gui::new(), &mut false);
+    renderer
+}
+
+/// Renders one PNG per growth iteration of `rule` into the directory `options.path` with `turtle`'s
+/// settings, at most as fast as `limiter` allows
+fn export_frame_sequence(rule: &LSystemRule, mut turtle: Turtle3D, camera: &Camera, options: &ExportOptions,
+                         limiter: &FrameLimiter) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut renderer = headless_renderer(rule, options.width, options.height);
+    turtle.set_view_proj(Some(camera.projection_matrix() * camera.view_matrix()));
+    let mut lsystem = LSystem::new(rule.clone());
+    let mut frame = 0;
+    loop {
+        let frame_start = std::time::Instant::now();
+        renderer.clear();
+        renderer.render_skybox(camera);
+        lsystem.draw_3d(&mut turtle, &mut renderer);
+        renderer.render(camera);
+        renderer.write_frame_sequence(&options.path, "frame", frame)?;
+        limiter.wait(frame_start);
+        frame += 1;
+        if lsystem.is_complete() || lsystem.truncated {
+            return Ok(frame);
+        }
+        lsystem.iterate();
+    }
+}
+
+/// Which part of the UI receives navigation keys this frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Scene,
+    MainMenu,
+    RuleMenu,
+    Wizard,
+    Gui,
+}
+
+/// This frame's key presses, with the modifiers held and whatever else is taking keys
+struct Keys<'a> {
+    window: &'a Window,
+    ctrl_down: bool,
+    shift_down: bool,
+    /// Keys are typed into the new rule wizard
+    wizard_active: bool,
+    /// A GUI control is taking keys until Escape releases it
+    widget_focused: bool,
+    /// Keys are typed into the rule menu's search or a focused GUI control
+    text_input_active: bool,
+    main_menu_visible: bool,
+}
+
+impl Keys<'_> {
+    fn pressed(&self, key: Key) -> bool {
+        self.window.is_key_pressed(key, minifb::KeyRepeat::No)
+    }
+
+    /// Pressed, or held long enough to repeat
+    fn repeated(&self, key: Key) -> bool {
+        self.window.is_key_pressed(key, minifb::KeyRepeat::Yes)
+    }
+
+    /// Neither the wizard nor a text field is taking keys
+    fn free(&self) -> bool {
+        !self.wizard_active && !self.text_input_active
+    }
+
+    /// Plain letters reach the scene: the keys are free, Ctrl is up and the main menu, whose
+    /// hotkeys share letters with the scene's, is closed
+    fn scene(&self) -> bool {
+        self.free() && !self.ctrl_down && !self.main_menu_visible
+    }
+}
+
+/// The open rule file and the L-system grown from it
+struct LoadedRule {
+    rule: LSystemRule,
+    path: std::path::PathBuf,
+    lsystem: LSystem,
+    needs_regeneration: bool,
+    /// Set by reloads, so edited render hints apply without reopening the rule
+    hints_pending: bool,
+}
+
+impl LoadedRule {
+    /// Switches to the rule file at `path`, grown from scratch; returns whether it loaded
+    fn open(&mut self, path: std::path::PathBuf) -> bool {
+        match load_rule_from_file(path.to_str().unwrap()) {
+            Ok(new_rule) => {
+                self.rule = new_rule;
+                self.path = path;
+                self.lsystem = LSystem::new(self.rule.clone());
+                self.needs_regeneration = true;
+                println!("Loaded L-system: {}", self.rule.name);
+                true
+            }
+            Err(e) => {
+                eprintln!("Error loading file: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Applies a reloaded version of the current rule. The regeneration pass always runs so the
+    /// skybox, shadow, fog and post-processing settings and the render hints are reapplied, but the
+    /// string is only expanded again when something that shapes it changed
+    fn reload(&mut self, mut new_rule: LSystemRule) {
+        // A file without a seed keeps the one the current tree was grown with
+        if new_rule.seed.is_none() {
+            new_rule.seed = self.rule.seed;
+        }
+        if new_rule.checksum() != self.rule.checksum() {
+            self.lsystem = LSystem::new(new_rule.clone());
+        } else {
+            self.lsystem.rule = new_rule.clone();
+        }
+        self.rule = new_rule;
+        self.needs_regeneration = true;
+        self.hints_pending = true;
+    }
+
+    /// Reads the open file again; returns whether it loaded
+    fn reload_from_disk(&mut self) -> bool {
+        match load_rule_from_file(self.path.to_str().unwrap()) {
+            Ok(new_rule) => {
+                self.reload(new_rule);
+                true
+            }
+            Err(e) => {
+                eprintln!("Error reloading file: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Opens the file in the external editor and reloads it once the editor exits
+    fn edit(&mut self, editor: &Editor) {
+        match editor.edit_file(Some(&self.path)) {
+            Ok(_) => {
+                println!("File edited, reloading...");
+                self.reload_from_disk();
+            }
+            Err(e) => eprintln!("Error editing file: {}", e),
+        }
+    }
+}
+
+/// T switches to growing only `iteration` iterations, moved with Space and Backspace; `segment`
+/// is the drawn segment labelled along with its neighbours, moved with , and ., and the turtle is
+/// shown as it was just after drawing it
+#[derive(Default)]
+struct StepMode {
+    enabled: bool,
+    iteration: u32,
+    segment: usize,
+    /// Where each segment's command ends in the string, for replaying the turtle up to it
+    segment_ends: Vec<usize>,
+}
+
+/// Exports asked for by keys or the context menu, written once the frame has rendered
+#[derive(Default)]
+struct PendingExports {
+    svg: bool,
+    screenshot: bool,
+    growth_gif: bool,
+}
+
+/// Where and how big exports are written
+struct ExportSettings {
+    /// S, P, Ctrl+G and snapshots are named for the window's size
+    window_size: (usize, usize),
+    /// `--export-width` and `--export-height`, for the main menu's image, SVG, OBJ and frame exports
+    size_override: (Option<usize>, Option<usize>),
+    gif_frame_delay_ms: u32,
+    compress_snapshots: bool,
+    headless_limiter: FrameLimiter,
+}
+
+impl ExportSettings {
+    /// The main menu's export size: the overrides where given, otherwise the window's
+    fn size(&self) -> (usize, usize) {
+        (self.size_override.0.unwrap_or(self.window_size.0), self.size_override.1.unwrap_or(self.window_size.1))
+    }
+}
+
+/// Right-click menu of camera shortcuts, drawn at the click position
+struct ContextMenu {
+    items: Vec<&'static str>,
+    x: usize,
+    y: usize,
+    visible: bool,
+    /// Geometry under the right-click, where "Fly to here" goes
+    picked_point: Option<Vec3>,
+}
+
+impl ContextMenu {
+    const ITEM_HEIGHT: usize = 14;
+    const PADDING: usize = 4;
+    /// How far the cursor may stray from the menu before it closes
+    const CLOSE_DISTANCE: f32 = 20.0;
+
+    fn new() -> Self {
+        Self {
+            items: vec!["Fly to here", "Reset View", "Top View", "Front View", "Screenshot", "Fit Scene"],
+            x: 0,
+            y: 0,
+            visible: false,
+            picked_point: None,
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let text_width = self.items.iter().map(|item| Renderer::measure_text(item, 1).0).max().unwrap_or(0);
+        (text_width + 2 * Self::PADDING, self.items.len() * Self::ITEM_HEIGHT + 2 * Self::PADDING)
+    }
+
+    /// Opens at `(x, y)`, moved up or left if it would run off a `width` x `height` window
+    fn show(&mut self, x: usize, y: usize, width: usize, height: usize, picked_point: Option<Vec3>) {
+        let (menu_width, menu_height) = self.size();
+        self.x = x.min(width.saturating_sub(menu_width));
+        self.y = y.min(height.saturating_sub(menu_height));
+        self.picked_point = picked_point;
+        self.visible = true;
+    }
+
+    fn hide(&mut self) {
+        self.visible = false;
+    }
+
+    fn item_at(&self, mouse_x: f32, mouse_y: f32) -> Option<usize> {
+        let (menu_width, _) = self.size();
+        let top = (self.y + Self::PADDING) as f32;
+        if mouse_x < self.x as f32 || mouse_x >= (self.x + menu_width) as f32 || mouse_y < top {
+            return None;
+        }
+        let index = ((mouse_y - top) / Self::ITEM_HEIGHT as f32) as usize;
+        (index < self.items.len()).then_some(index)
+    }
+
+    /// Distance from the cursor to the nearest edge of the menu, 0 inside it
+    fn distance_to(&self, mouse_x: f32, mouse_y: f32) -> f32 {
+        let (menu_width, menu_height) = self.size();
+        let dx = (self.x as f32 - mouse_x).max(mouse_x - (self.x + menu_width) as f32).max(0.0);
+        let dy = (self.y as f32 - mouse_y).max(mouse_y - (self.y + menu_height) as f32).max(0.0);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    fn render(&self, buffer: &mut [u32], width: usize, height: usize, mouse: Option<(f32, f32)>) {
+        if !self.visible {
+            return;
+        }
+        let (menu_width, menu_height) = self.size();
+        for py in self.y..(self.y + menu_height).min(height) {
+            for px in self.x..(self.x + menu_width).min(width) {
+                let edge = py == self.y || px == self.x || py + 1 == self.y + menu_height || px + 1 == self.x + menu_width;
+                buffer[py * width + px] = if edge { 0xFFFFFF } else { 0x404040 };
+            }
+        }
+        let hovered = mouse.and_then(|(mx, my)| self.item_at(mx, my));
+        for (i, item) in self.items.iter().enumerate() {
+            let color = if *item == "Fly to here" && self.picked_point.is_none() {
+                0x666666
+            } else if hovered == Some(i) {
+                0x00FF00
+            } else {
+                0xCCCCCC
+            };
+            let y = self.y + Self::PADDING + i * Self::ITEM_HEIGHT + (Self::ITEM_HEIGHT - 8) / 2;
+            Renderer::render_text_at_screen(buffer, width, height, (self.x + Self::PADDING, y), item, color, 1);
+        }
+    }
+}
+
+/// What the window title reports besides the rule name
+enum AppStatus {
+    Ready { chars: usize },
+    Generating { iteration: u32, total: u32 },
+    Saved(String),
+    Timing { render_time: std::time::Duration, fps: f32 },
+}
+
+/// Full expansions longer than this get a warning, and `--low-memory` stops generating at this length
+const LOW_MEMORY_STRING_LENGTH: usize = 5_000_000;
+
+/// Opacity the menus, GUI and panels are blended over the scene with
+const MENU_ALPHA: u8 = 220;
+/// Opacity of the stats overlay and its sparklines
+const STATS_ALPHA: u8 = 180;
+
+/// Blur strength of the D key's depth of field
+const DOF_APERTURE: f32 = 4.0;
+/// Per-symbol chance of a change when the main menu's Mutate perturbs the rule
+const MUTATION_RATE: f32 = 0.05;
+
+/// Sides of each branch's cylinder in OBJ exports
+const OBJ_SEGMENTS_PER_BRANCH: u32 = 8;
+
+/// Fully fogged distance at the lowest and highest Fog Density
+const FOG_FAR_RANGE: (f32, f32) = (200.0, 20.0);
+
+/// Segments labelled with their index around the one picked in step mode
+const STEP_SEGMENT_LABELS: usize = 10;
+
+/// How long each iteration of a growth GIF is shown
+const GIF_FRAME_DELAY_MS: u32 = 500;
+
+/// NeuQuant sampling speed for GIF frames, 1 (best) to 30 (fastest)
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+/// Generation stops once the string passes this many bytes, unless the rule sets `max_memory_mb`
+const DEFAULT_MAX_STRING_BYTES: usize = 100_000_000;
+
+/// First private-use character, which `LSystem::module_node` numbers the declared modules from
+const MODULE_NODE_BASE: u32 = 0xE000;
+
+/// Tropism strength for environments that don't set `tropism_weight`
+const DEFAULT_TROPISM_WEIGHT: f32 = 0.2;
+
+/// Frames of FPS and render time history, also the sparklines' width in pixels
+const METRICS_HISTORY: usize = 120;
+
+/// Seconds a `Saved` title stays up before reverting to the rule info
+const SAVED_TITLE_SECONDS: f32 = 2.0;
+
+/// Seconds between switching the title from rule info to timings and back
+const TITLE_CYCLE_SECONDS: f32 = 3.0;
+
+fn update_window_title(window: &mut Window, rule: &LSystemRule, status: &AppStatus) {
+    let title = match status {
+        AppStatus::Ready { chars } => format!("3D L-Systems | {} | {} chars", rule.name, chars),
+        AppStatus::Generating { iteration, total } => {
+            format!("3D L-Systems | Generating... (iteration {}/{})", iteration, total)
+        }
+        AppStatus::Saved(file_name) => format!("3D L-Systems | Saved {}", file_name),
+        AppStatus::Timing { render_time, fps } => {
+            format!("3D L-Systems | Render: {} ms | FPS: {:.0}", render_time.as_millis(), fps)
+        }
+    };
+    window.set_title(&title);
+}
+
+/// An export's file and whether writing it worked, for `report_export`
+type ExportOutcome = (std::path::PathBuf, Result<(), Box<dyn std::error::Error>>);
+
+/// Announces a finished export in the console, a toast and the window title, or reports its error
+fn report_export(path: &std::path::Path, result: Result<(), Box<dyn std::error::Error>>, rule: &LSystemRule,
+                 window: &mut Window, toast: &mut Toast, saved_title_remaining: &mut f32) {
+    match result {
+        Ok(_) => {
+            let file_name = path.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            println!("Exported {}", path.display());
+            toast.show(&format!("Exported {}", file_name));
+            update_window_title(window, rule, &AppStatus::Saved(file_name));
+            *saved_title_remaining = SAVED_TITLE_SECONDS;
+        }
+        Err(e) => {
+            eprintln!("Error exporting {}: {}", path.display(), e);
+            toast.show("Export failed");
+        }
+    }
+}
+
+/// Red banner along the top of the window while the tree is cut short by the memory limit
+fn render_truncation_warning(buffer: &mut [u32], width: usize, height: usize, iteration: u32, total: u32) {
+    let message = format!("String too large: stopped at iteration {} of {}", iteration, total);
+    let banner_width = Renderer::measure_text(&message, 1).0 + 20;
+    let banner_x = width.saturating_sub(banner_width) / 2;
+    for py in 10..(10 + 28).min(height) {
+        for px in banner_x..(banner_x + banner_width).min(width) {
+            buffer[py * width + px] = 0x801010;
+        }
+    }
+    Renderer::render_text_at_screen(buffer, width, height, (banner_x + 10, 18), &message, 0xFFFFFF, 1);
+}
+
+/// Banner along the bottom of the window while stepping through the iterations one at a time,
+/// with the segment picked for labelling out of `segments`
+fn render_step_mode_status(buffer: &mut [u32], width: usize, height: usize, iteration: u32, total: u32,
+                           segment: usize, segments: usize) {
+    let message = format!("Iteration: {} / {}  Segment: {} / {}  [Space=next, Backspace=prev, ,/.=segment]",
+                          iteration, total, segment, segments);
+    let banner_width = Renderer::measure_text(&message, 1).0 + 20;
+    let banner_x = width.saturating_sub(banner_width) / 2;
+    let banner_y = height.saturating_sub(38);
+    for py in banner_y..(banner_y + 28).min(height) {
+        for px in banner_x..(banner_x + banner_width).min(width) {
+            buffer[py * width + px] = 0x202040;
+        }
+    }
+    Renderer::render_text_at_screen(buffer, width, height, (banner_x + 10, banner_y + 8), &message, 0xFFFFFF, 1);
+}
+
+/// Applies the renderer settings a rule asks for; ones it leaves unset keep their current value,
+/// except the background, which returns to the default
+fn apply_render_hints(rule: &LSystemRule, renderer: &mut Renderer, gui: &mut Gui, show_world_axes: &mut bool) {
+    let hints = rule.render_hints.clone().unwrap_or_default();
+    for name in hints.unsupported() {
+        eprintln!("Render hint {} in {} is not supported and was ignored", name, rule.name);
+    }
+    if let Some(scale) = hints.line_width_scale {
+        renderer.set_line_width_multiplier(scale);
+    }
+    if let Some(show_axes) = hints.show_axes {
+        *show_world_axes = show_axes;
+    }
+    renderer.set_antialiased(hints.antialiased.unwrap_or(true));
+    renderer.clear_with_solid_background_from_rule(rule);
+    gui.load_hints_from_rule(rule);
+}
+
+/// What fog fades to: the skybox horizon, or the rule's background color with the skybox off
+fn fog_color(rule: &LSystemRule, skybox_enabled: bool) -> Vec3 {
+    if skybox_enabled {
+        rule.skybox_config().horizon
+    } else {
+        rule.render_hints.as_ref().and_then(|hints| hints.background_color).map_or(Vec3::ZERO, Vec3::from)
+    }
+}
+
+/// Where fog is total, from the GUI's Fog Density: 0 is off, and 0 to 1 brings the distance at
+/// which lines are fully fogged in from `FOG_FAR_RANGE.0` to `FOG_FAR_RANGE.1`
+fn fog_far(gui: &Gui) -> Option<f32> {
+    gui.get_parameter("Fog Density")
+        .filter(|&density| density > 0.0)
+        .map(|density| FOG_FAR_RANGE.0 + (FOG_FAR_RANGE.1 - FOG_FAR_RANGE.0) * density)
+}
+
+/// Whether the GUI's Fog Mode fogs the finished frame by depth (1) rather than line colors per vertex (0)
+fn per_pixel_fog(gui: &Gui) -> bool {
+    gui.get_parameter("Fog Mode").is_some_and(|mode| mode.round() as u32 == 1)
+}
+
+/// Fog, bloom and tone mapping as set in the GUI's post-processing controls. Fog is only a pass
+/// in per-pixel Fog Mode; otherwise the renderer applies it per vertex (see `apply_fog_density`)
+fn build_post_process_pipeline(gui: &Gui, rule: &LSystemRule, skybox_enabled: bool) -> PostProcessPipeline {
+    let mut pipeline = PostProcessPipeline::new();
+    if let Some(far) = fog_far(gui).filter(|_| per_pixel_fog(gui)) {
+        pipeline.add(FogPass { near: 0.0, far, color: fog_color(rule, skybox_enabled) });
+    }
+    if let Some(intensity) = gui.get_parameter("Bloom").filter(|&intensity| intensity > 0.0) {
+        pipeline.add(BloomPass { threshold: 0.8, radius: 4, intensity });
+    }
+    match gui.get_parameter("Tone Mapping").map(|mode| mode.round() as u32) {
+        Some(1) => pipeline.add(ToneMappingPass { mode: ToneMappingMode::Reinhard }),
+        Some(2) => pipeline.add(ToneMappingPass { mode: ToneMappingMode::Aces }),
+        _ => {}
+    }
+    pipeline
+}
+
+/// The renderer's per-vertex fog from the GUI's Fog Density, unless Fog Mode leaves fog to the
+/// post-processing pipeline
+fn apply_fog_density(gui: &Gui, rule: &LSystemRule, skybox_enabled: bool, renderer: &mut Renderer) {
+    match fog_far(gui).filter(|_| !per_pixel_fog(gui)) {
+        Some(far) => renderer.set_fog(fog_color(rule, skybox_enabled), 0.0, far),
+        None => renderer.disable_fog(),
+    }
+}
+
+/// Moves the camera to a rule's view preset, whose angles are in degrees
+fn apply_view_preset(camera: &mut Camera, preset: &ViewPreset) {
+    camera.set_yaw_pitch_distance(preset.yaw.to_radians(), preset.pitch.to_radians(), preset.distance);
+    camera.update_from_angles();
+}
+
+/// Opens a rule's reference link, or prints it when built without browser support
+fn open_url(url: &str, toast: &mut Toast) {
+    #[cfg(feature = "browser-links")]
+    match open::that(url) {
+        Ok(_) => toast.show("Opened in browser"),
+        Err(e) => toast.show(&format!("Could not open browser: {}", e)),
+    }
+
+    #[cfg(not(feature = "browser-links"))]
+    {
+        println!("{}", url);
+        toast.show("Link printed to the console");
+    }
+}
+
+/// Main menu focus: carries out a picked item once the menu has hidden. Exports and Exit are left
+/// to the event loop
+fn handle_main_menu_action(action: MenuAction, loaded: &mut LoadedRule, camera: &mut Camera, menu: &mut Menu,
+                           gui: &mut Gui, editor: &Editor, toast: &mut Toast) {
+    match action {
+        MenuAction::ShowTreeSelection => {
+            if !menu.visible {
+                menu.toggle();
+            }
+        },
+        MenuAction::ShowParameters => {
+            if !gui.visible {
+                gui.toggle();
+            }
+        },
+        MenuAction::EditLSystem => loaded.edit(editor),
+        MenuAction::DuplicateRule => {
+            let copy_path = editor.copy_path_for(&loaded.path);
+            match editor.new_from_existing(&loaded.rule, &copy_path) {
+                Ok(path) => {
+                    if loaded.open(path) {
+                        menu.load_items();
+                        toast.show(&format!("Copied to {}", loaded.path.display()));
+                    }
+                }
+                Err(e) => eprintln!("Error duplicating rule: {}", e),
+            }
+        },
+        MenuAction::ReloadLSystem => {
+            if loaded.reload_from_disk() {
+                println!("L-system reloaded");
+            }
+        },
+        MenuAction::MutateRule => {
+            let mutated = loaded.lsystem.mutate(MUTATION_RATE, &mut rand::thread_rng());
+            loaded.reload(mutated);
+            toast.show("Mutated; R reloads the original");
+        },
+        MenuAction::LoadCameraView(name) => {
+            if camera.load_named_view(&name) {
+                toast.show(&format!("{} loaded", name));
+            }
+        },
+        MenuAction::ApplyViewPreset(index) => {
+            if let Some(preset) = loaded.rule.view_presets.as_ref().and_then(|presets| presets.get(index)) {
+                apply_view_preset(camera, preset);
+                toast.show(&preset.name);
+            }
+        },
+        MenuAction::LoadRule(path) => {
+            loaded.open(path);
+        },
+        MenuAction::ExportPPM | MenuAction::ExportSVG | MenuAction::ExportOBJ | MenuAction::ExportFrames
+        | MenuAction::ExportSnapshot | MenuAction::Exit => {}
+    }
+}
+
+/// Main menu focus: writes the file for one of the Export items, returning its path and the outcome
+fn handle_export_action(action: &MenuAction, loaded: &LoadedRule, renderer: &Renderer, turtle: &Turtle3D,
+                        camera: &Camera, settings: &ExportSettings) -> ExportOutcome {
+    if let MenuAction::ExportSnapshot = action {
+        let (width, height) = settings.window_size;
+        let options = ExportOptions::new(&loaded.rule.name, "json", width, height);
+        let result = loaded.lsystem.export_to_json_with_generated_string(&options.path, settings.compress_snapshots);
+        return (options.path, result.map_err(Into::into));
+    }
+    let extension = match action {
+        MenuAction::ExportPPM => "ppm",
+        MenuAction::ExportSVG => "svg",
+        MenuAction::ExportOBJ => "obj",
+        _ => "",
+    };
+    let (width, height) = settings.size();
+    let options = ExportOptions::new(&loaded.rule.name, extension, width, height);
+    // Exports render off-screen at the export size; the window keeps its own resolution
+    let mut export_camera = camera.clone();
+    export_camera.set_aspect_ratio(options.width as f32 / options.height.max(1) as f32);
+    let result = match action {
+        MenuAction::ExportPPM => renderer.render_to_offscreen(options.width, options.height, &export_camera)
+            .save_ppm(&options.path),
+        MenuAction::ExportSVG => {
+            // Painting the sky lets the SVG's background match it
+            let mut offscreen = renderer.offscreen_copy(options.width, options.height);
+            offscreen.render_skybox(&export_camera);
+            offscreen.export_svg(&options.path, &export_camera, &loaded.rule.name)
+        }
+        MenuAction::ExportOBJ => renderer.export_obj(&options.path, OBJ_SEGMENTS_PER_BRANCH),
+        _ => export_frame_sequence(&loaded.rule, export_turtle(turtle),
+                                   &export_camera, &options, &settings.headless_limiter).map(|_| ()),
+    };
+    (options.path, result)
+}
+
+/// Writes the exports asked for this frame, now that it has rendered, returning each path and outcome
+fn run_pending_exports(exports: &mut PendingExports, loaded: &LoadedRule, renderer: &mut Renderer, turtle: &Turtle3D,
+                       camera: &Camera, settings: &ExportSettings) -> Vec<ExportOutcome> {
+    let (width, height) = settings.window_size;
+    let mut written = Vec::new();
+    if std::mem::take(&mut exports.svg) {
+        let options = ExportOptions::new(&loaded.rule.name, "svg", width, height);
+        let result = renderer.export_svg(&options.path, camera, &loaded.rule.name);
+        written.push((options.path, result));
+    }
+    if std::mem::take(&mut exports.screenshot) {
+        let options = ExportOptions::in_dir("screenshots", &loaded.rule.name, "png", width, height);
+        let result = renderer.save_png(&options.path);
+        written.push((options.path, result));
+    }
+    if std::mem::take(&mut exports.growth_gif) {
+        let options = ExportOptions::new(&loaded.rule.name, "gif", width, height);
+        // Regrow copies so the live system and turtle keep their state, e.g. a low-memory early
+        // stop or step mode's checkpoints
+        let result = loaded.lsystem.clone().render_growth_gif(renderer, &mut export_turtle(turtle),
+                                                              camera, &options.path, settings.gif_frame_delay_ms);
+        written.push((options.path, result));
+    }
+    written
+}
+
+/// Keys that show and hide overlays whatever has focus: Tab the rule menu, G the GUI, F3 the stats,
+/// I the info panel and Ctrl+A the world axes
+fn handle_overlay_keys(keys: &Keys, menu: &mut Menu, gui: &mut Gui, stats_overlay: &mut StatsOverlay,
+                       info_panel: &mut InfoPanel, show_world_axes: &mut bool) {
+    if keys.pressed(Key::Tab) && !keys.wizard_active && !keys.widget_focused {
+        println!("Tab key detected");
+        menu.toggle();
+    }
+    if keys.pressed(Key::G) && keys.free() {
+        gui.toggle();
+    }
+    if keys.pressed(Key::F3) {
+        stats_overlay.toggle();
+    }
+    if keys.pressed(Key::I) && keys.free() {
+        info_panel.toggle();
+    }
+    if keys.ctrl_down && keys.pressed(Key::A) && !keys.wizard_active {
+        *show_world_axes = !*show_world_axes;
+    }
+}
+
+/// Scene focus: D, A, F, N and L switch view and render settings, and S, P and Ctrl+G ask for exports
+fn handle_scene_keys(keys: &Keys, camera: &mut Camera, renderer: &mut Renderer, gui: &Gui, toast: &mut Toast,
+                     exports: &mut PendingExports) {
+    // D toggles depth of field, focused where the GUI's Focus Distance says; Ctrl+D is left free
+    if keys.pressed(Key::D) && keys.scene() {
+        if renderer.dof_enabled() {
+            renderer.disable_dof();
+            toast.show("Depth of field off");
+        } else {
+            renderer.enable_dof(gui.get_parameter("Focus Distance").unwrap_or(camera.distance), DOF_APERTURE);
+            toast.show("Depth of field on");
+        }
+    }
+
+    // S exports the view as SVG once this frame has rendered; in the main menu S picks Vector Export instead
+    if keys.pressed(Key::S) && keys.scene() {
+        exports.svg = true;
+    }
+
+    // A turns the camera around the tree by itself until pressed again; Ctrl+A toggles the axes
+    if keys.pressed(Key::A) && keys.scene() {
+        camera.auto_orbit = !camera.auto_orbit;
+        toast.show(if camera.auto_orbit { "Auto-orbit on" } else { "Auto-orbit off" });
+    }
+
+    // F frames the tree again after zooming or orbiting away; in the main menu F picks its own item
+    if keys.pressed(Key::F) && keys.scene() && camera.reset_to_fit() {
+        toast.show("View fitted to the tree");
+    }
+
+    // P saves a screenshot once this frame has rendered; in the main menu P picks Screenshot instead
+    if keys.pressed(Key::P) && keys.scene() {
+        exports.screenshot = true;
+    }
+
+    if keys.pressed(Key::N) && keys.free() {
+        let (mode, label) = match renderer.render_mode() {
+            RenderMode::Lines => (RenderMode::Cones, "Cone branches"),
+            RenderMode::Cones => (RenderMode::Lines, "Line branches"),
+        };
+        renderer.set_render_mode(mode);
+        toast.show(label);
+    }
+
+    // L switches the HDR buffer, with its bloom and tone mapping, on and off
+    if keys.pressed(Key::L) && keys.scene() {
+        if renderer.is_hdr_enabled() {
+            renderer.disable_hdr();
+            toast.show("HDR off");
+        } else {
+            renderer.enable_hdr();
+            toast.show("HDR on");
+        }
+    }
+
+    if keys.ctrl_down && keys.pressed(Key::G) && !keys.wizard_active && !keys.main_menu_visible {
+        exports.growth_gif = true;
+    }
+}
+
+/// Keys acting on the open rule: U opens its link while the info panel shows, E edits it, R reloads
+/// it as a change on disk does, and ? (Shift+/) prints its string's statistics
+fn handle_rule_keys(keys: &Keys, loaded: &mut LoadedRule, editor: &Editor, info_panel_visible: bool,
+                    string_stats: &LSystemStats, file_changed: bool, toast: &mut Toast) {
+    if keys.pressed(Key::U) && info_panel_visible && !keys.main_menu_visible && keys.free()
+        && let Some(url) = &loaded.rule.description_url {
+        open_url(url, toast);
+    }
+    if keys.pressed(Key::E) && keys.free() {
+        loaded.edit(editor);
+    }
+    if (file_changed || (keys.pressed(Key::R) && keys.free())) && loaded.reload_from_disk() {
+        println!("L-system reloaded");
+    }
+    if keys.pressed(Key::Slash) && keys.shift_down && !keys.main_menu_visible && keys.free() {
+        println!("Statistics for {}:", loaded.rule.name);
+        print!("{}", string_stats.report());
+    }
+}
+
+/// Ctrl+1-9: first press saves a camera view, later presses load it
+fn handle_camera_view_keys(keys: &Keys, camera: &mut Camera, gui: &Gui, main_menu: &mut MainMenu,
+                           views_path: &std::path::Path, toast: &mut Toast) {
+    if !keys.ctrl_down || keys.wizard_active || (gui.visible && gui.has_focus(keys.window)) {
+        return;
+    }
+    let view_keys = [Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5,
+                     Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+    for (i, key) in view_keys.iter().enumerate() {
+        if keys.pressed(*key) {
+            let name = format!("View {}", i + 1);
+            if camera.load_named_view(&name) {
+                toast.show(&format!("{} loaded", name));
+            } else {
+                camera.save_named_view(&name);
+                if let Err(e) = camera.save_views_to_file(views_path) {
+                    eprintln!("Error saving camera views: {}", e);
+                }
+                main_menu.camera_views = camera.named_view_names();
+                toast.show(&format!("{} saved", name));
+            }
+        }
+    }
+}
+
+/// Scene focus: T turns step mode on and off, then Space and Backspace grow and shrink the tree an
+/// iteration at a time up to `iterations`, and , and . move the labelled segment. Returns whether
+/// the tree needs regrowing
+fn handle_step_keys(keys: &Keys, step: &mut StepMode, iterations: u32, toast: &mut Toast) -> bool {
+    if !keys.scene() {
+        return false;
+    }
+    let mut regrow = false;
+    if keys.pressed(Key::T) {
+        step.enabled = !step.enabled;
+        step.iteration = 0;
+        step.segment = 0;
+        regrow = true;
+        toast.show(if step.enabled { "Step mode on" } else { "Step mode off" });
+    }
+    if step.enabled {
+        if keys.repeated(Key::Space) && step.iteration < iterations {
+            step.iteration += 1;
+            regrow = true;
+        }
+        if keys.repeated(Key::Backspace) && step.iteration > 0 {
+            step.iteration -= 1;
+            regrow = true;
+        }
+        if keys.repeated(Key::Period) {
+            step.segment += 1;
+        }
+        if keys.repeated(Key::Comma) {
+            step.segment = step.segment.saturating_sub(1);
+        }
+    }
+    regrow
+}
+
+/// GUI history: Ctrl+Z undoes a slider change, Ctrl+Y or Ctrl+Shift+Z redoes it and Ctrl+Backspace
+/// puts every slider back to its default. Returns whether any slider moved
+fn handle_gui_history_keys(keys: &Keys, gui: &mut Gui) -> bool {
+    if !keys.ctrl_down || !keys.free() {
+        false
+    } else if keys.pressed(Key::Z) {
+        if keys.shift_down { gui.redo() } else { gui.undo() }
+    } else if keys.pressed(Key::Y) {
+        gui.redo()
+    } else if keys.pressed(Key::Backspace) {
+        gui.reset_to_defaults();
+        true
+    } else {
+        false
+    }
+}
+
+fn main() {
+    let matches = Command::new("RustL-System")
+        .version("0.1.0")
+        .author("Christian")
+        .about("3D L-System generator with interactive menu and vim integration")
+        .arg(
+            Arg::new("rule-file")
+                .short('r')
+                .long("rule")
+                .value_name("FILE")
+                .help("JSON file containing L-System rules (defaults to the last rule used)"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .value_parser(["json", "toml"])
+                .help("Syntax of the --rule file (default: from its extension, JSON unless it ends in .toml)"),
+        )
+        .arg(
+            Arg::new("no-skybox")
+                .long("no-skybox")
+                .help("Disable the skybox background (useful for performance benchmarks)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("duplicate-rule")
+                .long("duplicate-rule")
+                .value_name("DEST")
+                .help("Copy the rule to DEST (numbered if DEST exists) and exit"),
+        )
+        .arg(
+            Arg::new("load-snapshot")
+                .long("load-snapshot")
+                .value_name("FILE")
+                .help("Start from a snapshot exported with the Snapshot (JSON) menu item instead of generating"),
+        )
+        .arg(
+            Arg::new("compress-snapshots")
+                .long("compress-snapshots")
+                .help("Run-length encode the generated string in exported snapshots")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("open-url")
+                .long("open-url")
+                .help("Print the rule's reference URL and exit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hdr")
+                .long("hdr")
+                .help("Render with an HDR color buffer, bloom and tone mapping")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preserve-camera")
+                .long("preserve-camera")
+                .help("Only center the camera on the first tree, not when opening other rules or after changes")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-watch")
+                .long("no-watch")
+                .help("Don't reload the rule automatically when its file changes; press R instead")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("Print the folded generated string after every regeneration, and when it is unchanged")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("overdraw")
+                .long("overdraw")
+                .help("Count writes per pixel and show the average overdraw in the stats overlay")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flat-line-width")
+                .long("flat-line-width")
+                .help("Draw every branch at its width in pixels instead of shrinking distant branches")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("DIR")
+                .help("Save every rendered frame as DIR/frame_NNNNN.png. Assemble a video with: \
+                       ffmpeg -r 30 -i frame_%05d.png -c:v libx264 output.mp4"),
+        )
+        .arg(
+            Arg::new("record-fps")
+                .long("record-fps")
+                .value_name("N")
+                .help("Frames per second to save when recording")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("record-ppm")
+                .long("record-ppm")
+                .help("Record binary PPM frames instead of PNG")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("export-width")
+                .long("export-width")
+                .value_name("W")
+                .value_parser(clap::value_parser!(usize))
+                .help("Width in pixels of exported images, SVGs and frames (default: window width)"),
+        )
+        .arg(
+            Arg::new("export-height")
+                .long("export-height")
+                .value_name("H")
+                .value_parser(clap::value_parser!(usize))
+                .help("Height in pixels of exported images, SVGs and frames (default: window height)"),
+        )
+        .arg(
+            Arg::new("gif-frame-delay")
+                .long("gif-frame-delay")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u32))
+                .help("Milliseconds each iteration is shown in growth GIFs (default: 500)"),
+        )
+        .arg(
+            Arg::new("export-svg-animated")
+                .long("export-svg-animated")
+                .value_name("FILE")
+                .help("Export the growth of the L-system as an animated SVG and exit"),
+        )
+        .arg(
+            Arg::new("anim-frames")
+                .long("anim-frames")
+                .value_name("N")
+                .help("Number of growth iterations to include in the animated SVG")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("anim-fps")
+                .long("anim-fps")
+                .value_name("F")
+                .help("Playback rate of the animated SVG in frames per second")
+                .value_parser(clap::value_parser!(f32))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("ascii")
+                .long("ascii")
+                .help("Print the L-system to the terminal as ASCII art and exit (colored when COLORTERM=truecolor)")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ascii-width")
+                .long("ascii-width")
+                .value_name("COLS")
+                .help("Columns of ASCII art to print")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("80"),
+        )
+        .arg(
+            Arg::new("ascii-height")
+                .long("ascii-height")
+                .value_name("ROWS")
+                .help("Rows of ASCII art to print")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("40"),
+        )
+        .arg(
+            Arg::new("fps")
+                .long("fps")
+                .value_name("N")
+                .help("Frame rate cap for the window; 0 for unlimited")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("headless-fps")
+                .long("headless-fps")
+                .value_name("N")
+                .help("Frame rate cap for frame sequence and animated SVG exports; 0 (the default) for unlimited")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed for rules with stochastic productions; by default each tree gets a random seed")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("compute-fractal-dim")
+                .long("compute-fractal-dim")
+                .help("Estimate the box-counting dimension of each generated tree and show it in the stats overlay")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("low-memory")
+                .long("low-memory")
+                .help("Stop expanding once the string reaches 5M characters instead of running every iteration")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .subcommand(
+            Command::new("convert")
+                .about("Rewrite a rule file as JSON or TOML, by the output file's extension")
+                .arg(Arg::new("input").value_name("INPUT").required(true))
+                .arg(Arg::new("output").value_name("OUTPUT").required(true)),
+        )
+        .subcommand(
+            Command::new("render")
+                .about("Render a rule to a PNG without opening a window, e.g. for scripts and CI")
+                .arg(Arg::new("rule").long("rule").value_name("FILE").required(true).help("Rule file to render"))
+                .arg(Arg::new("output").long("output").short('o').value_name("FILE").required(true).help("PNG to write"))
+                .arg(Arg::new("width").long("width").value_name("W").value_parser(clap::value_parser!(usize))
+                    .help("Image width in pixels (default: the window width)"))
+                .arg(Arg::new("height").long("height").value_name("H").value_parser(clap::value_parser!(usize))
+                    .help("Image height in pixels (default: the window height)"))
+                .arg(Arg::new("yaw").long("yaw").value_name("RADIANS").value_parser(clap::value_parser!(f32))
+                    .allow_negative_numbers(true).default_value("0"))
+                .arg(Arg::new("pitch").long("pitch").value_name("RADIANS").value_parser(clap::value_parser!(f32))
+                    .allow_negative_numbers(true).default_value("-0.3"))
+                .arg(Arg::new("iterations").long("iterations").value_name("N").value_parser(clap::value_parser!(u32))
+                    .help("Iterations to grow, instead of the rule's own")),
+        )
+        .subcommand(
+            Command::new("generate")
+                .about("Print a rule's generated string")
+                .arg(Arg::new("rule").long("rule").value_name("FILE").required(true).help("Rule file to grow"))
+                .arg(Arg::new("iterations").long("iterations").value_name("N").value_parser(clap::value_parser!(u32))
+                    .help("Iterations to grow, instead of the rule's own")),
+        )
+        .subcommand(
+            Command::new("iterations")
+                .about("Print the string after each iteration of a rule, one per line")
+                .arg(Arg::new("rule").long("rule").value_name("FILE").required(true).help("Rule file to grow")),
+        )
+        .get_matches();
+
+    if let Some(convert) = matches.subcommand_matches("convert") {
+        let input = convert.get_one::<String>("input").unwrap();
+        let output = std::path::Path::new(convert.get_one::<String>("output").unwrap());
+        let result = load_rule_from_file(input).map_err(|e| e.to_string())
+            .and_then(|rule| rule.save_to_file(output));
+        match result {
+            Ok(()) => println!("{}", output.display()),
+            Err(e) => {
+                eprintln!("Error converting {}: {}", input, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(render) = matches.subcommand_matches("render") {
+        let rule_path = render.get_one::<String>("rule").unwrap();
+        let output = std::path::Path::new(render.get_one::<String>("output").unwrap());
+        let result = load_rule_from_file(rule_path).and_then(|mut rule| {
+            if let Some(&iterations) = render.get_one::<u32>("iterations") {
+                rule.iterations = iterations;
+            }
+            render_rule_to_png(&rule, output, render.get_one::<usize>("width").copied().unwrap_or(WIDTH),
+                               render.get_one::<usize>("height").copied().unwrap_or(HEIGHT),
+                               *render.get_one::<f32>("yaw").unwrap(), *render.get_one::<f32>("pitch").unwrap())
+        });
+        match result {
+            Ok(()) => println!("{}", output.display()),
+            Err(e) => {
+                eprintln!("Error rendering {}: {}", rule_path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(generate) = matches.subcommand_matches("generate") {
+        let rule_path = generate.get_one::<String>("rule").unwrap();
+        match load_rule_from_file(rule_path) {
+            Ok(rule) => match generate.get_one::<u32>("iterations") {
+                Some(&iterations) => println!("{}", LSystem::generate_to_string_at(&rule, iterations)),
+                None => println!("{}", LSystem::generate_to_string(&rule)),
+            },
+            Err(e) => {
+                eprintln!("Error loading {}: {}", rule_path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(iterations) = matches.subcommand_matches("iterations") {
+        let rule_path = iterations.get_one::<String>("rule").unwrap();
+        match load_rule_from_file(rule_path) {
+            Ok(rule) => {
+                for string in LSystem::new(rule).generate_lazy() {
+                    println!("{}", string);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error loading {}: {}", rule_path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut app_state = AppState::load();
+    
+    // An explicit --rule wins; otherwise resume with the last rule from the previous session
+    let rule_file = match matches.get_one::<String>("rule-file") {
+        Some(rule_file) => rule_file.clone(),
+        None if app_state.last_rule_path.exists() => app_state.last_rule_path.to_string_lossy().to_string(),
+        None => {
+            eprintln!("Warning: last rule {} no longer exists, using the built-in cherry blossom", 
+                      app_state.last_rule_path.display());
+            DEFAULT_RULE_PATH.to_string()
+        }
+    };
+    let rule_file = &rule_file;
+    let skybox_enabled = !matches.get_flag("no-skybox");
+    let low_memory = matches.get_flag("low-memory");
+    let preserve_camera = matches.get_flag("preserve-camera");
+    let watch_rule_file = !matches.get_flag("no-watch");
+    let verbose = matches.get_flag("verbose");
+    let export_width = matches.get_one::<usize>("export-width").copied();
+    let export_height = matches.get_one::<usize>("export-height").copied();
+    let gif_frame_delay_ms = matches.get_one::<u32>("gif-frame-delay").copied().unwrap_or(GIF_FRAME_DELAY_MS);
+    let headless_limiter = FrameLimiter::new(*matches.get_one::<u32>("headless-fps").unwrap());
+    let compress_snapshots = matches.get_flag("compress-snapshots");
+    let record_dir = matches.get_one::<String>("record").map(std::path::PathBuf::from);
+    let record_interval = std::time::Duration::from_secs_f32(1.0 / (*matches.get_one::<u32>("record-fps").unwrap()).max(1) as f32);
+    let record_ppm = matches.get_flag("record-ppm");
+    
+    let loaded_rule = match matches.get_one::<String>("format").map(String::as_str) {
+        Some("toml") => load_rule_from_file_toml(rule_file),
+        Some(_) => load_rule_from_file_as(rule_file, RuleFormat::Json),
+        None => load_rule_from_file(rule_file),
+    };
+    let mut current_rule = match loaded_rule {
+        Ok(rule) => rule,
+        Err(_) if rule_file == DEFAULT_RULE_PATH => {
+            serde_json::from_str(EMBEDDED_DEFAULT_RULE).expect("embedded default rule is valid")
+        }
+        Err(e) => {
+            eprintln!("Error loading rule file {}: {}", rule_file, e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(dest) = matches.get_one::<String>("duplicate-rule") {
+        match Editor::duplicate_rule(&current_rule, std::path::Path::new(dest)) {
+            Ok(path) => println!("{}", path.display()),
+            Err(e) => {
+                eprintln!("Error duplicating {}: {}", rule_file, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if matches.get_flag("open-url") {
+        match &current_rule.description_url {
+            Some(url) => println!("{}", url),
+            None => {
+                eprintln!("{} has no description_url", current_rule.name);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(svg_path) = matches.get_one::<String>("export-svg-animated") {
+        let frame_count = *matches.get_one::<u32>("anim-frames").unwrap();
+        let fps = matches.get_one::<f32>("anim-fps").unwrap().max(0.01);
+        
+        let (svg_width, svg_height) = (export_width.unwrap_or(WIDTH), export_height.unwrap_or(HEIGHT));
+        let mut camera = Camera::new(svg_width as f32 / svg_height.max(1) as f32);
+        camera.yaw = app_state.camera_yaw;
+        camera.pitch = app_state.camera_pitch;
+        camera.distance = app_state.camera_distance;
+        camera.update_from_angles();
+        
+        let mut renderer = Renderer::new(svg_width, svg_height);
+        let mut turtle = Turtle3D::new();
+        register_custom_commands(&mut turtle);
+        let mut rule = current_rule.clone();
+        rule.iterations = rule.iterations.max(frame_count);
+        let mut lsystem = LSystem::new(rule);
+        let frames = lsystem.growth_line_frames(&mut renderer, &mut turtle, frame_count, &headless_limiter);
+        
+        let duration = frames.len() as f32 / fps;
+        match renderer.export_svg_animated(&frames, &camera, duration, std::path::Path::new(svg_path)) {
+            Ok(_) => println!("Exported {} frames to {}", frames.len(), svg_path),
+            Err(e) => {
+                eprintln!("Error exporting animated SVG: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if matches.get_flag("ascii") {
+        let cols = (*matches.get_one::<usize>("ascii-width").unwrap()).max(1);
+        let rows = (*matches.get_one::<usize>("ascii-height").unwrap()).max(1);
+        // Terminal cells are about twice as tall as wide, so render 8x16 pixels per cell
+        let (render_width, render_height) = (cols * 8, rows * 16);
+        let mut camera = Camera::new(render_width as f32 / render_height as f32);
+        camera.yaw = app_state.camera_yaw;
+        camera.pitch = app_state.camera_pitch;
+        camera.distance = app_state.camera_distance;
+        
+        let mut renderer = Renderer::new(render_width, render_height);
+        renderer.set_background_color(Some(Vec3::ZERO));
+        renderer.clear();
+        let mut lsystem = LSystem::new(current_rule.clone());
+        lsystem.generate();
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        camera.target = renderer.get_centroid_of_visible_geometry();
+        camera.update_from_angles();
+        if let Some(aabb) = renderer.bounding_box() {
+            camera.auto_adjust_clipping(aabb);
+        }
+        renderer.render(&camera);
+        
+        let truecolor = std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit");
+        if truecolor {
+            print!("{}", renderer.screenshot_as_ansi_art(cols, rows));
+        } else {
+            print!("{}", renderer.screenshot_as_ascii_art(cols, rows));
+        }
+        return;
+    }
+
+    println!("3D L-System Viewer Started");
+    println!("Controls:");
+    println!("  Mouse + Drag: Rotate camera");
+    println!("  Mouse Wheel: Zoom in/out");
+    println!("  Arrow Keys / Page Up / Page Down: Orbit and zoom the camera");
+    println!("  Ctrl+1-9: Save camera view (first press) / load camera view");
+    println!("  Ctrl+A: Toggle world-space axes");
+    println!("  F1: Toggle main menu");
+    println!("  Tab: Toggle tree selection menu");
+    println!("  1-9: Load tree species (1=Sierpinski, 2=Plant, 3=Oak, 4=Pine, 5=Cherry, 6=Maple, 7=Willow, 8=Baobab, 9=Eucalyptus)");
+    println!("  G: Toggle GUI parameter controls");
+    println!("  E: Edit current L-system in vim");
+    println!("  R: Reload current L-system");
+    println!("  A: Toggle auto-orbit");
+    println!("  F: Fit the view to the tree again");
+    println!("  S: Export the current view as SVG");
+    println!("  P: Save a PNG screenshot to screenshots/");
+    println!("  ?: Print statistics about the generated string");
+    println!("  T: Step mode; Space / Backspace grow or shrink one iteration, , / . pick the labelled segment");
+    println!("  Ctrl+G: Export a GIF of the tree growing one iteration per frame");
+    println!("  Ctrl+Z / Ctrl+Y: Undo / redo GUI slider changes");
+    println!("  Ctrl+Backspace: Reset GUI sliders to their defaults");
+    println!("  Escape: Exit");
+
+    let mut width = if app_state.window_width > 0 { app_state.window_width } else { WIDTH };
+    let mut height = if app_state.window_height > 0 { app_state.window_height } else { HEIGHT };
+    
+    let mut window = Window::new(
+        "3D L-System Viewer - Interactive",
+        width,
+        height,
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+
+    // FrameLimiter paces the loop; minifb's own limiting varies between platforms
+    window.set_target_fps(0);
+    let frame_limiter = FrameLimiter::new(*matches.get_one::<u32>("fps").unwrap());
+    if let Some((x, y)) = app_state.window_position {
+        window.set_position(x, y);
+    }
+
+    let mut camera = Camera::new(width as f32 / height as f32);
+    camera.yaw = app_state.camera_yaw;
+    camera.pitch = app_state.camera_pitch;
+    camera.distance = app_state.camera_distance;
+    camera.update_from_angles();
+    let mut renderer = Renderer::new(width, height);
+    if matches.get_flag("hdr") {
+        renderer.enable_hdr();
+    }
+    if matches.get_flag("overdraw") {
+        renderer.enable_overdraw_tracking();
+    }
+    renderer.set_perspective_line_width(!matches.get_flag("flat-line-width"));
+    let mut turtle = Turtle3D::new();
+    register_custom_commands(&mut turtle);
+    let mut menu = Menu::new();
+    let mut main_menu = MainMenu::new();
+    let editor = Editor::new();
+    let mut gui = Gui::new();
+    renderer.auto_scale_line_width(width, WIDTH);
+    gui.set_parameter("Line Width Scale", renderer.line_width_multiplier());
+    let mut toast = Toast::new();
+    let mut info_panel = InfoPanel::new();
+    let mut stats_overlay = StatsOverlay::new();
+    // Last METRICS_HISTORY frames for the stats sparklines; `history_next` is the oldest entry once full
+    let mut fps_history = [0.0f32; METRICS_HISTORY];
+    let mut render_time_history = [0.0f32; METRICS_HISTORY];
+    let mut history_next = 0;
+    let mut history_len = 0;
+    
+    let mut lsystem = LSystem::new(current_rule.clone());
+    let mut rule_watcher = OpenFileWatcher::default();
+    let mut string_stats = LSystemStats::default();
+    let mut step = StepMode::default();
+    if let Some(path) = matches.get_one::<String>("load-snapshot") {
+        // The snapshot's string counts as fully generated, so the first regeneration keeps it
+        match LSystem::from_snapshot(std::path::Path::new(path)) {
+            Ok(snapshot) => {
+                current_rule = snapshot.rule.clone();
+                lsystem = snapshot;
+                println!("Loaded snapshot {}", path);
+            }
+            Err(e) => eprintln!("Error loading snapshot: {}", e),
+        }
+    }
+    let mut loaded = LoadedRule {
+        rule: current_rule,
+        path: std::path::PathBuf::from(rule_file),
+        lsystem,
+        needs_regeneration: true,
+        hints_pending: false,
+    };
+    let mut views_path = std::path::PathBuf::new();
+    
+    let mut mouse_pressed = false;
+    let mut last_mouse_vec = Vec2::ZERO;
+    let mut context_menu = ContextMenu::new();
+    // Where the right button went down, to tell a click from a drag
+    let mut right_press_at: Option<Vec2> = None;
+    // The left click that picked a context menu item shouldn't also start rotating the camera
+    let mut left_click_consumed = false;
+    let mut orbit_pivot: Option<Vec3> = None;
+    let mut show_world_axes = false;
+    // Clip planes follow the tree until the user sets them in the GUI; the values the GUI
+    // showed last tell a change to them apart from other GUI changes
+    let mut auto_clipping = true;
+    let mut shown_clipping = (gui.get_parameter("Near Clip"), gui.get_parameter("Far Clip"));
+    let mut last_frame_time = std::time::Instant::now();
+    let mut last_record_time: Option<std::time::Instant> = None;
+    let mut record_frame = 0u32;
+    let mut exports = PendingExports::default();
+    let mut export_settings = ExportSettings {
+        window_size: (width, height),
+        size_override: (export_width, export_height),
+        gif_frame_delay_ms,
+        compress_snapshots,
+        headless_limiter,
+    };
+    let mut saved_title_remaining = 0.0f32;
+    let mut title_cycle_time = 0.0f32;
+    let mut generation_time = std::time::Duration::ZERO;
+    let mut last_string_checksum = 0u64;
+    let mut unexpanded_full_length: Option<u64> = None;
+    let compute_fractal_dim = matches.get_flag("compute-fractal-dim");
+    let seed_override = matches.get_one::<u64>("seed").copied();
+    let mut fractal_dimension: Option<f32> = None;
+    // Depends only on the rule, so it is found when the system regenerates rather than every frame
+    let mut longest_chain = 0;
+    let mut post_process = PostProcessPipeline::new();
+    let mut symbol_table: Vec<(char, SymbolStatus)> = Vec::new();
+    let mut camera_centered = false;
+    let app_start_time = std::time::Instant::now();
+
+    while window.is_open() {
+        let now = std::time::Instant::now();
+        let frame_time = now - last_frame_time;
+        let dt = frame_time.as_secs_f32();
+        last_frame_time = now;
+        stats_overlay.record_frame(frame_time);
+        fps_history[history_next] = stats_overlay.fps();
+        render_time_history[history_next] = renderer.get_render_time().as_secs_f32() * 1000.0;
+        history_next = (history_next + 1) % METRICS_HISTORY;
+        history_len = (history_len + 1).min(METRICS_HISTORY);
+        
+        // Follow the window when it is resized, rebuilding the renderer's buffers at the new size
+        let window_size = window.get_size();
+        if window_size != (width, height) && window_size.0 > 0 && window_size.1 > 0 {
+            (width, height) = window_size;
+            let overdraw_tracking = renderer.overdraw_tracking_enabled();
+            renderer = renderer.offscreen_copy(width, height);
+            if overdraw_tracking {
+                renderer.enable_overdraw_tracking();
+            }
+            camera.set_aspect_ratio(width as f32 / height as f32);
+            export_settings.window_size = (width, height);
+        }
+        
+        // Keys are typed into the new rule wizard while it is open
+        let wizard_active = menu.wizard.is_some();
+        // and into the rule menu's search while that is open, where Escape clears the search or closes the menu,
+        // or into a focused GUI control, where Escape releases the focus
+        let widget_focused = gui.is_any_widget_focused();
+        let text_input_active = menu.visible || widget_focused;
+        // An open context menu takes Escape to close itself, and the wizard takes it to step back
+        if window.is_key_pressed(Key::Escape, minifb::KeyRepeat::No) && !text_input_active && !context_menu.visible && !wizard_active {
+            break;
+        }
+        
+        // Handle main menu input - use F1 key (Menu)
+        if window.is_key_pressed(Key::F1, minifb::KeyRepeat::No) && !wizard_active && !widget_focused {
+            main_menu.toggle();
+        }
+        
+        // Handle main menu actions
+        let main_menu_action = if wizard_active || widget_focused { None } else { main_menu.handle_input(&window) };
+        match main_menu_action {
+            Some(MenuAction::Exit) => break,
+            Some(action @ (MenuAction::ExportPPM | MenuAction::ExportSVG | MenuAction::ExportOBJ
+                           | MenuAction::ExportFrames | MenuAction::ExportSnapshot)) => {
+                let (path, result) = handle_export_action(&action, &loaded, &renderer, &turtle, &camera, &export_settings);
+                report_export(&path, result, &loaded.rule, &mut window, &mut toast, &mut saved_title_remaining);
+                main_menu.show_main();
+            }
+            Some(action) => {
+                main_menu.hide();
+                handle_main_menu_action(action, &mut loaded, &mut camera, &mut menu, &mut gui, &editor, &mut toast);
+            }
+            None => {}
+        }
+        
+        let keys = Keys {
+            window: &window,
+            ctrl_down: window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl),
+            shift_down: window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift),
+            wizard_active,
+            widget_focused,
+            text_input_active,
+            main_menu_visible: main_menu.is_visible(),
+        };
+        handle_overlay_keys(&keys, &mut menu, &mut gui, &mut stats_overlay, &mut info_panel, &mut show_world_axes);
+        handle_scene_keys(&keys, &mut camera, &mut renderer, &gui, &mut toast, &mut exports);
+        let file_changed = watch_rule_file && rule_watcher.changed(&loaded.path);
+        handle_rule_keys(&keys, &mut loaded, &editor, info_panel.visible, &string_stats, file_changed, &mut toast);
+        handle_camera_view_keys(&keys, &mut camera, &gui, &mut main_menu, &views_path, &mut toast);
+        
+        // Handle menu input (number hotkeys are reserved for camera views while Ctrl is held)
+        let selected_rule = if keys.ctrl_down || widget_focused { None } else { menu.handle_input(&window) };
+        if let Some(selected_file) = selected_rule {
+            loaded.open(selected_file);
+        }
+        
+        // Right-click (without dragging) opens the camera context menu over the scene
+        let left_down = window.get_mouse_down(minifb::MouseButton::Left);
+        let right_down = window.get_mouse_down(minifb::MouseButton::Right);
+        if !left_down {
+            left_click_consumed = false;
+        }
+        if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
+            let mouse_vec = Vec2::new(mouse_pos.0, mouse_pos.1);
+            if context_menu.visible {
+                let clicked = (left_down && !mouse_pressed && !left_click_consumed) || (right_down && right_press_at.is_none());
+                if left_down && !mouse_pressed && !left_click_consumed {
+                    left_click_consumed = true;
+                    let picked = context_menu.item_at(mouse_pos.0, mouse_pos.1).map(|i| context_menu.items[i]);
+                    match picked {
+                        Some("Fly to here") => {
+                            if let Some(point) = context_menu.picked_point {
+                                camera.auto_center(point);
+                            }
+                        }
+                        Some("Reset View") => {
+                            camera.set_yaw_pitch_distance(0.0, -0.3, 10.0);
+                            camera.target = renderer.get_centroid_of_visible_geometry();
+                            camera.update_from_angles();
+                        }
+                        Some("Top View") => {
+                            camera.set_yaw_pitch_distance(camera.yaw, std::f32::consts::FRAC_PI_2, camera.distance);
+                            camera.update_from_angles();
+                        }
+                        Some("Front View") => {
+                            camera.set_yaw_pitch_distance(std::f32::consts::FRAC_PI_2, 0.0, camera.distance);
+                            camera.update_from_angles();
+                        }
+                        // Saved like P, once the menu is gone from the frame
+                        Some("Screenshot") => exports.screenshot = true,
+                        Some("Fit Scene") => {
+                            if let Some((min, max)) = loaded.lsystem.compute_bounds() {
+                                camera.fit_to_bounds(min, max);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let strayed = context_menu.distance_to(mouse_pos.0, mouse_pos.1) > ContextMenu::CLOSE_DISTANCE;
+                if clicked || strayed || !window.get_keys_pressed(minifb::KeyRepeat::No).is_empty() {
+                    context_menu.hide();
+                }
+            }
+            
+            let overlay_open = main_menu.is_visible() || menu.visible || gui.visible || wizard_active;
+            match (right_down, right_press_at) {
+                (true, None) => right_press_at = Some(mouse_vec),
+                (false, Some(pressed_at)) => {
+                    right_press_at = None;
+                    if pressed_at.distance(mouse_vec) < 4.0 && !overlay_open {
+                        let picked_point = renderer.pick_closest_world_point(&camera, mouse_vec);
+                        context_menu.show(mouse_pos.0 as usize, mouse_pos.1 as usize, width, height, picked_point);
+                    }
+                }
+                _ => {}
+            }
+        }
+        
+        // Handle mouse input for camera control
+        if let Some(mouse_pos) = window.get_mouse_pos(minifb::MouseMode::Clamp) {
+            let mouse_vec = Vec2::new(mouse_pos.0, mouse_pos.1);
+            
+            if left_down && !left_click_consumed {
+                if !mouse_pressed {
+                    // Ctrl+drag orbits around the geometry under the cursor
+                    orbit_pivot = if keys.ctrl_down {
+                        renderer.pick_closest_world_point(&camera, mouse_vec)
+                    } else {
+                        None
+                    };
+                    camera.start_rotation(mouse_vec);
+                    mouse_pressed = true;
+                } else if let Some(pivot) = orbit_pivot {
+                    let delta = mouse_vec - last_mouse_vec;
+                    camera.orbit_around_point(pivot, -delta.x * 0.01, -delta.y * 0.01);
+                } else {
+                    camera.update_rotation(mouse_vec);
+                }
+                last_mouse_vec = mouse_vec;
+            } else if mouse_pressed {
+                camera.stop_rotation();
+                mouse_pressed = false;
+            }
+        }
+        
+        // Arrow keys orbit and Page Up/Down zoom, unless a menu or the wizard wants them
+        let app_focus = if wizard_active {
+            Focus::Wizard
+        } else if main_menu.is_visible() {
+            Focus::MainMenu
+        } else if menu.visible {
+            Focus::RuleMenu
+        } else if widget_focused || (gui.visible && gui.has_focus(&window)) {
+            Focus::Gui
+        } else {
+            Focus::Scene
+        };
+        if app_focus == Focus::Scene && !mouse_pressed {
+            camera.keyboard_orbit(window.is_key_down(Key::Left), window.is_key_down(Key::Right),
+                                  window.is_key_down(Key::Up), window.is_key_down(Key::Down));
+            if window.is_key_pressed(Key::PageUp, minifb::KeyRepeat::Yes) {
+                camera.zoom(-1.0);
+            }
+            if window.is_key_pressed(Key::PageDown, minifb::KeyRepeat::Yes) {
+                camera.zoom(1.0);
+            }
+        }
+        
+        // Handle mouse wheel for zoom (the GUI panel scrolls instead while hovered)
+        if let Some(scroll) = window.get_scroll_wheel() {
+            let over_gui = window.get_mouse_pos(minifb::MouseMode::Clamp)
+                .is_some_and(|(x, y)| gui.is_mouse_over_panel(x, y));
+            if !over_gui {
+                camera.zoom(-scroll.1 * 0.1);
+            }
+        }
+        
+        if handle_step_keys(&keys, &mut step, loaded.lsystem.rule.iterations, &mut toast) {
+            loaded.needs_regeneration = true;
+        }
+        let history_moved = handle_gui_history_keys(&keys, &mut gui);
+
+        // Handle GUI input and parameter changes
+        let gui_changed = gui.handle_input(&window) | history_moved;
+        for edit in gui.take_rule_edits() {
+            let result = match &edit {
+                RuleEdit::Replace(symbol, replacement) => loaded.lsystem.replace_rule(*symbol, replacement),
+                RuleEdit::Remove(symbol) => loaded.lsystem.remove_rule(*symbol),
+            };
+            match result {
+                Ok(()) => {
+                    loaded.rule.rules = loaded.lsystem.rule.rules.clone();
+                    loaded.needs_regeneration = true;
+                }
+                Err(e) => toast.show(&e),
+            }
+        }
+        if gui_changed {
+            // Apply GUI parameters to the rule, since draw_3d configures the turtle from it
+            if let Some(angle) = gui.get_parameter("Angle") {
+                loaded.rule.angle = angle;
+                loaded.lsystem.rule.angle = angle;
+            }
+            if let Some(step_length) = gui.get_parameter("Step Length") {
+                loaded.rule.step_length = Some(step_length);
+                loaded.lsystem.rule.step_length = Some(step_length);
+            }
+            if let Some(iterations) = gui.get_parameter("Iterations") {
+                let iterations = iterations.round() as u32;
+                if iterations != loaded.rule.iterations {
+                    loaded.rule.iterations = iterations;
+                    loaded.lsystem = LSystem::new(loaded.rule.clone());
+                }
+            }
+            if let (Some(taper), Some(base), Some(decay)) =
+                (loaded.rule.taper.as_mut(), gui.get_parameter("Trunk Width"), gui.get_parameter("Branch Taper")) {
+                (taper.base, taper.decay) = (base, decay);
+                loaded.lsystem.rule.taper = Some(*taper);
+            }
+            if let Some(scale) = gui.get_parameter("Line Width Scale") {
+                renderer.set_line_width_multiplier(scale);
+            }
+            if let Some(strength) = gui.get_parameter("AO Strength") {
+                turtle.set_ao_strength(strength);
+            }
+            if let Some(speed) = gui.get_parameter("Orbit Speed") {
+                camera.set_orbit_speed(speed);
+            }
+            if let (Some(focus_distance), true) = (gui.get_parameter("Focus Distance"), renderer.dof_enabled()) {
+                renderer.enable_dof(focus_distance, DOF_APERTURE);
+            }
+            let clipping = (gui.get_parameter("Near Clip"), gui.get_parameter("Far Clip"));
+            if clipping != shown_clipping {
+                if let (Some(near), Some(far)) = clipping {
+                    camera.set_clipping_planes(near, far);
+                }
+                auto_clipping = false;
+                shown_clipping = clipping;
+            }
+            loaded.needs_regeneration = true;
+        }
+        
+        // Keyframed rules animate their angle and step length. draw_3d configures the turtle from
+        // loaded.lsystem.rule every frame, so new values only need writing there, not a regeneration
+        if let Some(params) = loaded.rule.animated_params(app_start_time.elapsed().as_secs_f32()) {
+            (loaded.lsystem.rule.angle, loaded.lsystem.rule.step_length) = params;
+        }
+        
+        // Regenerate L-system if needed
+        let regenerated = loaded.needs_regeneration;
+        // Bounds of a regenerated tree; measuring them leaves its metrics on the turtle for the stats
+        let mut tree_bounds = None;
+        let mut opened_rule = false;
+        if loaded.needs_regeneration {
+            if skybox_enabled {
+                renderer.set_skybox(loaded.rule.skybox_config());
+            } else {
+                renderer.disable_skybox();
+            }
+            renderer.set_ground_shadow(loaded.rule.ground_level());
+            if !gui_changed {
+                gui.load_rule(&loaded.rule);
+            }
+            if app_state.last_rule_path != loaded.path {
+                app_state.record_rule(&loaded.path);
+                if let Err(e) = app_state.save() {
+                    eprintln!("Error saving app state: {}", e);
+                }
+            }
+            main_menu.view_presets = loaded.rule.view_presets.iter().flatten()
+                .map(|preset| MainMenuItem {
+                    title: preset.name.clone(),
+                    description: preset.description.clone().unwrap_or_default(),
+                    hotkey: None,
+                })
+                .collect();
+            let rule_views_path = Camera::views_path_for_rule(&loaded.path);
+            if rule_views_path != views_path || loaded.hints_pending {
+                apply_render_hints(&loaded.rule, &mut renderer, &mut gui, &mut show_world_axes);
+                loaded.hints_pending = false;
+            }
+            if rule_views_path != views_path {
+                auto_clipping = true;
+                // The first rule keeps the camera restored from the last session
+                opened_rule = !views_path.as_os_str().is_empty();
+                views_path = rule_views_path;
+                if let Err(e) = camera.load_views_from_file(&views_path) {
+                    eprintln!("Error loading camera views: {}", e);
+                }
+                main_menu.camera_views = camera.named_view_names();
+            }
+            let full_length = loaded.lsystem.current_estimated_full_length();
+            if full_length > LOW_MEMORY_STRING_LENGTH as u64 && !low_memory {
+                eprintln!("Warning: {} expands to {} characters; use --low-memory to stop at {}",
+                          loaded.rule.name, full_length, LOW_MEMORY_STRING_LENGTH);
+            }
+            let generation_start = std::time::Instant::now();
+            if step.enabled {
+                // A new rule may have fewer iterations than the step reached
+                step.iteration = step.iteration.min(loaded.lsystem.rule.iterations);
+                // Every step regrows from the axiom, so stochastic rules need the same seed each time
+                if loaded.lsystem.rule.is_stochastic() {
+                    loaded.lsystem.rule.seed = seed_override.or(loaded.rule.seed).or(loaded.lsystem.last_seed);
+                }
+                loaded.lsystem.generate_n(step.iteration);
+                if loaded.lsystem.rule.is_stochastic() {
+                    loaded.rule.seed = loaded.lsystem.last_seed;
+                }
+            } else if loaded.lsystem.rule.is_stochastic() && !loaded.lsystem.is_complete() {
+                // Keep the seed in the rule so saving it, or regrowing at another depth, gives the same tree
+                match seed_override.or(loaded.rule.seed) {
+                    Some(seed) => loaded.lsystem.generate_with_seed(seed),
+                    None => {
+                        loaded.lsystem.generate_random();
+                    }
+                }
+                loaded.rule.seed = loaded.lsystem.last_seed;
+            } else if low_memory {
+                update_window_title(&mut window, &loaded.rule, &AppStatus::Generating {
+                    iteration: loaded.lsystem.current_iteration() + 1,
+                    total: loaded.lsystem.rule.iterations,
+                });
+                if !loaded.lsystem.generate_incremental(LOW_MEMORY_STRING_LENGTH) {
+                    println!("Stopped at iteration {} of {} to stay under {} characters",
+                             loaded.lsystem.current_iteration(), loaded.lsystem.rule.iterations, LOW_MEMORY_STRING_LENGTH);
+                }
+            } else {
+                while !loaded.lsystem.is_complete() && !loaded.lsystem.truncated {
+                    update_window_title(&mut window, &loaded.rule, &AppStatus::Generating {
+                        iteration: loaded.lsystem.current_iteration() + 1,
+                        total: loaded.lsystem.rule.iterations,
+                    });
+                    loaded.lsystem.iterate();
+                }
+            }
+            generation_time = generation_start.elapsed();
+            unexpanded_full_length = (!loaded.lsystem.is_complete() && !step.enabled).then_some(full_length);
+            tree_bounds = loaded.lsystem.drawn_bounds(&mut turtle);
+            fractal_dimension = compute_fractal_dim.then(|| loaded.lsystem.fractal_dimension(&mut turtle));
+            step.segment_ends = if step.enabled {
+                loaded.lsystem.configure_turtle(&mut turtle);
+                turtle.segment_ends(&loaded.lsystem.current_string)
+            } else {
+                Vec::new()
+            };
+            let string_checksum = loaded.lsystem.string_checksum();
+            if verbose && string_checksum == last_string_checksum {
+                println!("Generated string is unchanged");
+            }
+            last_string_checksum = string_checksum;
+            title_cycle_time = 0.0;
+            update_window_title(&mut window, &loaded.rule, &AppStatus::Ready { chars: loaded.lsystem.current_string.len() });
+            println!("Generated {}: {} characters ({} folded)", loaded.rule.name,
+                     loaded.lsystem.current_string.len(), loaded.lsystem.compressed_length());
+            if verbose {
+                println!("  {}", loaded.lsystem.fold_string(200));
+            }
+            string_stats = loaded.lsystem.statistics();
+            gui.stats_summary = string_stats.summary();
+            symbol_table = loaded.lsystem.validate_symbols().into_iter().collect();
+            symbol_table.sort_by_key(|&(c, _)| c);
+            longest_chain = loaded.lsystem.longest_chain_length();
+            post_process = build_post_process_pipeline(&gui, &loaded.rule, skybox_enabled);
+            apply_fog_density(&gui, &loaded.rule, skybox_enabled, &mut renderer);
+            loaded.needs_regeneration = false;
+        }
+        
+        // Render
+        // Size the world axes from the previous frame's geometry before it is cleared
+        let axes_scale = renderer.bounding_box()
+            .map(|(min, max)| (max - min).length() * 0.25)
+            .unwrap_or(5.0);
+        
+        // Frame a newly opened rule before painting the sky and drawing it, so its first frame
+        // already shows all of it; other regenerations only recenter, keeping the zoom
+        if regenerated && (!camera_centered || !preserve_camera) {
+            if let Some((min, max)) = tree_bounds {
+                if opened_rule {
+                    camera.fit_to_bounds(min, max);
+                } else {
+                    camera.center_on_bounds(min, max);
+                }
+            }
+            camera_centered = true;
+        }
+        // A newly opened rule then turns to its default view
+        if opened_rule
+            && let Some(preset) = loaded.rule.view_presets.iter().flatten().next() {
+            apply_view_preset(&mut camera, preset);
+        }
+        
+        // A new tree repaints everything; otherwise only last frame's footprint needs clearing
+        if regenerated {
+            renderer.clear();
+        } else {
+            renderer.clear_dirty();
+        }
+        renderer.render_skybox(&camera);
+        if show_world_axes {
+            renderer.render_axes_in_world_space(&camera, axes_scale);
+        }
+        turtle.set_view_proj(Some(camera.projection_matrix() * camera.view_matrix()));
+        loaded.lsystem.draw_3d(&mut turtle, &mut renderer);
+        if auto_clipping
+            && let Some(bounds) = renderer.bounding_box() {
+            camera.auto_adjust_clipping(bounds);
+            gui.set_parameter("Near Clip", camera.near);
+            gui.set_parameter("Far Clip", camera.far);
+            gui.set_parameter_max("Focus Distance", camera.far);
+            shown_clipping = (gui.get_parameter("Near Clip"), gui.get_parameter("Far Clip"));
+        }
+        renderer.render(&camera);
+        renderer.apply_post_process(&post_process, &camera);
+        if step.enabled {
+            // Number the picked segment, bracketed, and its neighbours, after post-processing so they stay sharp
+            let segment_count = renderer.lines().len();
+            step.segment = step.segment.min(segment_count.saturating_sub(1));
+            let first = step.segment.saturating_sub(STEP_SEGMENT_LABELS / 2);
+            let labels: Vec<(usize, String)> = (first..(first + STEP_SEGMENT_LABELS).min(segment_count))
+                .map(|i| (i, if i == step.segment { format!("[{}]", i) } else { i.to_string() }))
+                .collect();
+            let labels: Vec<(usize, &str)> = labels.iter().map(|(i, label)| (*i, label.as_str())).collect();
+            renderer.render_labels_at_segment_midpoints(&labels, &camera);
+            if let Some(&end) = step.segment_ends.get(step.segment) {
+                let state = turtle.replay_to_position(&loaded.lsystem.current_string, end);
+                renderer.render_turtle_marker(state.position, state.direction,
+                                              loaded.lsystem.rule.step_length.unwrap_or(1.0), &camera);
+            }
+        }
+        
+        // Save frames for video when recording, throttled to the requested rate
+        if let Some(dir) = &record_dir
+            && last_record_time.is_none_or(|t| t.elapsed() >= record_interval) {
+            last_record_time = Some(std::time::Instant::now());
+            let written = if record_ppm {
+                renderer.write_frame_sequence_ppm(dir, "frame", record_frame)
+            } else {
+                renderer.write_frame_sequence(dir, "frame", record_frame)
+            };
+            match written {
+                Ok(_) => record_frame += 1,
+                Err(e) => eprintln!("Error writing frame {}: {}", record_frame, e),
+            }
+        }
+        
+        for (path, result) in run_pending_exports(&mut exports, &loaded, &mut renderer, &turtle, &camera, &export_settings) {
+            report_export(&path, result, &loaded.rule, &mut window, &mut toast, &mut saved_title_remaining);
+        }
+
+        // Alternate the title between rule info and timings, unless a save message is showing
+        if saved_title_remaining > 0.0 {
+            saved_title_remaining -= dt;
+            if saved_title_remaining <= 0.0 {
+                title_cycle_time = 0.0;
+                update_window_title(&mut window, &loaded.rule, &AppStatus::Ready { chars: loaded.lsystem.current_string.len() });
+            }
+        } else {
+            let showing_timing = |t: f32| t % (2.0 * TITLE_CYCLE_SECONDS) >= TITLE_CYCLE_SECONDS;
+            let was_timing = showing_timing(title_cycle_time);
+            title_cycle_time += dt;
+            let is_timing = showing_timing(title_cycle_time);
+            if is_timing && !was_timing {
+                update_window_title(&mut window, &loaded.rule, &AppStatus::Timing {
+                    render_time: renderer.get_render_time(),
+                    fps: stats_overlay.fps(),
+                });
+            } else if was_timing && !is_timing {
+                update_window_title(&mut window, &loaded.rule, &AppStatus::Ready { chars: loaded.lsystem.current_string.len() });
+            }
+        }
+        
+        // Get buffer from renderer; overlays are blended into this copy so screenshots of the
+        // renderer's buffer never contain UI
+        let buffer = renderer.get_buffer();
+        let mut display_buffer = buffer.to_vec();
+        let mut ui_layer = vec![0u32; width * height];
+        
+        // Advance overlay slide animations, auto-orbit and camera auto-centering
+        menu.update_animation(dt);
+        menu.update_search(dt);
+        gui.update_animation(dt);
+        main_menu.update_animation(dt);
+        camera.advance_auto_orbit(dt);
+        camera.update(dt);
+        toast.update(dt);
+        
+        // Render menu overlay
+        menu.render_to_buffer(&mut ui_layer, width, height);
+        
+        // Render GUI overlay
+        gui.render(&mut ui_layer, width, height);
+        
+        info_panel.render(&mut ui_layer, width, height, &loaded.rule, &symbol_table);
+        
+        // Render main menu overlay (on top of everything)
+        main_menu.render(&mut ui_layer, width, height, &loaded.rule.name);
+        context_menu.render(&mut ui_layer, width, height, window.get_mouse_pos(minifb::MouseMode::Clamp));
+        renderer::composite_over(&mut display_buffer, width, height, &ui_layer, (width, height), MENU_ALPHA, (0, 0));
+        ui_layer.fill(0);
+        
+        // Counting pixels costs a full buffer pass, so only do it while the overlay shows the result
+        let (coverage, overdraw) = if stats_overlay.visible {
+            (renderer.compute_per_pixel_coverage(),
+             renderer.overdraw_tracking_enabled().then(|| renderer.compute_average_overdraw()))
+        } else {
+            (0.0, None)
+        };
+        let stats_panel = stats_overlay.render(&mut ui_layer, width, height, &FrameStats {
+            generation_time,
+            render_time: renderer.get_render_time(),
+            culled: renderer.culled_count(),
+            full_length: unexpanded_full_length,
+            coverage,
+            overdraw,
+            string_bytes: loaded.lsystem.string_size_bytes(),
+            truncated: loaded.lsystem.truncated,
+            longest_chain,
+            fractal_dimension,
+            seed: loaded.lsystem.last_seed,
+            metrics: turtle.last_metrics(),
+        });
+        if let Some((panel_x, panel_y, panel_width, panel_height)) = stats_panel {
+            // FPS and render time sparklines side by side, right-aligned under the panel
+            let right = panel_x + panel_width;
+            let y = panel_y + panel_height + 4;
+            let fps = history_in_order(&fps_history, history_next, history_len);
+            let render_ms = history_in_order(&render_time_history, history_next, history_len);
+            Gui::render_waveform(&mut ui_layer, width, height, (right.saturating_sub(2 * METRICS_HISTORY + 4), y, METRICS_HISTORY, 30), &fps, "FPS", 0x40FF40);
+            Gui::render_waveform(&mut ui_layer, width, height, (right.saturating_sub(METRICS_HISTORY), y, METRICS_HISTORY, 30), &render_ms, "Render ms", 0xFFAA00);
+            renderer::composite_over(&mut display_buffer, width, height, &ui_layer, (width, height), STATS_ALPHA, (0, 0));
+        }
+        if step.enabled {
+            render_step_mode_status(&mut display_buffer, width, height, loaded.lsystem.current_iteration(), loaded.lsystem.rule.iterations,
+                                    step.segment, renderer.lines().len());
+        }
+        if loaded.lsystem.truncated {
+            render_truncation_warning(&mut display_buffer, width, height, loaded.lsystem.current_iteration(), loaded.lsystem.rule.iterations);
+        }
+        
+        // Render toast notifications
+        toast.render(&mut display_buffer, width, height);
+        
+        window.update_with_buffer(&display_buffer, width, height).unwrap();
+        frame_limiter.wait(now);
+    }
+    
+    // Remember this session for the next launch
+    let (window_width, window_height) = window.get_size();
+    app_state.window_width = window_width;
+    app_state.window_height = window_height;
+    app_state.window_position = Some(window.get_position());
+    app_state.camera_yaw = camera.yaw;
+    app_state.camera_pitch = camera.pitch;
+    app_state.camera_distance = camera.distance;
+    app_state.record_rule(&loaded.path);
+    if let Err(e) = app_state.save() {
+        eprintln!("Error saving app state: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn koch_rule() -> LSystemRule {
+        LSystemRule::builder()
+            .name("Koch Curve")
+            .axiom("F")
+            .rule('F', "F-F++F-F")
+            .angle(60.0)
+            .iterations(2)
+            .build()
+            .unwrap()
+    }
+
+    fn keyframe(time_secs: f32, angle: Option<f32>) -> TimeKeyframe {
+        TimeKeyframe { time_secs, angle, step_length: None }
+    }
+
+    #[test]
+    fn generate_expands_koch_curve_to_iteration_two() {
+        let mut lsystem = LSystem::new(koch_rule());
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "F-F++F-F-F-F++F-F++F-F++F-F-F-F++F-F");
+    }
+
+    #[test]
+    fn generate_to_string_matches_generate_without_keeping_state() {
+        let json = r#"{
+            "name": "Bush", "axiom": "F", "angle": 25.0, "iterations": 4, "rules": {},
+            "stochastic_rules": { "F": [["F[+F]F", 0.5], ["F[-F]F", 0.5]] },
+            "seed": 3
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        let checksum = rule.checksum();
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate();
+        
+        assert_eq!(LSystem::generate_to_string(&rule), lsystem.current_string);
+        // A second call starts from the axiom and seed again rather than carrying on
+        assert_eq!(LSystem::generate_to_string(&rule), lsystem.current_string);
+        assert_eq!(rule.checksum(), checksum);
+        assert_eq!(LSystem::generate_to_string_at(&rule, 0), "F");
+        assert_eq!(LSystem::generate_to_string(&koch_rule()), "F-F++F-F-F-F++F-F++F-F++F-F-F-F++F-F");
+    }
+
+    #[test]
+    fn load_rule_from_file_reads_known_good_json() {
+        let path = std::env::temp_dir().join(format!("lsystem_known_good_{}.json", std::process::id()));
+        fs::write(&path, r#"{
+            "name": "Test Tree",
+            "axiom": "T",
+            "angle": 25.0,
+            "iterations": 3,
+            "rules": { "T": "F[+T][-T]" },
+            "time_varying_params": [{ "time_secs": 4.0, "angle": 30.0 }, { "time_secs": 1.0 }]
+        }"#).unwrap();
+        let rule = load_rule_from_file(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+
+        let rule = rule.unwrap();
+        assert_eq!(rule.name, "Test Tree");
+        assert_eq!(rule.axiom, "T");
+        assert_eq!(rule.iterations, 3);
+        assert_eq!(rule.rules.get(&'T').map(String::as_str), Some("F[+T][-T]"));
+        assert!(rule.validate().is_ok());
+
+        let times: Vec<f32> = rule.time_varying_params.unwrap().iter().map(|k| k.time_secs).collect();
+        assert_eq!(times, vec![1.0, 4.0]);
+    }
+
+    #[test]
+    fn rules_round_trip_through_toml_with_every_field_set() {
+        let rule: LSystemRule = serde_json::from_str(r#"{
+            "name": "Everything", "axiom": "A", "angle": 22.5, "iterations": 3,
+            "rules": { "A": "F[+A][-A]", "F": "FF" },
+            "step_length": 0.75, "start_position": [0.0, -1.0, 0.0], "start_direction": [0.0, 1.0, 0.0],
+            "colors": { "depth_based": false, "palette": [[0.4, 0.2, 0.1]], "gradient": [{ "t": 0.0, "color": [0.1, 0.2, 0.3] }] },
+            "description": "All optional fields", "description_url": "https://example.com",
+            "skybox": { "zenith": [0.1, 0.2, 0.3], "horizon": [0.4, 0.5, 0.6], "ground": [0.7, 0.8, 0.9] },
+            "axioms": ["A", "F"], "start_positions": [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0]],
+            "alternating_rules": { "F": ["F", "FF"] },
+            "parameter_presets": [{ "name": "Wide", "angle": 40.0, "step_length": null, "iterations": 2 }],
+            "taper": { "base": 3.0, "decay": 0.7, "min": 0.5 },
+            "iteration_limits": { "A": 2 },
+            "time_varying_params": [{ "time_secs": 1.0, "angle": 30.0, "step_length": 1.5 }],
+            "ground_collision": -0.5,
+            "view_presets": [{ "name": "Side", "yaw": 90.0, "pitch": 0.0, "distance": 12.0, "description": "From the side" }],
+            "max_memory_mb": 50,
+            "render_hints": { "line_width_scale": 1.5, "antialiased": false, "background_color": [0.0, 0.0, 0.1] },
+            "environment": { "gravity": [0.0, -1.0, 0.0], "wind": [0.2, 0.0, 0.0], "max_height": 10.0, "ground_y": 0.0, "tropism_weight": 0.1 },
+            "stochastic_rules": { "B": [["F", 2.0], { "successor": "FF", "weight": 1.0 }] },
+            "seed": 42,
+            "modules": ["FA"], "module_rules": { "FA": "F" },
+            "parametric": true, "parametric_rules": { "G(x)": "G(x*2)" },
+            "context_rules": [{ "left_context": "A", "symbol": "F", "right_context": null, "replacement": "G" }],
+            "symbol_colors": { "F": [0.4, 0.2, 0.1] }
+        }"#).unwrap();
+        
+        let toml = RuleFormat::Toml.serialize(&rule).unwrap();
+        let parsed = RuleFormat::Toml.parse(&toml).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(&rule).unwrap());
+        
+        // Files are read and written in the format their extension names
+        let path = std::env::temp_dir().join(format!("lsystem_round_trip_{}.toml", std::process::id()));
+        rule.save_to_file(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let loaded = load_rule_from_file(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        assert!(toml::from_str::<toml::Table>(&written).is_ok());
+        assert_eq!(serde_json::to_value(loaded.unwrap()).unwrap(), serde_json::to_value(&rule).unwrap());
+        assert_eq!(RuleFormat::from_path(std::path::Path::new("rules/tree.json")), RuleFormat::Json);
+    }
+
+    #[test]
+    fn validate_rule_reports_each_problem() {
+        let valid: LSystemRule = serde_json::from_str(r#"{
+            "name": "Valid", "axiom": "A", "angle": 25.0, "iterations": 4, "rules": { "A": "F[+A]" },
+            "step_length": 1.0, "colors": { "palette": [[0.2, 0.6, 1.0]] }
+        }"#).unwrap();
+        assert!(validate_rule(&valid).is_ok());
+        
+        let messages_for = |change: &dyn Fn(&mut LSystemRule)| {
+            let mut rule = valid.clone();
+            change(&mut rule);
+            validate_rule(&rule).unwrap_err()
+        };
+        let expect = |messages: Vec<String>, expected: &str| {
+            assert!(messages.iter().any(|m| m == expected), "{:?} lacks {:?}", messages, expected);
+        };
+        expect(messages_for(&|r| r.axiom.clear()), "[ERROR] axiom is empty");
+        expect(messages_for(&|r| { r.rules.insert('A', "F[+A".to_string()); }), "[ERROR] Rule 'A': 1 unclosed '['");
+        expect(messages_for(&|r| r.axiom = "A]".to_string()), "[ERROR] Axiom: unmatched ']' at position 1");
+        expect(messages_for(&|r| r.axiom = "AXF".to_string()), "[WARN] axiom symbol 'X' has no rule and is not a turtle command");
+        expect(messages_for(&|r| r.angle = 0.0), "[WARN] angle 0 is outside (0, 360)");
+        expect(messages_for(&|r| r.angle = 360.0), "[WARN] angle 360 is outside (0, 360)");
+        expect(messages_for(&|r| r.iterations = 0), "[WARN] iterations 0 is outside [1, 20]");
+        expect(messages_for(&|r| r.iterations = 21), "[WARN] iterations 21 is outside [1, 20]");
+        expect(messages_for(&|r| r.iterations = 12), "[WARN] iterations 12 may take a long time to expand");
+        expect(messages_for(&|r| r.step_length = Some(-1.0)), "[WARN] step_length -1 is not positive");
+        expect(messages_for(&|r| r.colors.as_mut().unwrap().palette = Some(vec![[0.5, 1.5, 0.0]])),
+            "[WARN] colors.palette[0] [0.5, 1.5, 0.0] has values outside [0.0, 1.0]");
+        
+        // Only errors stop a file from loading
+        let path = std::env::temp_dir().join(format!("lsystem_validate_{}.json", std::process::id()));
+        fs::write(&path, r#"{ "name": "Slow", "axiom": "A", "angle": 25.0, "iterations": 12, "rules": { "A": "FA" } }"#).unwrap();
+        assert!(load_rule_from_file(path.to_str().unwrap()).is_ok());
+        fs::write(&path, r#"{ "name": "Empty", "axiom": "", "angle": 25.0, "iterations": 2, "rules": { "A": "FA" } }"#).unwrap();
+        assert!(load_rule_from_file(path.to_str().unwrap()).is_err());
+        fs::write(&path, r#"{ "name": "Open", "axiom": "A", "angle": 25.0, "iterations": 2, "rules": { "A": "F[A" } }"#).unwrap();
+        let result = load_rule_from_file(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn changes_to_the_watched_file_send_one_debounced_reload() {
+        use notify::event::{CreateKind, EventKind, ModifyKind};
+        use std::time::Duration;
+        
+        let path = std::env::temp_dir().join(format!("lsystem_watched_{}.json", std::process::id()));
+        fs::write(&path, r#"{ "name": "Watched", "axiom": "F", "angle": 25.0, "iterations": 1, "rules": {} }"#).unwrap();
+        let (event_sender, events) = std::sync::mpsc::channel();
+        let (reload_sender, reloads) = std::sync::mpsc::channel();
+        let watched = path.clone();
+        let forwarder = std::thread::spawn(move || {
+            forward_debounced_changes(events, &watched, reload_sender, Duration::from_millis(50))
+        });
+        
+        // Other files in the directory are ignored
+        let other = path.with_file_name("lsystem_unwatched.json");
+        event_sender.send(Ok(notify::Event::new(EventKind::Modify(ModifyKind::Any)).add_path(other))).unwrap();
+        assert!(reloads.recv_timeout(Duration::from_millis(150)).is_err());
+        
+        // A burst of events is one reload
+        event_sender.send(Ok(notify::Event::new(EventKind::Create(CreateKind::File)).add_path(path.clone()))).unwrap();
+        event_sender.send(Ok(notify::Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone()))).unwrap();
+        assert!(reloads.recv_timeout(Duration::from_millis(500)).is_ok());
+        assert!(reloads.recv_timeout(Duration::from_millis(150)).is_err());
+        
+        drop(event_sender);
+        forwarder.join().unwrap();
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn load_rule_from_file_rejects_limits_without_a_rule() {
+        let path = std::env::temp_dir().join(format!("lsystem_bad_limits_{}.json", std::process::id()));
+        fs::write(&path, r#"{ "name": "Bad", "axiom": "A", "angle": 25.0, "iterations": 2,
+                              "rules": { "A": "FA" }, "iteration_limits": { "X": 1 } }"#).unwrap();
+        let result = load_rule_from_file(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn builder_requires_name_axiom_and_angle() {
+        assert!(LSystemRule::builder().axiom("F").angle(25.0).build().is_err());
+        assert!(LSystemRule::builder().name("No Axiom").angle(25.0).build().is_err());
+        assert!(LSystemRule::builder().name("No Angle").axiom("F").build().is_err());
+        assert!(LSystemRule::builder().name("Unbalanced").axiom("F").rule('F', "F[+F").angle(25.0).build().is_err());
+    }
+
+    #[test]
+    fn check_brackets_reports_imbalance() {
+        assert!(check_brackets("F[+F][-F[&F]]").is_ok());
+        assert!(check_brackets("F]").is_err());
+        assert!(check_brackets("[[F]").is_err());
+    }
+
+    #[test]
+    fn iteration_limits_freeze_symbols() {
+        let mut rule = LSystemRule::builder()
+            .name("Limits")
+            .axiom("AF")
+            .rule('A', "AF")
+            .rule('F', "FF")
+            .angle(25.0)
+            .iterations(3)
+            .build()
+            .unwrap();
+        assert_eq!(LSystem::generate_to_string(&rule), "AFFFFFFFFFFFFFFF");
+
+        rule.iteration_limits = Some(HashMap::from([('F', 1)]));
+        assert_eq!(LSystem::generate_to_string(&rule), "AFFFFF");
+    }
+
+    #[test]
+    fn estimate_string_length_matches_generation() {
+        let rule = koch_rule();
+        for iterations in 0..4 {
+            let actual = LSystem::generate_to_string_at(&rule, iterations).len() as u64;
+            assert_eq!(rule.estimate_string_length(iterations), actual);
+        }
+        assert_eq!(LSystem::new(rule).current_estimated_full_length(), 36);
+    }
+
+    #[test]
+    fn generate_incremental_stops_before_the_target_length() {
+        let mut lsystem = LSystem::new(koch_rule());
+        assert!(!lsystem.generate_incremental(20));
+        assert_eq!(lsystem.current_string, "F-F++F-F");
+        assert_eq!(lsystem.current_iteration(), 1);
+
+        let mut lsystem = LSystem::new(koch_rule());
+        assert!(lsystem.generate_incremental(1000));
+        assert!(lsystem.is_complete());
+    }
+
+    #[test]
+    fn generate_n_regrows_exactly_n_iterations_from_the_axiom() {
+        let mut lsystem = LSystem::new(koch_rule());
+        lsystem.generate();
+        lsystem.generate_n(0);
+        assert_eq!(lsystem.current_string, "F");
+        assert_eq!(lsystem.current_iteration(), 0);
+        
+        lsystem.generate_n(1);
+        assert_eq!(lsystem.current_string, "F-F++F-F");
+        assert_eq!(lsystem.current_iteration(), 1);
+        assert!(!lsystem.is_complete());
+    }
+
+    #[test]
+    fn compute_bounds_frames_a_unit_square_without_a_renderer() {
+        let rule = LSystemRule::builder()
+            .name("Square")
+            .axiom("F")
+            .rule('F', "F+F+F+F")
+            .angle(90.0)
+            .iterations(1)
+            .build()
+            .unwrap();
+        let mut lsystem = LSystem::new(rule);
+        lsystem.generate();
+        
+        // Up one step, then a quarter turn left each step: the square spans x in [-1, 0], y in [0, 1]
+        let (min, max) = lsystem.compute_bounds().unwrap();
+        assert!((min - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-5, "min {}", min);
+        assert!((max - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-5, "max {}", max);
+        assert!((lsystem.center() - Vec3::new(-0.5, 0.5, 0.0)).length() < 1e-5);
+        assert!((lsystem.diagonal_length() - 2.0_f32.sqrt()).abs() < 1e-5);
+        
+        // Turning on the spot draws nothing
+        lsystem.current_string = "+-+".to_string();
+        assert_eq!(lsystem.compute_bounds(), None);
+        lsystem.current_string.clear();
+        assert_eq!(lsystem.compute_bounds(), None);
+        assert_eq!(lsystem.center(), Vec3::ZERO);
+    }
+
+    #[test]
+    fn statistics_count_symbols_branches_and_depth() {
+        let mut lsystem = LSystem::new(koch_rule());
+        lsystem.current_string = "F[+F][-F]F".to_string();
+        let stats = lsystem.statistics();
+        assert_eq!(stats.total_symbols, 10);
+        assert_eq!(stats.unique_symbols, 5);
+        assert_eq!(stats.symbol_counts[&'F'], 4);
+        assert_eq!(stats.branch_count, 2);
+        assert_eq!(stats.max_stack_depth, 1);
+        assert_eq!(stats.forward_move_count, 4);
+        assert_eq!(stats.summary(), "Symbols: 10  Branches: 2  Depth: 1");
+        
+        // Letters inside module names aren't moves
+        lsystem.current_string = format!("f[[{0}FA{0}]]", turtle3d::MODULE_DELIMITER);
+        let stats = lsystem.statistics();
+        assert_eq!(stats.forward_move_count, 1);
+        assert_eq!(stats.max_stack_depth, 2);
+    }
+
+    #[test]
+    fn checksum_tracks_string_shaping_fields_only() {
+        let rule = koch_rule();
+        assert_eq!(rule.checksum(), koch_rule().checksum());
+        assert_ne!(rule.clone().with_angle(45.0).checksum(), rule.checksum());
+
+        let mut recolored = rule.clone();
+        recolored.step_length = Some(3.0);
+        assert_eq!(recolored.checksum(), rule.checksum());
+
+        let mut with_module = rule.clone();
+        with_module.module_rules = Some(HashMap::from([("FA".to_string(), "F[+FA]".to_string())]));
+        assert_ne!(with_module.checksum(), rule.checksum());
+        let mut rewritten_module = with_module.clone();
+        rewritten_module.module_rules = Some(HashMap::from([("FA".to_string(), "FF".to_string())]));
+        assert_ne!(rewritten_module.checksum(), with_module.checksum());
+
+        let mut stochastic = rule.clone();
+        stochastic.stochastic_rules = Some(HashMap::from([('F', vec![
+            StochasticProduction { successor: "F+F".to_string(), weight: 1.0 },
+            StochasticProduction { successor: "F-F".to_string(), weight: 1.0 },
+        ])]));
+        stochastic.seed = Some(1);
+        let mut reseeded = stochastic.clone();
+        reseeded.seed = Some(2);
+        assert_ne!(reseeded.checksum(), stochastic.checksum());
+    }
+
+    #[test]
+    fn fold_string_run_length_encodes_repeats() {
+        let mut lsystem = LSystem::new(koch_rule());
+        lsystem.current_string = "FFF[+F][+F]-".to_string();
+        assert_eq!(lsystem.fold_string(10), "3F[+F]2-");
+        assert_eq!(lsystem.compressed_length(), 8);
+        
+        lsystem.current_string = "F".repeat(12);
+        assert_eq!(lsystem.fold_string(200), "12F");
+        assert_eq!(unfold_string("12F"), lsystem.current_string);
+        
+        lsystem.current_string = "[X][X]FFF".to_string();
+        assert_eq!(lsystem.fold_string(10), "[X]2 3F");
+        assert_eq!(unfold_string("[X]2 3F"), lsystem.current_string);
+        assert_eq!(unfold_string("[X] 2F"), "[X]FF");
+    }
+    
+    #[test]
+    fn folding_round_trips_random_strings() {
+        let pieces = ["F", "FF", "FFF", "+", "-", "[X]", "[X][X]", "[+F][+F]", "[F[-F][-F]]", "A"];
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..500 {
+            let s: String = (0..rng.gen_range(1..12)).map(|_| pieces[rng.gen_range(0..pieces.len())]).collect();
+            let mut folded = String::new();
+            fold_segment(&s, &mut folded).unwrap();
+            assert_eq!(unfold_string(&folded), s, "{} folded to {}", s, folded);
+        }
+    }
+
+    #[test]
+    fn keyframes_interpolate_and_hold_at_the_ends() {
+        let keyframes = [keyframe(0.0, Some(5.0)), keyframe(5.0, None), keyframe(10.0, Some(25.0))];
+        let angle = |t| interpolate_keyframes(&keyframes, t, |k| k.angle);
+        assert_eq!(angle(0.0), Some(5.0));
+        assert_eq!(angle(5.0), Some(15.0));
+        assert_eq!(angle(12.0), Some(25.0));
+        assert_eq!(interpolate_keyframes(&keyframes, 5.0, |k| k.step_length), None);
+    }
+
+    #[test]
+    fn animated_params_loop_at_the_last_keyframe() {
+        let mut rule = koch_rule();
+        assert!(rule.animated_params(1.0).is_none());
+
+        rule.time_varying_params = Some(vec![keyframe(0.0, Some(10.0)), keyframe(4.0, Some(30.0))]);
+        assert_eq!(rule.animated_params(2.0), Some((20.0, None)));
+        assert_eq!(rule.animated_params(6.0), Some((20.0, None)));
+    }
+
+    #[test]
+    fn ground_collision_keeps_downward_rules_above_ground() {
+        let mut rule = LSystemRule::builder().name("Down").axiom("+FFF").rule('F', "F").angle(180.0).build().unwrap();
+        rule.ground_collision = Some(0.0);
+        let mut lsystem = LSystem::new(rule);
+        lsystem.generate();
+
+        let mut renderer = Renderer::new(64, 64);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        assert_eq!(renderer.lines().len(), 3);
+        assert!(renderer.lines().iter().all(|line| line.start.position.y >= 0.0 && line.end.position.y >= 0.0));
+    }
+
+    #[test]
+    fn validate_symbols_classifies_each_symbol() {
+        let rule = LSystemRule::builder().name("Symbols").axiom("AQ")
+            .rule('A', "F[+A]X").rule('B', "F").rule('F', "FF").angle(25.0).build().unwrap();
+        let lsystem = LSystem::new(rule);
+        let symbols = lsystem.validate_symbols();
+        assert_eq!(symbols[&'A'], SymbolStatus::DefinedRule);
+        assert_eq!(symbols[&'F'], SymbolStatus::DefinedRule);
+        assert_eq!(symbols[&'+'], SymbolStatus::TurtleCommand);
+        assert_eq!(symbols[&'X'], SymbolStatus::UndefinedInRHS);
+        assert_eq!(symbols[&'Q'], SymbolStatus::AppearanceInAxiomOnly);
+        assert_eq!(symbols[&'B'], SymbolStatus::Unreachable);
+        assert_eq!(lsystem.validate().len(), 2);
+    }
+
+    #[test]
+    fn symbol_checks_follow_every_production_map() {
+        let bush: LSystemRule = serde_json::from_str(include_str!("../rules/stochastic_bush.json")).unwrap();
+        let bush = LSystem::new(bush);
+        assert_eq!(bush.validate_symbols()[&'F'], SymbolStatus::DefinedRule);
+        assert!(bush.validate().is_empty());
+        assert!(bush.has_cycle());
+        
+        let rule: LSystemRule = serde_json::from_str(r#"{
+            "name": "Mixed", "axiom": "A", "angle": 25.0, "iterations": 3, "rules": {},
+            "stochastic_rules": { "A": [{ "successor": "F[+B]", "weight": 1.0 }, { "successor": "F[-B]", "weight": 1.0 }] },
+            "context_rules": [{ "left_context": "F", "symbol": "B", "right_context": null, "replacement": "FC" }],
+            "modules": ["Bud"], "module_rules": { "Bud": "F" },
+            "parametric": true, "parametric_rules": { "C(x)": "F(x)Bud" }
+        }"#).unwrap();
+        rule.validate().unwrap();
+        let lsystem = LSystem::new(rule);
+        assert_eq!(lsystem.rules_graph()[&'A'], vec!['B']);
+        assert_eq!(lsystem.longest_chain_length(), 4);
+        let reachable = lsystem.reachable_symbols_from_axiom();
+        assert!(['A', 'B', 'C'].iter().all(|c| reachable.contains(c)));
+        assert!(reachable.contains(&lsystem.module_node("Bud").unwrap()));
+        assert!(lsystem.validate().is_empty(), "{:?}", lsystem.validate());
+    }
+
+    #[test]
+    fn context_menu_stays_on_screen_and_hit_tests_items() {
+        let mut context_menu = ContextMenu::new();
+        let (menu_width, menu_height) = context_menu.size();
+        context_menu.show(790, 590, 800, 600, None);
+        assert_eq!((context_menu.x, context_menu.y), (800 - menu_width, 600 - menu_height));
+
+        context_menu.show(100, 100, 800, 600, None);
+        let first_row = (100 + ContextMenu::PADDING) as f32 + 1.0;
+        assert_eq!(context_menu.item_at(105.0, first_row), Some(0));
+        assert_eq!(context_menu.item_at(105.0, first_row + ContextMenu::ITEM_HEIGHT as f32), Some(1));
+        assert_eq!(context_menu.item_at(95.0, first_row), None);
+        assert_eq!(context_menu.distance_to(105.0, first_row), 0.0);
+        assert_eq!(context_menu.distance_to(70.0, first_row), 30.0);
+    }
+
+    #[test]
+    fn history_in_order_unrolls_the_ring_oldest_first() {
+        let history = [4.0, 5.0, 1.0, 2.0, 3.0];
+        assert_eq!(history_in_order(&history, 2, 5), vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(history_in_order(&history, 2, 2), vec![4.0, 5.0]);
+        assert!(history_in_order(&history, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn replace_rule_validates_and_restarts_from_the_axiom() {
+        let mut lsystem = LSystem::new(koch_rule());
+        lsystem.generate();
+        assert!(lsystem.replace_rule('F', "F[+F").is_err());
+        assert!(lsystem.replace_rule('F', "F\nF").is_err());
+        assert_eq!(lsystem.current_iteration(), 2);
+
+        lsystem.replace_rule('F', "FF").unwrap();
+        assert_eq!((lsystem.current_string.as_str(), lsystem.current_iteration()), ("F", 0));
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "FFFF");
+
+        lsystem.remove_rule('F').unwrap();
+        assert!(lsystem.remove_rule('F').is_err());
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "F");
+    }
+
+    #[test]
+    fn rules_graph_finds_cycles_chains_and_reachable_symbols() {
+        let chain = LSystemRule::builder().name("Chain").axiom("A")
+            .rule('A', "F[+B]B").rule('B', "C-").rule('C', "F").rule('X', "A").angle(25.0).build().unwrap();
+        let lsystem = LSystem::new(chain);
+        assert_eq!(lsystem.rules_graph()[&'A'], vec!['B']);
+        assert!(!lsystem.has_cycle());
+        assert_eq!(lsystem.longest_chain_length(), 4);
+        let reachable = lsystem.reachable_symbols_from_axiom();
+        assert!(reachable.contains(&'C'));
+        assert!(!reachable.contains(&'X'));
+
+        let koch = LSystem::new(koch_rule());
+        assert_eq!(koch.rules_graph()[&'F'], vec!['F']);
+        assert!(koch.has_cycle());
+        assert_eq!(koch.longest_chain_length(), usize::MAX);
+    }
+
+    #[test]
+    fn generate_stops_early_past_the_memory_limit() {
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 6, ..koch_rule() });
+        lsystem.max_bytes = 100;
+        lsystem.generate();
+        assert!(lsystem.truncated);
+        assert_eq!(lsystem.current_iteration(), 3);
+        assert!(lsystem.string_size_bytes() > 100);
+        lsystem.reset();
+        assert!(!lsystem.truncated);
+    }
+
+    #[test]
+    fn modules_rewrite_as_single_symbols_the_turtle_skips() {
+        let rule = LSystemRule {
+            modules: Some(vec!["FA".to_string()]),
+            module_rules: Some(HashMap::from([("FA".to_string(), "F[+FA]".to_string())])),
+            ..LSystemRule::builder().name("Modules").axiom("FA").rule('F', "FF").angle(30.0).iterations(2).build().unwrap()
+        };
+        rule.validate().unwrap();
+        
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "FF[+F[+\0FA\0]]");
+        assert_eq!(rule.estimate_string_length(2), lsystem.current_string.len() as u64);
+        
+        let mut renderer = Renderer::new(0, 0);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        assert_eq!(renderer.lines().len(), 3);
+        
+        let undeclared = LSystemRule { modules: None, ..rule };
+        assert!(undeclared.validate().is_err());
+    }
+
+    #[test]
+    fn parametric_rules_rewrite_arguments_the_turtle_follows() {
+        let json = r#"{
+            "name": "Square", "axiom": "F(4)", "angle": 25.0, "iterations": 2,
+            "rules": {}, "parametric": true,
+            "parametric_rules": { "F(x)": "F(x/2)+(90)F(x/2)" }
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        rule.validate().unwrap();
+        
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "F(1)+(90)F(1)+(90)F(1)+(90)F(1)");
+        assert_eq!(rule.estimate_string_length(2), lsystem.current_string.len() as u64);
+        
+        // Four unit steps, each a quarter turn left of the last, close a square back at the start
+        let mut turtle = Turtle3D::new();
+        let (min, max) = lsystem.compute_bounding_box(&mut turtle);
+        assert!((min - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-5 && (max - Vec3::Y).length() < 1e-5);
+        assert!(turtle.last_metrics().unwrap().final_state.position.length() < 1e-5);
+        
+        let unknown = LSystemRule {
+            parametric_rules: Some(HashMap::from([("F(x)".to_string(), "F(y)".to_string())])),
+            ..rule.clone()
+        };
+        assert!(unknown.validate().is_err());
+        let not_parametric = LSystemRule { parametric: None, ..rule };
+        assert!(not_parametric.validate().is_err());
+    }
+
+    #[test]
+    fn export_svg_writes_well_formed_lines_clipped_to_the_view() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate();
+        let mut renderer = Renderer::new(200, 150);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        // The triangle is 32 steps across, so from the default distance much of it is off-screen
+        let mut camera = Camera::new(200.0 / 150.0);
+        camera.update_from_angles();
+        
+        let path = std::env::temp_dir().join(format!("lsystem_export_{}.svg", std::process::id()));
+        renderer.export_svg(&path, &camera, &rule.name).unwrap();
+        let svg = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        
+        assert!(svg.contains("<title>3D Sierpinski Triangle</title>"));
+        let lines: Vec<&str> = svg.lines().filter(|line| line.starts_with("<line ")).collect();
+        assert!(!lines.is_empty());
+        assert!(lines.len() < renderer.lines().len());
+        
+        // Every element is closed in order
+        let mut open = Vec::new();
+        for tag in svg.split('<').skip(1).map(|rest| &rest[..rest.find('>').unwrap()]) {
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name));
+            } else if !tag.ends_with('/') {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty());
+        
+        for line in lines {
+            let coordinate = |name: &str| -> f32 {
+                let value = &line[line.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3..];
+                value[..value.find('"').unwrap()].parse().unwrap()
+            };
+            assert!([coordinate("x1"), coordinate("x2")].iter().all(|x| (0.0..=200.0).contains(x)), "{}", line);
+            assert!([coordinate("y1"), coordinate("y2")].iter().all(|y| (0.0..=150.0).contains(y)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn growth_line_frames_stop_once_the_system_is_complete() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 2, ..rule });
+        let frames = lsystem.growth_line_frames(&mut Renderer::new(32, 32), &mut Turtle3D::new(), 10, &FrameLimiter::new(0));
+        assert_eq!(frames.len(), 3);
+        assert_eq!(lsystem.current_iteration(), 2);
+    }
+
+    #[test]
+    fn animated_svg_shows_each_growth_frame_in_turn() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 10, ..rule });
+        let mut renderer = Renderer::new(200, 150);
+        let frames = lsystem.growth_line_frames(&mut renderer, &mut Turtle3D::new(), 10, &FrameLimiter::new(0));
+        assert_eq!(frames.len(), 10);
+        assert_eq!(lsystem.current_iteration(), 9);
+        assert!(frames.windows(2).all(|pair| pair[1].len() > pair[0].len()));
+        let mut camera = Camera::new(200.0 / 150.0);
+        camera.update_from_angles();
+        
+        let path = std::env::temp_dir().join(format!("lsystem_animated_{}.svg", std::process::id()));
+        renderer.export_svg_animated(&frames, &camera, 2.0, &path).unwrap();
+        let svg = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        
+        // Frame i is shown for the i-th tenth of each two-second loop
+        for i in 0..10 {
+            assert!(svg.contains(&format!("<g id=\"frame_{}\" style=\"display:none\">", i)));
+            let key_times = format!("keyTimes=\"0;{:.4};{:.4}\" dur=\"2s\"", i as f32 / 10.0, (i + 1) as f32 / 10.0);
+            assert!(svg.contains(&key_times), "missing {}", key_times);
+        }
+        assert_eq!(svg.matches("<animate ").count(), 10);
+        
+        // Every element is closed in order
+        let mut open = Vec::new();
+        for tag in svg.split('<').skip(1).map(|rest| &rest[..rest.find('>').unwrap()]) {
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name));
+            } else if !tag.ends_with('/') {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn save_png_renders_headless_into_a_new_directory() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 2, ..rule });
+        lsystem.generate();
+        let mut renderer = Renderer::new(64, 48);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        let mut camera = Camera::new(64.0 / 48.0);
+        camera.update_from_angles();
+        renderer.render(&camera);
+        
+        let dir = std::env::temp_dir().join(format!("lsystem_screenshots_{}", std::process::id()));
+        let path = dir.join("sierpinski.png");
+        renderer.save_png(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        
+        assert_eq!(bytes[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+    }
+
+    #[test]
+    fn export_turtles_keep_the_level_command() {
+        let tip = |axiom: &str, turtle: &mut Turtle3D| {
+            let rule = LSystemRule::builder().name("Level").axiom(axiom).angle(45.0).iterations(1).build().unwrap();
+            let mut renderer = Renderer::new(16, 16);
+            LSystem::new(rule).draw_3d(turtle, &mut renderer);
+            renderer.lines().last().unwrap().end.position
+        };
+        let mut window_turtle = Turtle3D::new();
+        register_custom_commands(&mut window_turtle);
+        
+        let leveled = tip("&/$&F", &mut export_turtle(&window_turtle));
+        assert!(leveled.abs_diff_eq(tip("&&F", &mut Turtle3D::new()), 1e-4));
+        assert!(!leveled.abs_diff_eq(tip("&/$&F", &mut window_turtle.clone_without_custom_commands()), 1e-4));
+    }
+
+    #[test]
+    fn render_rule_to_png_writes_an_image_of_the_requested_size() {
+        let mut rule = load_rule_from_file("rules/cherry_blossom.json").unwrap();
+        rule.iterations = 3;
+        let path = std::env::temp_dir().join(format!("lsystem_render_{}", std::process::id())).join("cherry.png");
+        render_rule_to_png(&rule, &path, 160, 90, 0.5, -0.3).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        
+        // IHDR is the first chunk: 8 signature bytes, a 4-byte length and the "IHDR" tag, then width and height
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(bytes[16..20].try_into().unwrap()), 160);
+        assert_eq!(u32::from_be_bytes(bytes[20..24].try_into().unwrap()), 90);
+    }
+
+    #[test]
+    fn render_growth_gif_writes_a_frame_per_iteration() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 3, ..rule });
+        let mut renderer = Renderer::new(64, 48);
+        let mut camera = Camera::new(64.0 / 48.0);
+        camera.update_from_angles();
+        
+        let path = std::env::temp_dir().join(format!("lsystem_growth_{}.gif", std::process::id()));
+        lsystem.render_growth_gif(&mut renderer, &mut Turtle3D::new(), &camera, &path, 250).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(lsystem.is_complete());
+        
+        assert_eq!(&bytes[..6], b"GIF89a");
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes.as_slice()).unwrap();
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 25);
+            frames += 1;
+        }
+        assert_eq!(frames, 4);
+    }
+
+    #[test]
+    fn export_frame_sequence_writes_a_frame_per_iteration() {
+        let rule: LSystemRule = serde_json::from_str(include_str!("../rules/sierpinski.json")).unwrap();
+        let rule = LSystemRule { iterations: 2, ..rule };
+        let mut camera = Camera::new(1.0);
+        camera.update_from_angles();
+        let path = std::env::temp_dir().join(format!("lsystem_frames_{}", std::process::id()));
+        let options = ExportOptions { width: 32, height: 32, path: path.clone() };
+        
+        let frames = export_frame_sequence(&rule, Turtle3D::new(), &camera, &options, &FrameLimiter::new(0)).unwrap();
+        let written = fs::read_dir(&path).unwrap().count();
+        let _ = fs::remove_dir_all(&path);
+        assert_eq!(frames, 3);
+        assert_eq!(written, 3);
+    }
+
+    #[test]
+    fn export_obj_builds_a_closed_tapered_cylinder_per_branch() {
+        let rule = LSystemRule::builder().name("Two").axiom("FF").angle(90.0).iterations(1).build().unwrap();
+        let mut lsystem = LSystem::new(rule);
+        lsystem.generate();
+        let mut renderer = Renderer::new(64, 64);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        // The turtle's line width reaches the lines, so it can size the branches
+        assert_eq!(renderer.lines().len(), 2);
+        assert!(renderer.lines().iter().all(|line| line.thickness == 2.5));
+        
+        let path = std::env::temp_dir().join(format!("lsystem_export_{}.obj", std::process::id()));
+        let segments = 6;
+        renderer.export_obj(&path, segments).unwrap();
+        let obj = fs::read_to_string(&path).unwrap();
+        let mtl = fs::read_to_string(path.with_extension("mtl")).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("mtl"));
+        
+        let records = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+        let segments = segments as usize;
+        assert_eq!(records("v "), 2 * (2 * segments));
+        assert_eq!(records("vn "), 2 * (segments + 2));
+        // Two triangles per side, and a fan of `segments - 2` closing each end
+        assert_eq!(records("f "), 2 * (2 * segments + 2 * (segments - 2)));
+        let vertices = records("v ");
+        assert!(obj.lines().filter(|line| line.starts_with("f ")).flat_map(|line| line.split_whitespace().skip(1))
+            .all(|corner| (1..=vertices).contains(&corner.split("//").next().unwrap().parse::<usize>().unwrap())));
+        
+        // The first ring sits 0.75x the thickness out from the trunk's base
+        let first: Vec<f32> = obj.lines().find(|line| line.starts_with("v ")).unwrap()
+            .split_whitespace().skip(1).map(|n| n.parse().unwrap()).collect();
+        let radius = Vec3::new(first[0], first[1], first[2]).length();
+        assert!((radius - 2.5 * 0.75 * 0.02).abs() < 1e-5);
+        
+        assert!(obj.contains("mtllib "));
+        let used: HashSet<&str> = obj.lines().filter_map(|line| line.strip_prefix("usemtl ")).collect();
+        assert!(!used.is_empty());
+        assert!(used.iter().all(|name| mtl.contains(&format!("newmtl {}", name))));
+    }
+
+    fn context_rule(left_context: Option<char>, symbol: char, right_context: Option<char>, replacement: &str) -> ContextRule {
+        ContextRule { left_context, symbol, right_context, replacement: replacement.to_string() }
+    }
+
+    #[test]
+    fn context_rules_propagate_a_signal_along_the_string() {
+        let rule = LSystemRule {
+            context_rules: Some(vec![context_rule(Some('b'), 'a', None, "b")]),
+            ..LSystemRule::builder().name("Signal").axiom("baaa").rule('b', "a").rule('a', "a").angle(90.0).iterations(2).build().unwrap()
+        };
+        rule.validate().unwrap();
+        let mut lsystem = LSystem::new(rule);
+        lsystem.iterate();
+        assert_eq!(lsystem.current_string, "abaa");
+        lsystem.iterate();
+        assert_eq!(lsystem.current_string, "aaba");
+    }
+
+    #[test]
+    fn the_most_specific_context_rule_wins_and_brackets_are_skipped() {
+        let rules = vec![
+            context_rule(Some('b'), 'a', None, "L"),
+            context_rule(Some('b'), 'a', Some('c'), "R"),
+            context_rule(None, 'a', None, "X"),
+        ];
+        let grow = |axiom: &str| {
+            let rule = LSystemRule {
+                context_rules: Some(rules.clone()),
+                ..LSystemRule::builder().name("Context").axiom(axiom).rule('a', "P").angle(90.0).iterations(1).build().unwrap()
+            };
+            let mut lsystem = LSystem::new(rule);
+            lsystem.generate();
+            lsystem.current_string
+        };
+        
+        // Both contexts beat one, and a context-free context rule still beats the plain rule
+        assert_eq!(grow("bac"), "bRc");
+        assert_eq!(grow("ba"), "bL");
+        assert_eq!(grow("a"), "X");
+        assert_eq!(grow("ab"), "Xb");
+        
+        // A branch's first symbol sees the symbol before `[`, later ones skip whole branches,
+        // and a branch's last symbol has no right neighbor
+        assert_eq!(grow("b[a]"), "b[L]");
+        assert_eq!(grow("b[x]a"), "b[x]L");
+        assert_eq!(grow("ba[x]c"), "bR[x]c");
+        assert_eq!(grow("b[a]c"), "b[L]c");
+        assert_eq!(grow("[ba]c"), "[bL]c");
+        
+        let bracket_context = LSystemRule {
+            context_rules: Some(vec![context_rule(Some('['), 'a', None, "L")]),
+            ..koch_rule()
+        };
+        assert!(bracket_context.validate().is_err());
+    }
+
+    #[test]
+    fn frame_limiter_waits_out_the_frame_unless_unlimited() {
+        assert_eq!(FrameLimiter::new(0).frame_duration, None);
+        let limiter = FrameLimiter::new(100);
+        assert_eq!(limiter.frame_duration, Some(std::time::Duration::from_millis(10)));
+        
+        let start = std::time::Instant::now();
+        limiter.wait(start);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+        
+        // A frame that already overran returns at once
+        let late = std::time::Instant::now() - std::time::Duration::from_millis(50);
+        let before = std::time::Instant::now();
+        limiter.wait(late);
+        assert!(before.elapsed() < std::time::Duration::from_millis(10));
+    }
+
+    #[test]
+    fn generate_with_seed_regrows_the_same_stochastic_string() {
+        let productions = vec![
+            StochasticProduction { successor: "F[+F]".to_string(), weight: 1.0 },
+            StochasticProduction { successor: "F[-F]".to_string(), weight: 1.0 },
+        ];
+        let rule = LSystemRule {
+            iterations: 6,
+            stochastic_rules: Some(HashMap::from([('F', productions)])),
+            ..koch_rule()
+        };
+        
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate_with_seed(42);
+        let first = lsystem.current_string.clone();
+        lsystem.generate_with_seed(42);
+        assert_eq!(lsystem.current_string, first);
+        assert_eq!(lsystem.last_seed, Some(42));
+        assert_eq!(lsystem.rule.seed, Some(42));
+        assert!(first.contains('+') && first.contains('-'));
+        
+        let (random, seed) = lsystem.generate_random();
+        let mut replay = LSystem::new(rule.clone());
+        replay.generate_with_seed(seed);
+        assert_eq!(replay.current_string, random);
+    }
+    
+    #[test]
+    fn generate_lazy_yields_every_iteration_ending_with_generate() {
+        let mut koch = LSystem::new(koch_rule());
+        let iterations: Vec<String> = koch.generate_lazy().collect();
+        koch.generate();
+        assert_eq!(iterations, vec![LSystem::generate_to_string_at(&koch_rule(), 1), koch.current_string]);
+        
+        // Unseeded stochastic rules still follow the system's own seed
+        let productions = vec![
+            StochasticProduction { successor: "F[+F]".to_string(), weight: 1.0 },
+            StochasticProduction { successor: "F[-F]".to_string(), weight: 1.0 },
+        ];
+        let rule = LSystemRule {
+            iterations: 6,
+            axioms: vec!["F".to_string(), "FF".to_string()],
+            stochastic_rules: Some(HashMap::from([('F', productions)])),
+            ..koch_rule()
+        };
+        let mut stochastic = LSystem::new(rule);
+        let iterations: Vec<String> = stochastic.generate_lazy().collect();
+        stochastic.generate();
+        assert_eq!(iterations.len(), 6);
+        assert_eq!(iterations.last(), Some(&stochastic.current_string));
+    }
+    
+    #[test]
+    fn mutations_stay_valid_and_a_zero_rate_changes_nothing() {
+        let lsystem = LSystem::new(LSystemRule { step_length: Some(1.0), ..koch_rule() });
+        let mut rng = SmallRng::seed_from_u64(3);
+        assert_eq!(lsystem.mutate(0.0, &mut rng).checksum(), lsystem.rule.checksum());
+        for _ in 0..20 {
+            let mutated = lsystem.mutate(0.3, &mut rng);
+            assert!(mutated.validate().is_ok());
+            assert!(mutated.angle > 0.0 && mutated.angle <= 180.0);
+            assert!(mutated.step_length.unwrap() >= 0.01);
+        }
+    }
+    
+    #[test]
+    fn each_axiom_grows_on_its_own() {
+        // B only becomes X after an A, which it follows only across the joint between the axioms
+        let json = r#"{
+            "name": "Pair", "axiom": "A", "axioms": ["A", "B"], "angle": 25.0, "iterations": 2, "rules": {},
+            "context_rules": [{ "left_context": "A", "symbol": "B", "right_context": null, "replacement": "X" }]
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        let mut lsystem = LSystem::new(rule.clone());
+        lsystem.generate();
+        assert_eq!(lsystem.current_string, "AB");
+        assert_eq!(lsystem.axiom_segments(), vec!["A", "B"]);
+        
+        let joined = LSystemRule { axiom: "AB".to_string(), axioms: Vec::new(), ..rule };
+        assert_eq!(LSystem::generate_to_string(&joined), "AX");
+    }
+    
+    #[test]
+    fn seeded_stochastic_rules_grow_the_same_tree_from_fresh_state() {
+        let json = r#"{
+            "name": "Bush", "axiom": "F", "angle": 25.0, "iterations": 5,
+            "rules": {},
+            "stochastic_rules": { "F": [["F[+F]F", 0.5], ["F[-F]F", 0.3], ["FF", 0.2]] },
+            "seed": 7
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        assert!(rule.validate().is_ok());
+        assert_eq!(rule.stochastic_rules.as_ref().unwrap()[&'F'][1],
+            StochasticProduction { successor: "F[-F]F".to_string(), weight: 0.3 });
+        
+        let mut first = LSystem::new(rule.clone());
+        first.generate();
+        let mut second = LSystem::new(rule.clone());
+        second.generate();
+        assert_eq!(first.current_string, second.current_string);
+        
+        // Resetting reseeds, so regrowing in place repeats the tree too
+        first.reset();
+        first.generate();
+        assert_eq!(first.current_string, second.current_string);
+        
+        let mut other = LSystem::new(LSystemRule { seed: Some(8), ..rule });
+        other.generate();
+        assert_ne!(other.current_string, second.current_string);
+    }
+
+    #[test]
+    fn environment_sets_tropism_and_overrides_the_ground() {
+        let json = r#"{
+            "name": "Windy", "axiom": "FF", "angle": 25.0, "iterations": 1, "rules": {},
+            "ground_collision": -3.0,
+            "environment": { "wind": [1.0, 0.0, 0.0], "ground_y": -1.0 }
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        assert_eq!(rule.ground_level(), Some(-1.0));
+        assert_eq!(rule.environment.and_then(|e| e.tropism()), Some(Vec3::X));
+
+        let lsystem = LSystem::new(rule);
+        let mut renderer = Renderer::new(64, 64);
+        lsystem.draw_3d(&mut Turtle3D::new(), &mut renderer);
+        assert!(renderer.lines()[1].end.position.x > renderer.lines()[0].end.position.x);
+    }
+
+    #[test]
+    fn fog_mode_switches_fog_between_vertices_and_the_pipeline() {
+        let mut gui = Gui::new();
+        let rule = koch_rule();
+        gui.set_parameter("Fog Density", 0.5);
+        assert_eq!(fog_far(&gui), Some(110.0));
+        assert!(build_post_process_pipeline(&gui, &rule, true).is_empty());
+        
+        gui.set_parameter("Fog Mode", 1.0);
+        assert!(!build_post_process_pipeline(&gui, &rule, true).is_empty());
+        
+        gui.set_parameter("Fog Density", 0.0);
+        assert_eq!(fog_far(&gui), None);
+        assert!(build_post_process_pipeline(&gui, &rule, true).is_empty());
+    }
+    
+    #[test]
+    fn render_hints_sky_gradient_overrides_the_skybox() {
+        let json = r#"{
+            "name": "Sunset", "axiom": "F", "angle": 25.0, "iterations": 1, "rules": { "F": "FF" },
+            "render_hints": { "line_width_scale": 2.0, "fog_density": 0.1, "show_grid": true, "sky_gradient": [[1.0, 0.5, 0.0], [0.2, 0.1, 0.3]] }
+        }"#;
+        let rule: LSystemRule = serde_json::from_str(json).unwrap();
+        let hints = rule.render_hints.as_ref().unwrap();
+        assert_eq!(hints.line_width_scale, Some(2.0));
+        assert_eq!(hints.unsupported(), vec!["show_grid"]);
+        let skybox = rule.skybox_config();
+        assert_eq!(skybox.zenith, Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(skybox.horizon, Vec3::new(0.2, 0.1, 0.3));
+        assert_eq!(skybox.ground, SkyboxConfig::default().ground);
+    }
+
+    #[test]
+    fn skybox_hemisphere_is_opt_in() {
+        let sky = r#""skybox": { "zenith": [0.1, 0.2, 0.5], "horizon": [0.6, 0.7, 0.8], "ground": [0.1, 0.1, 0.1] }"#;
+        let rule_with = |skybox: &str| -> LSystemRule { serde_json::from_str(&format!(
+            r#"{{ "name": "Dome", "axiom": "F", "angle": 25.0, "iterations": 1, "rules": {{ "F": "FF" }}, {} }}"#, skybox)).unwrap() };
+        assert!(!rule_with(sky).skybox_config().hemisphere);
+        assert!(rule_with(&sky.replace(" }", r#", "hemisphere": true }"#)).skybox_config().hemisphere);
+    }
+
+    #[test]
+    fn snapshot_round_trips_the_generated_string() {
+        let mut lsystem = LSystem::new(LSystemRule { iterations: 3, ..koch_rule() });
+        lsystem.generate();
+        lsystem.current_string = format!("FF[+F[-F]][+F[-F]]{}", lsystem.current_string);
+        assert_eq!(unfold_string(&lsystem.fold_string(1000)), lsystem.current_string);
+
+        for compress in [false, true] {
+            let path = std::env::temp_dir().join(format!("lsystem_snapshot_{}_{}.json", std::process::id(), compress));
+            lsystem.export_to_json_with_generated_string(&path, compress).unwrap();
+            let loaded = LSystem::from_snapshot(&path);
+            fs::remove_file(&path).ok();
+
+            let loaded = loaded.unwrap();
+            assert_eq!(loaded.current_string, lsystem.current_string);
+            assert_eq!(loaded.rule.name, "Koch Curve");
+            assert!(loaded.is_complete());
+        }
+        assert_eq!(lsystem.max_bracket_depth(), 2);
     }
 }
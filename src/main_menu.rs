@@ -1,12 +1,26 @@
+use std::path::{Path, PathBuf};
 use minifb::{Key, Window};
+use crate::gallery::{GalleryBrowser, GalleryRow};
+use crate::renderer::Renderer;
 
-#[derive(Debug, PartialEq)]
+/// Items Page Up and Page Down move the main menu selection by
+const PAGE_JUMP: usize = 5;
+const FOCUS_RING_COLOR: u32 = 0x00FF00;
+/// Main menu layout: the first item's offset below the top, the spacing between items and
+/// the space the footer hint takes at the bottom
+const MAIN_ITEMS_TOP: usize = 80;
+const MAIN_ITEM_PITCH: usize = 42;
+const MAIN_FOOTER_HEIGHT: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MenuState {
     Main,
-    TreeSelection,
-    Parameters,
-    Settings,
     Help,
+    Edit,
+    CameraViews,
+    ViewPresets,
+    Gallery,
+    Export,
     Hidden,
 }
 
@@ -17,44 +31,116 @@ pub struct MainMenuItem {
     pub hotkey: Option<Key>,
 }
 
+/// One row of the main menu, in display order
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MainEntry {
+    TreeSpecies,
+    Gallery,
+    Parameters,
+    EditLSystem,
+    Reload,
+    Mutate,
+    CameraViews,
+    ViewPresets,
+    Export,
+    Help,
+    Exit,
+}
+
+impl MainEntry {
+    const ALL: [MainEntry; 11] = [
+        MainEntry::TreeSpecies,
+        MainEntry::Gallery,
+        MainEntry::Parameters,
+        MainEntry::EditLSystem,
+        MainEntry::Reload,
+        MainEntry::Mutate,
+        MainEntry::CameraViews,
+        MainEntry::ViewPresets,
+        MainEntry::Export,
+        MainEntry::Help,
+        MainEntry::Exit,
+    ];
+    
+    fn item(self) -> MainMenuItem {
+        let (title, description, hotkey) = match self {
+            MainEntry::TreeSpecies => ("Tree Species", "Browse and select different tree species (Tab)", Key::Tab),
+            MainEntry::Gallery => ("Gallery", "Browse example L-systems by category (B)", Key::B),
+            MainEntry::Parameters => ("Parameters", "Adjust L-system parameters in real-time (G)", Key::G),
+            MainEntry::EditLSystem => ("Edit L-system", "Edit the current rule or a copy of it (E)", Key::E),
+            MainEntry::Reload => ("Reload", "Reload current L-system from disk (R)", Key::R),
+            MainEntry::Mutate => ("Mutate", "Randomly perturb the rules, angle and step length; R restores (U)", Key::U),
+            MainEntry::CameraViews => ("Camera Views", "Jump to a saved camera view (V, Ctrl+1-9 to save)", Key::V),
+            MainEntry::ViewPresets => ("View Presets", "Camera angles chosen by the rule's author (K)", Key::K),
+            MainEntry::Export => ("Export", "Save a screenshot, SVG, OBJ mesh or frames (X)", Key::X),
+            MainEntry::Help => ("Help", "Show controls and usage information (H)", Key::H),
+            MainEntry::Exit => ("Exit", "Exit the application (Escape)", Key::Escape),
+        };
+        MainMenuItem { title: title.to_string(), description: description.to_string(), hotkey: Some(hotkey) }
+    }
+}
+
 pub struct MainMenu {
     pub state: MenuState,
     pub main_items: Vec<MainMenuItem>,
     pub selected_index: usize,
+    pub animation_progress: f32,
+    pub animating: bool,
+    pub camera_views: Vec<String>,
+    pub selected_view_index: usize,
+    /// The current rule's `view_presets`, listed by name and description
+    pub view_presets: Vec<MainMenuItem>,
+    pub selected_preset_index: usize,
+    pub gallery: GalleryBrowser,
+    pub export_items: Vec<MainMenuItem>,
+    pub selected_export_index: usize,
+    pub edit_items: Vec<MainMenuItem>,
+    pub selected_edit_index: usize,
+    closing_state: MenuState,
 }
 
 impl MainMenu {
     pub fn new() -> Self {
-        let main_items = vec![
+        let main_items = MainEntry::ALL.iter().map(|entry| entry.item()).collect();
+
+        let export_items = vec![
             MainMenuItem {
-                title: "Tree Species".to_string(),
-                description: "Browse and select different tree species (Tab)".to_string(),
-                hotkey: Some(Key::Tab),
+                title: "Screenshot (PPM)".to_string(),
+                description: "Current view as a PPM image (P)".to_string(),
+                hotkey: Some(Key::P),
             },
             MainMenuItem {
-                title: "Parameters".to_string(),
-                description: "Adjust L-system parameters in real-time (G)".to_string(),
-                hotkey: Some(Key::G),
+                title: "Vector Export (SVG)".to_string(),
+                description: "Current view as SVG line art (S)".to_string(),
+                hotkey: Some(Key::S),
             },
             MainMenuItem {
-                title: "Edit L-system".to_string(),
-                description: "Edit current L-system rules in vim (E)".to_string(),
-                hotkey: Some(Key::E),
+                title: "3D Mesh (OBJ)".to_string(),
+                description: "Branches as solid cylinders with colors (O)".to_string(),
+                hotkey: Some(Key::O),
+            },
+            MainMenuItem {
+                title: "Frame Sequence...".to_string(),
+                description: "One PNG per growth iteration (F)".to_string(),
+                hotkey: Some(Key::F),
             },
             MainMenuItem {
-                title: "Reload".to_string(),
-                description: "Reload current L-system from disk (R)".to_string(),
-                hotkey: Some(Key::R),
+                title: "Snapshot (JSON)".to_string(),
+                description: "The rule and its generated string (J)".to_string(),
+                hotkey: Some(Key::J),
             },
+        ];
+
+        let edit_items = vec![
             MainMenuItem {
-                title: "Help".to_string(),
-                description: "Show controls and usage information (H)".to_string(),
-                hotkey: Some(Key::H),
+                title: "Edit Current Rule".to_string(),
+                description: "Open the rule file in your editor (E)".to_string(),
+                hotkey: Some(Key::E),
             },
             MainMenuItem {
-                title: "Exit".to_string(),
-                description: "Exit the application (Escape)".to_string(),
-                hotkey: Some(Key::Escape),
+                title: "Duplicate Rule".to_string(),
+                description: "Copy the rule to a new file and edit the copy (D)".to_string(),
+                hotkey: Some(Key::D),
             },
         ];
 
@@ -62,25 +148,57 @@ impl MainMenu {
             state: MenuState::Hidden,
             main_items,
             selected_index: 0,
+            animation_progress: 0.0,
+            animating: false,
+            camera_views: Vec::new(),
+            selected_view_index: 0,
+            view_presets: Vec::new(),
+            selected_preset_index: 0,
+            gallery: GalleryBrowser::scan(Path::new("rules")),
+            export_items,
+            selected_export_index: 0,
+            edit_items,
+            selected_edit_index: 0,
+            closing_state: MenuState::Main,
         }
     }
     
     pub fn toggle(&mut self) {
-        self.state = match self.state {
-            MenuState::Hidden => MenuState::Main,
-            _ => MenuState::Hidden,
-        };
+        match self.state {
+            MenuState::Hidden => self.state = MenuState::Main,
+            _ => self.hide(),
+        }
     }
     
+    /// Opens the main menu on the item that was selected when it was last closed
     pub fn show_main(&mut self) {
         self.state = MenuState::Main;
-        self.selected_index = 0;
+        self.selected_index = self.selected_index.min(self.main_items.len().saturating_sub(1));
     }
     
     pub fn hide(&mut self) {
+        if self.state != MenuState::Hidden {
+            // Remember what was on screen so it can slide out
+            self.closing_state = self.state;
+        }
         self.state = MenuState::Hidden;
     }
     
+    pub fn update_animation(&mut self, dt: f32) {
+        let target = if self.is_visible() { 1.0 } else { 0.0 };
+        if self.animation_progress < target {
+            self.animation_progress = (self.animation_progress + dt * 5.0).min(1.0);
+        } else if self.animation_progress > target {
+            self.animation_progress = (self.animation_progress - dt * 5.0).max(0.0);
+        }
+        self.animating = self.animation_progress != target;
+    }
+    
+    fn slide_in_y(&self, target_y: usize, height: usize) -> usize {
+        // Slide in from the bottom edge of the screen
+        target_y + ((1.0 - self.animation_progress) * height.saturating_sub(target_y) as f32) as usize
+    }
+    
     pub fn is_visible(&self) -> bool {
         self.state != MenuState::Hidden
     }
@@ -93,10 +211,27 @@ impl MainMenu {
         match self.state {
             MenuState::Main => self.handle_main_menu_input(window),
             MenuState::Help => self.handle_help_input(window),
+            MenuState::CameraViews => self.handle_camera_views_input(window),
+            MenuState::ViewPresets => self.handle_view_presets_input(window),
+            MenuState::Gallery => self.handle_gallery_input(window),
+            MenuState::Export => self.handle_export_input(window),
+            MenuState::Edit => self.handle_edit_input(window),
             _ => None,
         }
     }
     
+    /// Page Up and Page Down move five items without wrapping; Home and End go to either end
+    fn jump_selection(&mut self, key: Key) {
+        let last = self.main_items.len().saturating_sub(1);
+        self.selected_index = match key {
+            Key::PageUp => self.selected_index.saturating_sub(PAGE_JUMP),
+            Key::PageDown => (self.selected_index + PAGE_JUMP).min(last),
+            Key::Home => 0,
+            Key::End => last,
+            _ => self.selected_index,
+        };
+    }
+    
     fn handle_main_menu_input(&mut self, window: &Window) -> Option<MenuAction> {
         // Navigation
         if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
@@ -111,6 +246,12 @@ impl MainMenu {
             self.selected_index = (self.selected_index + 1) % self.main_items.len();
         }
         
+        for key in [Key::PageUp, Key::PageDown, Key::Home, Key::End] {
+            if window.is_key_pressed(key, minifb::KeyRepeat::No) {
+                self.jump_selection(key);
+            }
+        }
+        
         // Selection
         if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
             return self.execute_selected_item();
@@ -126,13 +267,38 @@ impl MainMenu {
         }
         
         if window.is_key_pressed(Key::E, minifb::KeyRepeat::No) {
-            return Some(MenuAction::EditLSystem);
+            self.show_edit();
+            return None;
         }
         
         if window.is_key_pressed(Key::R, minifb::KeyRepeat::No) {
             return Some(MenuAction::ReloadLSystem);
         }
         
+        if window.is_key_pressed(Key::U, minifb::KeyRepeat::No) {
+            return Some(MenuAction::MutateRule);
+        }
+        
+        if window.is_key_pressed(Key::B, minifb::KeyRepeat::No) {
+            self.show_gallery();
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::V, minifb::KeyRepeat::No) {
+            self.show_camera_views();
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::K, minifb::KeyRepeat::No) {
+            self.show_view_presets();
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::X, minifb::KeyRepeat::No) {
+            self.show_export();
+            return None;
+        }
+        
         if window.is_key_pressed(Key::H, minifb::KeyRepeat::No) {
             self.state = MenuState::Help;
             return None;
@@ -141,6 +307,173 @@ impl MainMenu {
         None
     }
     
+    fn show_edit(&mut self) {
+        self.state = MenuState::Edit;
+        self.selected_edit_index = 0;
+    }
+    
+    fn handle_edit_input(&mut self, window: &Window) -> Option<MenuAction> {
+        if window.is_key_pressed(Key::Backspace, minifb::KeyRepeat::No) {
+            self.state = MenuState::Main;
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
+            if self.selected_edit_index > 0 {
+                self.selected_edit_index -= 1;
+            } else {
+                self.selected_edit_index = self.edit_items.len() - 1;
+            }
+        }
+        
+        if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
+            self.selected_edit_index = (self.selected_edit_index + 1) % self.edit_items.len();
+        }
+        
+        let actions = [MenuAction::EditLSystem, MenuAction::DuplicateRule];
+        if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
+            return actions.into_iter().nth(self.selected_edit_index);
+        }
+        
+        // Direct hotkeys
+        for (item, action) in self.edit_items.iter().zip(actions) {
+            if let Some(key) = item.hotkey
+                && window.is_key_pressed(key, minifb::KeyRepeat::No) {
+                return Some(action);
+            }
+        }
+        
+        None
+    }
+    
+    fn show_export(&mut self) {
+        self.state = MenuState::Export;
+        self.selected_export_index = 0;
+    }
+    
+    fn handle_export_input(&mut self, window: &Window) -> Option<MenuAction> {
+        if window.is_key_pressed(Key::X, minifb::KeyRepeat::No) ||
+           window.is_key_pressed(Key::Backspace, minifb::KeyRepeat::No) {
+            self.state = MenuState::Main;
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
+            if self.selected_export_index > 0 {
+                self.selected_export_index -= 1;
+            } else {
+                self.selected_export_index = self.export_items.len() - 1;
+            }
+        }
+        
+        if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
+            self.selected_export_index = (self.selected_export_index + 1) % self.export_items.len();
+        }
+        
+        let actions = [MenuAction::ExportPPM, MenuAction::ExportSVG, MenuAction::ExportOBJ, MenuAction::ExportFrames,
+                       MenuAction::ExportSnapshot];
+        if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
+            return actions.into_iter().nth(self.selected_export_index);
+        }
+        
+        // Direct hotkeys
+        for (item, action) in self.export_items.iter().zip(actions) {
+            if let Some(key) = item.hotkey
+                && window.is_key_pressed(key, minifb::KeyRepeat::No) {
+                return Some(action);
+            }
+        }
+        
+        None
+    }
+    
+    fn show_gallery(&mut self) {
+        // Rescan so newly added example files show up
+        self.gallery = GalleryBrowser::scan(Path::new("rules"));
+        self.state = MenuState::Gallery;
+    }
+    
+    fn handle_gallery_input(&mut self, window: &Window) -> Option<MenuAction> {
+        if window.is_key_pressed(Key::B, minifb::KeyRepeat::No) ||
+           window.is_key_pressed(Key::Backspace, minifb::KeyRepeat::No) {
+            self.state = MenuState::Main;
+            return None;
+        }
+        
+        self.gallery.handle_input(window).map(MenuAction::LoadRule)
+    }
+    
+    fn show_view_presets(&mut self) {
+        self.state = MenuState::ViewPresets;
+        self.selected_preset_index = 0;
+    }
+    
+    fn handle_view_presets_input(&mut self, window: &Window) -> Option<MenuAction> {
+        if window.is_key_pressed(Key::K, minifb::KeyRepeat::No) ||
+           window.is_key_pressed(Key::Backspace, minifb::KeyRepeat::No) {
+            self.state = MenuState::Main;
+            return None;
+        }
+        
+        if self.view_presets.is_empty() {
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
+            if self.selected_preset_index > 0 {
+                self.selected_preset_index -= 1;
+            } else {
+                self.selected_preset_index = self.view_presets.len() - 1;
+            }
+        }
+        
+        if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
+            self.selected_preset_index = (self.selected_preset_index + 1) % self.view_presets.len();
+        }
+        
+        if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
+            return Some(MenuAction::ApplyViewPreset(self.selected_preset_index));
+        }
+        
+        None
+    }
+    
+    fn show_camera_views(&mut self) {
+        self.state = MenuState::CameraViews;
+        self.selected_view_index = 0;
+    }
+    
+    fn handle_camera_views_input(&mut self, window: &Window) -> Option<MenuAction> {
+        if window.is_key_pressed(Key::V, minifb::KeyRepeat::No) ||
+           window.is_key_pressed(Key::Backspace, minifb::KeyRepeat::No) {
+            self.state = MenuState::Main;
+            return None;
+        }
+        
+        if self.camera_views.is_empty() {
+            return None;
+        }
+        
+        if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
+            if self.selected_view_index > 0 {
+                self.selected_view_index -= 1;
+            } else {
+                self.selected_view_index = self.camera_views.len() - 1;
+            }
+        }
+        
+        if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
+            self.selected_view_index = (self.selected_view_index + 1) % self.camera_views.len();
+        }
+        
+        if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No)
+            && let Some(name) = self.camera_views.get(self.selected_view_index) {
+            return Some(MenuAction::LoadCameraView(name.clone()));
+        }
+        
+        None
+    }
+    
     fn handle_help_input(&mut self, window: &Window) -> Option<MenuAction> {
         if window.is_key_pressed(Key::Escape, minifb::KeyRepeat::No) ||
            window.is_key_pressed(Key::H, minifb::KeyRepeat::No) ||
@@ -151,109 +484,301 @@ impl MainMenu {
     }
     
     fn execute_selected_item(&mut self) -> Option<MenuAction> {
-        match self.selected_index {
-            0 => Some(MenuAction::ShowTreeSelection),
-            1 => Some(MenuAction::ShowParameters),
-            2 => Some(MenuAction::EditLSystem),
-            3 => Some(MenuAction::ReloadLSystem),
-            4 => {
+        match MainEntry::ALL.get(self.selected_index)? {
+            MainEntry::TreeSpecies => Some(MenuAction::ShowTreeSelection),
+            MainEntry::Gallery => {
+                self.show_gallery();
+                None
+            },
+            MainEntry::Parameters => Some(MenuAction::ShowParameters),
+            MainEntry::EditLSystem => {
+                self.show_edit();
+                None
+            },
+            MainEntry::Reload => Some(MenuAction::ReloadLSystem),
+            MainEntry::Mutate => Some(MenuAction::MutateRule),
+            MainEntry::CameraViews => {
+                self.show_camera_views();
+                None
+            },
+            MainEntry::ViewPresets => {
+                self.show_view_presets();
+                None
+            },
+            MainEntry::Export => {
+                self.show_export();
+                None
+            },
+            MainEntry::Help => {
                 self.state = MenuState::Help;
                 None
             },
-            5 => Some(MenuAction::Exit),
-            _ => None,
+            MainEntry::Exit => Some(MenuAction::Exit),
         }
     }
     
     pub fn render(&self, buffer: &mut [u32], width: usize, height: usize, current_tree_name: &str) {
-        if self.state == MenuState::Hidden {
+        if self.animation_progress <= 0.0 {
             return;
         }
         
-        match self.state {
+        let state = if self.state == MenuState::Hidden { self.closing_state } else { self.state };
+        match state {
             MenuState::Main => self.render_main_menu(buffer, width, height, current_tree_name),
             MenuState::Help => self.render_help(buffer, width, height),
+            MenuState::CameraViews => self.render_camera_views(buffer, width, height),
+            MenuState::ViewPresets => self.render_view_presets(buffer, width, height),
+            MenuState::Gallery => self.render_gallery(buffer, width, height),
+            MenuState::Export => self.render_export(buffer, width, height),
+            MenuState::Edit => self.render_edit(buffer, width, height),
             _ => {},
         }
     }
     
     fn render_main_menu(&self, buffer: &mut [u32], width: usize, height: usize, current_tree_name: &str) {
         let menu_width = 500;
-        let menu_height = 400;
-        let menu_x = (width - menu_width) / 2;
-        let menu_y = (height - menu_height) / 2;
+        let menu_height = self.main_menu_height();
+        let menu_x = width.saturating_sub(menu_width) / 2;
+        let menu_y = self.slide_in_y(height.saturating_sub(menu_height) / 2, height);
         
         // Draw menu background with gradient
-        self.fill_rect(buffer, width, height, menu_x, menu_y, menu_width, menu_height, 0x1a1a1a);
-        self.draw_rect(buffer, width, height, menu_x, menu_y, menu_width, menu_height, 0x444444);
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
         
         // Draw title bar
-        self.fill_rect(buffer, width, height, menu_x, menu_y, menu_width, 40, 0x2d2d2d);
-        self.draw_text(buffer, width, height, menu_x + 20, menu_y + 15, "3D L-Systems Main Menu", 0xFFFFFF);
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), "3D L-Systems Main Menu", 0xFFFFFF, 1);
         
         // Draw current tree info
         let info_text = format!("Current: {}", current_tree_name);
-        self.draw_text(buffer, width, height, menu_x + 20, menu_y + 50, &info_text, 0x888888);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 50), &info_text, 0x888888, 1);
         
         // Draw menu items
-        let start_y = menu_y + 80;
+        let start_y = menu_y + MAIN_ITEMS_TOP;
         for (i, item) in self.main_items.iter().enumerate() {
-            let y = start_y + i * 45;
+            let y = start_y + i * MAIN_ITEM_PITCH;
             let color = if i == self.selected_index { 0x00FF00 } else { 0xCCCCCC };
             let bg_color = if i == self.selected_index { 0x333333 } else { 0x1a1a1a };
             
-            // Highlight selected item
+            // Highlight selected item with a 2px focus ring
             if i == self.selected_index {
-                self.fill_rect(buffer, width, height, menu_x + 10, y - 5, menu_width - 20, 35, bg_color);
+                self.fill_rect(buffer, width, height, (menu_x + 10, y - 5, menu_width - 20, 35), bg_color);
+                self.draw_rect(buffer, width, height, (menu_x + 10, y - 5, menu_width - 20, 35), FOCUS_RING_COLOR);
+                self.draw_rect(buffer, width, height, (menu_x + 11, y - 4, menu_width - 22, 33), FOCUS_RING_COLOR);
             }
             
             // Draw hotkey indicator
             if let Some(key) = item.hotkey {
                 let key_text = self.key_to_string(key);
-                self.draw_text(buffer, width, height, menu_x + 20, y, &format!("[{}]", key_text), 0x666666);
+                Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), &format!("[{}]", key_text), 0x666666, 1);
             }
             
             // Draw title and description
-            self.draw_text(buffer, width, height, menu_x + 60, y, &item.title, color);
-            self.draw_text(buffer, width, height, menu_x + 60, y + 15, &item.description, 0x888888);
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 60, y), &item.title, color, 1);
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 60, y + 15), &item.description, 0x888888, 1);
         }
         
         // Draw footer
-        let footer_y = menu_y + menu_height - 30;
-        self.draw_text(buffer, width, height, menu_x + 20, footer_y, 
-                      "Arrow Keys: Navigate | Enter: Select | M: Toggle Menu | Escape: Close", 0x666666);
+        let footer_y = menu_y + menu_height - MAIN_FOOTER_HEIGHT;
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, footer_y), 
+                      "Arrows/PgUp/PgDn/Home/End: Navigate | Enter: Select | M: Menu | Esc: Close", 0x666666, 1);
+    }
+    
+    /// Title bar, current rule, one row per item and the footer, so the footer never overlaps the last item
+    fn main_menu_height(&self) -> usize {
+        MAIN_ITEMS_TOP + self.main_items.len() * MAIN_ITEM_PITCH + MAIN_FOOTER_HEIGHT
+    }
+    
+    fn render_gallery(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let menu_width = 500;
+        let menu_height = 480;
+        let menu_x = (width - menu_width) / 2;
+        let menu_y = self.slide_in_y((height - menu_height) / 2, height);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), "Example Gallery", 0xFFFFFF, 1);
+        
+        let rows = self.gallery.visible_rows();
+        if rows.is_empty() {
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 60), 
+                          "No examples found in rules/ subdirectories", 0x888888, 1);
+        }
+        
+        // Keep the selected row on screen when the list is taller than the panel
+        let max_rows = (menu_height - 100) / 22;
+        let first_row = self.gallery.selected_index.saturating_sub(max_rows.saturating_sub(1));
+        
+        for (i, row) in rows.iter().enumerate().skip(first_row).take(max_rows) {
+            let y = menu_y + 60 + (i - first_row) * 22;
+            let selected = i == self.gallery.selected_index;
+            
+            match *row {
+                GalleryRow::Category(ci) => {
+                    let category = &self.gallery.categories[ci];
+                    let marker = if category.expanded { "[-]" } else { "[+]" };
+                    let text = format!("{} {} ({})", marker, category.name, category.entries.len());
+                    let color = if selected { 0x00FF00 } else { 0xFFFFFF };
+                    Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), &text, color, 1);
+                }
+                GalleryRow::Entry(ci, ei) => {
+                    let entry = &self.gallery.categories[ci].entries[ei];
+                    let color = if selected { 0x00FF00 } else { 0xCCCCCC };
+                    Renderer::render_text_at_screen(buffer, width, height, (menu_x + 52, y), &entry.name, color, 1);
+                }
+            }
+        }
+        
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + menu_height - 30), 
+                      "Enter: Expand/Load | B/Backspace: Back", 0x666666, 1);
+    }
+    
+    fn render_export(&self, buffer: &mut [u32], width: usize, height: usize) {
+        self.render_item_list(buffer, width, height, 345, ("Export", "Enter: Export to exports/ | X/Backspace: Back"),
+                              (&self.export_items, self.selected_export_index));
+    }
+    
+    fn render_edit(&self, buffer: &mut [u32], width: usize, height: usize) {
+        self.render_item_list(buffer, width, height, 210, ("Edit L-system", "Enter: Select | Backspace: Back"),
+                              (&self.edit_items, self.selected_edit_index));
+    }
+    
+    /// Submenu panel listing `items` with their hotkeys and descriptions, between a title bar and a footer hint
+    fn render_item_list(&self, buffer: &mut [u32], width: usize, height: usize, menu_height: usize,
+                        (title, footer): (&str, &str), (items, selected_index): (&[MainMenuItem], usize)) {
+        let menu_width = 400;
+        let menu_x = (width - menu_width) / 2;
+        let menu_y = self.slide_in_y((height - menu_height) / 2, height);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), title, 0xFFFFFF, 1);
+        
+        for (i, item) in items.iter().enumerate() {
+            let y = menu_y + 60 + i * 45;
+            let selected = i == selected_index;
+            if selected {
+                self.fill_rect(buffer, width, height, (menu_x + 10, y - 5, menu_width - 20, 35), 0x333333);
+            }
+            if let Some(key) = item.hotkey {
+                Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), &format!("[{}]", self.key_to_string(key)), 0x666666, 1);
+            }
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 60, y), &item.title, if selected { 0x00FF00 } else { 0xCCCCCC }, 1);
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 60, y + 15), &item.description, 0x888888, 1);
+        }
+        
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + menu_height - 30), 
+                      footer, 0x666666, 1);
+    }
+    
+    fn render_camera_views(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let menu_width = 400;
+        let menu_height = 360;
+        let menu_x = (width - menu_width) / 2;
+        let menu_y = self.slide_in_y((height - menu_height) / 2, height);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), "Camera Views", 0xFFFFFF, 1);
+        
+        if self.camera_views.is_empty() {
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 60), 
+                          "No saved views - press Ctrl+1-9 to save one", 0x888888, 1);
+        }
+        
+        for (i, name) in self.camera_views.iter().enumerate() {
+            let y = menu_y + 60 + i * 25;
+            let color = if i == self.selected_view_index { 0x00FF00 } else { 0xCCCCCC };
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), name, color, 1);
+        }
+        
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + menu_height - 30), 
+                      "Enter: Load view | V/Backspace: Back", 0x666666, 1);
+    }
+    
+    fn render_view_presets(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let menu_width = 400;
+        let menu_height = 360;
+        let menu_x = (width - menu_width) / 2;
+        let menu_y = self.slide_in_y((height - menu_height) / 2, height);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
+        
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), "View Presets", 0xFFFFFF, 1);
+        
+        if self.view_presets.is_empty() {
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 60), 
+                          "This rule has no view_presets", 0x888888, 1);
+        }
+        
+        for (i, preset) in self.view_presets.iter().enumerate() {
+            let y = menu_y + 60 + i * 40;
+            let selected = i == self.selected_preset_index;
+            if selected {
+                self.fill_rect(buffer, width, height, (menu_x + 10, y - 5, menu_width - 20, 35), 0x333333);
+            }
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), &preset.title, if selected { 0x00FF00 } else { 0xCCCCCC }, 1);
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y + 15), &preset.description, 0x888888, 1);
+        }
+        
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + menu_height - 30), 
+                      "Enter: Apply view | K/Backspace: Back", 0x666666, 1);
     }
     
     fn render_help(&self, buffer: &mut [u32], width: usize, height: usize) {
         let menu_width = 600;
-        let menu_height = 500;
+        let menu_height = 600;
         let menu_x = (width - menu_width) / 2;
-        let menu_y = (height - menu_height) / 2;
+        let menu_y = self.slide_in_y((height - menu_height) / 2, height);
         
         // Draw help background
-        self.fill_rect(buffer, width, height, menu_x, menu_y, menu_width, menu_height, 0x1a1a1a);
-        self.draw_rect(buffer, width, height, menu_x, menu_y, menu_width, menu_height, 0x444444);
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x1a1a1a);
+        self.draw_rect(buffer, width, height, (menu_x, menu_y, menu_width, menu_height), 0x444444);
         
         // Draw title
-        self.fill_rect(buffer, width, height, menu_x, menu_y, menu_width, 40, 0x2d2d2d);
-        self.draw_text(buffer, width, height, menu_x + 20, menu_y + 15, "Controls & Help", 0xFFFFFF);
+        self.fill_rect(buffer, width, height, (menu_x, menu_y, menu_width, 40), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, menu_y + 15), "Controls & Help", 0xFFFFFF, 1);
         
         let help_text = vec![
             "Camera Controls:",
             "  Mouse + Drag: Rotate camera around tree",
             "  Mouse Wheel: Zoom in/out",
+            "  Arrow Keys: Orbit camera (when no menu is open)",
+            "  Page Up/Page Down: Zoom in/out",
+            "  Ctrl+1-9: Save view (first press) / load view",
+            "  K (in main menu): Rule's view presets",
             "",
             "Tree Selection:",
             "  1-9: Load specific tree species",
             "  Tab: Open tree species menu",
+            "  B (in main menu): Browse example gallery",
+            "  X (in main menu): Export screenshot, SVG, OBJ or frames",
             "",
             "Editing & Parameters:",
-            "  G: Toggle parameter sliders (real-time editing)",
+            "  G: Toggle parameter controls (real-time editing)",
             "  E: Edit L-system rules in vim",
             "  R: Reload current L-system from disk",
             "",
             "Interface:",
             "  M: Toggle this main menu",
+            "  N: Switch between line and cone branches",
+            "  A: Toggle auto-orbit",
+            "  F: Fit the view to the tree again",
+            "  S: Export the current view as SVG",
+            "  P: Save a PNG screenshot to screenshots/",
+            "  ?: Print statistics about the generated string",
+            "  T: Step through iterations (Space / Backspace)",
+            "  Ctrl+G: Export a growth GIF, one frame per iteration",
+            "  Ctrl+Z / Ctrl+Y: Undo / redo slider changes",
+            "  Ctrl+Backspace: Reset sliders to defaults",
             "  H: Toggle help screen",
             "  Escape: Exit application",
             "",
@@ -277,9 +802,10 @@ impl MainMenu {
             };
             
             if !line.is_empty() {
-                self.draw_text(buffer, width, height, menu_x + 20, y, line, color);
+                Renderer::render_text_at_screen(buffer, width, height, (menu_x + 20, y), line, color, 1);
             }
-            y += 18;
+            // Tight enough that every line fits inside the 600 pixel panel
+            y += 14;
         }
     }
     
@@ -289,36 +815,47 @@ impl MainMenu {
             Key::G => "G",
             Key::E => "E", 
             Key::R => "R",
+            Key::U => "U",
             Key::H => "H",
+            Key::V => "V",
+            Key::B => "B",
+            Key::X => "X",
+            Key::P => "P",
+            Key::S => "S",
+            Key::O => "O",
+            Key::F => "F",
             Key::Escape => "Esc",
             _ => "?",
         }
     }
     
     fn fill_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize, 
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
         for dy in 0..h {
             for dx in 0..w {
                 let px = x + dx;
                 let py = y + dy;
                 if px < buf_width && py < buf_height {
-                    buffer[py * buf_width + px] = color;
+                    let idx = py * buf_width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
     }
     
     fn draw_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize,
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
         // Top and bottom borders
         for dx in 0..w {
             let px = x + dx;
             if px < buf_width {
                 if y < buf_height {
-                    buffer[y * buf_width + px] = color;
+                    let idx = y * buf_width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if y + h - 1 < buf_height {
-                    buffer[(y + h - 1) * buf_width + px] = color;
+                    let idx = (y + h - 1) * buf_width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
@@ -328,48 +865,91 @@ impl MainMenu {
             let py = y + dy;
             if py < buf_height {
                 if x < buf_width {
-                    buffer[py * buf_width + x] = color;
+                    let idx = py * buf_width + x;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if x + w - 1 < buf_width {
-                    buffer[py * buf_width + (x + w - 1)] = color;
-                }
-            }
-        }
-    }
-    
-    fn draw_text(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize,
-                x: usize, y: usize, text: &str, color: u32) {
-        // Use same text rendering as menu.rs for consistency
-        let char_width = 8;
-        let char_height = 12;
-        
-        for (i, _c) in text.chars().enumerate() {
-            let char_x = x + i * char_width;
-            
-            // Draw a simple rectangle for each character
-            for dy in 0..char_height {
-                for dx in 0..char_width {
-                    let px = char_x + dx;
-                    let py = y + dy;
-                    
-                    if px < buf_width && py < buf_height {
-                        // Simple pattern to make text visible
-                        if (dy == 0 || dy == char_height - 1 || dx == 0 || dx == char_width - 1) && 
-                           dy >= 2 && dy < char_height - 2 {
-                            buffer[py * buf_width + px] = color;
-                        }
-                    }
+                    let idx = py * buf_width + (x + w - 1);
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
     }
 }
 
+fn lerp_color(existing: u32, color: u32, alpha: f32) -> u32 {
+    let alpha = alpha.clamp(0.0, 1.0);
+    let blend = |shift: u32| {
+        let a = ((existing >> shift) & 0xFF) as f32;
+        let b = ((color >> shift) & 0xFF) as f32;
+        ((a + (b - a) * alpha) as u32) << shift
+    };
+    blend(16) | blend(8) | blend(0)
+}
+
 #[derive(Debug, PartialEq)]
 pub enum MenuAction {
     ShowTreeSelection,
     ShowParameters,
     EditLSystem,
     ReloadLSystem,
+    /// Replace the current rule with a randomly perturbed copy, without saving it
+    MutateRule,
+    LoadCameraView(String),
+    /// Index into `MainMenu::view_presets`
+    ApplyViewPreset(usize),
+    LoadRule(PathBuf),
+    ExportPPM,
+    ExportSVG,
+    ExportOBJ,
+    ExportFrames,
+    ExportSnapshot,
+    DuplicateRule,
     Exit,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_selection_pages_without_wrapping() {
+        let mut menu = MainMenu::new();
+        let last = menu.main_items.len() - 1;
+        menu.jump_selection(Key::PageDown);
+        assert_eq!(menu.selected_index, 5);
+        menu.jump_selection(Key::PageDown);
+        assert_eq!(menu.selected_index, last);
+        menu.jump_selection(Key::PageUp);
+        assert_eq!(menu.selected_index, last - 5);
+        menu.jump_selection(Key::Home);
+        assert_eq!(menu.selected_index, 0);
+        menu.jump_selection(Key::End);
+        assert_eq!(menu.selected_index, last);
+
+        menu.hide();
+        menu.show_main();
+        assert_eq!(menu.selected_index, last);
+    }
+    
+    #[test]
+    fn main_menu_fits_the_window_and_every_item_has_an_action() {
+        let mut menu = MainMenu::new();
+        assert_eq!(menu.main_items.len(), MainEntry::ALL.len());
+        // The last description, 15px below its title, ends above the footer
+        let last_description = MAIN_ITEMS_TOP + (menu.main_items.len() - 1) * MAIN_ITEM_PITCH + 15 + 8;
+        assert!(last_description < menu.main_menu_height() - MAIN_FOOTER_HEIGHT);
+        assert!(menu.main_menu_height() <= 600);
+        
+        menu.selected_index = menu.main_items.len() - 1;
+        assert_eq!(menu.execute_selected_item(), Some(MenuAction::Exit));
+        menu.selected_index = 1;
+        assert_eq!(menu.execute_selected_item(), None);
+        assert_eq!(menu.state, MenuState::Gallery);
+        
+        menu.animation_progress = 1.0;
+        menu.state = MenuState::Main;
+        let mut buffer = vec![0; 400 * 300];
+        menu.render(&mut buffer, 400, 300, "Small");
+    }
+}
@@ -1,12 +1,24 @@
 use std::fs;
-use std::path::PathBuf;
-use minifb::{Key, Window};
+use std::path::{Path, PathBuf};
+use glam::Vec3;
+use minifb::{Key, KeyRepeat, Window};
+use crate::gui::Gui;
+use crate::renderer::Renderer;
+use crate::wizard::{key_to_char, Wizard, WizardOutcome};
+
+/// Seconds without typing before the search query is cleared
+const SEARCH_RESET_SECONDS: f32 = 1.0;
+const SEARCH_BOX_HEIGHT: usize = 24;
+const ROW_HEIGHT: usize = 30;
+/// Space above the rows for the title and search box
+const MENU_HEADER_HEIGHT: usize = 40 + SEARCH_BOX_HEIGHT;
 
 #[derive(Debug, Clone)]
 pub struct MenuItem {
     pub name: String,
     pub file_path: PathBuf,
     pub hotkey: Option<Key>,
+    pub primary_color: Option<Vec3>,
 }
 
 pub struct Menu {
@@ -14,6 +26,12 @@ pub struct Menu {
     pub selected_index: usize,
     pub visible: bool,
     pub rules_directory: PathBuf,
+    pub animation_progress: f32,
+    pub animating: bool,
+    pub wizard: Option<Wizard>,
+    /// Typed while the menu is open; selects the first item whose name contains it
+    pub search_query: String,
+    search_idle_time: f32,
 }
 
 impl Menu {
@@ -24,6 +42,11 @@ impl Menu {
             selected_index: 0,
             visible: false,
             rules_directory: rules_dir,
+            animation_progress: 0.0,
+            animating: false,
+            wizard: None,
+            search_query: String::new(),
+            search_idle_time: 0.0,
         };
         menu.load_items();
         menu
@@ -50,68 +73,168 @@ impl Menu {
             if path_buf.exists() {
                 self.items.push(MenuItem {
                     name: name.to_string(),
+                    primary_color: read_primary_color(&path_buf),
                     file_path: path_buf,
                     hotkey: key,
                 });
             }
         }
         
-        // Load additional JSON files from rules directory
+        // Load additional JSON and TOML files from rules directory
         if let Ok(entries) = fs::read_dir(&self.rules_directory) {
             for entry in entries.flatten() {
                 let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == "json" {
-                        let file_name = path.file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("Unknown");
-                        
-                        // Skip if already added as default
-                        if !self.items.iter().any(|item| item.file_path == path) {
-                            self.items.push(MenuItem {
-                                name: file_name.replace('_', " ").to_string(),
-                                file_path: path,
-                                hotkey: None,
-                            });
-                        }
+                let is_views_file = path.to_str().is_some_and(|p| p.ends_with(".views.json"));
+                if let Some(extension) = path.extension()
+                    && (extension == "json" || extension == "toml") && !is_views_file {
+                    let file_name = path.file_stem()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or("Unknown");
+                    
+                    // Skip if already added as default
+                    if !self.items.iter().any(|item| item.file_path == path) {
+                        self.items.push(MenuItem {
+                            name: file_name.replace('_', " ").to_string(),
+                            primary_color: read_primary_color(&path),
+                            file_path: path,
+                            hotkey: None,
+                        });
                     }
                 }
             }
         }
     }
     
+    /// Opens the step-by-step dialog for writing a new rule file
+    pub fn new_rule_wizard(&mut self) {
+        self.visible = false;
+        self.wizard = Some(Wizard::new(self.rules_directory.clone()));
+    }
+    
     pub fn toggle(&mut self) {
         self.visible = !self.visible;
+        self.search_query.clear();
+    }
+    
+    /// Adds `query` to the search and moves the selection to the first item whose name contains
+    /// the whole query, ignoring case
+    pub fn keyboard_search(&mut self, query: char) -> Option<PathBuf> {
+        self.search_query.push(query.to_ascii_lowercase());
+        self.search_idle_time = 0.0;
+        self.select_first_match()
+    }
+    
+    fn select_first_match(&mut self) -> Option<PathBuf> {
+        let index = self.items.iter().position(|item| self.match_range(&item.name).is_some())?;
+        self.selected_index = index;
+        Some(self.items[index].file_path.clone())
+    }
+    
+    /// Byte range of the search query within `name`
+    fn match_range(&self, name: &str) -> Option<std::ops::Range<usize>> {
+        if self.search_query.is_empty() {
+            return None;
+        }
+        let start = name.to_ascii_lowercase().find(&self.search_query)?;
+        Some(start..start + self.search_query.len())
+    }
+    
+    /// Clears the search query after a second without typing
+    pub fn update_search(&mut self, dt: f32) {
+        if self.search_query.is_empty() {
+            return;
+        }
+        self.search_idle_time += dt;
+        if self.search_idle_time >= SEARCH_RESET_SECONDS {
+            self.search_query.clear();
+        }
+    }
+    
+    pub fn update_animation(&mut self, dt: f32) {
+        let target = if self.visible { 1.0 } else { 0.0 };
+        if self.animation_progress < target {
+            self.animation_progress = (self.animation_progress + dt * 5.0).min(1.0);
+        } else if self.animation_progress > target {
+            self.animation_progress = (self.animation_progress - dt * 5.0).max(0.0);
+        }
+        self.animating = self.animation_progress != target;
     }
     
     pub fn handle_input(&mut self, window: &Window) -> Option<PathBuf> {
+        // The wizard owns the keyboard while it is open
+        if let Some(wizard) = &mut self.wizard {
+            match wizard.handle_input(window) {
+                Some(WizardOutcome::Finished { rule, path }) => {
+                    self.wizard = None;
+                    match rule.save_to_file(&path) {
+                        Ok(_) => {
+                            self.load_items();
+                            if let Some(index) = self.items.iter().position(|item| item.file_path == path) {
+                                self.selected_index = index;
+                            }
+                            return Some(path);
+                        }
+                        Err(e) => eprintln!("Error saving new rule: {}", e),
+                    }
+                }
+                Some(WizardOutcome::Cancelled) => self.wizard = None,
+                None => {}
+            }
+            return None;
+        }
+        
         if !self.visible {
             // Handle hotkeys even when menu is not visible
             for item in &self.items {
-                if let Some(key) = item.hotkey {
-                    if window.is_key_pressed(key, minifb::KeyRepeat::No) {
-                        return Some(item.file_path.clone());
-                    }
+                if let Some(key) = item.hotkey
+                    && window.is_key_pressed(key, minifb::KeyRepeat::No) {
+                    return Some(item.file_path.clone());
                 }
             }
             return None;
         }
         
+        // Printable keys search the menu; Escape clears the search before closing the menu
+        if window.is_key_pressed(Key::Escape, KeyRepeat::No) {
+            if self.search_query.is_empty() {
+                self.visible = false;
+            } else {
+                self.search_query.clear();
+            }
+            return None;
+        }
+        
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for key in window.get_keys_pressed(KeyRepeat::Yes) {
+            if key == Key::Backspace {
+                if self.search_query.pop().is_some() {
+                    self.search_idle_time = 0.0;
+                    self.select_first_match();
+                }
+            } else if let Some(c) = key_to_char(key, shift) {
+                self.keyboard_search(c);
+            }
+        }
+        
         // Navigation when menu is visible
+        // The last row is "New Rule..."
+        let row_count = self.items.len() + 1;
         if window.is_key_pressed(Key::Up, minifb::KeyRepeat::No) {
             if self.selected_index > 0 {
                 self.selected_index -= 1;
             } else {
-                self.selected_index = self.items.len().saturating_sub(1);
+                self.selected_index = row_count - 1;
             }
         }
         
         if window.is_key_pressed(Key::Down, minifb::KeyRepeat::No) {
-            self.selected_index = (self.selected_index + 1) % self.items.len().max(1);
+            self.selected_index = (self.selected_index + 1) % row_count;
         }
         
         if window.is_key_pressed(Key::Enter, minifb::KeyRepeat::No) {
-            if let Some(item) = self.items.get(self.selected_index) {
+            if self.selected_index == self.items.len() {
+                self.new_rule_wizard();
+            } else if let Some(item) = self.items.get(self.selected_index) {
                 self.visible = false;
                 return Some(item.file_path.clone());
             }
@@ -121,30 +244,50 @@ impl Menu {
     }
     
     pub fn render_to_buffer(&self, buffer: &mut [u32], width: usize, height: usize) {
-        if !self.visible || self.items.is_empty() {
+        if let Some(wizard) = &self.wizard {
+            wizard.render(buffer, width, height);
+            return;
+        }
+        
+        if self.animation_progress <= 0.0 {
             return;
         }
         
         let menu_width = 300;
-        let menu_height = self.items.len() * 30 + 40;
-        let menu_x = (width - menu_width) / 2;
-        let menu_y = (height - menu_height) / 2;
+        let rows = self.visible_rows(height);
+        let menu_height = rows.len() * ROW_HEIGHT + MENU_HEADER_HEIGHT;
+        let menu_y = height.saturating_sub(menu_height) / 2;
+        
+        // Slide in from the right edge of the screen
+        let target_x = width.saturating_sub(menu_width) / 2;
+        let menu_x = target_x + ((1.0 - self.animation_progress) * (width - target_x) as f32) as usize;
         
         // Draw menu background
         self.fill_rect(buffer, width, height, 
-                      menu_x, menu_y, menu_width, menu_height, 0x404040);
+                      (menu_x, menu_y, menu_width, menu_height), 0x404040);
         
         // Draw border
         self.draw_rect(buffer, width, height, 
-                      menu_x, menu_y, menu_width, menu_height, 0xFFFFFF);
+                      (menu_x, menu_y, menu_width, menu_height), 0xFFFFFF);
         
         // Draw title
-        self.draw_text(buffer, width, height, 
-                      menu_x + 10, menu_y + 10, "L-System Menu", 0xFFFFFF);
+        Renderer::render_text_at_screen(buffer, width, height, 
+                      (menu_x + 10, menu_y + 10), "L-System Menu", 0xFFFFFF, 1);
+        
+        // Draw search box
+        let search_y = menu_y + 30;
+        self.fill_rect(buffer, width, height, (menu_x + 10, search_y, menu_width - 20, SEARCH_BOX_HEIGHT - 6), 0x202020);
+        let (search_text, search_color) = if self.search_query.is_empty() {
+            ("Type to search".to_string(), 0x666666)
+        } else {
+            (format!("Search: {}_", self.search_query), 0xFFFFFF)
+        };
+        Renderer::render_text_at_screen(buffer, width, height, (menu_x + 14, search_y + 5), &search_text, search_color, 1);
         
         // Draw menu items
-        for (i, item) in self.items.iter().enumerate() {
-            let y = menu_y + 40 + i * 30;
+        let items_y = menu_y + MENU_HEADER_HEIGHT;
+        for (i, item) in self.items.iter().enumerate().skip(rows.start).take(rows.len()) {
+            let y = items_y + (i - rows.start) * ROW_HEIGHT;
             let color = if i == self.selected_index { 0x00FF00 } else { 0xCCCCCC };
             
             let text = if let Some(key) = item.hotkey {
@@ -153,13 +296,37 @@ impl Menu {
                 item.name.clone()
             };
             
-            self.draw_text(buffer, width, height, menu_x + 10, y, &text, color);
+            Renderer::render_text_at_screen(buffer, width, height, (menu_x + 10, y), &text, color, 1);
+            // Redraw the part of the name matching the search in its own color
+            if let Some(range) = self.match_range(&item.name) {
+                let match_x = menu_x + 10 + Renderer::measure_text(&item.name[..range.start], 1).0;
+                Renderer::render_text_at_screen(buffer, width, height, (match_x, y), &item.name[range], 0xFFFF00, 1);
+            }
+            if let Some(primary) = item.primary_color {
+                Gui::render_color_swatch(buffer, width, height, (menu_x + menu_width - 24, y + 1, 10, 10), primary);
+            }
+        }
+        
+        if rows.contains(&self.items.len()) {
+            let new_rule_color = if self.selected_index == self.items.len() { 0x00FF00 } else { 0xCCCCCC };
+            Renderer::render_text_at_screen(buffer, width, height, 
+                          (menu_x + 10, items_y + (self.items.len() - rows.start) * ROW_HEIGHT), "New Rule...", new_rule_color, 1);
         }
         
         // Draw instructions
-        let instructions = "Arrow keys: Navigate | Enter: Select | Tab: Toggle Menu | E: Edit";
-        self.draw_text(buffer, width, height, 
-                      menu_x + 10, menu_y + menu_height - 20, instructions, 0x888888);
+        let instructions = "Type: Search | Arrow keys: Navigate | Enter: Select | Esc: Clear/Close";
+        Renderer::render_text_at_screen(buffer, width, height, 
+                      (menu_x + 10, menu_y + menu_height - 20), instructions, 0x888888, 1);
+    }
+    
+    /// Rows that fit in a window `height` pixels tall, scrolled to keep the selection in view;
+    /// the last row is "New Rule..."
+    fn visible_rows(&self, height: usize) -> std::ops::Range<usize> {
+        let row_count = self.items.len() + 1;
+        let max_rows = (height.saturating_sub(MENU_HEADER_HEIGHT) / ROW_HEIGHT).clamp(1, row_count);
+        let selected = self.selected_index.min(row_count - 1);
+        let start = (selected + 1).saturating_sub(max_rows);
+        start..start + max_rows
     }
     
     fn key_to_string(&self, key: Key) -> &'static str {
@@ -179,29 +346,32 @@ impl Menu {
     }
     
     fn fill_rect(&self, buffer: &mut [u32], width: usize, height: usize, 
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
         for dy in 0..h {
             for dx in 0..w {
                 let px = x + dx;
                 let py = y + dy;
                 if px < width && py < height {
-                    buffer[py * width + px] = color;
+                    let idx = py * width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
     }
     
     fn draw_rect(&self, buffer: &mut [u32], width: usize, height: usize,
-                x: usize, y: usize, w: usize, h: usize, color: u32) {
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
         // Top and bottom borders
         for dx in 0..w {
             let px = x + dx;
             if px < width {
                 if y < height {
-                    buffer[y * width + px] = color;
+                    let idx = y * width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if y + h - 1 < height {
-                    buffer[(y + h - 1) * width + px] = color;
+                    let idx = (y + h - 1) * width + px;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
@@ -211,44 +381,106 @@ impl Menu {
             let py = y + dy;
             if py < height {
                 if x < width {
-                    buffer[py * width + x] = color;
+                    let idx = py * width + x;
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
                 if x + w - 1 < width {
-                    buffer[py * width + (x + w - 1)] = color;
+                    let idx = py * width + (x + w - 1);
+                    buffer[idx] = lerp_color(buffer[idx], color, self.animation_progress);
                 }
             }
         }
     }
+}
+
+/// First palette entry of a rule file, without fully loading the rule
+fn read_primary_color(path: &Path) -> Option<Vec3> {
+    let contents = fs::read_to_string(path).ok()?;
+    let json: serde_json::Value = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&contents).ok()?
+    } else {
+        serde_json::from_str(&contents).ok()?
+    };
+    let first = json.get("colors")?.get("palette")?.get(0)?.as_array()?;
+    let channel = |i: usize| first.get(i).and_then(|v| v.as_f64()).map(|v| v as f32);
+    Some(Vec3::new(channel(0)?, channel(1)?, channel(2)?))
+}
+
+fn lerp_color(existing: u32, color: u32, alpha: f32) -> u32 {
+    let alpha = alpha.clamp(0.0, 1.0);
+    let blend = |shift: u32| {
+        let a = ((existing >> shift) & 0xFF) as f32;
+        let b = ((color >> shift) & 0xFF) as f32;
+        ((a + (b - a) * alpha) as u32) << shift
+    };
+    blend(16) | blend(8) | blend(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn menu_with(names: &[&str]) -> Menu {
+        Menu {
+            items: names.iter().map(|name| MenuItem {
+                name: name.to_string(),
+                file_path: PathBuf::from(format!("rules/{}.json", name)),
+                hotkey: None,
+                primary_color: None,
+            }).collect(),
+            selected_index: 0,
+            visible: true,
+            rules_directory: PathBuf::from("rules"),
+            animation_progress: 1.0,
+            animating: false,
+            wizard: None,
+            search_query: String::new(),
+            search_idle_time: 0.0,
+        }
+    }
     
-    fn draw_text(&self, buffer: &mut [u32], width: usize, height: usize,
-                x: usize, y: usize, text: &str, color: u32) {
-        // Simple bitmap font rendering - just draw colored pixels
-        // This is a basic implementation, could be improved with actual font rendering
-        let char_width = 8;
-        let char_height = 12;
+    #[test]
+    fn keyboard_search_selects_first_case_insensitive_match() {
+        let mut menu = menu_with(&["Oak Tree", "Pine Tree", "Weeping Willow"]);
+        assert_eq!(menu.keyboard_search('T'), Some(PathBuf::from("rules/Oak Tree.json")));
+        menu.search_query.clear();
+        assert_eq!(menu.keyboard_search('w'), Some(PathBuf::from("rules/Weeping Willow.json")));
+        assert_eq!(menu.keyboard_search('I'), Some(PathBuf::from("rules/Weeping Willow.json")));
+        assert_eq!(menu.selected_index, 2);
+        assert_eq!(menu.match_range("Weeping Willow"), Some(8..10));
         
-        for (i, _c) in text.chars().enumerate() {
-            let char_x = x + i * char_width;
-            
-            // Draw a simple rectangle for each character
-            for dy in 0..char_height {
-                for dx in 0..char_width {
-                    let px = char_x + dx;
-                    let py = y + dy;
-                    
-                    if px < width && py < height {
-                        // Simple pattern to make text visible
-                        if (dy == 0 || dy == char_height - 1 || dx == 0 || dx == char_width - 1) && 
-                           dy >= 2 && dy < char_height - 2 {
-                            buffer[py * width + px] = color;
-                        }
-                    }
-                }
-            }
-        }
+        assert_eq!(menu.keyboard_search('x'), None);
+        assert_eq!(menu.selected_index, 2);
     }
     
-    pub fn get_selected_file(&self) -> Option<PathBuf> {
-        self.items.get(self.selected_index).map(|item| item.file_path.clone())
+    #[test]
+    fn long_menu_scrolls_to_keep_selection_visible() {
+        let names: Vec<String> = (0..40).map(|i| format!("Rule {}", i)).collect();
+        let mut menu = menu_with(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        let (width, height) = (800, 600);
+        let max_rows = (height - MENU_HEADER_HEIGHT) / ROW_HEIGHT;
+        
+        assert_eq!(menu.visible_rows(height), 0..max_rows);
+        menu.selected_index = 30;
+        let rows = menu.visible_rows(height);
+        assert!(rows.contains(&30) && rows.len() == max_rows);
+        menu.selected_index = menu.items.len();
+        assert_eq!(menu.visible_rows(height).end, menu.items.len() + 1);
+        
+        let mut buffer = vec![0; width * height];
+        menu.render_to_buffer(&mut buffer, width, height);
+        menu.render_to_buffer(&mut buffer[..width * 20], width, 20);
+    }
+    
+    #[test]
+    fn search_query_clears_after_a_second_idle() {
+        let mut menu = menu_with(&["Oak Tree"]);
+        menu.keyboard_search('o');
+        menu.update_search(0.6);
+        menu.keyboard_search('a');
+        menu.update_search(0.6);
+        assert_eq!(menu.search_query, "oa");
+        menu.update_search(0.5);
+        assert!(menu.search_query.is_empty());
     }
-}
\ No newline at end of file
+}
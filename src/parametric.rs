@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+
+/// Left-hand side of a parametric production such as `F(x, y)`: the symbol and the names its
+/// arguments are bound to in the successor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predecessor {
+    pub symbol: char,
+    pub params: Vec<String>,
+}
+
+impl Predecessor {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let symbol = s.chars().next().ok_or("Empty predecessor")?;
+        let rest = &s[symbol.len_utf8()..];
+        if rest.is_empty() {
+            return Ok(Predecessor { symbol, params: Vec::new() });
+        }
+        let inner = rest.strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| format!("Predecessor '{}' must look like F(x, y)", s))?;
+        let params: Vec<String> = split_arguments(inner).into_iter().map(|param| param.trim().to_string()).collect();
+        if let Some(param) = params.iter().find(|param| !is_identifier(param)) {
+            return Err(format!("Predecessor '{}' has an invalid parameter name '{}'", s, param));
+        }
+        Ok(Predecessor { symbol, params })
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Byte length of the `(...)` group `s` starts with, parentheses included, or `None` if `s`
+/// doesn't start with one or never closes it
+pub fn argument_group_len(s: &str) -> Option<usize> {
+    if !s.starts_with('(') {
+        return None;
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `s` split at the commas that aren't inside nested parentheses
+pub fn split_arguments(s: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(&s[start..]);
+    arguments
+}
+
+/// The literal arguments of a token such as `F(1.5,2)`; empty for a plain symbol
+pub fn literal_arguments(token: &str) -> Vec<f32> {
+    let Some(c) = token.chars().next() else { return Vec::new() };
+    let group = &token[c.len_utf8()..];
+    match argument_group_len(group) {
+        Some(len) => split_arguments(&group[1..len - 1]).iter().filter_map(|arg| arg.trim().parse().ok()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// `successor` with every argument expression replaced by its value, where `params` name `args`;
+/// `F(x*0.5)` with `x` bound to 2 becomes `F(1)`
+pub fn instantiate<'a>(successor: &'a str, params: &[String], args: &[f32]) -> Result<Cow<'a, str>, String> {
+    if !successor.contains('(') {
+        return Ok(Cow::Borrowed(successor));
+    }
+    let mut output = String::with_capacity(successor.len());
+    let mut rest = successor;
+    while let Some(c) = rest.chars().next() {
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+        let Some(len) = argument_group_len(rest) else { continue };
+        let values = split_arguments(&rest[1..len - 1]).into_iter()
+            .map(|expression| evaluate(expression, params, args))
+            .collect::<Result<Vec<f32>, String>>()
+            .map_err(|e| format!("{} in '{}'", e, successor))?;
+        output.push('(');
+        output.push_str(&values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(","));
+        output.push(')');
+        rest = &rest[len..];
+    }
+    Ok(Cow::Owned(output))
+}
+
+/// Value of an arithmetic expression of numbers, `params` (bound to `args`), `+ - * /` and
+/// parentheses
+pub fn evaluate(expression: &str, params: &[String], args: &[f32]) -> Result<f32, String> {
+    let mut parser = Parser { input: expression.as_bytes(), pos: 0, params, args };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    match parser.input.get(parser.pos) {
+        Some(&c) => Err(format!("Unexpected '{}' in expression '{}'", c as char, expression)),
+        None => Ok(value),
+    }
+}
+
+/// Recursive-descent parser over `expression := term (('+' | '-') term)*`,
+/// `term := factor (('*' | '/') factor)*` and `factor := ('-' | '+') factor | number | name | '(' expression ')'`
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    params: &'a [String],
+    args: &'a [f32],
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.input.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+    
+    /// Consumes the next non-space byte if it is one of `operators`
+    fn operator(&mut self, operators: &[u8]) -> Option<u8> {
+        self.skip_whitespace();
+        let c = *self.input.get(self.pos)?;
+        if operators.contains(&c) {
+            self.pos += 1;
+            Some(c)
+        } else {
+            None
+        }
+    }
+    
+    fn expression(&mut self) -> Result<f32, String> {
+        let mut value = self.term()?;
+        while let Some(op) = self.operator(b"+-") {
+            let rhs = self.term()?;
+            value = if op == b'+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+    
+    fn term(&mut self) -> Result<f32, String> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.operator(b"*/") {
+            let rhs = self.factor()?;
+            value = if op == b'*' { value * rhs } else { value / rhs };
+        }
+        Ok(value)
+    }
+    
+    fn factor(&mut self) -> Result<f32, String> {
+        if let Some(sign) = self.operator(b"+-") {
+            let value = self.factor()?;
+            return Ok(if sign == b'-' { -value } else { value });
+        }
+        if self.operator(b"(").is_some() {
+            let value = self.expression()?;
+            return match self.operator(b")") {
+                Some(_) => Ok(value),
+                None => Err("Missing ')'".to_string()),
+            };
+        }
+        
+        let start = self.pos;
+        match self.input.get(self.pos) {
+            Some(c) if c.is_ascii_digit() || *c == b'.' => {
+                while self.input.get(self.pos).is_some_and(|c| c.is_ascii_digit() || *c == b'.') {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.input[start..self.pos]).expect("digits are ASCII");
+                number.parse().map_err(|_| format!("Invalid number '{}'", number))
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {
+                while self.input.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_') {
+                    self.pos += 1;
+                }
+                let name = std::str::from_utf8(&self.input[start..self.pos]).expect("names are ASCII");
+                self.params.iter().position(|param| param == name)
+                    .and_then(|i| self.args.get(i).copied())
+                    .ok_or_else(|| format!("Unknown parameter '{}'", name))
+            }
+            Some(&c) => Err(format!("Unexpected '{}'", c as char)),
+            None => Err("Expression ends too early".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn evaluate_follows_precedence_and_parentheses() {
+        let params = vec!["x".to_string(), "len".to_string()];
+        assert_eq!(evaluate("1 + 2 * 3", &[], &[]), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3", &[], &[]), Ok(9.0));
+        assert_eq!(evaluate("-x / 4 - 1", &params, &[2.0, 0.0]), Ok(-1.5));
+        assert_eq!(evaluate("len*0.5", &params, &[0.0, 3.0]), Ok(1.5));
+        assert!(evaluate("y + 1", &params, &[1.0, 1.0]).is_err());
+        assert!(evaluate("(1 + 2", &[], &[]).is_err());
+        assert!(evaluate("1 2", &[], &[]).is_err());
+    }
+    
+    #[test]
+    fn instantiate_evaluates_each_argument_list() {
+        let predecessor = Predecessor::parse("F(x, y)").unwrap();
+        assert_eq!(predecessor, Predecessor { symbol: 'F', params: vec!["x".to_string(), "y".to_string()] });
+        let successor = instantiate("F(x*0.5)[+(y)F(x, (y+1)*2)]G", &predecessor.params, &[2.0, 30.0]).unwrap();
+        assert_eq!(successor, "F(1)[+(30)F(2,62)]G");
+        assert_eq!(literal_arguments("F(2,62)"), vec![2.0, 62.0]);
+        assert!(Predecessor::parse("F(2x)").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use glam::Vec3;
+use crate::renderer::{color_to_u32, u32_to_rgb};
+
+/// Blur radii, in pixels, of the levels `DOFPass` blends between
+pub(crate) const DOF_BLUR_RADII: [usize; 3] = [1, 3, 5];
+
+/// One full-screen effect applied to a finished frame. `depth` holds each pixel's distance from
+/// the camera in world units, or infinity where nothing was drawn
+pub trait PostProcessPass {
+    fn apply(&self, buffer: &mut [u32], depth: &[f32], width: usize, height: usize);
+}
+
+/// Post-processing passes run one after another, in the order they were added
+#[derive(Default)]
+pub struct PostProcessPipeline {
+    passes: Vec<Box<dyn PostProcessPass>>,
+}
+
+impl PostProcessPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn add(&mut self, pass: impl PostProcessPass + 'static) {
+        self.passes.push(Box::new(pass));
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+    
+    pub fn run(&self, buffer: &mut [u32], depth: &[f32], width: usize, height: usize) {
+        for pass in &self.passes {
+            pass.apply(buffer, depth, width, height);
+        }
+    }
+}
+
+/// Fades geometry towards `color`, from none at `near` to fully at `far` world units away.
+/// The background is left alone so the sky still shows
+pub struct FogPass {
+    pub near: f32,
+    pub far: f32,
+    pub color: Vec3,
+}
+
+impl PostProcessPass for FogPass {
+    fn apply(&self, buffer: &mut [u32], depth: &[f32], _width: usize, _height: usize) {
+        let range = (self.far - self.near).max(1e-4);
+        for (pixel, &distance) in buffer.iter_mut().zip(depth) {
+            if !distance.is_finite() {
+                continue;
+            }
+            let t = ((distance - self.near) / range).clamp(0.0, 1.0);
+            *pixel = color_to_u32(Vec3::from(u32_to_rgb(*pixel)).lerp(self.color, t));
+        }
+    }
+}
+
+/// Adds a blurred copy of every pixel brighter than `threshold` back onto the frame
+pub struct BloomPass {
+    pub threshold: f32,
+    pub radius: usize,
+    pub intensity: f32,
+}
+
+impl PostProcessPass for BloomPass {
+    fn apply(&self, buffer: &mut [u32], _depth: &[f32], width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let bright: Vec<Vec3> = buffer.iter()
+            .map(|&pixel| {
+                let color = Vec3::from(u32_to_rgb(pixel));
+                let luminance = color.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+                if luminance > self.threshold { color } else { Vec3::ZERO }
+            })
+            .collect();
+        let glow = box_blur(&bright, width, height, self.radius);
+        for (pixel, glow) in buffer.iter_mut().zip(glow) {
+            *pixel = color_to_u32(Vec3::from(u32_to_rgb(*pixel)) + glow * self.intensity);
+        }
+    }
+}
+
+/// Blurs each pixel by its circle of confusion `|depth - focus_distance| * aperture / depth`,
+/// blending between copies of the frame box-blurred at `DOF_BLUR_RADII`
+pub struct DOFPass {
+    pub focus_distance: f32,
+    pub aperture: f32,
+}
+
+impl PostProcessPass for DOFPass {
+    fn apply(&self, buffer: &mut [u32], depth: &[f32], width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        let pixels: Vec<Vec3> = buffer.iter().map(|&pixel| u32_to_rgb(pixel).into()).collect();
+        let blurred: Vec<Vec<Vec3>> = DOF_BLUR_RADII.iter()
+            .map(|&radius| box_blur(&pixels, width, height, radius))
+            .collect();
+        let max_radius = DOF_BLUR_RADII[DOF_BLUR_RADII.len() - 1] as f32;
+        
+        for (idx, pixel) in buffer.iter_mut().enumerate() {
+            // The background is infinitely far away, so it gets the full aperture
+            let coc = if depth[idx].is_finite() {
+                (depth[idx] - self.focus_distance).abs() * self.aperture / depth[idx].max(1e-4)
+            } else {
+                self.aperture
+            }.clamp(0.0, max_radius);
+            
+            // Interpolate between the sharp pixel and the blur levels either side of `coc`
+            let mut lower = (0.0, pixels[idx]);
+            let mut color = pixels[idx];
+            for (level, &radius) in blurred.iter().zip(DOF_BLUR_RADII.iter()) {
+                let upper = (radius as f32, level[idx]);
+                if coc <= upper.0 {
+                    let t = (coc - lower.0) / (upper.0 - lower.0);
+                    color = lower.1.lerp(upper.1, t);
+                    break;
+                }
+                lower = upper;
+                color = upper.1;
+            }
+            *pixel = color_to_u32(color);
+        }
+    }
+}
+
+/// Curve `ToneMappingPass` compresses each channel with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMappingMode {
+    /// `c / (1 + c)`
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, which keeps more contrast than Reinhard
+    Aces,
+}
+
+pub struct ToneMappingPass {
+    pub mode: ToneMappingMode,
+}
+
+impl PostProcessPass for ToneMappingPass {
+    fn apply(&self, buffer: &mut [u32], _depth: &[f32], _width: usize, _height: usize) {
+        for pixel in buffer.iter_mut() {
+            let c = Vec3::from(u32_to_rgb(*pixel));
+            let mapped = match self.mode {
+                ToneMappingMode::Reinhard => c / (Vec3::ONE + c),
+                ToneMappingMode::Aces => (c * (c * 2.51 + 0.03)) / (c * (c * 2.43 + 0.59) + 0.14),
+            };
+            *pixel = color_to_u32(mapped);
+        }
+    }
+}
+
+/// Separable box blur of a `width` x `height` image, averaging `radius` pixels each way
+fn box_blur(pixels: &[Vec3], width: usize, height: usize, radius: usize) -> Vec<Vec3> {
+    let mut horizontal = vec![Vec3::ZERO; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let x0 = x.saturating_sub(radius);
+            let x1 = (x + radius).min(width - 1);
+            let sum: Vec3 = pixels[y * width + x0..=y * width + x1].iter().copied().sum();
+            horizontal[y * width + x] = sum / (x1 - x0 + 1) as f32;
+        }
+    }
+    
+    let mut blurred = vec![Vec3::ZERO; width * height];
+    for y in 0..height {
+        let y0 = y.saturating_sub(radius);
+        let y1 = (y + radius).min(height - 1);
+        for x in 0..width {
+            let sum: Vec3 = (y0..=y1).map(|sy| horizontal[sy * width + x]).sum();
+            blurred[y * width + x] = sum / (y1 - y0 + 1) as f32;
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn fog_fades_distant_geometry_but_not_the_background() {
+        let fog = FogPass { near: 10.0, far: 20.0, color: Vec3::ONE };
+        let mut buffer = [0x000000, 0x000000, 0x000000, 0x000000];
+        let depth = [5.0, 15.0, 30.0, f32::INFINITY];
+        fog.apply(&mut buffer, &depth, 4, 1);
+        assert_eq!(buffer, [0x000000, 0x7F7F7F, 0xFFFFFF, 0x000000]);
+    }
+    
+    #[test]
+    fn pipeline_runs_passes_in_order() {
+        let mut pipeline = PostProcessPipeline::new();
+        assert!(pipeline.is_empty());
+        pipeline.add(FogPass { near: 0.0, far: 1.0, color: Vec3::ONE });
+        pipeline.add(ToneMappingPass { mode: ToneMappingMode::Reinhard });
+        
+        // White fog, then Reinhard halves it
+        let mut buffer = [0x000000];
+        pipeline.run(&mut buffer, &[2.0], 1, 1);
+        assert_eq!(buffer, [0x7F7F7F]);
+    }
+    
+    #[test]
+    fn bloom_spreads_bright_pixels_only() {
+        let bloom = BloomPass { threshold: 0.8, radius: 1, intensity: 1.0 };
+        let mut buffer = [0x000000, 0xFFFFFF, 0x000000, 0x000000, 0x606060];
+        bloom.apply(&mut buffer, &[f32::INFINITY; 5], 5, 1);
+        assert_ne!(buffer[0], 0);
+        assert_ne!(buffer[2], 0);
+        assert_eq!(buffer[3], 0);
+    }
+}
@@ -1,5 +1,15 @@
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use crate::camera::Camera;
+use crate::font;
+use crate::LSystemRule;
+use crate::post_process::{DOFPass, PostProcessPass, PostProcessPipeline, DOF_BLUR_RADII};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
@@ -17,27 +27,163 @@ impl Vertex {
 pub struct Line {
     pub start: Vertex,
     pub end: Vertex,
+    /// Logical width at the 800-pixel reference resolution; scaled by the renderer's line width multiplier
     pub thickness: f32,
 }
 
 impl Line {
-    pub fn new(start: Vertex, end: Vertex) -> Self {
-        Self { start, end, thickness: 1.0 }
-    }
-    
     pub fn new_with_thickness(start: Vertex, end: Vertex, thickness: f32) -> Self {
         Self { start, end, thickness }
     }
 }
 
+/// A rule's preferred viewing settings, applied when it is opened; changes made in the GUI
+/// afterwards still win
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RenderHints {
+    pub line_width_scale: Option<f32>,
+    /// See `Renderer::set_antialiased`
+    pub antialiased: Option<bool>,
+    /// Sets the GUI's fog distance to `1 / fog_density`
+    pub fog_density: Option<f32>,
+    pub show_grid: Option<bool>,
+    pub show_axes: Option<bool>,
+    /// Clear color when the skybox is off
+    pub background_color: Option<[f32; 3]>,
+    /// Vertical `[top, bottom]` clear gradient when the skybox is off; wins over `background_color`
+    pub background_gradient: Option<[[f32; 3]; 2]>,
+    /// Skybox zenith and horizon colors
+    pub sky_gradient: Option<[[f32; 3]; 2]>,
+}
+
+/// A line's screen-space `(start, end, visible)`, as cached by `Renderer::render`
+pub type ProjectedLine = (Vec2, Vec2, bool);
+
+/// What `Renderer::clear` fills the color buffer with
+#[derive(Debug, Clone)]
+pub enum BackgroundConfig {
+    Solid(u32),
+    /// Blends from `top` on the first row to `bottom` on the last
+    LinearGradient { top: u32, bottom: u32 },
+    /// Sky seen through `camera`: `horizon` at eye level up to `zenith` overhead, and `horizon` below
+    Hemisphere { zenith: Vec3, horizon: Vec3, camera: Box<Camera> },
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        BackgroundConfig::Solid(BACKGROUND_COLOR)
+    }
+}
+
+impl RenderHints {
+    /// Names of the hints that are set but have no effect
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        [("show_grid", self.show_grid.is_some())]
+            .into_iter()
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect()
+    }
+    
+    /// What the buffer is cleared to: the gradient, else the color, else the default dark blue
+    pub fn background(&self) -> BackgroundConfig {
+        match (self.background_gradient, self.background_color) {
+            (Some([top, bottom]), _) => BackgroundConfig::LinearGradient {
+                top: color_to_u32(Vec3::from(top)),
+                bottom: color_to_u32(Vec3::from(bottom)),
+            },
+            (None, Some(color)) => BackgroundConfig::Solid(color_to_u32(Vec3::from(color))),
+            (None, None) => BackgroundConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkyboxConfig {
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+    pub ground: Vec3,
+    /// Clear to a dome of `horizon` up to `zenith`, with `horizon` below, instead of painting the sky
+    #[serde(default)]
+    pub hemisphere: bool,
+}
+
+impl Default for SkyboxConfig {
+    fn default() -> Self {
+        Self {
+            zenith: Vec3::new(0.05, 0.1, 0.35),
+            horizon: Vec3::new(0.45, 0.55, 0.7),
+            ground: Vec3::new(0.12, 0.1, 0.08),
+            hemisphere: false,
+        }
+    }
+}
+
+impl SkyboxConfig {
+    fn color_at_elevation(&self, elevation: f32) -> Vec3 {
+        // Map elevation -90..+90 degrees to ground -> horizon -> zenith
+        let t = (elevation / std::f32::consts::FRAC_PI_2).clamp(-1.0, 1.0);
+        if t >= 0.0 {
+            self.horizon + t * (self.zenith - self.horizon)
+        } else {
+            self.horizon + -t * (self.ground - self.horizon)
+        }
+    }
+}
+
+/// How `render()` rasterizes each projected line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// Constant-width strokes
+    #[default]
+    Lines,
+    /// Screen-space trapezoids that taper from 1.5x to 0.5x the line width, shaded darker at the sides
+    Cones,
+}
+
 pub struct Renderer {
     lines: Vec<Line>,
     width: usize,
     height: usize,
     buffer: Vec<u32>,
     depth_buffer: Vec<f32>,
+    skybox: Option<SkyboxConfig>,
+    // The last sky painted and the view-projection it was painted for; clears restore it
+    sky_cache: Option<(Mat4, Vec<u32>)>,
+    // Screen-space line endpoints and the view-projection they were computed with
+    projection_cache: Option<(Mat4, Vec<ProjectedLine>)>,
+    hdr_buffer: Option<Vec<[f32; 3]>>,
+    // Screen area touched since the last clear, as (x, y, width, height)
+    dirty_rect: Option<(usize, usize, usize, usize)>,
+    culled_count: usize,
+    line_width_multiplier: f32,
+    perspective_line_width: bool,
+    antialiased: bool,
+    last_render_time: Duration,
+    mode: RenderMode,
+    ground_shadow: Option<f32>,
+    background: BackgroundConfig,
+    // Writes per pixel since the last clear, when overdraw tracking is enabled
+    write_count: Option<Vec<u16>>,
+    // Depth of field applied after each render: (focus distance, aperture)
+    dof: Option<(f32, f32)>,
+    // Vertex colors fade to fog_color between fog_near and fog_far world units from the camera
+    fog_enabled: bool,
+    fog_color: Vec3,
+    fog_near: f32,
+    fog_far: f32,
 }
 
+const BACKGROUND_COLOR: u32 = 0x000020; // Dark blue background
+const SHADOW_COLOR: Vec3 = Vec3::new(0.05, 0.05, 0.06);
+/// World units per unit of turtle line width when line widths follow perspective
+const LINE_THICKNESS_WORLD_SCALE: f32 = 0.02;
+/// Most labels `render_labels_at_segment_midpoints` draws in one call
+const MAX_SEGMENT_LABELS: usize = 50;
+/// How far in pixels the cursor may be from a line for `pick_closest_world_point` to hit it
+const PICK_RADIUS: f32 = 8.0;
+/// Characters for `screenshot_as_ascii_art`, from empty to fully lit
+const ASCII_RAMP: &[u8] = b" .:;+=xX$&#";
+
 impl Renderer {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
@@ -46,63 +192,1020 @@ impl Renderer {
             height,
             buffer: vec![0; width * height],
             depth_buffer: vec![f32::MAX; width * height],
+            skybox: None,
+            sky_cache: None,
+            projection_cache: None,
+            hdr_buffer: None,
+            dirty_rect: None,
+            culled_count: 0,
+            line_width_multiplier: 1.0,
+            perspective_line_width: true,
+            antialiased: true,
+            last_render_time: Duration::ZERO,
+            mode: RenderMode::default(),
+            ground_shadow: None,
+            background: BackgroundConfig::default(),
+            write_count: None,
+            dof: None,
+            fog_enabled: false,
+            fog_color: Vec3::ZERO,
+            fog_near: 0.0,
+            fog_far: 1.0,
         }
     }
     
     pub fn clear(&mut self) {
-        self.buffer.fill(0x000020); // Dark blue background
-        self.depth_buffer.fill(f32::MAX);
+        self.clear_partial((0, 0, self.width, self.height));
         self.lines.clear();
+        self.projection_cache = None;
+        self.dirty_rect = None;
+    }
+    
+    /// Sets the background from the rule's render hints, or the default without any, and clears to it
+    pub fn clear_with_solid_background_from_rule(&mut self, rule: &LSystemRule) {
+        self.set_background(rule.render_hints.as_ref().map_or_else(BackgroundConfig::default, RenderHints::background));
+        self.clear();
+    }
+    
+    /// Fills the buffer with a sky seen through `camera`, leaving the configured background as it
+    /// was; unlike `clear` it keeps the lines
+    pub fn clear_with_sky_gradient(&mut self, zenith: Vec3, horizon: Vec3, camera: &Camera) {
+        let sky = BackgroundConfig::Hemisphere { zenith, horizon, camera: Box::new(camera.clone()) };
+        let background = std::mem::replace(&mut self.background, sky);
+        self.sky_cache = None;
+        self.clear_partial((0, 0, self.width, self.height));
+        self.background = background;
+    }
+    
+    /// Resets only `(x, y, width, height)` of the color and depth buffers to the background
+    pub fn clear_partial(&mut self, region: (usize, usize, usize, usize)) {
+        let (x, y, w, h) = region;
+        let x_end = (x + w).min(self.width);
+        let y_end = (y + h).min(self.height);
+        
+        for py in y.min(y_end)..y_end {
+            let row = py * self.width;
+            match (&self.sky_cache, &self.background) {
+                (Some((_, sky)), _) => self.buffer[row + x.min(x_end)..row + x_end].copy_from_slice(&sky[row + x.min(x_end)..row + x_end]),
+                (None, BackgroundConfig::Solid(color)) => self.buffer[row + x.min(x_end)..row + x_end].fill(*color),
+                _ => for px in x.min(x_end)..x_end {
+                    let color = self.get_background_at_pixel(px, py);
+                    self.buffer[row + px] = color;
+                },
+            }
+            self.depth_buffer[row + x.min(x_end)..row + x_end].fill(f32::MAX);
+            if let Some(hdr) = &mut self.hdr_buffer {
+                for px in x.min(x_end)..x_end {
+                    hdr[row + px] = u32_to_rgb(self.buffer[row + px]);
+                }
+            }
+            if let Some(counts) = &mut self.write_count {
+                counts[row + x.min(x_end)..row + x_end].fill(0);
+            }
+        }
+    }
+    
+    /// Clears only the area drawn since the last clear, plus the line list. Everything that draws
+    /// into the buffer marks what it touched; the skybox is restored from its cache
+    pub fn clear_dirty(&mut self) {
+        if let Some(region) = self.dirty_rect.take() {
+            self.clear_partial(region);
+        }
+        self.lines.clear();
+    }
+    
+    /// Scales every line's thickness when rasterizing (default 1.0)
+    pub fn set_line_width_multiplier(&mut self, scale: f32) {
+        self.line_width_multiplier = scale.max(0.0);
+    }
+    
+    pub fn line_width_multiplier(&self) -> f32 {
+        self.line_width_multiplier
+    }
+    
+    /// Screen-space width in pixels of something `thickness_world` wide at `distance` from a
+    /// camera with vertical field of view `fov` (radians) and a `screen_h` pixel tall viewport
+    pub fn line_width_from_thickness_at_distance(thickness_world: f32, distance: f32, fov: f32, screen_h: f32) -> f32 {
+        thickness_world / (2.0 * (fov * 0.5).tan() * distance.max(1e-4)) * screen_h
+    }
+    
+    /// When enabled (the default), line widths are world-space and shrink with distance from the
+    /// camera; otherwise every line is drawn its raw width in pixels
+    pub fn set_perspective_line_width(&mut self, enabled: bool) {
+        self.perspective_line_width = enabled;
+    }
+    
+    /// When enabled (the default), lines are blended into the buffer by how much of each pixel
+    /// they cover; otherwise they are stamped solid, which is faster but jagged
+    pub fn set_antialiased(&mut self, enabled: bool) {
+        self.antialiased = enabled;
+    }
+    
+    /// Pixel width to draw `line` with, seen from `camera`
+    fn screen_thickness(&self, line: &Line, camera: &Camera) -> f32 {
+        let thickness = line.thickness * self.line_width_multiplier;
+        if !self.perspective_line_width || line.thickness <= 0.0 {
+            return thickness;
+        }
+        let distance = (line.start.position.distance(camera.position) + line.end.position.distance(camera.position)) * 0.5;
+        Self::line_width_from_thickness_at_distance(thickness * LINE_THICKNESS_WORLD_SCALE, distance,
+                                                    camera.fov, self.height as f32)
+    }
+    
+    /// Keeps branch widths proportional when rendering at a width other than `reference_width`
+    pub fn auto_scale_line_width(&mut self, render_width: usize, reference_width: usize) {
+        if reference_width > 0 {
+            self.set_line_width_multiplier(render_width as f32 / reference_width as f32);
+        }
+    }
+    
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+    
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+    
+    /// Number of lines skipped by frustum culling in the last `render()`
+    pub fn culled_count(&self) -> usize {
+        self.culled_count
+    }
+    
+    /// Expands the dirty rectangle to include `region`, for systems that draw into the buffer directly
+    pub fn mark_dirty(&mut self, region: (usize, usize, usize, usize)) {
+        let (x, y, w, h) = region;
+        self.dirty_rect = Some(match self.dirty_rect {
+            Some((dx, dy, dw, dh)) => {
+                let x0 = dx.min(x);
+                let y0 = dy.min(y);
+                let x1 = (dx + dw).max(x + w);
+                let y1 = (dy + dh).max(y + h);
+                (x0, y0, x1 - x0, y1 - y0)
+            }
+            None => region,
+        });
+    }
+    
+    pub fn enable_hdr(&mut self) {
+        if self.width * self.height > 1920 * 1080 {
+            eprintln!("Warning: HDR at {}x{} uses {} MB for the color buffer", 
+                      self.width, self.height, self.width * self.height * 12 / (1024 * 1024));
+        }
+        
+        let background = (0..self.width * self.height)
+            .map(|idx| u32_to_rgb(self.get_background_at_pixel(idx % self.width, idx / self.width)))
+            .collect();
+        self.hdr_buffer = Some(background);
+    }
+    
+    pub fn disable_hdr(&mut self) {
+        self.hdr_buffer = None;
+    }
+    
+    pub fn is_hdr_enabled(&self) -> bool {
+        self.hdr_buffer.is_some()
+    }
+    
+    /// Counts how often each pixel is written so `compute_average_overdraw` can report it
+    pub fn enable_overdraw_tracking(&mut self) {
+        self.write_count = Some(vec![0; self.width * self.height]);
+    }
+    
+    /// Fraction of pixels geometry has been drawn to since the last clear, read from the depth
+    /// buffer so sky and background pixels never count
+    pub fn compute_per_pixel_coverage(&self) -> f32 {
+        if self.depth_buffer.is_empty() {
+            return 0.0;
+        }
+        let covered = self.depth_buffer.iter().filter(|&&depth| depth < f32::MAX).count();
+        covered as f32 / self.depth_buffer.len() as f32
+    }
+    
+    /// Average writes per covered pixel since the last clear; 0.0 unless tracking is enabled
+    pub fn compute_average_overdraw(&self) -> f32 {
+        let Some(counts) = &self.write_count else { return 0.0 };
+        let (total_writes, covered) = counts.iter()
+            .filter(|&&count| count > 0)
+            .fold((0u64, 0u64), |(total, covered), &count| (total + count as u64, covered + 1));
+        if covered == 0 {
+            return 0.0;
+        }
+        total_writes as f32 / covered as f32
+    }
+    
+    pub fn overdraw_tracking_enabled(&self) -> bool {
+        self.write_count.is_some()
+    }
+    
+    fn write_pixel(&mut self, idx: usize, color: Vec3) {
+        if let Some(counts) = &mut self.write_count {
+            counts[idx] = counts[idx].saturating_add(1);
+        }
+        match &mut self.hdr_buffer {
+            // HDR values are left unclamped so bright pixels can feed the bloom pass
+            Some(hdr) => hdr[idx] = [color.x, color.y, color.z],
+            None => self.buffer[idx] = color_to_u32(color),
+        }
+    }
+    
+    /// What the buffers are cleared to from the next clear on
+    pub fn set_background(&mut self, background: BackgroundConfig) {
+        self.background = background;
+    }
+    
+    /// A solid background from the next clear on; `None` restores the default
+    pub fn set_background_color(&mut self, color: Option<Vec3>) {
+        self.set_background(color.map_or_else(BackgroundConfig::default, |color| BackgroundConfig::Solid(color_to_u32(color))));
+    }
+    
+    /// The background's color at a pixel, without drawing it; lets effects that sample outside
+    /// the drawn geometry see what `clear` put there
+    pub fn get_background_at_pixel(&self, px: usize, py: usize) -> u32 {
+        match &self.background {
+            BackgroundConfig::Solid(color) => *color,
+            BackgroundConfig::LinearGradient { top, bottom } => {
+                let t = py as f32 / self.height.saturating_sub(1).max(1) as f32;
+                color_to_u32(Vec3::from(u32_to_rgb(*top)).lerp(Vec3::from(u32_to_rgb(*bottom)), t.min(1.0)))
+            }
+            BackgroundConfig::Hemisphere { zenith, horizon, camera } => {
+                let (screen_w, screen_h) = (self.width as f32, self.height as f32);
+                let (_, direction) = camera.screen_to_world_ray(Vec2::new(px as f32 + 0.5, py as f32 + 0.5), screen_w, screen_h);
+                let t = (direction.normalize_or_zero().y.clamp(-1.0, 1.0).asin() / std::f32::consts::FRAC_PI_2).max(0.0);
+                color_to_u32(horizon.lerp(*zenith, t))
+            }
+        }
+    }
+    
+    pub fn set_skybox(&mut self, config: SkyboxConfig) {
+        self.skybox = Some(config);
+        self.sky_cache = None;
+    }
+    
+    /// Draws every line flattened onto the plane `y = ground` as a dark shadow beneath the lines
+    pub fn set_ground_shadow(&mut self, ground: Option<f32>) {
+        self.ground_shadow = ground;
+    }
+    
+    pub fn disable_skybox(&mut self) {
+        self.skybox = None;
+        self.sky_cache = None;
+    }
+    
+    /// Paints the skybox as seen through `camera` over the whole buffer, resetting depth. Call it
+    /// before drawing anything. While the camera stays still the sky is already there, since
+    /// clears restore it from a cache, so this only repaints after the camera moves
+    pub fn render_skybox(&mut self, camera: &Camera) {
+        let skybox = match &self.skybox {
+            Some(skybox) => skybox.clone(),
+            None => return,
+        };
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        if self.sky_cache.as_ref().is_some_and(|(cached, _)| *cached == view_proj) {
+            return;
+        }
+        if skybox.hemisphere {
+            self.clear_with_sky_gradient(skybox.zenith, skybox.horizon, camera);
+            self.sky_cache = Some((view_proj, self.buffer.clone()));
+            return;
+        }
+        
+        let screen_w = self.width as f32;
+        let screen_h = self.height as f32;
+        let mut sky = vec![0; self.width * self.height];
+        for y in 0..self.height {
+            // Rays vary smoothly across a row, so cast one per edge and interpolate
+            let (_, left) = camera.screen_to_world_ray(Vec2::new(0.0, y as f32 + 0.5), screen_w, screen_h);
+            let (_, right) = camera.screen_to_world_ray(Vec2::new(screen_w, y as f32 + 0.5), screen_w, screen_h);
+            
+            for x in 0..self.width {
+                let t = (x as f32 + 0.5) / screen_w;
+                let direction = (left + t * (right - left)).normalize_or_zero();
+                let elevation = direction.y.clamp(-1.0, 1.0).asin();
+                
+                sky[y * self.width + x] = color_to_u32(skybox.color_at_elevation(elevation));
+            }
+        }
+        // The sky is background, so it is cleared to rather than drawn and is not overdraw
+        self.sky_cache = Some((view_proj, sky));
+        self.clear_partial((0, 0, self.width, self.height));
+    }
+    
+    /// Draws a large XYZ triad at the world origin, labelled at each endpoint.
+    /// Drawn straight into the depth-tested buffer, so call it before rendering the tree.
+    pub fn render_axes_in_world_space(&mut self, camera: &Camera, scale: f32) {
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let axes = [
+            (Vec3::X, Vec3::new(1.0, 0.2, 0.2), 0xFF4040, "X"),
+            (Vec3::Y, Vec3::new(0.2, 1.0, 0.2), 0x40FF40, "Y"),
+            (Vec3::Z, Vec3::new(0.3, 0.3, 1.0), 0x4040FF, "Z"),
+        ];
+        
+        for (axis, color, label_color, label) in axes {
+            let end = axis * scale;
+            let start_screen = self.project_to_screen(Vec3::ZERO, &view_proj);
+            let end_screen = self.project_to_screen(end, &view_proj);
+            if let (Some(start), Some(end_screen)) = (start_screen, end_screen) {
+                self.draw_line_2d(start, end_screen, color, color, 3.0);
+            }
+            self.draw_text_at_world_position(end, label, label_color, camera);
+        }
+    }
+    
+    /// Marks a turtle at `position` heading along `direction`: a ring around it and an arrow
+    /// `length` world units long, drawn in magenta just in front of the lines there
+    pub fn render_turtle_marker(&mut self, position: Vec3, direction: Vec3, length: f32, camera: &Camera) {
+        const RING_RADIUS: f32 = 6.0;
+        const ARROWHEAD_LENGTH: f32 = 8.0;
+        let color = Vec3::new(1.0, 0.2, 1.0);
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let Some(center) = self.project_to_screen(position, &view_proj) else { return };
+        let center = center - Vec3::Z * 1e-3;
+        
+        let ring: Vec<Vec3> = (0..=12)
+            .map(|i| {
+                let angle = i as f32 / 12.0 * std::f32::consts::TAU;
+                center + Vec3::new(angle.cos(), angle.sin(), 0.0) * RING_RADIUS
+            })
+            .collect();
+        for pair in ring.windows(2) {
+            self.draw_line_2d(pair[0], pair[1], color, color, 2.0);
+        }
+        
+        let Some(tip) = self.project_to_screen(position + direction * length, &view_proj) else { return };
+        let tip = tip - Vec3::Z * 1e-3;
+        self.draw_line_2d(center, tip, color, color, 2.0);
+        let back = (center - tip).truncate().normalize_or_zero() * ARROWHEAD_LENGTH;
+        for side in [-0.5f32, 0.5] {
+            let (sin, cos) = side.sin_cos();
+            let barb = Vec2::new(back.x * cos - back.y * sin, back.x * sin + back.y * cos);
+            self.draw_line_2d(tip, tip + barb.extend(0.0), color, color, 2.0);
+        }
+    }
+    
+    pub fn draw_text_at_world_position(&mut self, pos: Vec3, text: &str, color: u32, camera: &Camera) {
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        if let Some(screen) = self.project_to_screen(pos, &view_proj)
+            && screen.x >= 0.0 && screen.y >= 0.0 {
+            self.draw_text(screen.x as usize + 4, screen.y as usize, text, color);
+        }
+    }
+    
+    /// Labels each `(line index, label)` at the middle of that line, centered on it, in yellow.
+    /// Points off screen or behind the camera are skipped, as is any label that would overlap one
+    /// already placed. Returns how many were drawn, at most `MAX_SEGMENT_LABELS`.
+    pub fn render_labels_at_segment_midpoints(&mut self, segments: &[(usize, &str)], camera: &Camera) -> usize {
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let mut placed: Vec<(usize, usize, usize, usize)> = Vec::new();
+        for &(index, label) in segments {
+            if placed.len() >= MAX_SEGMENT_LABELS {
+                break;
+            }
+            let Some(line) = self.lines.get(index) else { continue };
+            let midpoint = (line.start.position + line.end.position) * 0.5;
+            let Some(screen) = self.project_to_screen(midpoint, &view_proj) else { continue };
+            if screen.x < 0.0 || screen.y < 0.0 || screen.x >= self.width as f32 || screen.y >= self.height as f32 {
+                continue;
+            }
+            
+            let (text_width, text_height) = font::measure(label, 1);
+            let x = (screen.x as usize).saturating_sub(text_width / 2);
+            let y = (screen.y as usize).saturating_sub(text_height / 2);
+            let overlaps = placed.iter().any(|&(px, py, pw, ph)| {
+                x < px + pw && px < x + text_width && y < py + ph && py < y + text_height
+            });
+            if overlaps {
+                continue;
+            }
+            self.draw_text(x, y, label, 0xFFFF00);
+            placed.push((x, y, text_width, text_height));
+        }
+        placed.len()
+    }
+    
+    fn draw_text(&mut self, x: usize, y: usize, text: &str, color: u32) {
+        let rgb = u32_to_rgb(color);
+        let color = Vec3::new(rgb[0], rgb[1], rgb[2]);
+        let (text_width, text_height) = font::measure(text, 1);
+        self.mark_dirty((x, y, text_width, text_height));
+        
+        // Goes through write_pixel rather than font::draw_string so labels reach the HDR buffer
+        for (i, c) in text.chars().enumerate() {
+            let char_x = x + i * font::CELL_WIDTH;
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for col in 0..font::GLYPH_WIDTH {
+                    let (px, py) = (char_x + col, y + row);
+                    if bits & (0x10 >> col) != 0 && px < self.width && py < self.height {
+                        self.write_pixel(py * self.width + px, color);
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Draws overlay text into any 0RGB buffer; all menus and panels render their text through this
+    pub fn render_text_at_screen(buffer: &mut [u32], buf_width: usize, buf_height: usize,
+                                 (x, y): (usize, usize), text: &str, color: u32, scale: u32) {
+        font::draw_string(buffer, buf_width, buf_height, (x, y), text, color, scale);
+    }
+    
+    /// Pixel (width, height) of `text` as drawn by `render_text_at_screen`
+    pub fn measure_text(text: &str, scale: u32) -> (usize, usize) {
+        font::measure(text, scale)
+    }
+    
+    /// World point on the line under `screen_pos` (within `PICK_RADIUS` pixels) closest to the
+    /// camera ray through it
+    pub fn pick_closest_world_point(&mut self, camera: &Camera, screen_pos: Vec2) -> Option<Vec3> {
+        let index = self.pick_line(camera, screen_pos, PICK_RADIUS)?;
+        let (origin, direction) = camera.screen_to_world_ray(screen_pos, self.width as f32, self.height as f32);
+        let line = &self.lines[index];
+        let start = line.start.position;
+        let segment = line.end.position - start;
+        
+        // Closest approach between the (normalized) ray and the segment
+        let to_origin = origin - start;
+        let along = direction.dot(segment);
+        let length_sq = segment.length_squared();
+        let denom = length_sq - along * along;
+        let s = if denom > f32::EPSILON {
+            ((segment.dot(to_origin) - direction.dot(to_origin) * along) / denom).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Some(start + segment * s)
+    }
+    
+    /// Axis-aligned bounds of all lines added since the last `clear()`
+    pub fn bounding_box(&self) -> Option<(Vec3, Vec3)> {
+        let mut points = self.lines.iter().flat_map(|line| [line.start.position, line.end.position]);
+        let first = points.next()?;
+        Some(points.fold((first, first), |(min, max), p| (min.min(p), max.max(p))))
+    }
+    
+    /// An empty renderer of the given size with this one's lines and settings, for exporting
+    /// at a resolution other than the window's or following the window when it is resized
+    pub fn offscreen_copy(&self, width: usize, height: usize) -> Renderer {
+        if width * height > 3840 * 2160 {
+            eprintln!("Warning: rendering {}x{} off-screen is larger than 4K and may run out of memory", width, height);
+        }
+        let mut offscreen = Renderer::new(width, height);
+        if self.hdr_buffer.is_some() {
+            offscreen.enable_hdr();
+        }
+        offscreen.skybox = self.skybox.clone();
+        offscreen.line_width_multiplier = self.line_width_multiplier;
+        offscreen.perspective_line_width = self.perspective_line_width;
+        offscreen.antialiased = self.antialiased;
+        offscreen.mode = self.mode;
+        offscreen.ground_shadow = self.ground_shadow;
+        offscreen.background = self.background.clone();
+        offscreen.dof = self.dof;
+        offscreen.fog_enabled = self.fog_enabled;
+        offscreen.fog_color = self.fog_color;
+        offscreen.fog_near = self.fog_near;
+        offscreen.fog_far = self.fog_far;
+        offscreen.clear();
+        offscreen.lines = self.lines.clone();
+        offscreen
+    }
+    
+    /// Renders the current lines at `width` x `height` into a new renderer, without touching the
+    /// on-screen buffers; `camera` is used with its aspect ratio changed to match
+    pub fn render_to_offscreen(&self, width: usize, height: usize, camera: &Camera) -> Renderer {
+        let mut camera = camera.clone();
+        camera.set_aspect_ratio(width as f32 / height.max(1) as f32);
+        let mut offscreen = self.offscreen_copy(width, height);
+        offscreen.render_skybox(&camera);
+        offscreen.render(&camera);
+        offscreen
+    }
+    
+    /// Average of every line's endpoints, or the origin when nothing has been drawn
+    pub fn get_centroid_of_visible_geometry(&self) -> Vec3 {
+        if self.lines.is_empty() {
+            return Vec3::ZERO;
+        }
+        let sum: Vec3 = self.lines.iter().map(|line| line.start.position + line.end.position).sum();
+        sum / (self.lines.len() * 2) as f32
+    }
+    
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+    
+    /// Writes a self-contained SVG where each entry of `frames` is shown in turn, looping every `duration_secs`
+    pub fn export_svg_animated(&self, frames: &[Vec<Line>], camera: &Camera, duration_secs: f32, path: &Path) -> Result<(), Box<dyn Error>> {
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let frame_count = frames.len().max(1) as f32;
+        let mut svg = self.svg_header(None);
+        
+        for (i, lines) in frames.iter().enumerate() {
+            // Discrete visibility toggle: frame i is shown from i/n to (i+1)/n of the cycle
+            svg.push_str(&format!("<g id=\"frame_{}\" style=\"display:none\">\n", i));
+            svg.push_str(&format!(
+                "<animate attributeName=\"display\" values=\"none;inline;none\" keyTimes=\"0;{:.4};{:.4}\" \
+                 dur=\"{}s\" calcMode=\"discrete\" repeatCount=\"indefinite\"/>\n",
+                i as f32 / frame_count, (i + 1) as f32 / frame_count, duration_secs));
+            
+            for line in lines {
+                let start = self.project_to_screen(line.start.position, &view_proj);
+                let end = self.project_to_screen(line.end.position, &view_proj);
+                if let (Some(start), Some(end)) = (start, end) {
+                    self.push_svg_line(&mut svg, start.truncate(), end.truncate(), line.start.color, line.thickness);
+                }
+            }
+            
+            svg.push_str("</g>\n");
+        }
+        
+        svg.push_str("</svg>\n");
+        fs::write(path, svg)?;
+        Ok(())
+    }
+    
+    /// Writes the current lines as a static SVG titled `title`, as seen from `camera`. Lines are
+    /// clipped to the viewport, and each is stroked with its color halfway along the visible part
+    pub fn export_svg(&mut self, path: &Path, camera: &Camera, title: &str) -> Result<(), Box<dyn Error>> {
+        let projected = self.project_all_lines(camera);
+        let mut svg = self.svg_header(Some(title));
+        let viewport = Vec2::new(self.width as f32, self.height as f32);
+        for (line, &(start, end, _)) in self.lines.iter().zip(projected.iter()) {
+            // Lines behind the camera or culled have NaN endpoints
+            if !(start.is_finite() && end.is_finite()) {
+                continue;
+            }
+            let Some((t0, t1)) = clip_segment_to_rect(start, end, viewport) else { continue };
+            let color = line.start.color.lerp(line.end.color, (t0 + t1) * 0.5);
+            self.push_svg_line(&mut svg, start.lerp(end, t0), start.lerp(end, t1), color, line.thickness);
+        }
+        svg.push_str("</svg>\n");
+        fs::write(path, svg)?;
+        Ok(())
+    }
+    
+    /// Writes the current lines as a Wavefront OBJ of closed, tapered branches with
+    /// `segments_per_branch` sides (at least 3), plus a `.mtl` beside it with a material for each
+    /// face color. As in cone rendering, a branch's radius is 0.75x its thickness at the start and
+    /// 0.25x at the end
+    pub fn export_obj(&self, path: &Path, segments_per_branch: u32) -> Result<(), Box<dyn Error>> {
+        let segments = segments_per_branch.max(3) as usize;
+        let mtl_path = path.with_extension("mtl");
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writeln!(writer, "# L-system export: {} branches", self.lines.len())?;
+        if let Some(mtl_name) = mtl_path.file_name() {
+            writeln!(writer, "mtllib {}", mtl_name.to_string_lossy())?;
+        }
+        
+        let mut materials = BTreeSet::new();
+        let mut current_material = None;
+        // OBJ indices are 1-based and count from the start of the file
+        let (mut vertex_base, mut normal_base) = (1, 1);
+        for line in &self.lines {
+            let axis = line.end.position - line.start.position;
+            let length = axis.length();
+            if length == 0.0 {
+                continue;
+            }
+            let direction = axis / length;
+            let u = direction.any_orthonormal_vector();
+            let v = direction.cross(u);
+            let radial = |i: usize| {
+                let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+                u * angle.cos() + v * angle.sin()
+            };
+            let radii = [0.75, 0.25].map(|scale| line.thickness * scale * LINE_THICKNESS_WORLD_SCALE);
+            
+            for (center, radius) in [(line.start.position, radii[0]), (line.end.position, radii[1])] {
+                for i in 0..segments {
+                    let p = center + radial(i) * radius;
+                    writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+                }
+            }
+            // The taper tilts the side normals back towards the wider end
+            for i in 0..segments {
+                let n = (radial(i) * length + direction * (radii[0] - radii[1])).normalize();
+                writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+            writeln!(writer, "vn {} {} {}", -direction.x, -direction.y, -direction.z)?;
+            writeln!(writer, "vn {} {} {}", direction.x, direction.y, direction.z)?;
+            
+            let start_ring = |i: usize| vertex_base + i % segments;
+            let end_ring = |i: usize| vertex_base + segments + i % segments;
+            let side_normal = |i: usize| normal_base + i % segments;
+            let (start_cap, end_cap) = (normal_base + segments, normal_base + segments + 1);
+            
+            write_obj_material(&mut writer, &mut materials, &mut current_material, line.start.color)?;
+            for i in 1..segments - 1 {
+                writeln!(writer, "f {}//{n} {}//{n} {}//{n}", start_ring(0), start_ring(i + 1), start_ring(i), n = start_cap)?;
+            }
+            write_obj_material(&mut writer, &mut materials, &mut current_material, line.start.color.lerp(line.end.color, 0.5))?;
+            for i in 0..segments {
+                let (a, b) = (side_normal(i), side_normal(i + 1));
+                writeln!(writer, "f {}//{a} {}//{b} {}//{b}", start_ring(i), start_ring(i + 1), end_ring(i + 1))?;
+                writeln!(writer, "f {}//{a} {}//{b} {}//{a}", start_ring(i), end_ring(i + 1), end_ring(i))?;
+            }
+            write_obj_material(&mut writer, &mut materials, &mut current_material, line.end.color)?;
+            for i in 1..segments - 1 {
+                writeln!(writer, "f {}//{n} {}//{n} {}//{n}", end_ring(0), end_ring(i), end_ring(i + 1), n = end_cap)?;
+            }
+            
+            vertex_base += 2 * segments;
+            normal_base += segments + 2;
+        }
+        writer.flush()?;
+        
+        let mut mtl = String::from("# Branch colors for the L-system export\n");
+        for color in materials {
+            let [r, g, b] = u32_to_rgb(color);
+            mtl.push_str(&format!("newmtl color_{:06x}\nKd {} {} {}\n", color, r, g, b));
+        }
+        fs::write(mtl_path, mtl)?;
+        Ok(())
+    }
+    
+    fn svg_header(&self, title: Option<&str>) -> String {
+        let mut header = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+            w = self.width, h = self.height);
+        if let Some(title) = title {
+            header.push_str(&format!("<title>{}</title>\n", escape_xml(title)));
+        }
+        // A sky or gradient background becomes a vertical gradient between its top and bottom rows
+        let (top, bottom) = self.background_top_and_bottom();
+        if top == bottom {
+            header.push_str(&format!("<rect width=\"100%\" height=\"100%\" fill=\"#{:06x}\"/>\n", top));
+        } else {
+            header.push_str(&format!(
+                "<defs><linearGradient id=\"background\" x1=\"0\" y1=\"0\" x2=\"0\" y2=\"1\">\
+                 <stop offset=\"0\" stop-color=\"#{:06x}\"/><stop offset=\"1\" stop-color=\"#{:06x}\"/>\
+                 </linearGradient></defs>\n", top, bottom));
+            header.push_str("<rect width=\"100%\" height=\"100%\" fill=\"url(#background)\"/>\n");
+        }
+        header
+    }
+    
+    /// The background's color at the middle of the top and bottom rows, taken from the painted
+    /// skybox while there is one
+    fn background_top_and_bottom(&self) -> (u32, u32) {
+        let (x, bottom_y) = (self.width / 2, self.height.saturating_sub(1));
+        match &self.sky_cache {
+            Some((_, sky)) if !sky.is_empty() => (sky[x], sky[bottom_y * self.width + x]),
+            _ => (self.get_background_at_pixel(x, 0), self.get_background_at_pixel(x, bottom_y)),
+        }
+    }
+    
+    fn push_svg_line(&self, svg: &mut String, start: Vec2, end: Vec2, color: Vec3, thickness: f32) {
+        svg.push_str(&format!(
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#{:06x}\" stroke-width=\"{:.2}\"/>\n",
+            start.x, start.y, end.x, end.y, color_to_u32(color), thickness * self.line_width_multiplier));
     }
     
     pub fn add_line(&mut self, line: Line) {
         self.lines.push(line);
+        self.projection_cache = None;
     }
     
     pub fn render(&mut self, camera: &Camera) {
+        match self.mode {
+            RenderMode::Lines => self.rasterize_lines(camera, Self::draw_line_2d),
+            RenderMode::Cones => self.render_all_branches_as_cones(camera),
+        }
+    }
+    
+    /// Renders every line as a tapered branch regardless of the current mode
+    pub fn render_all_branches_as_cones(&mut self, camera: &Camera) {
+        self.rasterize_lines(camera, Self::draw_cone_2d);
+    }
+    
+    /// Culls, projects and draws every line with `draw`, then runs the HDR passes
+    fn rasterize_lines(&mut self, camera: &Camera, draw: fn(&mut Self, Vec3, Vec3, Vec3, Vec3, f32)) {
+        let render_start = Instant::now();
         let view_proj = camera.projection_matrix() * camera.view_matrix();
         let lines = self.lines.clone(); // Clone to avoid borrow checker issues
+        let mut endpoints = Vec::with_capacity(lines.len());
+        let planes = camera.get_frustum_planes();
+        self.culled_count = 0;
+        
+        if let Some(ground) = self.ground_shadow {
+            for line in &lines {
+                let flatten = |position: Vec3| Vec3::new(position.x, ground, position.z);
+                let start_screen = self.project_to_screen(flatten(line.start.position), &view_proj);
+                let end_screen = self.project_to_screen(flatten(line.end.position), &view_proj);
+                if let (Some(start), Some(end)) = (start_screen, end_screen) {
+                    let thickness = self.screen_thickness(line, camera);
+                    self.draw_line_2d(start, end, SHADOW_COLOR, SHADOW_COLOR, thickness);
+                }
+            }
+        }
         
         for line in &lines {
-            self.draw_line_3d(&line.start, &line.end, line.thickness, &view_proj);
+            // Skip lines with both endpoints outside the same frustum plane; lines that merely
+            // cross a plane are kept and clipped by the rasterizer's bounds checks
+            let start = line.start.position.extend(1.0);
+            let end = line.end.position.extend(1.0);
+            if planes.iter().any(|plane| plane.dot(start) < 0.0 && plane.dot(end) < 0.0) {
+                self.culled_count += 1;
+                endpoints.push((Vec2::NAN, Vec2::NAN, false));
+                continue;
+            }
+            
+            let start_screen = self.project_to_screen(line.start.position, &view_proj);
+            let end_screen = self.project_to_screen(line.end.position, &view_proj);
+            
+            endpoints.push(self.projection_entry(start_screen, end_screen));
+            if let (Some(start), Some(end)) = (start_screen, end_screen) {
+                let thickness = self.screen_thickness(line, camera);
+                let start_color = self.apply_fog(line.start.color, line.start.position, camera.position);
+                let end_color = self.apply_fog(line.end.color, line.end.position, camera.position);
+                draw(self, start, end, start_color, end_color, thickness);
+            }
+        }
+        
+        self.projection_cache = Some((view_proj, endpoints));
+        
+        if self.hdr_buffer.is_some() {
+            self.apply_bloom_hdr(0.8, 4, 0.6);
+            self.tone_map_hdr();
         }
+        if let Some((focus_distance, aperture)) = self.dof {
+            self.render_depth_of_field(camera, focus_distance, aperture);
+        }
+        
+        self.last_render_time = render_start.elapsed();
+    }
+    
+    /// Blurs each render by how far each pixel is from `focus_distance` (world units from the
+    /// camera); a larger `aperture` blurs more
+    pub fn enable_dof(&mut self, focus_distance: f32, aperture: f32) {
+        self.dof = Some((focus_distance, aperture));
     }
     
-    fn draw_line_3d(&mut self, start: &Vertex, end: &Vertex, thickness: f32, view_proj: &Mat4) {
-        let start_clip = *view_proj * Vec4::new(start.position.x, start.position.y, start.position.z, 1.0);
-        let end_clip = *view_proj * Vec4::new(end.position.x, end.position.y, end.position.z, 1.0);
+    pub fn disable_dof(&mut self) {
+        self.dof = None;
+    }
+    
+    pub fn dof_enabled(&self) -> bool {
+        self.dof.is_some()
+    }
+    
+    /// Fades each line's vertex colors towards `color`, from none at `near` to fully at `far`
+    /// world units from the camera, so distant branches melt into the sky before the far plane
+    /// cuts them off. It works per vertex, so the color gradient along a line already includes it
+    pub fn set_fog(&mut self, color: Vec3, near: f32, far: f32) {
+        self.fog_enabled = true;
+        self.fog_color = color;
+        self.fog_near = near;
+        self.fog_far = far;
+    }
+    
+    pub fn disable_fog(&mut self) {
+        self.fog_enabled = false;
+    }
+    
+    /// `color` at `position` after fog, seen from `eye`
+    fn apply_fog(&self, color: Vec3, position: Vec3, eye: Vec3) -> Vec3 {
+        if !self.fog_enabled {
+            return color;
+        }
+        let fog = ((position.distance(eye) - self.fog_near) / (self.fog_far - self.fog_near).max(1e-4)).clamp(0.0, 1.0);
+        color.lerp(self.fog_color, fog)
+    }
+    
+    /// Blurs the buffer with a `DOFPass`, by each pixel's distance from `focus_distance`
+    pub fn render_depth_of_field(&mut self, camera: &Camera, focus_distance: f32, aperture: f32) {
+        let depth = self.linear_depth(camera);
+        DOFPass { focus_distance, aperture }.apply(&mut self.buffer, &depth, self.width, self.height);
         
-        // Perspective divide
-        if start_clip.w <= 0.0 || end_clip.w <= 0.0 {
-            return; // Behind camera
+        // Blur spreads past the drawn area, so partial clears must cover the spread too
+        if let Some((x, y, w, h)) = self.dirty_rect {
+            let pad = DOF_BLUR_RADII[DOF_BLUR_RADII.len() - 1];
+            self.mark_dirty((x.saturating_sub(pad), y.saturating_sub(pad), w + pad * 2, h + pad * 2));
         }
+    }
+    
+    /// Runs `pipeline` over the rendered frame. Passes may touch any pixel, so the next
+    /// partial clear covers the whole buffer
+    pub fn apply_post_process(&mut self, pipeline: &PostProcessPipeline, camera: &Camera) {
+        if pipeline.is_empty() {
+            return;
+        }
+        let depth = self.linear_depth(camera);
+        pipeline.run(&mut self.buffer, &depth, self.width, self.height);
+        self.mark_dirty((0, 0, self.width, self.height));
+    }
+    
+    /// Each pixel's distance from the camera in world units, or infinity where nothing was drawn
+    fn linear_depth(&self, camera: &Camera) -> Vec<f32> {
+        let (near, far) = (camera.near, camera.far);
+        self.depth_buffer.iter()
+            .map(|&ndc_z| if ndc_z >= 1.0 { f32::INFINITY } else { near * far / (far - ndc_z * (far - near)) })
+            .collect()
+    }
+    
+    /// Wall-clock time of the last `render()` call, including bloom and tone mapping
+    pub fn get_render_time(&self) -> Duration {
+        self.last_render_time
+    }
+    
+    /// Adds a blurred copy of every pixel brighter than `threshold` back onto the HDR buffer
+    pub fn apply_bloom_hdr(&mut self, threshold: f32, radius: usize, intensity: f32) {
+        let (width, height) = (self.width, self.height);
+        let hdr = match &mut self.hdr_buffer {
+            Some(hdr) => hdr,
+            None => return,
+        };
         
-        let start_ndc = Vec3::new(
-            start_clip.x / start_clip.w,
-            start_clip.y / start_clip.w,
-            start_clip.z / start_clip.w,
-        );
+        let bright: Vec<[f32; 3]> = hdr.iter()
+            .map(|&[r, g, b]| {
+                let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                if luminance > threshold { [r, g, b] } else { [0.0; 3] }
+            })
+            .collect();
         
-        let end_ndc = Vec3::new(
-            end_clip.x / end_clip.w,
-            end_clip.y / end_clip.w,
-            end_clip.z / end_clip.w,
-        );
+        // Separable box blur: horizontal then vertical
+        let mut horizontal = vec![[0.0f32; 3]; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = x.saturating_sub(radius);
+                let x1 = (x + radius).min(width - 1);
+                let mut sum = [0.0f32; 3];
+                for sx in x0..=x1 {
+                    let p = bright[y * width + sx];
+                    sum[0] += p[0];
+                    sum[1] += p[1];
+                    sum[2] += p[2];
+                }
+                let count = (x1 - x0 + 1) as f32;
+                horizontal[y * width + x] = [sum[0] / count, sum[1] / count, sum[2] / count];
+            }
+        }
+        
+        for y in 0..height {
+            let y0 = y.saturating_sub(radius);
+            let y1 = (y + radius).min(height - 1);
+            for x in 0..width {
+                let mut sum = [0.0f32; 3];
+                for sy in y0..=y1 {
+                    let p = horizontal[sy * width + x];
+                    sum[0] += p[0];
+                    sum[1] += p[1];
+                    sum[2] += p[2];
+                }
+                let count = (y1 - y0 + 1) as f32;
+                let pixel = &mut hdr[y * width + x];
+                pixel[0] += sum[0] / count * intensity;
+                pixel[1] += sum[1] / count * intensity;
+                pixel[2] += sum[2] / count * intensity;
+            }
+        }
+    }
+    
+    /// Reinhard tone mapping (L_out = L_in / (1 + L_in)) from the HDR buffer into the display buffer
+    fn tone_map_hdr(&mut self) {
+        if let Some(hdr) = &self.hdr_buffer {
+            for (pixel, &[r, g, b]) in self.buffer.iter_mut().zip(hdr.iter()) {
+                let mapped = Vec3::new(r / (1.0 + r), g / (1.0 + g), b / (1.0 + b));
+                *pixel = color_to_u32(mapped);
+            }
+        }
+    }
+    
+    /// Screen-space `(start, end, visible)` for every line, reusing the cache filled by `render()`
+    /// when `camera` sees the scene the same way. A line is visible when neither endpoint is behind
+    /// the camera and both lie within the screen bounds extended by half again; lines behind the
+    /// camera have NaN endpoints.
+    pub fn project_all_lines(&mut self, camera: &Camera) -> Vec<ProjectedLine> {
+        self.update_projection_cache(camera);
+        self.get_screen_space_line_endpoints().to_vec()
+    }
+    
+    fn update_projection_cache(&mut self, camera: &Camera) {
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        if self.projection_cache.as_ref().is_some_and(|(cached, _)| *cached == view_proj) {
+            return;
+        }
+        
+        let projected: Vec<_> = self.lines.iter()
+            .map(|line| self.projection_entry(
+                self.project_to_screen(line.start.position, &view_proj),
+                self.project_to_screen(line.end.position, &view_proj)))
+            .collect();
+        self.projection_cache = Some((view_proj, projected));
+    }
+    
+    fn projection_entry(&self, start: Option<Vec3>, end: Option<Vec3>) -> ProjectedLine {
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                let (start, end) = (start.truncate(), end.truncate());
+                let margin = Vec2::new(self.width as f32, self.height as f32) * 0.25;
+                let min = -margin;
+                let max = Vec2::new(self.width as f32, self.height as f32) + margin;
+                let inside = |p: Vec2| p.cmpge(min).all() && p.cmple(max).all();
+                (start, end, inside(start) && inside(end))
+            }
+            // Lines behind the camera are stored as NaN so indices line up with the line list
+            _ => (Vec2::NAN, Vec2::NAN, false),
+        }
+    }
+    
+    /// Screen-space endpoints of every line from the last `render()` or projection; empty until then
+    pub fn get_screen_space_line_endpoints(&self) -> &[ProjectedLine] {
+        self.projection_cache.as_ref().map_or(&[], |(_, endpoints)| endpoints.as_slice())
+    }
+    
+    /// Index of the line closest to `screen_pos` as `camera` sees it, if any lies within
+    /// `max_distance` pixels. Reuses the endpoints cached by `render()` while the camera is unchanged
+    pub fn pick_line(&mut self, camera: &Camera, screen_pos: Vec2, max_distance: f32) -> Option<usize> {
+        self.update_projection_cache(camera);
+        let mut best: Option<(usize, f32)> = None;
+        
+        // Long lines can pass under the cursor while an endpoint is far off screen, so the
+        // visibility flag is ignored; NaN endpoints never compare as close
+        for (i, &(start, end, _)) in self.get_screen_space_line_endpoints().iter().enumerate() {
+            let segment = end - start;
+            let length_sq = segment.length_squared();
+            let t = if length_sq > 0.0 {
+                ((screen_pos - start).dot(segment) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = (start + segment * t).distance(screen_pos);
+            
+            if distance <= max_distance && best.is_none_or(|(_, d)| distance < d) {
+                best = Some((i, distance));
+            }
+        }
+        
+        best.map(|(i, _)| i)
+    }
+    
+    /// Screen-space position and NDC depth of `position`, or None when it is behind the camera
+    pub fn project_to_screen(&self, position: Vec3, view_proj: &Mat4) -> Option<Vec3> {
+        let clip = *view_proj * Vec4::new(position.x, position.y, position.z, 1.0);
+        
+        // Perspective divide
+        if clip.w <= 0.0 {
+            return None; // Behind camera
+        }
+        
+        let ndc = Vec3::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w);
         
         // Convert to screen space
-        let start_screen = Vec3::new(
-            (start_ndc.x + 1.0) * 0.5 * self.width as f32,
-            (1.0 - start_ndc.y) * 0.5 * self.height as f32,
-            start_ndc.z,
-        );
+        Some(Vec3::new(
+            (ndc.x + 1.0) * 0.5 * self.width as f32,
+            (1.0 - ndc.y) * 0.5 * self.height as f32,
+            ndc.z,
+        ))
+    }
+    
+    /// Fills the polygon through `vertices_2d` (screen space) with the even-odd rule, depth-testing
+    /// every pixel against `depth`
+    pub fn rasterize_polygon(&mut self, vertices_2d: &[(f32, f32)], depth: f32, color: u32) {
+        if vertices_2d.len() < 3 || self.width == 0 || self.height == 0 {
+            return;
+        }
         
-        let end_screen = Vec3::new(
-            (end_ndc.x + 1.0) * 0.5 * self.width as f32,
-            (1.0 - end_ndc.y) * 0.5 * self.height as f32,
-            end_ndc.z,
-        );
+        let (min_y, max_y) = vertices_2d.iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &(_, y)| (lo.min(y), hi.max(y)));
+        let (min_x, max_x) = vertices_2d.iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &(x, _)| (lo.min(x), hi.max(x)));
+        if max_x < 0.0 || max_y < 0.0 || min_x >= self.width as f32 || min_y >= self.height as f32 {
+            return;
+        }
+        let y_start = min_y.max(0.0) as usize;
+        let y_end = (max_y.ceil() as usize).clamp(y_start + 1, self.height);
+        let x_start = min_x.max(0.0) as usize;
+        let x_end = (max_x.ceil() as usize).clamp(x_start + 1, self.width);
+        self.mark_dirty((x_start, y_start, x_end - x_start, y_end - y_start));
         
-        self.draw_line_2d(start_screen, end_screen, start.color, end.color, thickness);
+        let shaded = self.apply_depth_shading(Vec3::from(u32_to_rgb(color)), depth);
+        let mut crossings = Vec::new();
+        for py in y_start..y_end {
+            // Crossings of the pixel-center row with every edge, paired up left to right
+            let row_y = py as f32 + 0.5;
+            crossings.clear();
+            for i in 0..vertices_2d.len() {
+                let (ax, ay) = vertices_2d[i];
+                let (bx, by) = vertices_2d[(i + 1) % vertices_2d.len()];
+                if (ay <= row_y) != (by <= row_y) {
+                    crossings.push(ax + (row_y - ay) * (bx - ax) / (by - ay));
+                }
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            
+            for span in crossings.chunks_exact(2) {
+                let left = (span[0] - 0.5).ceil().max(0.0) as usize;
+                let right = ((span[1] - 0.5).floor() as i64 + 1).clamp(0, self.width as i64) as usize;
+                for px in left..right {
+                    let idx = py * self.width + px;
+                    if depth < self.depth_buffer[idx] {
+                        self.depth_buffer[idx] = depth;
+                        self.write_pixel(idx, shaded);
+                    }
+                }
+            }
+        }
     }
     
     fn draw_line_2d(&mut self, start: Vec3, end: Vec3, start_color: Vec3, end_color: Vec3, thickness: f32) {
@@ -117,9 +1220,19 @@ impl Renderer {
             return;
         }
         
-        // Perpendicular vector for thickness
-        let perp_x = -dy / length * thickness * 0.5;
-        let perp_y = dx / length * thickness * 0.5;
+        // Track the screen area covered by this line, including its thickness.
+        // Off-screen pixels are clamped onto the border, so the rect is never empty.
+        let pad = (thickness * 0.5).max(1.0) + 1.0;
+        let min_x = (start.x.min(end.x) - pad).clamp(0.0, (self.width - 1) as f32) as usize;
+        let min_y = (start.y.min(end.y) - pad).clamp(0.0, (self.height - 1) as f32) as usize;
+        let max_x = ((start.x.max(end.x) + pad).max(0.0) as usize).clamp(min_x + 1, self.width);
+        let max_y = ((start.y.max(end.y) + pad).max(0.0) as usize).clamp(min_y + 1, self.height);
+        self.mark_dirty((min_x, min_y, max_x - min_x, max_y - min_y));
+        
+        if self.antialiased {
+            self.draw_line_2d_antialiased(start, end, start_shaded, end_shaded, thickness);
+            return;
+        }
         
         let steps = (length as i32).max(1);
         
@@ -131,10 +1244,6 @@ impl Renderer {
             let z = start.z + t * (end.z - start.z);
             
             let color = start_shaded + t * (end_shaded - start_shaded);
-            let r = (color.x.clamp(0.0, 1.0) * 255.0) as u32;
-            let g = (color.y.clamp(0.0, 1.0) * 255.0) as u32;
-            let b = (color.z.clamp(0.0, 1.0) * 255.0) as u32;
-            let pixel_color = (r << 16) | (g << 8) | b;
             
             // Draw thick line as a series of circles
             let radius = (thickness * 0.5).max(1.0) as i32;
@@ -149,7 +1258,7 @@ impl Renderer {
                             
                             if z < self.depth_buffer[idx] {
                                 self.depth_buffer[idx] = z;
-                                self.buffer[idx] = pixel_color;
+                                self.write_pixel(idx, color);
                             }
                         }
                     }
@@ -158,15 +1267,236 @@ impl Renderer {
         }
     }
     
+    /// Xiaolin Wu's line, widened to `thickness`. Each step along the major axis covers a run of
+    /// pixels across the line, sized so the line is `thickness` wide perpendicular to itself; pixels
+    /// inside the run are drawn solid and the two at its ends are blended by how much of them it
+    /// covers, which for a one-pixel line are Wu's two weights. A single run rather than parallel
+    /// AA lines keeps the inside of thick lines from being blended twice.
+    fn draw_line_2d_antialiased(&mut self, start: Vec3, end: Vec3, start_color: Vec3, end_color: Vec3, thickness: f32) {
+        // Work in (major, minor) coordinates so one loop handles steep and shallow lines
+        let steep = (end.y - start.y).abs() > (end.x - start.x).abs();
+        let to_major_minor = |p: Vec3| if steep { Vec3::new(p.y, p.x, p.z) } else { p };
+        let (mut a, mut b) = (to_major_minor(start), to_major_minor(end));
+        let (mut a_color, mut b_color) = (start_color, end_color);
+        if a.x > b.x {
+            std::mem::swap(&mut a, &mut b);
+            std::mem::swap(&mut a_color, &mut b_color);
+        }
+        let (major_limit, minor_limit) = if steep { (self.height, self.width) } else { (self.width, self.height) };
+        let first = a.x.floor().max(0.0);
+        let last = b.x.floor().min(major_limit as f32 - 1.0);
+        if last < first {
+            return;
+        }
+        
+        let major_length = b.x - a.x;
+        let gradient = (b.y - a.y) / major_length;
+        let half_run = thickness.max(1.0) * 0.5 * (1.0 + gradient * gradient).sqrt();
+        for major in first as usize..=last as usize {
+            let t = ((major as f32 + 0.5 - a.x) / major_length).clamp(0.0, 1.0);
+            let center = a.y + (b.y - a.y) * t;
+            let depth = a.z + (b.z - a.z) * t;
+            let color = a_color.lerp(b_color, t);
+            let (low, high) = (center - half_run, center + half_run);
+            if high <= 0.0 || low >= minor_limit as f32 {
+                continue;
+            }
+            for minor in low.floor().max(0.0) as usize..(high.ceil() as usize).min(minor_limit) {
+                let coverage = (high.min(minor as f32 + 1.0) - low.max(minor as f32)).clamp(0.0, 1.0);
+                let idx = if steep { major * self.width + minor } else { minor * self.width + major };
+                self.blend_pixel(idx, color, coverage, depth);
+            }
+        }
+    }
+    
+    /// Depth-tested blend of `color` over the pixel, `alpha` of the way from what is there. Only
+    /// mostly covered pixels write depth, so faint edges don't hide lines drawn behind them later
+    fn blend_pixel(&mut self, idx: usize, color: Vec3, alpha: f32, depth: f32) {
+        if alpha <= 0.0 || depth >= self.depth_buffer[idx] {
+            return;
+        }
+        if alpha >= 0.5 {
+            self.depth_buffer[idx] = depth;
+        }
+        let existing = match &self.hdr_buffer {
+            Some(hdr) => Vec3::from(hdr[idx]),
+            None => Vec3::from(u32_to_rgb(self.buffer[idx])),
+        };
+        self.write_pixel(idx, existing.lerp(color, alpha));
+    }
+    
+    /// Scan-converts the trapezoid around `start`-`end`, `thickness * 1.5` wide at the start and
+    /// `thickness * 0.5` at the end, interpolating depth and color along the branch
+    fn draw_cone_2d(&mut self, start: Vec3, end: Vec3, start_color: Vec3, end_color: Vec3, thickness: f32) {
+        let start_shaded = self.apply_depth_shading(start_color, start.z);
+        let end_shaded = self.apply_depth_shading(end_color, end.z);
+        let axis = end.truncate() - start.truncate();
+        let length_sq = axis.length_squared();
+        if length_sq == 0.0 || self.width == 0 || self.height == 0 {
+            return;
+        }
+        
+        let start_half = (thickness * 0.75).max(0.5);
+        let end_half = (thickness * 0.25).max(0.5);
+        let normal = axis.perp() / length_sq.sqrt();
+        let corners = [
+            start.truncate() + normal * start_half,
+            end.truncate() + normal * end_half,
+            end.truncate() - normal * end_half,
+            start.truncate() - normal * start_half,
+        ];
+        
+        let (min, max) = corners.iter().fold((corners[0], corners[0]), |(min, max), &c| (min.min(c), max.max(c)));
+        if max.x < 0.0 || max.y < 0.0 || min.x >= self.width as f32 || min.y >= self.height as f32 {
+            return;
+        }
+        let min_x = min.x.max(0.0) as usize;
+        let min_y = min.y.max(0.0) as usize;
+        let max_x = (max.x.ceil() as usize).clamp(min_x + 1, self.width);
+        let max_y = (max.y.ceil() as usize).clamp(min_y + 1, self.height);
+        self.mark_dirty((min_x, min_y, max_x - min_x, max_y - min_y));
+        
+        for py in min_y..max_y {
+            // Span of the trapezoid on this row, from its edge crossings at the pixel center
+            let row_y = py as f32 + 0.5;
+            let mut span: Option<(f32, f32)> = None;
+            for i in 0..corners.len() {
+                let (a, b) = (corners[i], corners[(i + 1) % corners.len()]);
+                if (a.y <= row_y) == (b.y <= row_y) {
+                    continue;
+                }
+                let x = a.x + (row_y - a.y) * (b.x - a.x) / (b.y - a.y);
+                span = Some(span.map_or((x, x), |(l, r)| (l.min(x), r.max(x))));
+            }
+            let Some((left, right)) = span else { continue };
+            
+            let x_start = (left - 0.5).ceil().max(0.0) as usize;
+            let x_end = ((right - 0.5).floor() as i64 + 1).clamp(0, self.width as i64) as usize;
+            for px in x_start..x_end {
+                let offset = Vec2::new(px as f32 + 0.5, row_y) - start.truncate();
+                let t = (offset.dot(axis) / length_sq).clamp(0.0, 1.0);
+                let half_width = start_half + t * (end_half - start_half);
+                let across = (offset.dot(normal) / half_width).clamp(-1.0, 1.0);
+                
+                let idx = py * self.width + px;
+                let z = start.z + t * (end.z - start.z);
+                if z < self.depth_buffer[idx] {
+                    self.depth_buffer[idx] = z;
+                    // Darken towards the silhouette so the branch reads as round
+                    let color = (start_shaded + t * (end_shaded - start_shaded)) * (1.0 - 0.4 * across * across);
+                    self.write_pixel(idx, color);
+                }
+            }
+        }
+    }
+    
     pub fn get_buffer(&self) -> &[u32] {
         &self.buffer
     }
     
-    pub fn resize(&mut self, width: usize, height: usize) {
-        self.width = width;
-        self.height = height;
-        self.buffer.resize(width * height, 0);
-        self.depth_buffer.resize(width * height, f32::MAX);
+    /// Width and height of the buffer in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+    
+    /// The frame as opaque RGBA bytes, row by row
+    pub fn rgba_bytes(&self) -> Vec<u8> {
+        // minifb packs pixels as 0RGB
+        let mut data = Vec::with_capacity(self.buffer.len() * 4);
+        for &pixel in &self.buffer {
+            data.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 0xFF]);
+        }
+        data
+    }
+    
+    /// The buffer box-averaged down to `cols` x `rows` characters of `ASCII_RAMP`, one line per row.
+    /// Brightness is relative to the brightest cell, so thin lines on a dark background still show.
+    pub fn screenshot_as_ascii_art(&self, cols: usize, rows: usize) -> String {
+        self.ascii_art(cols, rows, false)
+    }
+    
+    /// `screenshot_as_ascii_art` with each character colored by a 24-bit ANSI escape
+    pub fn screenshot_as_ansi_art(&self, cols: usize, rows: usize) -> String {
+        self.ascii_art(cols, rows, true)
+    }
+    
+    fn ascii_art(&self, cols: usize, rows: usize, ansi: bool) -> String {
+        let (cols, rows) = (cols.clamp(1, self.width.max(1)), rows.clamp(1, self.height.max(1)));
+        let mut cells = Vec::with_capacity(cols * rows);
+        for row in 0..rows {
+            let (y0, y1) = (row * self.height / rows, ((row + 1) * self.height / rows).min(self.height));
+            for col in 0..cols {
+                let (x0, x1) = (col * self.width / cols, ((col + 1) * self.width / cols).min(self.width));
+                let sum: Vec3 = (y0..y1)
+                    .flat_map(|y| (x0..x1).map(move |x| Vec3::from(u32_to_rgb(self.buffer[y * self.width + x]))))
+                    .sum();
+                cells.push(sum / ((y1 - y0) * (x1 - x0)).max(1) as f32);
+            }
+        }
+        
+        let luminance = |c: Vec3| c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let brightest = cells.iter().copied().map(luminance).fold(0.0, f32::max);
+        let gain = if brightest > 0.0 { 1.0 / brightest } else { 0.0 };
+        
+        let mut art = String::with_capacity(cells.len() * if ansi { 20 } else { 1 } + rows);
+        for row in cells.chunks(cols) {
+            for &cell in row {
+                let level = (luminance(cell) * gain * (ASCII_RAMP.len() - 1) as f32).round() as usize;
+                let symbol = ASCII_RAMP[level.min(ASCII_RAMP.len() - 1)] as char;
+                if ansi {
+                    let [r, g, b] = (cell * gain * 255.0).round().min(Vec3::splat(255.0)).to_array().map(|c| c as u8);
+                    art.push_str(&format!("\x1b[38;2;{};{};{}m{}", r, g, b, symbol));
+                } else {
+                    art.push(symbol);
+                }
+            }
+            if ansi {
+                art.push_str("\x1b[0m");
+            }
+            art.push('\n');
+        }
+        art
+    }
+    
+    /// Encodes the frame as an 8-bit RGBA PNG, creating `path`'s directory if needed. Needs no
+    /// window, so it works for headless renders too
+    pub fn save_png(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = fs::File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgba_bytes())?;
+        Ok(())
+    }
+    
+    pub fn save_ppm(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for &pixel in &self.buffer {
+            writer.write_all(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+    
+    /// Writes `<dir>/<base_name>_<frame:05>.png` and returns the path written
+    pub fn write_frame_sequence(&self, dir: &Path, base_name: &str, frame: u32) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}_{:05}.png", base_name, frame));
+        self.save_png(&path)?;
+        Ok(path)
+    }
+    
+    /// Same as `write_frame_sequence` but writes dependency-free binary PPM frames
+    pub fn write_frame_sequence_ppm(&self, dir: &Path, base_name: &str, frame: u32) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}_{:05}.ppm", base_name, frame));
+        self.save_ppm(&path)?;
+        Ok(path)
     }
     
     fn apply_depth_shading(&self, color: Vec3, depth: f32) -> Vec3 {
@@ -180,4 +1510,489 @@ impl Renderer {
         
         color * total_lighting
     }
-}
\ No newline at end of file
+}
+
+pub(crate) fn color_to_u32(color: Vec3) -> u32 {
+    let r = (color.x.clamp(0.0, 1.0) * 255.0) as u32;
+    let g = (color.y.clamp(0.0, 1.0) * 255.0) as u32;
+    let b = (color.z.clamp(0.0, 1.0) * 255.0) as u32;
+    (r << 16) | (g << 8) | b
+}
+
+/// Alpha-blends the non-zero pixels of an `overlay_width` x `overlay_height` overlay over a 0RGB
+/// buffer, such as the display copy UI is drawn into, with its top-left corner at (`dest_x`, `dest_y`);
+/// zero pixels are left transparent. `out = (src * alpha + dst * (255 - alpha)) / 255` per channel
+pub(crate) fn composite_over(buffer: &mut [u32], width: usize, height: usize, overlay: &[u32],
+                             (overlay_width, overlay_height): (usize, usize), alpha: u8, (dest_x, dest_y): (usize, usize)) {
+    let alpha = alpha as u32;
+    for oy in 0..overlay_height.min(height.saturating_sub(dest_y)) {
+        for ox in 0..overlay_width.min(width.saturating_sub(dest_x)) {
+            let src = overlay[oy * overlay_width + ox];
+            if src == 0 {
+                continue;
+            }
+            let idx = (dest_y + oy) * width + dest_x + ox;
+            let dst = buffer[idx];
+            let blend = |shift: u32| {
+                let s = (src >> shift) & 0xFF;
+                let d = (dst >> shift) & 0xFF;
+                ((s * alpha + d * (255 - alpha)) / 255) << shift
+            };
+            buffer[idx] = blend(16) | blend(8) | blend(0);
+        }
+    }
+}
+
+pub(crate) fn u32_to_rgb(color: u32) -> [f32; 3] {
+    [
+        ((color >> 16) & 0xFF) as f32 / 255.0,
+        ((color >> 8) & 0xFF) as f32 / 255.0,
+        (color & 0xFF) as f32 / 255.0,
+    ]
+}
+
+/// Liang-Barsky clip of the segment `start`-`end` to the rectangle from the origin to `size`:
+/// the fractions along it where the visible part begins and ends, or `None` if none of it is
+fn clip_segment_to_rect(start: Vec2, end: Vec2, size: Vec2) -> Option<(f32, f32)> {
+    let delta = end - start;
+    let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+    // Each edge as (-direction, distance inside), left, right, top, bottom
+    let edges = [(-delta.x, start.x), (delta.x, size.x - start.x), (-delta.y, start.y), (delta.y, size.y - start.y)];
+    for (p, q) in edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None; // Parallel to and outside this edge
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// Switches an OBJ to the material for `color`, rounded to 8 bits per channel, and adds it to
+/// `materials` for the MTL file
+fn write_obj_material(writer: &mut impl Write, materials: &mut BTreeSet<u32>, current: &mut Option<u32>, color: Vec3) -> std::io::Result<()> {
+    let color = color_to_u32(color);
+    if *current == Some(color) {
+        return Ok(());
+    }
+    materials.insert(color);
+    *current = Some(color);
+    writeln!(writer, "usemtl color_{:06x}", color)
+}
+
+/// `text` with the characters XML treats specially replaced by entities
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn test_line(start: Vec3, end: Vec3) -> Line {
+        Line::new_with_thickness(Vertex::new(start, Vec3::ONE), Vertex::new(end, Vec3::ONE), 1.0)
+    }
+    
+    #[test]
+    fn clear_fills_every_pixel_with_the_background() {
+        let mut renderer = Renderer::new(16, 8);
+        renderer.buffer.fill(0xFFFFFF);
+        renderer.add_line(test_line(Vec3::ZERO, Vec3::Y));
+        renderer.clear();
+        assert!(renderer.get_buffer().iter().all(|&pixel| pixel == BACKGROUND_COLOR));
+        assert!(renderer.lines().is_empty());
+        assert_eq!(renderer.dirty_rect, None);
+    }
+    
+    #[test]
+    fn clear_partial_only_touches_the_region() {
+        let mut renderer = Renderer::new(8, 8);
+        renderer.buffer.fill(0xFFFFFF);
+        renderer.clear_partial((2, 2, 3, 3));
+        assert_eq!(renderer.get_buffer()[2 * 8 + 2], BACKGROUND_COLOR);
+        assert_eq!(renderer.get_buffer()[4 * 8 + 4], BACKGROUND_COLOR);
+        assert_eq!(renderer.get_buffer()[0], 0xFFFFFF);
+        assert_eq!(renderer.get_buffer()[5 * 8 + 5], 0xFFFFFF);
+    }
+    
+    #[test]
+    fn mark_dirty_grows_to_cover_both_regions() {
+        let mut renderer = Renderer::new(100, 100);
+        renderer.mark_dirty((10, 10, 5, 5));
+        renderer.mark_dirty((40, 2, 10, 10));
+        assert_eq!(renderer.dirty_rect, Some((10, 2, 40, 13)));
+    }
+    
+    #[test]
+    fn project_all_lines_centers_the_camera_target_and_follows_the_camera() {
+        let (width, height) = (crate::WIDTH, crate::HEIGHT);
+        let mut renderer = Renderer::new(width, height);
+        let mut camera = Camera::new(width as f32 / height as f32);
+        camera.update_from_angles();
+        // Crosses the target side to side, so it is centered on screen
+        renderer.add_line(test_line(camera.target - Vec3::Z, camera.target + Vec3::Z));
+        
+        let (start, end, visible) = renderer.project_all_lines(&camera)[0];
+        let middle = (start + end) * 0.5;
+        assert!(visible);
+        assert!((middle.x - width as f32 / 2.0).abs() <= 1.0, "{}", middle);
+        assert!((middle.y - height as f32 / 2.0).abs() <= 1.0, "{}", middle);
+        
+        // Another camera gets its own projection rather than the cached one
+        camera.target += Vec3::Y;
+        camera.update_from_angles();
+        let (moved_start, _, _) = renderer.project_all_lines(&camera)[0];
+        assert!(moved_start.y > start.y + 1.0);
+    }
+    
+    #[test]
+    fn picking_hits_the_line_under_the_cursor_only() {
+        let (width, height) = (crate::WIDTH, crate::HEIGHT);
+        let mut renderer = Renderer::new(width, height);
+        let mut camera = Camera::new(width as f32 / height as f32);
+        camera.update_from_angles();
+        renderer.add_line(test_line(camera.target - Vec3::Z, camera.target + Vec3::Z));
+        renderer.render(&camera);
+        let center = Vec2::new(width as f32 / 2.0, height as f32 / 2.0);
+        
+        assert_eq!(renderer.pick_line(&camera, center, 2.0), Some(0));
+        let picked = renderer.pick_closest_world_point(&camera, center).unwrap();
+        assert!(picked.distance(camera.target) < 0.05, "{}", picked);
+        assert_eq!(renderer.pick_closest_world_point(&camera, Vec2::ZERO), None);
+    }
+    
+    #[test]
+    fn clear_dirty_restores_the_cached_sky() {
+        let mut renderer = Renderer::new(32, 32);
+        let mut camera = Camera::new(1.0);
+        camera.update_from_angles();
+        renderer.set_skybox(SkyboxConfig::default());
+        renderer.render_skybox(&camera);
+        let sky = renderer.get_buffer().to_vec();
+        assert_eq!(renderer.dirty_rect, None);
+        
+        renderer.add_line(test_line(camera.target - Vec3::Z, camera.target + Vec3::Z));
+        renderer.render(&camera);
+        assert_ne!(renderer.get_buffer(), sky.as_slice());
+        renderer.clear_dirty();
+        renderer.render_skybox(&camera);
+        assert_eq!(renderer.get_buffer(), sky.as_slice());
+        
+        // A moved camera sees a different sky
+        camera.pitch += 0.5;
+        camera.update_from_angles();
+        renderer.render_skybox(&camera);
+        assert_ne!(renderer.get_buffer(), sky.as_slice());
+    }
+    
+    #[test]
+    fn color_round_trips_through_u32() {
+        assert_eq!(color_to_u32(Vec3::new(1.0, 0.0, 0.0)), 0xFF0000);
+        assert_eq!(u32_to_rgb(0x00FF00), [0.0, 1.0, 0.0]);
+        assert_eq!(color_to_u32(Vec3::from(u32_to_rgb(0xFF00FF))), 0xFF00FF);
+    }
+    
+    #[test]
+    fn ascii_art_box_averages_cells_relative_to_the_brightest() {
+        let mut renderer = Renderer::new(6, 2);
+        renderer.buffer = vec![0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0, 0, 0,
+                               0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0, 0, 0];
+        assert_eq!(renderer.screenshot_as_ascii_art(3, 1), "#= \n");
+        assert_eq!(renderer.screenshot_as_ansi_art(1, 1),
+                   "\x1b[38;2;255;255;255m#\x1b[0m\n");
+    }
+    
+    #[test]
+    fn measure_text_scales_with_length() {
+        assert_eq!(Renderer::measure_text("abc", 1), (18, 8));
+        assert_eq!(Renderer::measure_text("abc", 2), (36, 16));
+        assert_eq!(Renderer::measure_text("", 1), (0, 8));
+    }
+    
+    #[test]
+    fn render_text_clips_at_the_buffer_edge() {
+        let mut buffer = vec![0u32; 10 * 4];
+        Renderer::render_text_at_screen(&mut buffer, 10, 4, (4, 1), "HELLO", 0xFFFFFF, 1);
+        assert!(buffer.contains(&0xFFFFFF));
+        assert!(buffer[..4].iter().all(|&pixel| pixel == 0));
+    }
+    
+    #[test]
+    fn rasterize_polygon_fills_with_even_odd_rule() {
+        let mut renderer = Renderer::new(16, 16);
+        let square = [(2.0, 2.0), (10.0, 2.0), (10.0, 10.0), (2.0, 10.0)];
+        renderer.rasterize_polygon(&square, 0.0, 0xFFFFFF);
+        let filled = renderer.get_buffer().iter().filter(|&&pixel| pixel != 0).count();
+        assert_eq!(filled, 64);
+        assert_eq!(renderer.dirty_rect, Some((2, 2, 8, 8)));
+        
+        // A pentagram's center is crossed twice on each side, so it stays empty
+        let mut renderer = Renderer::new(64, 64);
+        let star: Vec<(f32, f32)> = (0..5)
+            .map(|i| {
+                let angle = (i * 2) as f32 * std::f32::consts::TAU / 5.0;
+                (32.0 + 30.0 * angle.sin(), 32.0 - 30.0 * angle.cos())
+            })
+            .collect();
+        renderer.rasterize_polygon(&star, 0.0, 0xFFFFFF);
+        assert_eq!(renderer.get_buffer()[32 * 64 + 32], 0);
+        assert_ne!(renderer.get_buffer()[10 * 64 + 32], 0);
+    }
+    
+    #[test]
+    fn coverage_and_overdraw_count_drawn_pixels() {
+        let mut renderer = Renderer::new(10, 10);
+        renderer.enable_overdraw_tracking();
+        renderer.clear();
+        assert_eq!(renderer.compute_per_pixel_coverage(), 0.0);
+        assert_eq!(renderer.compute_average_overdraw(), 0.0);
+        
+        let square = [(0.0, 0.0), (5.0, 0.0), (5.0, 5.0), (0.0, 5.0)];
+        renderer.rasterize_polygon(&square, 0.5, 0xFFFFFF);
+        renderer.rasterize_polygon(&square, 0.1, 0xFFFFFF);
+        assert_eq!(renderer.compute_per_pixel_coverage(), 0.25);
+        assert_eq!(renderer.compute_average_overdraw(), 2.0);
+        
+        renderer.clear();
+        assert_eq!(renderer.compute_average_overdraw(), 0.0);
+        
+        // A sky paints every pixel but covers none of them
+        let camera = Camera::new(1.0);
+        renderer.set_background(BackgroundConfig::Hemisphere {
+            zenith: Vec3::new(0.2, 0.4, 0.9), horizon: Vec3::ONE, camera: Box::new(camera),
+        });
+        renderer.clear();
+        assert_eq!(renderer.compute_per_pixel_coverage(), 0.0);
+        renderer.rasterize_polygon(&square, 0.5, 0xFFFFFF);
+        assert_eq!(renderer.compute_per_pixel_coverage(), 0.25);
+    }
+    
+    #[test]
+    fn offscreen_render_matches_the_requested_size() {
+        let mut renderer = Renderer::new(32, 24);
+        renderer.add_line(test_line(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)));
+        let camera = Camera::new(32.0 / 24.0);
+        
+        let offscreen = renderer.render_to_offscreen(64, 64, &camera);
+        let buffer = offscreen.get_buffer();
+        assert_eq!(buffer.len(), 64 * 64);
+        assert!(buffer.iter().any(|&pixel| pixel != BACKGROUND_COLOR));
+        assert_eq!(renderer.get_buffer().len(), 32 * 24);
+        assert!(renderer.get_buffer().iter().all(|&pixel| pixel == 0));
+    }
+    
+    #[test]
+    fn fog_fades_vertex_colors_by_distance_from_the_camera() {
+        let camera = Camera::new(1.0);
+        let right = (camera.target - camera.position).cross(camera.up).normalize();
+        let (start, end) = (camera.target - right, camera.target + right);
+        let distance = start.distance(camera.position);
+        let fog_color = Vec3::new(0.2, 0.4, 0.8);
+        
+        let mut renderer = Renderer::new(32, 32);
+        renderer.set_perspective_line_width(false);
+        renderer.set_fog(fog_color, 0.0, distance * 1.6);
+        let line = Line::new_with_thickness(Vertex::new(start, Vec3::ONE), Vertex::new(end, Vec3::ONE), 4.0);
+        renderer.add_line(line.clone());
+        renderer.render(&camera);
+        
+        let view_proj = camera.projection_matrix() * camera.view_matrix();
+        let depth = renderer.project_to_screen(camera.target, &view_proj).unwrap().z;
+        let expected = renderer.apply_depth_shading(Vec3::ONE.lerp(fog_color, 1.0 / 1.6), depth);
+        let pixel = Vec3::from(u32_to_rgb(renderer.get_buffer()[16 * 32 + 16]));
+        assert!((pixel - expected).abs().max_element() <= 2.0 / 255.0, "{} vs {}", pixel, expected);
+        
+        renderer.disable_fog();
+        renderer.clear();
+        renderer.add_line(line);
+        renderer.render(&camera);
+        let unfogged = Vec3::from(u32_to_rgb(renderer.get_buffer()[16 * 32 + 16]));
+        assert!((unfogged - renderer.apply_depth_shading(Vec3::ONE, depth)).abs().max_element() <= 2.0 / 255.0);
+    }
+    
+    #[test]
+    fn hemisphere_skybox_paints_the_dome_and_keeps_the_lines() {
+        let mut renderer = Renderer::new(32, 32);
+        let mut camera = Camera::new(1.0);
+        camera.update_from_angles();
+        renderer.set_skybox(SkyboxConfig { hemisphere: true, ..SkyboxConfig::default() });
+        renderer.add_line(test_line(camera.target - Vec3::Z, camera.target + Vec3::Z));
+        renderer.render_skybox(&camera);
+        
+        assert_eq!(renderer.lines().len(), 1);
+        let mut dome = Renderer::new(32, 32);
+        let SkyboxConfig { zenith, horizon, .. } = SkyboxConfig::default();
+        dome.set_background(BackgroundConfig::Hemisphere { zenith, horizon, camera: Box::new(camera.clone()) });
+        dome.clear();
+        assert_eq!(renderer.get_buffer(), dome.get_buffer());
+        
+        // The sky is cached for later clears rather than replacing the configured background
+        assert!(matches!(renderer.background, BackgroundConfig::Solid(BACKGROUND_COLOR)));
+        assert!(renderer.sky_cache.is_some());
+        renderer.clear();
+        assert_eq!(renderer.get_buffer(), dome.get_buffer());
+        renderer.disable_skybox();
+        renderer.clear();
+        assert!(renderer.get_buffer().iter().all(|&pixel| pixel == BACKGROUND_COLOR));
+    }
+    
+    #[test]
+    fn svg_background_follows_the_current_background() {
+        let mut renderer = Renderer::new(8, 8);
+        renderer.set_background(BackgroundConfig::Solid(0x336699));
+        assert!(renderer.svg_header(None).contains("fill=\"#336699\""));
+        
+        renderer.set_background(BackgroundConfig::LinearGradient { top: 0x000000, bottom: 0xFFFFFF });
+        let header = renderer.svg_header(None);
+        assert!(header.contains("<stop offset=\"0\" stop-color=\"#000000\"/><stop offset=\"1\" stop-color=\"#ffffff\"/>"));
+        assert!(header.contains("fill=\"url(#background)\""));
+    }
+    
+    #[test]
+    fn segment_labels_skip_overlaps_and_points_behind_the_camera() {
+        let mut renderer = Renderer::new(64, 64);
+        renderer.add_line(test_line(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)));
+        renderer.add_line(test_line(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+        renderer.add_line(test_line(Vec3::new(0.0, 5.0, 19.0), Vec3::new(0.0, 5.0, 21.0)));
+        let camera = Camera::new(1.0);
+        
+        let drawn = renderer.render_labels_at_segment_midpoints(&[(0, "0"), (1, "1"), (2, "2"), (7, "7")], &camera);
+        assert_eq!(drawn, 1);
+        assert!(renderer.get_buffer().contains(&0xFFFF00));
+    }
+    
+    #[test]
+    fn depth_of_field_blurs_by_circle_of_confusion() {
+        let camera = Camera::new(1.0);
+        let mut renderer = Renderer::new(16, 16);
+        renderer.buffer[8 * 16 + 8] = 0xFFFFFF;
+        
+        renderer.render_depth_of_field(&camera, 10.0, 0.0);
+        assert_eq!(renderer.get_buffer()[8 * 16 + 8], 0xFFFFFF);
+        assert_eq!(renderer.get_buffer()[8 * 16 + 11], 0);
+        
+        // Empty pixels count as infinitely far, so they get the full aperture of blur
+        renderer.render_depth_of_field(&camera, 10.0, 5.0);
+        assert_ne!(renderer.get_buffer()[8 * 16 + 11], 0);
+        assert!(renderer.get_buffer()[8 * 16 + 8] < 0xFFFFFF);
+    }
+    
+    #[test]
+    fn perspective_line_width_shrinks_with_distance() {
+        let fov = 90.0_f32.to_radians();
+        assert!((Renderer::line_width_from_thickness_at_distance(2.0, 1.0, fov, 100.0) - 100.0).abs() < 1e-3);
+        assert!((Renderer::line_width_from_thickness_at_distance(2.0, 2.0, fov, 100.0) - 50.0).abs() < 1e-3);
+    }
+    
+    #[test]
+    fn centroid_averages_line_endpoints() {
+        let mut renderer = Renderer::new(8, 8);
+        assert_eq!(renderer.get_centroid_of_visible_geometry(), Vec3::ZERO);
+        renderer.add_line(test_line(Vec3::ZERO, Vec3::new(0.0, 4.0, 0.0)));
+        renderer.add_line(test_line(Vec3::new(0.0, 4.0, 0.0), Vec3::new(4.0, 8.0, 0.0)));
+        assert_eq!(renderer.get_centroid_of_visible_geometry(), Vec3::new(1.0, 4.0, 0.0));
+    }
+    
+    #[test]
+    fn antialiased_diagonals_blend_their_edges() {
+        let mut renderer = Renderer::new(32, 32);
+        renderer.draw_line_2d(Vec3::new(4.0, 4.0, 0.5), Vec3::new(28.0, 28.0, 0.5), Vec3::ONE, Vec3::ONE, 1.0);
+        let pixel = |renderer: &Renderer, x: usize, y: usize| u32_to_rgb(renderer.get_buffer()[y * 32 + x]);
+        let foreground = pixel(&renderer, 16, 16);
+        assert!(foreground[0] > 0.5);
+        for i in 8..24 {
+            assert_eq!(pixel(&renderer, i, i), foreground);
+            // Beside the diagonal only part of the pixel is covered
+            for edge in [pixel(&renderer, i + 1, i), pixel(&renderer, i, i + 1)] {
+                assert!(edge.iter().zip(foreground).all(|(&channel, full)| channel > 0.0 && channel < full), "{:?}", edge);
+            }
+        }
+        
+        let mut jagged = Renderer::new(32, 32);
+        jagged.set_antialiased(false);
+        jagged.draw_line_2d(Vec3::new(4.0, 4.0, 0.5), Vec3::new(28.0, 28.0, 0.5), Vec3::ONE, Vec3::ONE, 1.0);
+        assert!(jagged.get_buffer().iter().all(|&p| p == 0 || u32_to_rgb(p) == foreground));
+    }
+    
+    #[test]
+    fn cones_are_wider_at_the_start() {
+        let mut renderer = Renderer::new(64, 64);
+        renderer.draw_cone_2d(Vec3::new(8.0, 32.0, 0.5), Vec3::new(56.0, 32.0, 0.5), Vec3::ONE, Vec3::ONE, 8.0);
+        let column = |x: usize| (0..64).filter(|&y| renderer.get_buffer()[y * 64 + x] != 0).count();
+        assert_eq!(column(9), 12);
+        assert!(column(54) < column(30) && column(30) < column(9));
+        assert_eq!(column(4), 0);
+        assert!(renderer.dirty_rect.is_some());
+    }
+
+    #[test]
+    fn cones_clip_at_the_screen_edge() {
+        let mut renderer = Renderer::new(16, 16);
+        renderer.draw_cone_2d(Vec3::new(-20.0, 8.0, 0.0), Vec3::new(40.0, 20.0, 0.0), Vec3::ONE, Vec3::ONE, 10.0);
+        renderer.draw_cone_2d(Vec3::new(-20.0, -20.0, 0.0), Vec3::new(-5.0, -5.0, 0.0), Vec3::ONE, Vec3::ONE, 4.0);
+        assert!(renderer.get_buffer().iter().any(|&pixel| pixel != 0));
+    }
+
+    #[test]
+    fn bounding_box_covers_all_lines() {
+        let mut renderer = Renderer::new(16, 16);
+        assert_eq!(renderer.bounding_box(), None);
+        renderer.add_line(test_line(Vec3::new(-1.0, 0.0, 2.0), Vec3::new(3.0, 1.0, 0.0)));
+        renderer.add_line(test_line(Vec3::ZERO, Vec3::new(0.0, 5.0, 0.0)));
+        assert_eq!(renderer.bounding_box(), Some((Vec3::new(-1.0, 0.0, 0.0), Vec3::new(3.0, 5.0, 2.0))));
+    }
+    
+    #[test]
+    fn composite_over_blends_only_non_zero_overlay_pixels() {
+        let mut buffer = [0x0000FF; 16];
+        let overlay = [0xFF0000, 0, 0xFFFFFF, 0];
+        composite_over(&mut buffer, 4, 4, &overlay, (2, 2), 255, (3, 3));
+        assert_eq!(buffer[15], 0xFF0000);
+        assert_eq!(buffer[14], 0x0000FF);
+        
+        buffer.fill(0x0000FF);
+        composite_over(&mut buffer, 4, 4, &overlay, (2, 2), 128, (0, 0));
+        assert_eq!(buffer[0], (128 << 16) | 127);
+        assert_eq!(buffer[1], 0x0000FF);
+        assert_eq!(buffer[4], 0x8080FF);
+    }
+    
+    #[test]
+    fn frame_sequence_ppm_writes_numbered_binary_frames() {
+        let dir = std::env::temp_dir().join(format!("lsystem_frames_ppm_{}", std::process::id()));
+        let mut renderer = Renderer::new(2, 1);
+        renderer.buffer[1] = 0x102030;
+        let path = renderer.write_frame_sequence_ppm(&dir, "frame", 7).unwrap();
+        assert_eq!(path, dir.join("frame_00007.ppm"));
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"P6\n2 1\n255\n"));
+        assert_eq!(&bytes[bytes.len() - 3..], &[0x10, 0x20, 0x30]);
+        fs::remove_dir_all(&dir).ok();
+    }
+    
+    #[test]
+    fn gradient_background_clears_row_by_row() {
+        let mut renderer = Renderer::new(4, 3);
+        renderer.set_background(BackgroundConfig::LinearGradient { top: 0x000000, bottom: 0xFFFFFF });
+        renderer.clear();
+        assert_eq!(renderer.get_buffer()[0], 0x000000);
+        assert_eq!(renderer.get_buffer()[2 * 4 + 3], 0xFFFFFF);
+        assert_eq!(renderer.get_background_at_pixel(1, 1), 0x7F7F7F);
+        assert_eq!(renderer.compute_per_pixel_coverage(), 0.0);
+        
+        renderer.buffer[4 + 1] = 0xFF0000;
+        renderer.clear_partial((1, 1, 1, 1));
+        assert_eq!(renderer.get_buffer()[4 + 1], 0x7F7F7F);
+    }
+}
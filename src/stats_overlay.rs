@@ -0,0 +1,185 @@
+use std::time::Duration;
+use crate::renderer::Renderer;
+use crate::turtle3d::TurtleMetrics;
+
+const FRAME_WINDOW: usize = 60;
+
+/// One frame's numbers for `StatsOverlay::render`
+pub struct FrameStats<'a> {
+    pub generation_time: Duration,
+    pub render_time: Duration,
+    pub culled: usize,
+    /// The full expansion's size when generation stopped early (`--low-memory`)
+    pub full_length: Option<u64>,
+    /// 0-1 fraction of the screen covered by lines
+    pub coverage: f32,
+    /// Only shown when tracking is enabled
+    pub overdraw: Option<f32>,
+    pub string_bytes: usize,
+    pub truncated: bool,
+    /// `usize::MAX` when the rule graph is cyclic
+    pub longest_chain: usize,
+    /// Only computed with `--compute-fractal-dim`
+    pub fractal_dimension: Option<f32>,
+    /// Shown for stochastic rules
+    pub seed: Option<u64>,
+    pub metrics: Option<&'a TurtleMetrics>,
+}
+
+/// Top-right readout of generation time, render time, FPS, culling and the turtle's last dry run
+pub struct StatsOverlay {
+    pub visible: bool,
+    frame_times: [Duration; FRAME_WINDOW],
+    next_frame: usize,
+    recorded_frames: usize,
+}
+
+impl StatsOverlay {
+    pub fn new() -> Self {
+        Self {
+            visible: true,
+            frame_times: [Duration::ZERO; FRAME_WINDOW],
+            next_frame: 0,
+            recorded_frames: 0,
+        }
+    }
+    
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+    
+    pub fn record_frame(&mut self, frame_time: Duration) {
+        self.frame_times[self.next_frame] = frame_time;
+        self.next_frame = (self.next_frame + 1) % FRAME_WINDOW;
+        self.recorded_frames = (self.recorded_frames + 1).min(FRAME_WINDOW);
+    }
+    
+    /// Average over the last 60 frames
+    pub fn fps(&self) -> f32 {
+        let total: Duration = self.frame_times[..self.recorded_frames].iter().sum();
+        if total.is_zero() {
+            return 0.0;
+        }
+        self.recorded_frames as f32 / total.as_secs_f32()
+    }
+    
+    /// Returns the panel's bounds as `(x, y, width, height)`, or `None` while hidden
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize, stats: &FrameStats) -> Option<(usize, usize, usize, usize)> {
+        if !self.visible {
+            return None;
+        }
+        
+        let FrameStats { generation_time, render_time, culled, full_length, coverage, overdraw, string_bytes,
+                         truncated, longest_chain, fractal_dimension, seed, metrics } = *stats;
+        let text = format!("Gen: {} ms | Render: {} ms | FPS: {:.0} | Culled: {}",
+                           generation_time.as_millis(), render_time.as_millis(), self.fps(), culled);
+        let mut fill = match overdraw {
+            Some(overdraw) => format!("Coverage: {:.1}% | Overdraw: {:.1}x", coverage * 100.0, overdraw),
+            None => format!("Coverage: {:.1}%", coverage * 100.0),
+        };
+        if let Some(metrics) = metrics {
+            fill.push_str(&format!(" | Segments: {} | Depth: {} | Tips: {}",
+                                   metrics.total_segments, metrics.max_stack_depth, metrics.tip_positions.len()));
+        }
+        let graph = match longest_chain {
+            usize::MAX => "Rule graph: cyclic".to_string(),
+            length => format!("max chain: {}", length),
+        };
+        let mut memory = format!("String: {:.1} MB | {}", string_bytes as f64 / 1_000_000.0, graph);
+        if let Some(dimension) = fractal_dimension {
+            memory.push_str(&format!(" | Dimension: {:.2}", dimension));
+        }
+        if let Some(seed) = seed {
+            memory.push_str(&format!(" | Seed: {}", seed));
+        }
+        let note = full_length.map(|length| format!("(would be {} chars at full depth)", format_count(length)));
+        let text_width = note.iter().chain([&fill, &memory]).map(|line| Renderer::measure_text(line, 1).0)
+            .fold(Renderer::measure_text(&text, 1).0, usize::max);
+        let panel_width = text_width + 16;
+        let panel_height = if note.is_some() { 72 } else { 56 };
+        let panel_x = width.saturating_sub(panel_width + 10);
+        let panel_y = 10;
+        
+        for dy in 0..panel_height {
+            for dx in 0..panel_width {
+                let (px, py) = (panel_x + dx, panel_y + dy);
+                if px < width && py < height {
+                    buffer[py * width + px] = 0x101018;
+                }
+            }
+        }
+        Renderer::render_text_at_screen(buffer, width, height, (panel_x + 8, panel_y + 6), &text, 0xCCCCCC, 1);
+        Renderer::render_text_at_screen(buffer, width, height, (panel_x + 8, panel_y + 22), &fill, 0xCCCCCC, 1);
+        let memory_color = if truncated { 0xFF4040 } else { 0xCCCCCC };
+        Renderer::render_text_at_screen(buffer, width, height, (panel_x + 8, panel_y + 38), &memory, memory_color, 1);
+        if let Some(note) = &note {
+            Renderer::render_text_at_screen(buffer, width, height, (panel_x + 8, panel_y + 54), note, 0xFFAA00, 1);
+        }
+        Some((panel_x, panel_y, panel_width, panel_height))
+    }
+}
+
+/// `45 M`, `12 K` or the plain number
+fn format_count(count: u64) -> String {
+    match count {
+        1_000_000.. => format!("{} M", count / 1_000_000),
+        1_000.. => format!("{} K", count / 1_000),
+        _ => count.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn frame_stats(full_length: Option<u64>) -> FrameStats<'static> {
+        FrameStats {
+            generation_time: Duration::from_millis(5),
+            render_time: Duration::from_millis(2),
+            culled: 0,
+            full_length,
+            coverage: 0.25,
+            overdraw: None,
+            string_bytes: 1024,
+            truncated: false,
+            longest_chain: 3,
+            fractal_dimension: None,
+            seed: None,
+            metrics: None,
+        }
+    }
+    
+    #[test]
+    fn fps_averages_the_recorded_frames() {
+        let mut overlay = StatsOverlay::new();
+        assert_eq!(overlay.fps(), 0.0);
+        for _ in 0..FRAME_WINDOW + 5 {
+            overlay.record_frame(Duration::from_millis(20));
+        }
+        assert!((overlay.fps() - 50.0).abs() < 0.01);
+    }
+    
+    #[test]
+    fn render_returns_bounds_only_while_visible() {
+        let (width, height) = (800, 200);
+        let mut buffer = vec![0u32; width * height];
+        let mut overlay = StatsOverlay::new();
+        let (x, y, panel_width, panel_height) = overlay.render(&mut buffer, width, height, &frame_stats(None)).unwrap();
+        assert_eq!((y, panel_height), (10, 56));
+        assert_eq!(x + panel_width + 10, width);
+        assert_eq!(buffer[(y + 1) * width + x + 1], 0x101018);
+        
+        // The full-depth note adds a line
+        assert_eq!(overlay.render(&mut buffer, width, height, &frame_stats(Some(5_000_000))).unwrap().3, 72);
+        
+        overlay.toggle();
+        assert!(overlay.render(&mut buffer, width, height, &frame_stats(None)).is_none());
+    }
+    
+    #[test]
+    fn format_count_abbreviates_thousands_and_millions() {
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(12_345), "12 K");
+        assert_eq!(format_count(45_000_000), "45 M");
+    }
+}
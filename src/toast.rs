@@ -0,0 +1,89 @@
+use crate::renderer::Renderer;
+
+pub struct Toast {
+    message: Option<String>,
+    remaining: f32,
+    duration: f32,
+}
+
+impl Toast {
+    pub fn new() -> Self {
+        Self {
+            message: None,
+            remaining: 0.0,
+            duration: 2.0,
+        }
+    }
+    
+    pub fn show(&mut self, message: &str) {
+        println!("{}", message);
+        self.message = Some(message.to_string());
+        self.remaining = self.duration;
+    }
+    
+    pub fn update(&mut self, dt: f32) {
+        if self.message.is_some() {
+            self.remaining -= dt;
+            if self.remaining <= 0.0 {
+                self.message = None;
+            }
+        }
+    }
+    
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let message = match &self.message {
+            Some(message) => message,
+            None => return,
+        };
+        
+        let toast_width = Renderer::measure_text(message, 1).0 + 20;
+        let toast_height = 28;
+        let toast_x = width.saturating_sub(toast_width) / 2;
+        let toast_y = height.saturating_sub(toast_height + 40);
+        
+        self.fill_rect(buffer, width, height, (toast_x, toast_y, toast_width, toast_height), 0x2d2d2d);
+        Renderer::render_text_at_screen(buffer, width, height, (toast_x + 10, toast_y + 8), message, 0xFFFFFF, 1);
+    }
+    
+    fn fill_rect(&self, buffer: &mut [u32], buf_width: usize, buf_height: usize, 
+                (x, y, w, h): (usize, usize, usize, usize), color: u32) {
+        for dy in 0..h {
+            for dx in 0..w {
+                let px = x + dx;
+                let py = y + dy;
+                if px < buf_width && py < buf_height {
+                    buffer[py * buf_width + px] = color;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn message_expires_after_its_duration() {
+        let mut toast = Toast::new();
+        toast.show("Saved");
+        toast.update(1.5);
+        assert_eq!(toast.message.as_deref(), Some("Saved"));
+        toast.update(1.0);
+        assert!(toast.message.is_none());
+    }
+    
+    #[test]
+    fn render_draws_only_while_a_message_is_shown() {
+        let (width, height) = (200, 100);
+        let mut buffer = vec![0u32; width * height];
+        let mut toast = Toast::new();
+        toast.render(&mut buffer, width, height);
+        assert!(buffer.iter().all(|&pixel| pixel == 0));
+        
+        toast.show("Hi");
+        toast.render(&mut buffer, width, height);
+        assert!(buffer.contains(&0x2d2d2d));
+        assert!(buffer.contains(&0xFFFFFF));
+    }
+}
@@ -1,6 +1,7 @@
-use glam::{Mat3, Vec3};
-use crate::renderer::{Renderer, Vertex, Line};
-use std::collections::HashMap;
+use glam::{Mat3, Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::renderer::{color_to_u32, Renderer, Vertex, Line};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub struct TurtleState {
@@ -21,6 +22,188 @@ impl TurtleState {
             line_width: 2.5, // Start with thicker trunk
         }
     }
+    
+    /// Rolls the turtle so its up vector is as close to world up as possible
+    /// while keeping the heading unchanged (the ABOP `$` command)
+    pub fn make_up_horizontal(&mut self) {
+        let left = Vec3::Y.cross(self.direction);
+        if left.length_squared() < 1e-6 {
+            return; // Heading is vertical, so any roll is equally valid
+        }
+        let left = left.normalize();
+        self.up = self.direction.cross(left).normalize();
+    }
+}
+
+/// Width taper read from a rule file: `base * decay^depth`, never below `min`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct TaperConfig {
+    pub base: f32,
+    /// Defaults to `DEFAULT_TAPER_DECAY`
+    #[serde(default = "default_taper_decay")]
+    pub decay: f32,
+    pub min: f32,
+}
+
+fn default_taper_decay() -> f32 {
+    DEFAULT_TAPER_DECAY
+}
+
+/// One color of a rule's gradient; `t` runs from 0 at the ground to 1 at the tips
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GradientStop {
+    pub t: f32,
+    pub color: [f32; 3],
+}
+
+/// Color at `t`, linearly interpolated between the stops around it; `gradient` must be sorted by `t`
+pub fn sample_gradient(gradient: &[GradientStop], t: f32) -> Vec3 {
+    let (Some(first), Some(last)) = (gradient.first(), gradient.last()) else { return Vec3::ONE };
+    if t <= first.t {
+        return Vec3::from(first.color);
+    }
+    if t >= last.t {
+        return Vec3::from(last.color);
+    }
+    let upper = gradient.iter().position(|stop| stop.t > t).unwrap_or(gradient.len() - 1);
+    let (a, b) = (gradient[upper - 1], gradient[upper]);
+    let span = b.t - a.t;
+    let local = if span > 0.0 { (t - a.t) / span } else { 1.0 };
+    Vec3::from(a.color).lerp(Vec3::from(b.color), local)
+}
+
+/// How line width is chosen while interpreting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaperMode {
+    /// Width follows the branch stack depth; `!` and `'` are ignored
+    ByDepth { base: f32, decay: f32, min: f32 },
+    /// Width changes only through `!` and `'`
+    FromCommands,
+}
+
+/// How much narrower each branch level is when a rule's taper leaves out `decay`
+const DEFAULT_TAPER_DECAY: f32 = 0.7;
+
+/// Summary of a turtle path produced by `Turtle3D::dry_run`
+#[derive(Debug, Clone)]
+pub struct TurtleMetrics {
+    pub bounding_box: (Vec3, Vec3),
+    pub total_segments: usize,
+    pub max_stack_depth: usize,
+    /// Where each branch ended: the position at every `]`, plus the final position
+    pub tip_positions: Vec<Vec3>,
+    pub final_state: TurtleState,
+}
+
+impl TurtleMetrics {
+    fn new(start: Vec3) -> Self {
+        Self {
+            bounding_box: (start, start),
+            total_segments: 0,
+            max_stack_depth: 0,
+            tip_positions: Vec::new(),
+            final_state: TurtleState::new(),
+        }
+    }
+}
+
+pub type CustomCommand = Box<dyn Fn(&mut TurtleState, &mut Renderer)>;
+
+/// Bytes of the command string between the checkpoints `dry_run` records for `replay_to_position`
+const CHECKPOINT_INTERVAL: usize = 1000;
+
+/// What `replay_to_position` needs to resume interpreting part way through a string
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    state: TurtleState,
+    stack: Vec<TurtleState>,
+    color_index: usize,
+    in_module: bool,
+}
+
+/// Symbols with a built-in meaning in `Turtle3D::interpret`
+pub const TURTLE_COMMANDS: &[char] = &[
+    'F', 'G', 'f', 'g', 'H', 'h', 'Z', 'z', '+', '-', '&', '^', '\\', '/', '|', '[', ']', '#', '!', '\'',
+    '{', '}',
+];
+
+pub fn is_turtle_command(c: char) -> bool {
+    TURTLE_COMMANDS.contains(&c)
+}
+
+/// Commands that move the turtle forward, drawing or not
+pub fn is_forward_command(c: char) -> bool {
+    matches!(c, 'F' | 'G' | 'f' | 'g' | 'H' | 'h' | 'Z' | 'z')
+}
+
+/// Surrounds multi-character module names in generated strings; the turtle skips everything
+/// between a pair of them
+pub const MODULE_DELIMITER: char = '\0';
+
+/// `index`, moved forward to the start of a character and capped at the end of `s`
+fn next_char_boundary(s: &str, index: usize) -> usize {
+    (index.min(s.len())..=s.len()).find(|&i| s.is_char_boundary(i)).unwrap_or(s.len())
+}
+
+/// One command of a string being interpreted. In parametric strings a symbol may carry literal
+/// arguments, as in `F(1.5)` or `+(30)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParametricSymbol {
+    Plain(char),
+    Parametric(char, Vec<f32>),
+}
+
+/// The commands of `commands`; with `parametric` set, a `(...)` group right after a symbol is
+/// read as that symbol's comma-separated arguments
+pub fn parametric_symbols(commands: &str, parametric: bool) -> impl Iterator<Item = ParametricSymbol> + '_ {
+    let mut rest = commands;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        rest = &rest[c.len_utf8()..];
+        if parametric && rest.starts_with('(')
+            && let Some(close) = rest.find(')') {
+            let args = rest[1..close].split(',').filter_map(|arg| arg.trim().parse().ok()).collect();
+            rest = &rest[close + 1..];
+            return Some(ParametricSymbol::Parametric(c, args));
+        }
+        Some(ParametricSymbol::Plain(c))
+    })
+}
+
+/// Byte range of each command of `commands`, including its `(...)` arguments when `parametric`,
+/// split the way `parametric_symbols` reads them
+fn command_spans(commands: &str, parametric: bool) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        let c = commands[start..].chars().next()?;
+        let mut end = start + c.len_utf8();
+        if parametric && commands[end..].starts_with('(')
+            && let Some(close) = commands[end..].find(')') {
+            end += close + 1;
+        }
+        let span = (start, end);
+        start = end;
+        Some(span)
+    })
+}
+
+/// Deepest the state stack gets while interpreting `commands` from a stack `start_depth` deep,
+/// ignoring brackets inside module names
+fn max_bracket_depth(commands: &str, start_depth: usize) -> usize {
+    let (mut depth, mut max_depth, mut in_module) = (start_depth, start_depth, false);
+    for c in commands.chars() {
+        match c {
+            MODULE_DELIMITER => in_module = !in_module,
+            _ if in_module => {}
+            '[' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            ']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max_depth
 }
 
 pub struct Turtle3D {
@@ -31,6 +214,38 @@ pub struct Turtle3D {
     color_palette: Vec<Vec3>,
     current_color_index: usize,
     depth_colors: bool,
+    taper_mode: TaperMode,
+    custom_commands: HashMap<char, CustomCommand>,
+    // Y of the ground plane that forward moves may not cross
+    ground_level: Option<f32>,
+    // Y forward moves may not rise above
+    max_height: Option<f32>,
+    // Direction every forward move bends the heading towards, and how strongly
+    tropism: Option<(Vec3, f32)>,
+    // Colors each vertex by its height, overriding depth and palette colors
+    gradient: Option<Vec<GradientStop>>,
+    // Color each listed symbol switches to before it runs; any entry turns depth colors off
+    symbol_colors: HashMap<char, Vec3>,
+    // Between `{` and `}`, drawing moves record polygon vertices instead of lines
+    polygon_mode: bool,
+    polygon_buffer: Vec<Vec3>,
+    // Projects closed polygons, which are filled straight into the renderer's buffer
+    view_proj: Option<Mat4>,
+    // Collected instead of drawing while a dry run is in progress
+    dry_run_metrics: Option<TurtleMetrics>,
+    last_metrics: Option<TurtleMetrics>,
+    // Turtle state every CHECKPOINT_INTERVAL bytes through the last dry run's string, and
+    // that string's length
+    checkpoints: Vec<(usize, Checkpoint)>,
+    checkpoint_source_len: usize,
+    // Inside a module name, which has no turtle meaning
+    in_module: bool,
+    // Reads `F(n)`-style arguments after symbols
+    parametric: bool,
+    // How much the deepest branches are darkened, 0 to 1
+    ao_strength: f32,
+    // Deepest bracket nesting in the string being interpreted, which gets the full darkening
+    ao_max_depth: usize,
 }
 
 impl Turtle3D {
@@ -43,6 +258,59 @@ impl Turtle3D {
             color_palette: Self::create_color_palette(),
             current_color_index: 0,
             depth_colors: true,
+            taper_mode: TaperMode::FromCommands,
+            custom_commands: HashMap::new(),
+            ground_level: None,
+            max_height: None,
+            tropism: None,
+            gradient: None,
+            symbol_colors: HashMap::new(),
+            polygon_mode: false,
+            polygon_buffer: Vec::new(),
+            view_proj: None,
+            dry_run_metrics: None,
+            last_metrics: None,
+            checkpoints: Vec::new(),
+            checkpoint_source_len: 0,
+            in_module: false,
+            parametric: false,
+            ao_strength: 0.0,
+            ao_max_depth: 0,
+        }
+    }
+    
+    pub fn set_custom_command(&mut self, symbol: char, handler: CustomCommand) {
+        self.custom_commands.insert(symbol, handler);
+    }
+    
+    /// Copies all turtle settings except the custom command handlers, which cannot be cloned
+    pub fn clone_without_custom_commands(&self) -> Turtle3D {
+        Self {
+            current_state: self.current_state.clone(),
+            state_stack: self.state_stack.clone(),
+            step_length: self.step_length,
+            angle: self.angle,
+            color_palette: self.color_palette.clone(),
+            current_color_index: self.current_color_index,
+            depth_colors: self.depth_colors,
+            taper_mode: self.taper_mode,
+            custom_commands: HashMap::new(),
+            ground_level: self.ground_level,
+            max_height: self.max_height,
+            tropism: self.tropism,
+            gradient: self.gradient.clone(),
+            symbol_colors: self.symbol_colors.clone(),
+            polygon_mode: self.polygon_mode,
+            polygon_buffer: self.polygon_buffer.clone(),
+            view_proj: self.view_proj,
+            dry_run_metrics: None,
+            last_metrics: self.last_metrics.clone(),
+            checkpoints: self.checkpoints.clone(),
+            checkpoint_source_len: self.checkpoint_source_len,
+            in_module: self.in_module,
+            parametric: self.parametric,
+            ao_strength: self.ao_strength,
+            ao_max_depth: self.ao_max_depth,
         }
     }
     
@@ -69,17 +337,121 @@ impl Turtle3D {
         self.angle = angle_degrees.to_radians();
     }
     
+    pub fn set_position(&mut self, position: Vec3) {
+        self.current_state.position = position;
+    }
+    
+    /// Keeps every forward move at or above `y`; a move that would dip below it is clamped
+    /// to the ground and the turtle bounces back up
+    pub fn set_ground_level(&mut self, y: f32) {
+        self.ground_level = Some(y);
+    }
+    
+    pub fn clear_ground_level(&mut self) {
+        self.ground_level = None;
+    }
+    
+    /// Mirror of `set_ground_level`: forward moves that would rise above `y` are clamped and turned back down
+    pub fn set_max_height(&mut self, y: f32) {
+        self.max_height = Some(y);
+    }
+    
+    pub fn clear_max_height(&mut self) {
+        self.max_height = None;
+    }
+    
+    /// After each forward move the heading turns towards `direction` by `weight` times the sine of
+    /// the angle between them, as in The Algorithmic Beauty of Plants; a longer `direction` pulls harder
+    pub fn set_tropism(&mut self, direction: Vec3, weight: f32) {
+        self.tropism = Some((direction, weight));
+    }
+    
+    pub fn clear_tropism(&mut self) {
+        self.tropism = None;
+    }
+    
+    /// Colors both ends of every line from `gradient` by their own height, so a branch fades
+    /// along its length; `None` goes back to depth or palette colors
+    pub fn set_gradient(&mut self, gradient: Option<Vec<GradientStop>>) {
+        self.gradient = gradient;
+    }
+    
+    /// Colors to switch to before each listed symbol runs. The color is part of the turtle state,
+    /// so `[` and `]` save and restore it; an empty map goes back to depth or palette colors
+    pub fn set_symbol_colors(&mut self, colors: HashMap<char, Vec3>) {
+        self.symbol_colors = colors;
+    }
+    
+    /// Camera transform used to fill `{...}` polygons; without one they are skipped
+    pub fn set_view_proj(&mut self, view_proj: Option<Mat4>) {
+        self.view_proj = view_proj;
+    }
+    
+    /// Reads arguments after symbols: `F(n)` moves `n` steps and `+(n)`, `-(n)`, `&(n)`, `^(n)`,
+    /// `\(n)` and `/(n)` turn by `n` degrees instead of the turtle's angle
+    pub fn set_parametric(&mut self, parametric: bool) {
+        self.parametric = parametric;
+    }
+    
+    /// Cheap stand-in for ambient occlusion: lines are darkened in proportion to their bracket
+    /// depth, by up to `strength` (0 to 1) on the most deeply nested branches of each `interpret`
+    pub fn set_ao_strength(&mut self, strength: f32) {
+        self.ao_strength = strength.clamp(0.0, 1.0);
+    }
+    
     pub fn reset(&mut self) {
         self.current_state = TurtleState::new();
         self.state_stack.clear();
+        self.polygon_mode = false;
+        self.polygon_buffer.clear();
         self.current_color_index = 0;
+        self.in_module = false;
+        self.apply_depth_taper();
+    }
+    
+    pub fn set_taper_mode(&mut self, mode: TaperMode) {
+        self.taper_mode = mode;
+        self.apply_depth_taper();
+    }
+    
+    /// Sets the current width from the stack depth when tapering by depth
+    fn apply_depth_taper(&mut self) {
+        if let TaperMode::ByDepth { base, decay, min } = self.taper_mode {
+            let depth = self.state_stack.len() as i32;
+            self.current_state.line_width = (base * decay.powi(depth)).max(min);
+        }
     }
     
     pub fn interpret(&mut self, commands: &str, renderer: &mut Renderer, custom_rules: Option<&HashMap<char, String>>) {
-        for c in commands.chars() {
+        if self.ao_strength > 0.0 {
+            self.ao_max_depth = max_bracket_depth(commands, self.state_stack.len());
+        }
+        for symbol in parametric_symbols(commands, self.parametric) {
+            let (c, arg) = match symbol {
+                ParametricSymbol::Plain(c) => (c, None),
+                ParametricSymbol::Parametric(c, args) => (c, args.first().copied()),
+            };
+            if c == MODULE_DELIMITER {
+                self.in_module = !self.in_module;
+                continue;
+            }
+            if self.in_module {
+                continue;
+            }
+            if let Some(&color) = self.symbol_colors.get(&c) {
+                self.current_state.color = color;
+            }
+            if let Some(n) = arg
+                && self.interpret_with_argument(c, n, renderer) {
+                continue;
+            }
             match c {
                 'F' | 'G' => self.forward(renderer, true),
                 'f' | 'g' => self.forward(renderer, false),
+                'H' => self.forward_scaled(renderer, true, 0.5),
+                'h' => self.forward_scaled(renderer, false, 0.5),
+                'Z' => self.forward_scaled(renderer, true, 2.0),
+                'z' => self.forward_scaled(renderer, false, 2.0),
                 '+' => self.turn_left(),
                 '-' => self.turn_right(),
                 '&' => self.pitch_down(),
@@ -92,30 +464,244 @@ impl Turtle3D {
                 '#' => self.increment_color(),
                 '!' => self.increment_line_width(), // ! makes lines thicker
                 '\'' => self.decrement_line_width(), // ' makes lines thinner
+                '{' => self.begin_polygon(),
+                '}' => self.end_polygon(renderer),
                 _ => {
-                    if let Some(rules) = custom_rules {
-                        if rules.contains_key(&c) {
-                            // Custom rule - could be handled recursively if needed
-                            continue;
-                        }
+                    if let Some(handler) = self.custom_commands.get(&c) {
+                        handler(&mut self.current_state, renderer);
+                        continue;
                     }
+                    if let Some(rules) = custom_rules
+                        && rules.contains_key(&c) {
+                        // Custom rule - could be handled recursively if needed
+                        continue;
+                    }
+                }
+            }
+        }
+    }
+    
+    /// Runs a command that takes an argument, returning false for those that don't
+    fn interpret_with_argument(&mut self, c: char, n: f32, renderer: &mut Renderer) -> bool {
+        match c {
+            'F' | 'G' => self.forward_scaled(renderer, true, n),
+            'f' | 'g' => self.forward_scaled(renderer, false, n),
+            '+' => self.rotate_by(n, Self::turn_left),
+            '-' => self.rotate_by(n, Self::turn_right),
+            '&' => self.rotate_by(n, Self::pitch_down),
+            '^' => self.rotate_by(n, Self::pitch_up),
+            '\\' => self.rotate_by(n, Self::roll_left),
+            '/' => self.rotate_by(n, Self::roll_right),
+            _ => return false,
+        }
+        true
+    }
+    
+    /// Walks `commands` from the current position without drawing and returns what the path
+    /// would look like; the turtle's state is restored afterwards
+    pub fn dry_run(&mut self, commands: &str, rules: Option<&HashMap<char, String>>) -> TurtleMetrics {
+        let saved_state = self.current_state.clone();
+        let saved_stack = std::mem::take(&mut self.state_stack);
+        let saved_color_index = self.current_color_index;
+        let saved_in_module = std::mem::take(&mut self.in_module);
+        
+        self.dry_run_metrics = Some(TurtleMetrics::new(self.current_state.position));
+        // Custom commands still get a renderer, but nothing reads from it
+        let mut scratch = Renderer::new(0, 0);
+        self.checkpoints.clear();
+        self.checkpoint_source_len = commands.len();
+        let mut start = 0;
+        while start < commands.len() {
+            self.checkpoints.push((start, self.checkpoint()));
+            let end = self.chunk_end(commands, start);
+            self.interpret(&commands[start..end], &mut scratch, rules);
+            start = end;
+        }
+        
+        let mut metrics = self.dry_run_metrics.take().expect("dry run metrics are set above");
+        metrics.tip_positions.push(self.current_state.position);
+        metrics.final_state = self.current_state.clone();
+        
+        self.current_state = saved_state;
+        self.state_stack = saved_stack;
+        self.current_color_index = saved_color_index;
+        self.in_module = saved_in_module;
+        self.last_metrics = Some(metrics.clone());
+        metrics
+    }
+    
+    /// End of the chunk starting at `start` that `dry_run` interprets before its next
+    /// checkpoint, moved past any argument list the chunk would otherwise split
+    fn chunk_end(&self, commands: &str, start: usize) -> usize {
+        let end = next_char_boundary(commands, start + CHECKPOINT_INTERVAL);
+        if !self.parametric {
+            return end;
+        }
+        match commands[start..end].rfind('(') {
+            Some(open) if !commands[start + open..end].contains(')') => {
+                commands[end..].find(')').map_or(commands.len(), |close| end + close + 1)
+            }
+            _ => end,
+        }
+    }
+    
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            state: self.current_state.clone(),
+            stack: self.state_stack.clone(),
+            color_index: self.current_color_index,
+            in_module: self.in_module,
+        }
+    }
+    
+    /// The turtle state after interpreting `commands[..char_index]`, for stepping through a
+    /// string. Resumes from the nearest checkpoint of the last `dry_run` when `commands` is
+    /// that run's string, so moving along a long string doesn't replay it from the start
+    pub fn replay_to_position(&mut self, commands: &str, char_index: usize) -> TurtleState {
+        let char_index = next_char_boundary(commands, char_index);
+        let saved = self.checkpoint();
+        
+        let resume = if self.checkpoint_source_len == commands.len() {
+            let nearest = self.checkpoints.partition_point(|(index, _)| *index <= char_index);
+            nearest.checked_sub(1).map(|i| self.checkpoints[i].clone())
+        } else {
+            None
+        };
+        let start = match resume {
+            Some((index, checkpoint)) => {
+                self.current_state = checkpoint.state;
+                self.state_stack = checkpoint.stack;
+                self.current_color_index = checkpoint.color_index;
+                self.in_module = checkpoint.in_module;
+                index
+            }
+            None => {
+                self.in_module = false;
+                0
+            }
+        };
+        
+        // Interpreting in dry-run mode moves the turtle without drawing
+        self.dry_run_metrics = Some(TurtleMetrics::new(self.current_state.position));
+        let mut scratch = Renderer::new(0, 0);
+        self.interpret(&commands[start..char_index], &mut scratch, None);
+        self.dry_run_metrics = None;
+        
+        let state = std::mem::replace(&mut self.current_state, saved.state);
+        self.state_stack = saved.stack;
+        self.current_color_index = saved.color_index;
+        self.in_module = saved.in_module;
+        state
+    }
+    
+    /// Byte offset just past the command that draws each segment of `commands`, in drawing order,
+    /// so `replay_to_position` can stop right after any line. Walks the string without drawing,
+    /// recording checkpoints for `replay_to_position` like `dry_run`, and restores the turtle
+    pub fn segment_ends(&mut self, commands: &str) -> Vec<usize> {
+        let saved = self.checkpoint();
+        self.dry_run_metrics = Some(TurtleMetrics::new(self.current_state.position));
+        let mut scratch = Renderer::new(0, 0);
+        self.checkpoints.clear();
+        self.checkpoint_source_len = commands.len();
+        
+        let mut ends = Vec::new();
+        for (start, end) in command_spans(commands, self.parametric) {
+            if self.checkpoints.last().is_none_or(|(index, _)| start >= index + CHECKPOINT_INTERVAL) {
+                self.checkpoints.push((start, self.checkpoint()));
+            }
+            self.interpret(&commands[start..end], &mut scratch, None);
+            if self.dry_run_metrics.as_ref().is_some_and(|metrics| metrics.total_segments > ends.len()) {
+                ends.push(end);
+            }
+        }
+        self.dry_run_metrics = None;
+        
+        self.current_state = saved.state;
+        self.state_stack = saved.stack;
+        self.current_color_index = saved.color_index;
+        self.in_module = saved.in_module;
+        ends
+    }
+    
+    pub fn last_metrics(&self) -> Option<&TurtleMetrics> {
+        self.last_metrics.as_ref()
+    }
+    
+    /// Box-counting dimension of the path drawn by `commands`: counts the grid cells the
+    /// segments touch at box sizes halving from `max_scale` down to `min_scale` over `steps`
+    /// halvings, and returns the slope of log(count) against log(1 / size). The turtle's
+    /// state is restored afterwards; returns 0 when fewer than two scales are usable
+    pub fn compute_fractal_dimension(&mut self, commands: &str, min_scale: f32, max_scale: f32, steps: usize) -> f32 {
+        let saved = self.checkpoint();
+        let mut scratch = Renderer::new(0, 0);
+        self.interpret(commands, &mut scratch, None);
+        self.current_state = saved.state;
+        self.state_stack = saved.stack;
+        self.current_color_index = saved.color_index;
+        self.in_module = saved.in_module;
+        
+        let lines = scratch.lines();
+        if lines.is_empty() || max_scale <= 0.0 {
+            return 0.0;
+        }
+        
+        let mut samples = Vec::new();
+        for k in 0..=steps {
+            let size = max_scale / (1u32 << k.min(31)) as f32;
+            if size < min_scale {
+                break;
+            }
+            // Sample each segment finely enough that no cell it crosses is skipped
+            let mut cells = HashSet::new();
+            for line in lines {
+                let (start, end) = (line.start.position, line.end.position);
+                let count = ((end - start).length() / (size * 0.25)).ceil().max(1.0) as usize;
+                for i in 0..=count {
+                    let p = start.lerp(end, i as f32 / count as f32) / size;
+                    cells.insert((p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64));
                 }
             }
+            samples.push(((1.0 / size).ln(), (cells.len() as f32).ln()));
         }
+        if samples.len() < 2 {
+            return 0.0;
+        }
+        
+        // Least-squares slope
+        let n = samples.len() as f32;
+        let mean_x = samples.iter().map(|(x, _)| x).sum::<f32>() / n;
+        let mean_y = samples.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let covariance: f32 = samples.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+        let variance: f32 = samples.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        if variance == 0.0 { 0.0 } else { covariance / variance }
     }
     
     fn forward(&mut self, renderer: &mut Renderer, draw: bool) {
-        let new_position = self.current_state.position + self.current_state.direction * self.step_length;
+        if self.dry_run_metrics.is_some() {
+            self.forward_no_draw(draw);
+            return;
+        }
         
-        if draw {
-            let color = if self.depth_colors {
+        let new_position = self.next_position();
+        
+        if draw && self.polygon_mode {
+            self.polygon_buffer.push(new_position);
+        } else if draw {
+            let color = if self.depth_colors && self.symbol_colors.is_empty() {
                 self.get_depth_color(self.current_state.position.y)
             } else {
                 self.current_state.color
             };
             
-            let start = Vertex::new(self.current_state.position, color);
-            let end = Vertex::new(new_position, color);
+            let (start_color, end_color) = match &self.gradient {
+                Some(gradient) => (sample_gradient(gradient, Self::height_factor(self.current_state.position.y)),
+                                   sample_gradient(gradient, Self::height_factor(new_position.y))),
+                None => (color, color),
+            };
+            let occlusion = self.occlusion();
+            let (start_color, end_color) = (start_color * occlusion, end_color * occlusion);
+            let start = Vertex::new(self.current_state.position, start_color);
+            let end = Vertex::new(new_position, end_color);
             
             renderer.add_line(Line::new_with_thickness(start, end, self.current_state.line_width));
         }
@@ -123,9 +709,102 @@ impl Turtle3D {
         self.current_state.position = new_position;
     }
     
+    /// Brightness factor for lines drawn at the current bracket depth
+    fn occlusion(&self) -> f32 {
+        if self.ao_strength <= 0.0 || self.ao_max_depth == 0 {
+            return 1.0;
+        }
+        let depth = self.state_stack.len().min(self.ao_max_depth) as f32 / self.ao_max_depth as f32;
+        1.0 - depth * self.ao_strength
+    }
+    
+    /// Dry-run counterpart of `forward`: moves and records metrics instead of adding a line
+    fn forward_no_draw(&mut self, draw: bool) {
+        let new_position = self.next_position();
+        if let Some(metrics) = &mut self.dry_run_metrics
+            && draw {
+            let (min, max) = metrics.bounding_box;
+            metrics.bounding_box = (min.min(new_position), max.max(new_position));
+            // Inside a polygon a drawing move adds a vertex rather than a line
+            if !self.polygon_mode {
+                metrics.total_segments += 1;
+            }
+        }
+        self.current_state.position = new_position;
+    }
+    
+    /// Where a forward step ends, clamped between the ground level and max height; hitting either
+    /// mirrors the heading and up vector so the branch grows away from it. Tropism then bends the
+    /// heading for the next step.
+    fn next_position(&mut self) -> Vec3 {
+        let mut new_position = self.current_state.position + self.current_state.direction * self.step_length;
+        let limit = self.ground_level.filter(|&ground| new_position.y < ground)
+            .or(self.max_height.filter(|&ceiling| new_position.y > ceiling));
+        if let Some(limit) = limit {
+            new_position.y = limit;
+            self.current_state.direction.y = -self.current_state.direction.y;
+            self.current_state.up.y = -self.current_state.up.y;
+        }
+        
+        if let Some((tropism, weight)) = self.tropism {
+            let torque = self.current_state.direction.cross(tropism);
+            if torque.length_squared() > 1e-12 {
+                let rotation = Mat3::from_axis_angle(torque.normalize(), weight * torque.length());
+                self.current_state.direction = (rotation * self.current_state.direction).normalize();
+                self.current_state.up = (rotation * self.current_state.up).normalize();
+            }
+        }
+        new_position
+    }
+    
+    /// Starts a polygon whose first vertex is the current position
+    fn begin_polygon(&mut self) {
+        self.polygon_mode = true;
+        self.polygon_buffer.clear();
+        self.polygon_buffer.push(self.current_state.position);
+    }
+    
+    /// Projects the recorded vertices and fills them at their average depth
+    fn end_polygon(&mut self, renderer: &mut Renderer) {
+        self.polygon_mode = false;
+        let vertices = std::mem::take(&mut self.polygon_buffer);
+        let Some(view_proj) = self.view_proj else { return };
+        if self.dry_run_metrics.is_some() {
+            return;
+        }
+        
+        let projected: Option<Vec<Vec3>> = vertices.iter()
+            .map(|&vertex| renderer.project_to_screen(vertex, &view_proj))
+            .collect();
+        let Some(projected) = projected else { return }; // A vertex is behind the camera
+        if projected.is_empty() {
+            return;
+        }
+        let depth = projected.iter().map(|p| p.z).sum::<f32>() / projected.len() as f32;
+        let vertices_2d: Vec<(f32, f32)> = projected.iter().map(|p| (p.x, p.y)).collect();
+        // Symbol colors win over depth coloring, as they do for lines
+        let color = if self.depth_colors && self.symbol_colors.is_empty() {
+            self.get_depth_color(self.current_state.position.y)
+        } else {
+            self.current_state.color
+        };
+        renderer.rasterize_polygon(&vertices_2d, depth, color_to_u32(color));
+    }
+    
+    fn forward_scaled(&mut self, renderer: &mut Renderer, draw: bool, scale: f32) {
+        let step_length = self.step_length;
+        self.step_length *= scale;
+        self.forward(renderer, draw);
+        self.step_length = step_length;
+    }
+    
+    /// `y` mapped from the -10..10 range trees are drawn in to 0-1
+    fn height_factor(y: f32) -> f32 {
+        ((y + 10.0) / 20.0).clamp(0.0, 1.0)
+    }
+    
     fn get_depth_color(&self, y: f32) -> Vec3 {
-        let depth_factor = (y + 10.0) / 20.0; // Normalize to 0-1 range
-        let depth_factor = depth_factor.clamp(0.0, 1.0);
+        let depth_factor = Self::height_factor(y);
         
         // Interpolate between brown (bottom) and green (top)
         let brown = Vec3::new(0.4, 0.2, 0.0);
@@ -134,14 +813,20 @@ impl Turtle3D {
         brown + depth_factor * (green - brown)
     }
     
+    /// Runs a rotation command with `degrees` in place of the turtle's angle
+    fn rotate_by(&mut self, degrees: f32, rotate: fn(&mut Self)) {
+        let angle = self.angle;
+        self.angle = degrees.to_radians();
+        rotate(self);
+        self.angle = angle;
+    }
+    
     fn turn_left(&mut self) {
-        let right = self.current_state.direction.cross(self.current_state.up);
         let rotation = Mat3::from_axis_angle(self.current_state.up, self.angle);
         self.current_state.direction = rotation * self.current_state.direction;
     }
     
     fn turn_right(&mut self) {
-        let right = self.current_state.direction.cross(self.current_state.up);
         let rotation = Mat3::from_axis_angle(self.current_state.up, -self.angle);
         self.current_state.direction = rotation * self.current_state.direction;
     }
@@ -176,9 +861,16 @@ impl Turtle3D {
     
     fn push_state(&mut self) {
         self.state_stack.push(self.current_state.clone());
+        self.apply_depth_taper();
+        if let Some(metrics) = &mut self.dry_run_metrics {
+            metrics.max_stack_depth = metrics.max_stack_depth.max(self.state_stack.len());
+        }
     }
     
     fn pop_state(&mut self) {
+        if let Some(metrics) = &mut self.dry_run_metrics {
+            metrics.tip_positions.push(self.current_state.position);
+        }
         if let Some(state) = self.state_stack.pop() {
             self.current_state = state;
         }
@@ -190,14 +882,319 @@ impl Turtle3D {
     }
     
     fn increment_line_width(&mut self) {
+        if self.taper_mode != TaperMode::FromCommands {
+            return;
+        }
         self.current_state.line_width = (self.current_state.line_width * 1.3).min(8.0);
     }
     
     fn decrement_line_width(&mut self) {
+        if self.taper_mode != TaperMode::FromCommands {
+            return;
+        }
         self.current_state.line_width = (self.current_state.line_width * 0.7).max(0.2);
     }
     
     pub fn set_depth_colors(&mut self, enabled: bool) {
         self.depth_colors = enabled;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn interpret_draws_one_segment_per_forward() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.interpret("F+F-F", &mut renderer, None);
+        assert_eq!(renderer.lines().len(), 3);
+    }
+    
+    #[test]
+    fn branches_return_to_an_empty_stack() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.interpret("F[+F][-F]", &mut renderer, None);
+        assert_eq!(renderer.lines().len(), 3);
+        assert!(turtle.state_stack.is_empty());
+        assert_eq!(turtle.current_state.position, Vec3::Y);
+    }
+    
+    #[test]
+    fn parametric_arguments_scale_steps_and_set_turn_angles() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_parametric(true);
+        turtle.set_step_length(0.5);
+        turtle.interpret("F(4)+(90)F(6)-F", &mut renderer, None);
+        // Up 2, a quarter turn left to -X for 3, then the default 25 degrees back for 0.5
+        let heading = Vec3::new(-(25.0_f32.to_radians().cos()), 25.0_f32.to_radians().sin(), 0.0);
+        let expected = Vec3::new(-3.0, 2.0, 0.0) + heading * 0.5;
+        assert!((turtle.current_state.position - expected).length() < 1e-4, "{:?}", turtle.current_state.position);
+        assert_eq!(renderer.lines().len(), 3);
+        
+        assert_eq!(parametric_symbols("F(1.5)+[", true).collect::<Vec<_>>(), vec![
+            ParametricSymbol::Parametric('F', vec![1.5]),
+            ParametricSymbol::Plain('+'),
+            ParametricSymbol::Plain('['),
+        ]);
+        // Without the flag the parentheses are just unknown symbols
+        assert_eq!(parametric_symbols("F(1)", false).count(), 4);
+    }
+    
+    #[test]
+    fn ground_level_bounces_downward_branches() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_ground_level(-0.5);
+        turtle.interpret("|FFF", &mut renderer, None);
+        assert_eq!(renderer.lines().len(), 3);
+        assert!(renderer.lines().iter().all(|line| line.start.position.y >= -0.5 && line.end.position.y >= -0.5));
+        assert_eq!(turtle.current_state.direction, Vec3::Y);
+        assert_eq!(turtle.current_state.position.y, 1.5);
+    }
+    
+    #[test]
+    fn tropism_bends_the_heading_and_max_height_caps_growth() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_tropism(Vec3::new(1.0, 0.0, 0.0), 0.2);
+        turtle.interpret("FF", &mut renderer, None);
+        let first = renderer.lines()[0].end.position - renderer.lines()[0].start.position;
+        let second = renderer.lines()[1].end.position - renderer.lines()[1].start.position;
+        assert_eq!(first.x, 0.0);
+        assert!((second.x - 0.2_f32.sin()).abs() < 1e-4);
+        
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_max_height(1.5);
+        turtle.interpret("FFF", &mut renderer, None);
+        assert!(renderer.lines().iter().all(|line| line.end.position.y <= 1.5));
+        assert_eq!(turtle.current_state.direction, -Vec3::Y);
+    }
+    
+    #[test]
+    fn gradient_colors_each_end_of_a_line_by_its_height() {
+        let gradient = [
+            GradientStop { t: 0.0, color: [0.0, 0.0, 0.0] },
+            GradientStop { t: 0.5, color: [1.0, 0.0, 0.0] },
+            GradientStop { t: 1.0, color: [1.0, 1.0, 0.0] },
+        ];
+        assert_eq!(sample_gradient(&gradient, -1.0), Vec3::ZERO);
+        assert_eq!(sample_gradient(&gradient, 0.25), Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(sample_gradient(&gradient, 0.75), Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(sample_gradient(&[], 0.5), Vec3::ONE);
+        
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_gradient(Some(gradient.to_vec()));
+        turtle.set_step_length(10.0);
+        turtle.interpret("F", &mut renderer, None);
+        let line = &renderer.lines()[0];
+        assert_eq!(line.start.color, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(line.end.color, Vec3::new(1.0, 1.0, 0.0));
+    }
+    
+    #[test]
+    fn polygons_record_vertices_instead_of_lines() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.interpret("{F+F+F}", &mut renderer, None);
+        assert!(renderer.lines().is_empty());
+        assert!(!turtle.polygon_mode && turtle.polygon_buffer.is_empty());
+        
+        turtle.reset();
+        turtle.interpret("{F+F", &mut renderer, None);
+        assert_eq!(turtle.polygon_buffer.len(), 3);
+    }
+    
+    #[test]
+    fn closed_polygons_are_filled_when_projected() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_angle(90.0);
+        turtle.set_position(Vec3::new(-0.5, -0.5, 0.0));
+        turtle.set_view_proj(Some(Mat4::orthographic_rh(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0)));
+        turtle.interpret("{F-F-F-F}", &mut renderer, None);
+        assert_ne!(renderer.get_buffer()[32 * 64 + 32], 0);
+        assert_eq!(renderer.get_buffer()[4 * 64 + 4], 0);
+    }
+    
+    #[test]
+    fn polygons_use_symbol_colors_over_depth_colors() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_angle(90.0);
+        turtle.set_position(Vec3::new(-0.5, -0.5, 0.0));
+        turtle.set_view_proj(Some(Mat4::orthographic_rh(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0)));
+        turtle.set_depth_colors(true);
+        turtle.set_symbol_colors([('F', red)].into_iter().collect());
+        turtle.interpret("{F-F-F-F}", &mut renderer, None);
+        // Depth shading darkens the red, but no depth color's green or blue shows through
+        let pixel = renderer.get_buffer()[32 * 64 + 32];
+        assert!(pixel & 0xFF0000 != 0 && pixel & 0x00FFFF == 0);
+    }
+    
+    #[test]
+    fn unbalanced_brackets_do_not_panic() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.interpret("F[+F", &mut renderer, None);
+        assert_eq!(renderer.lines().len(), 2);
+        assert_eq!(turtle.state_stack.len(), 1);
+        
+        turtle.reset();
+        turtle.interpret("F]]F", &mut renderer, None);
+        assert_eq!(renderer.lines().len(), 4);
+    }
+    
+    #[test]
+    fn move_without_drawing_adds_no_lines() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.interpret("fghz", &mut renderer, None);
+        assert!(renderer.lines().is_empty());
+        assert_eq!(turtle.current_state.position, Vec3::Y * 4.5);
+    }
+    
+    #[test]
+    fn dry_run_measures_without_drawing_or_moving() {
+        let mut turtle = Turtle3D::new();
+        let metrics = turtle.dry_run("F[+F]F", None);
+        assert_eq!(metrics.total_segments, 3);
+        assert_eq!(metrics.max_stack_depth, 1);
+        assert_eq!(metrics.tip_positions.len(), 2);
+        assert_eq!(metrics.final_state.position, Vec3::Y * 2.0);
+        assert_eq!(turtle.current_state.position, Vec3::ZERO);
+        assert!(turtle.last_metrics().is_some());
+    }
+    
+    #[test]
+    fn replay_to_position_matches_interpreting_the_prefix() {
+        let commands = "F[+F]F-".repeat(400);
+        let mut turtle = Turtle3D::new();
+        turtle.dry_run(&commands, None);
+        assert!(turtle.checkpoints.len() > 1);
+        
+        for index in [0, 5, 999, 1000, 1003, 2799] {
+            let mut reference = Turtle3D::new();
+            let mut renderer = Renderer::new(0, 0);
+            reference.interpret(&commands[..index], &mut renderer, None);
+            let state = turtle.replay_to_position(&commands, index);
+            assert!((state.position - reference.current_state.position).length() < 1e-3, "at {}", index);
+            assert!((state.direction - reference.current_state.direction).length() < 1e-3, "at {}", index);
+        }
+        assert_eq!(turtle.current_state.position, Vec3::ZERO);
+    }
+    
+    #[test]
+    fn segment_ends_stop_replays_right_after_each_line() {
+        let commands = format!("F[+F]f{{FF}}G{0}F{0}-H", MODULE_DELIMITER);
+        let mut turtle = Turtle3D::new();
+        let ends = turtle.segment_ends(&commands);
+        assert_eq!(ends, vec![1, 4, 11, commands.len()]);
+        
+        for (i, &end) in ends.iter().enumerate() {
+            let mut reference = Turtle3D::new();
+            let mut renderer = Renderer::new(0, 0);
+            reference.interpret(&commands[..end], &mut renderer, None);
+            assert_eq!(renderer.lines().len(), i + 1);
+            assert_eq!(turtle.replay_to_position(&commands, end).position, renderer.lines()[i].end.position);
+        }
+        assert_eq!(turtle.current_state.position, Vec3::ZERO);
+        
+        turtle.set_parametric(true);
+        assert_eq!(turtle.segment_ends("F(2)+(30)F(0.5)"), vec![4, 15]);
+    }
+    
+    #[test]
+    fn copies_keep_settings_but_not_custom_commands() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(8, 8);
+        turtle.set_angle(45.0);
+        turtle.set_custom_command('X', Box::new(|state, _| state.line_width = 9.0));
+        let mut copy = turtle.clone_without_custom_commands();
+        assert_eq!(copy.angle, turtle.angle);
+        
+        turtle.interpret("X", &mut renderer, None);
+        copy.interpret("X", &mut renderer, None);
+        assert_eq!(turtle.current_state.line_width, 9.0);
+        assert_ne!(copy.current_state.line_width, 9.0);
+    }
+    
+    #[test]
+    fn depth_taper_narrows_each_branch_level() {
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        // Rules that leave out the decay get the default one
+        let taper: TaperConfig = serde_json::from_str(r#"{ "base": 4.0, "min": 1.0 }"#).unwrap();
+        assert_eq!(taper.decay, DEFAULT_TAPER_DECAY);
+        turtle.set_taper_mode(TaperMode::ByDepth { base: taper.base, decay: taper.decay, min: taper.min });
+        assert_eq!(turtle.current_state.line_width, 4.0);
+        
+        turtle.interpret("[[[[[", &mut renderer, None);
+        assert_eq!(turtle.current_state.line_width, 1.0);
+        
+        // Width commands are ignored while tapering by depth
+        turtle.interpret("!!", &mut renderer, None);
+        assert_eq!(turtle.current_state.line_width, 1.0);
+    }
+    
+    #[test]
+    fn symbol_colors_switch_color_and_branches_restore_it() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let blue = Vec3::new(0.0, 0.0, 1.0);
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_symbol_colors([('F', red), ('G', blue)].into_iter().collect());
+        turtle.interpret("FG", &mut renderer, None);
+        assert_eq!(renderer.lines()[0].start.color, red);
+        assert_eq!(renderer.lines()[1].start.color, blue);
+        
+        // The branch's G color does not leak into the F that follows it
+        let mut turtle = Turtle3D::new();
+        let mut renderer = Renderer::new(64, 64);
+        turtle.set_symbol_colors([('A', red), ('G', blue)].into_iter().collect());
+        turtle.interpret("A[G]F", &mut renderer, None);
+        assert_eq!(renderer.lines()[0].start.color, blue);
+        assert_eq!(renderer.lines()[1].start.color, red);
+    }
+    
+    #[test]
+    fn ambient_occlusion_darkens_nested_branches() {
+        let brightness = |strength: f32| {
+            let mut turtle = Turtle3D::new();
+            let mut renderer = Renderer::new(64, 64);
+            turtle.set_ao_strength(strength);
+            turtle.interpret("[[[F]]]F", &mut renderer, None);
+            let lines = renderer.lines();
+            (lines[0].start.color.element_sum(), lines[1].start.color.element_sum())
+        };
+        
+        let (inner, outer) = brightness(1.0);
+        assert!(inner < outer);
+        assert_eq!(inner, 0.0);
+        let (half_inner, half_outer) = brightness(0.5);
+        assert_eq!(half_outer, outer);
+        assert!((half_inner - outer * 0.5).abs() < 1e-5);
+        assert_eq!(brightness(0.0), (outer, outer));
+    }
+    
+    #[test]
+    fn sierpinski_box_counting_dimension_is_near_log2_3() {
+        let rules: HashMap<char, &str> = [('F', "F-G+F+G-F"), ('G', "GG")].into_iter().collect();
+        let mut commands = String::from("F-G-G");
+        for _ in 0..5 {
+            commands = commands.chars().map(|c| rules.get(&c).map_or(c.to_string(), |r| r.to_string())).collect();
+        }
+        
+        let mut turtle = Turtle3D::new();
+        turtle.set_angle(120.0);
+        let dimension = turtle.compute_fractal_dimension(&commands, 0.5, 4.0, 3);
+        assert!((dimension - 1.585).abs() < 0.1, "dimension {}", dimension);
+        assert_eq!(turtle.current_state.position, Vec3::ZERO);
+    }
+}
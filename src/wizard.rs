@@ -0,0 +1,593 @@
+use std::path::PathBuf;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
+use crate::camera::Camera;
+use crate::renderer::Renderer;
+use crate::turtle3d::Turtle3D;
+use crate::{LSystem, LSystemRule};
+
+const PANEL_WIDTH: usize = 440;
+const PANEL_HEIGHT: usize = 340;
+const BUTTON_WIDTH: usize = 80;
+const BUTTON_HEIGHT: usize = 24;
+const PREVIEW_WIDTH: usize = 260;
+const PREVIEW_HEIGHT: usize = 180;
+const PREVIEW_ITERATIONS: u32 = 2;
+/// Degrees Left and Right turn the angle by on the preview
+const PREVIEW_ANGLE_STEP: f32 = 1.0;
+
+/// Single-line text field edited from the keyboard
+#[derive(Debug, Clone)]
+pub struct TextInput {
+    pub label: String,
+    pub text: String,
+    pub max_length: usize,
+}
+
+impl TextInput {
+    pub fn new(label: &str, text: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            text: text.to_string(),
+            max_length: 40,
+        }
+    }
+    
+    /// Applies this frame's key presses; returns true if the text changed
+    pub fn handle_input(&mut self, window: &Window) -> bool {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let mut changed = false;
+        
+        for key in window.get_keys_pressed(KeyRepeat::Yes) {
+            if key == Key::Backspace {
+                changed |= self.text.pop().is_some();
+            } else if let Some(c) = key_to_char(key, shift)
+                && self.text.len() < self.max_length {
+                self.text.push(c);
+                changed = true;
+            }
+        }
+        changed
+    }
+    
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize,
+                  x: usize, y: usize, focused: bool) {
+        Renderer::render_text_at_screen(buffer, width, height, (x, y + 4), &self.label, 0xCCCCCC, 1);
+        
+        let box_x = x + 140;
+        let box_width = 240;
+        fill_rect(buffer, width, height, (box_x, y, box_width, 20), 0x202020);
+        draw_rect(buffer, width, height, (box_x, y, box_width, 20),
+                  if focused { 0x00FF00 } else { 0x808080 });
+        
+        let text = if focused { format!("{}_", self.text) } else { self.text.clone() };
+        Renderer::render_text_at_screen(buffer, width, height, (box_x + 4, y + 4), &text, 0xFFFFFF, 1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WizardStep {
+    NameAndAxiom,
+    Productions,
+    Parameters,
+    Preview,
+}
+
+pub enum WizardOutcome {
+    Finished { rule: Box<LSystemRule>, path: PathBuf },
+    Cancelled,
+}
+
+/// The parsed Parameters step; `color` is `None` to color by height
+struct WizardParameters {
+    angle: f32,
+    iterations: u32,
+    step_length: f32,
+    color: Option<[f32; 3]>,
+}
+
+/// Step-by-step dialog that builds a new rule file without writing JSON by hand
+pub struct Wizard {
+    pub step: WizardStep,
+    pub name: TextInput,
+    pub axiom: TextInput,
+    pub description: TextInput,
+    pub productions: Vec<(char, TextInput)>,
+    pub angle: TextInput,
+    pub iterations: TextInput,
+    pub step_length: TextInput,
+    /// `r g b` in 0-1; empty colors by height instead
+    pub color: TextInput,
+    pub focused: usize,
+    pub error: Option<String>,
+    rules_directory: PathBuf,
+    preview: Vec<u32>,
+    mouse_was_down: bool,
+}
+
+impl Wizard {
+    pub fn new(rules_directory: PathBuf) -> Self {
+        Self {
+            step: WizardStep::NameAndAxiom,
+            name: TextInput::new("Name", ""),
+            axiom: TextInput::new("Axiom", ""),
+            description: TextInput::new("Description", ""),
+            productions: Vec::new(),
+            angle: TextInput::new("Angle (degrees)", "25"),
+            iterations: TextInput::new("Iterations", "4"),
+            step_length: TextInput::new("Step length", "1.0"),
+            color: TextInput::new("Color (r g b)", ""),
+            focused: 0,
+            error: None,
+            rules_directory,
+            preview: Vec::new(),
+            mouse_was_down: true,
+        }
+    }
+    
+    /// File the rule is written to, derived from its name
+    pub fn target_path(&self) -> PathBuf {
+        let stem: String = self.name.text.trim().to_lowercase().chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.rules_directory.join(format!("{}.json", stem))
+    }
+    
+    fn field_count(&self) -> usize {
+        match self.step {
+            WizardStep::NameAndAxiom => 3,
+            WizardStep::Productions => self.productions.len(),
+            WizardStep::Parameters => 4,
+            WizardStep::Preview => 0,
+        }
+    }
+    
+    fn focused_field(&mut self) -> Option<&mut TextInput> {
+        match (self.step, self.focused) {
+            (WizardStep::NameAndAxiom, 0) => Some(&mut self.name),
+            (WizardStep::NameAndAxiom, 1) => Some(&mut self.axiom),
+            (WizardStep::NameAndAxiom, 2) => Some(&mut self.description),
+            (WizardStep::Productions, i) => self.productions.get_mut(i).map(|(_, input)| input),
+            (WizardStep::Parameters, 0) => Some(&mut self.angle),
+            (WizardStep::Parameters, 1) => Some(&mut self.iterations),
+            (WizardStep::Parameters, 2) => Some(&mut self.step_length),
+            (WizardStep::Parameters, 3) => Some(&mut self.color),
+            _ => None,
+        }
+    }
+    
+    fn validate_step(&self) -> Result<(), String> {
+        match self.step {
+            WizardStep::NameAndAxiom => {
+                if self.name.text.trim().is_empty() {
+                    return Err("Name must not be empty".to_string());
+                }
+                if self.axiom.text.is_empty() {
+                    return Err("Axiom must not be empty".to_string());
+                }
+                crate::check_brackets(&self.axiom.text).map_err(|e| format!("Axiom: {}", e))?;
+                if self.target_path().exists() {
+                    return Err(format!("{} already exists", self.target_path().display()));
+                }
+                Ok(())
+            }
+            WizardStep::Productions => {
+                for (symbol, input) in &self.productions {
+                    crate::check_brackets(&input.text).map_err(|e| format!("Rule '{}': {}", symbol, e))?;
+                }
+                Ok(())
+            }
+            WizardStep::Parameters => self.parse_parameters().map(|_| ()),
+            WizardStep::Preview => Ok(()),
+        }
+    }
+    
+    fn parse_parameters(&self) -> Result<WizardParameters, String> {
+        let angle = self.angle.text.trim().parse::<f32>().ok()
+            .filter(|a| a.is_finite())
+            .ok_or("Angle must be a number")?;
+        let iterations = self.iterations.text.trim().parse::<u32>().ok()
+            .filter(|n| (1..=8).contains(n))
+            .ok_or("Iterations must be between 1 and 8")?;
+        let step_length = self.step_length.text.trim().parse::<f32>().ok()
+            .filter(|l| l.is_finite() && *l > 0.0)
+            .ok_or("Step length must be a positive number")?;
+        let color = match self.color.text.split_whitespace().map(|c| c.parse::<f32>()).collect::<Result<Vec<_>, _>>() {
+            Ok(channels) if channels.is_empty() => None,
+            Ok(channels) if channels.len() == 3 && channels.iter().all(|c| (0.0..=1.0).contains(c)) => {
+                Some([channels[0], channels[1], channels[2]])
+            }
+            _ => return Err("Color must be three numbers from 0 to 1, or empty".to_string()),
+        };
+        Ok(WizardParameters { angle, iterations, step_length, color })
+    }
+    
+    pub fn build_rule(&self) -> Result<LSystemRule, String> {
+        let WizardParameters { angle, iterations, step_length, color } = self.parse_parameters()?;
+        let mut builder = LSystemRule::builder();
+        builder
+            .name(self.name.text.trim())
+            .axiom(&self.axiom.text)
+            .angle(angle)
+            .iterations(iterations)
+            .step_length(step_length)
+            .depth_colors(color.is_none());
+        if let Some(color) = color {
+            builder.palette(vec![color]);
+        }
+        if !self.description.text.trim().is_empty() {
+            builder.description(self.description.text.trim());
+        }
+        
+        // An empty production leaves the symbol unchanged
+        for (symbol, input) in &self.productions {
+            if !input.text.is_empty() {
+                builder.rule(*symbol, &input.text);
+            }
+        }
+        builder.build()
+    }
+    
+    /// One production per distinct letter in the axiom, keeping anything already typed
+    fn sync_productions(&mut self) {
+        let mut symbols: Vec<char> = self.axiom.text.chars().filter(|c| c.is_alphabetic()).collect();
+        symbols.sort();
+        symbols.dedup();
+        
+        let previous = std::mem::take(&mut self.productions);
+        self.productions = symbols.into_iter().map(|symbol| {
+            previous.iter()
+                .find(|(s, _)| *s == symbol)
+                .cloned()
+                .unwrap_or_else(|| (symbol, TextInput::new(&format!("{} ->", symbol), "")))
+        }).collect();
+    }
+    
+    fn render_preview(&mut self, rule: &LSystemRule) {
+        let mut preview_rule = rule.clone();
+        preview_rule.iterations = PREVIEW_ITERATIONS.min(rule.iterations);
+        let mut lsystem = LSystem::new(preview_rule);
+        lsystem.generate();
+        
+        let mut renderer = Renderer::new(PREVIEW_WIDTH, PREVIEW_HEIGHT);
+        let mut turtle = Turtle3D::new();
+        
+        // Frame the whole structure
+        let mut camera = Camera::new(PREVIEW_WIDTH as f32 / PREVIEW_HEIGHT as f32);
+        camera.target = lsystem.center();
+        camera.distance = (lsystem.diagonal_length() * 1.5).max(1.0);
+        camera.update_from_angles();
+        
+        turtle.set_view_proj(Some(camera.projection_matrix() * camera.view_matrix()));
+        lsystem.draw_3d(&mut turtle, &mut renderer);
+        renderer.render(&camera);
+        self.preview = renderer.get_buffer().to_vec();
+    }
+    
+    /// Turns the rule's angle by `delta` degrees and redraws the preview with it
+    fn nudge_preview_angle(&mut self, delta: f32) {
+        if let Ok(rule) = self.build_rule() {
+            let angle = (rule.angle + delta).clamp(PREVIEW_ANGLE_STEP, 180.0);
+            let rule = rule.with_angle(angle);
+            self.angle.text = format!("{}", rule.angle);
+            self.render_preview(&rule);
+        }
+    }
+    
+    fn next(&mut self) -> Option<WizardOutcome> {
+        if let Err(e) = self.validate_step() {
+            self.error = Some(e);
+            return None;
+        }
+        self.error = None;
+        self.focused = 0;
+        
+        match self.step {
+            WizardStep::NameAndAxiom => {
+                self.sync_productions();
+                self.step = WizardStep::Productions;
+            }
+            WizardStep::Productions => self.step = WizardStep::Parameters,
+            WizardStep::Parameters => match self.build_rule() {
+                Ok(rule) => {
+                    self.render_preview(&rule);
+                    self.step = WizardStep::Preview;
+                }
+                Err(e) => self.error = Some(e),
+            },
+            WizardStep::Preview => match self.build_rule() {
+                Ok(rule) => return Some(WizardOutcome::Finished { rule: Box::new(rule), path: self.target_path() }),
+                Err(e) => self.error = Some(e),
+            },
+        }
+        None
+    }
+    
+    fn back(&mut self) -> Option<WizardOutcome> {
+        self.error = None;
+        self.focused = 0;
+        self.step = match self.step {
+            WizardStep::NameAndAxiom => return Some(WizardOutcome::Cancelled),
+            WizardStep::Productions => WizardStep::NameAndAxiom,
+            WizardStep::Parameters => WizardStep::Productions,
+            WizardStep::Preview => WizardStep::Parameters,
+        };
+        None
+    }
+    
+    fn panel_origin(width: usize, height: usize) -> (usize, usize) {
+        (width.saturating_sub(PANEL_WIDTH) / 2, height.saturating_sub(PANEL_HEIGHT) / 2)
+    }
+    
+    /// Positions of the Back and Next buttons
+    fn button_positions(width: usize, height: usize) -> ((usize, usize), (usize, usize)) {
+        let (x, y) = Self::panel_origin(width, height);
+        let button_y = y + PANEL_HEIGHT - BUTTON_HEIGHT - 10;
+        ((x + 10, button_y), (x + PANEL_WIDTH - BUTTON_WIDTH - 10, button_y))
+    }
+    
+    pub fn handle_input(&mut self, window: &Window) -> Option<WizardOutcome> {
+        let field_count = self.field_count();
+        if field_count > 0 {
+            if window.is_key_pressed(Key::Tab, KeyRepeat::No) || window.is_key_pressed(Key::Down, KeyRepeat::No) {
+                self.focused = (self.focused + 1) % field_count;
+            }
+            if window.is_key_pressed(Key::Up, KeyRepeat::No) {
+                self.focused = (self.focused + field_count - 1) % field_count;
+            }
+        }
+        
+        if let Some(field) = self.focused_field() {
+            field.handle_input(window);
+        }
+        
+        // Left and Right try other angles on the preview
+        if self.step == WizardStep::Preview {
+            for (key, delta) in [(Key::Left, -PREVIEW_ANGLE_STEP), (Key::Right, PREVIEW_ANGLE_STEP)] {
+                if window.is_key_pressed(key, KeyRepeat::Yes) {
+                    self.nudge_preview_angle(delta);
+                }
+            }
+        }
+        
+        if window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+            return self.next();
+        }
+        // Escape steps back like the Back button, cancelling from the first step
+        if window.is_key_pressed(Key::Escape, KeyRepeat::No) {
+            return self.back();
+        }
+        
+        // Buttons act on the press, not on every frame the mouse is held
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        let clicked = mouse_down && !self.mouse_was_down;
+        self.mouse_was_down = mouse_down;
+        if clicked
+            && let Some((mx, my)) = window.get_mouse_pos(MouseMode::Discard) {
+            let (width, height) = window.get_size();
+            let (back, next) = Self::button_positions(width, height);
+            let hit = |(bx, by): (usize, usize)| {
+                mx >= bx as f32 && mx <= (bx + BUTTON_WIDTH) as f32 &&
+                my >= by as f32 && my <= (by + BUTTON_HEIGHT) as f32
+            };
+            if hit(back) {
+                return self.back();
+            }
+            if hit(next) {
+                return self.next();
+            }
+        }
+        
+        None
+    }
+    
+    pub fn render(&self, buffer: &mut [u32], width: usize, height: usize) {
+        let (x, y) = Self::panel_origin(width, height);
+        fill_rect(buffer, width, height, (x, y, PANEL_WIDTH, PANEL_HEIGHT), 0x303040);
+        draw_rect(buffer, width, height, (x, y, PANEL_WIDTH, PANEL_HEIGHT), 0xFFFFFF);
+        
+        let (title, hint) = match self.step {
+            WizardStep::NameAndAxiom => ("New Rule 1/4: Name and axiom", "Tab: next field | Enter: next step"),
+            WizardStep::Productions => ("New Rule 2/4: Production rules", "Leave a rule empty to keep the symbol"),
+            WizardStep::Parameters => ("New Rule 3/4: Parameters", "Angle, iterations (1-8), step length, color"),
+            WizardStep::Preview => ("New Rule 4/4: Preview", "Left/Right: angle | Enter or Finish saves"),
+        };
+        Renderer::render_text_at_screen(buffer, width, height, (x + 10, y + 10), title, 0xFFFFFF, 1);
+        Renderer::render_text_at_screen(buffer, width, height, (x + 10, y + 30), hint, 0x888888, 1);
+        
+        let content_y = y + 60;
+        match self.step {
+            WizardStep::NameAndAxiom => {
+                self.name.render(buffer, width, height, x + 10, content_y, self.focused == 0);
+                self.axiom.render(buffer, width, height, x + 10, content_y + 30, self.focused == 1);
+                self.description.render(buffer, width, height, x + 10, content_y + 60, self.focused == 2);
+            }
+            WizardStep::Productions => {
+                if self.productions.is_empty() {
+                    Renderer::render_text_at_screen(buffer, width, height, (x + 10, content_y), "No symbols to rewrite", 0xCCCCCC, 1);
+                }
+                for (i, (_, input)) in self.productions.iter().enumerate().take(7) {
+                    input.render(buffer, width, height, x + 10, content_y + i * 30, self.focused == i);
+                }
+            }
+            WizardStep::Parameters => {
+                self.angle.render(buffer, width, height, x + 10, content_y, self.focused == 0);
+                self.iterations.render(buffer, width, height, x + 10, content_y + 30, self.focused == 1);
+                self.step_length.render(buffer, width, height, x + 10, content_y + 60, self.focused == 2);
+                self.color.render(buffer, width, height, x + 10, content_y + 90, self.focused == 3);
+            }
+            WizardStep::Preview => {
+                let preview_x = x + (PANEL_WIDTH - PREVIEW_WIDTH) / 2;
+                for py in 0..PREVIEW_HEIGHT.min(self.preview.len() / PREVIEW_WIDTH) {
+                    for px in 0..PREVIEW_WIDTH {
+                        let (sx, sy) = (preview_x + px, content_y + py);
+                        if sx < width && sy < height {
+                            buffer[sy * width + sx] = self.preview[py * PREVIEW_WIDTH + px];
+                        }
+                    }
+                }
+                draw_rect(buffer, width, height, (preview_x, content_y, PREVIEW_WIDTH, PREVIEW_HEIGHT), 0x808080);
+            }
+        }
+        
+        if let Some(error) = &self.error {
+            Renderer::render_text_at_screen(buffer, width, height, (x + 10, y + PANEL_HEIGHT - BUTTON_HEIGHT - 30), error, 0xFF4040, 1);
+        }
+        
+        let (back, next) = Self::button_positions(width, height);
+        let back_label = if self.step == WizardStep::NameAndAxiom { "Cancel" } else { "Back" };
+        let next_label = if self.step == WizardStep::Preview { "Finish" } else { "Next" };
+        for ((bx, by), label) in [(back, back_label), (next, next_label)] {
+            fill_rect(buffer, width, height, (bx, by, BUTTON_WIDTH, BUTTON_HEIGHT), 0x404060);
+            draw_rect(buffer, width, height, (bx, by, BUTTON_WIDTH, BUTTON_HEIGHT), 0xCCCCCC);
+            Renderer::render_text_at_screen(buffer, width, height, (bx + 10, by + 6), label, 0xFFFFFF, 1);
+        }
+    }
+}
+
+pub(crate) fn key_to_char(key: Key, shift: bool) -> Option<char> {
+    const LETTERS: [(Key, char); 26] = [
+        (Key::A, 'a'), (Key::B, 'b'), (Key::C, 'c'), (Key::D, 'd'), (Key::E, 'e'), (Key::F, 'f'),
+        (Key::G, 'g'), (Key::H, 'h'), (Key::I, 'i'), (Key::J, 'j'), (Key::K, 'k'), (Key::L, 'l'),
+        (Key::M, 'm'), (Key::N, 'n'), (Key::O, 'o'), (Key::P, 'p'), (Key::Q, 'q'), (Key::R, 'r'),
+        (Key::S, 's'), (Key::T, 't'), (Key::U, 'u'), (Key::V, 'v'), (Key::W, 'w'), (Key::X, 'x'),
+        (Key::Y, 'y'), (Key::Z, 'z'),
+    ];
+    if let Some((_, c)) = LETTERS.iter().find(|(k, _)| *k == key) {
+        return Some(if shift { c.to_ascii_uppercase() } else { *c });
+    }
+    
+    // US layout; the shifted digits cover the turtle commands ! # ^ &
+    let c = match (key, shift) {
+        (Key::Key0, false) => '0', (Key::Key0, true) => ')',
+        (Key::Key1, false) => '1', (Key::Key1, true) => '!',
+        (Key::Key2, false) => '2', (Key::Key2, true) => '@',
+        (Key::Key3, false) => '3', (Key::Key3, true) => '#',
+        (Key::Key4, false) => '4', (Key::Key4, true) => '$',
+        (Key::Key5, false) => '5', (Key::Key5, true) => '%',
+        (Key::Key6, false) => '6', (Key::Key6, true) => '^',
+        (Key::Key7, false) => '7', (Key::Key7, true) => '&',
+        (Key::Key8, false) => '8', (Key::Key8, true) => '*',
+        (Key::Key9, false) => '9', (Key::Key9, true) => '(',
+        (Key::Minus, false) => '-', (Key::Minus, true) => '_',
+        (Key::Equal, false) => '=', (Key::Equal, true) => '+',
+        (Key::LeftBracket, _) => '[',
+        (Key::RightBracket, _) => ']',
+        (Key::Backslash, false) => '\\', (Key::Backslash, true) => '|',
+        (Key::Slash, _) => '/',
+        (Key::Apostrophe, _) => '\'',
+        (Key::Period, _) => '.',
+        (Key::Comma, _) => ',',
+        (Key::Space, _) => ' ',
+        _ => return None,
+    };
+    Some(c)
+}
+
+fn fill_rect(buffer: &mut [u32], width: usize, height: usize,
+             (x, y, w, h): (usize, usize, usize, usize), color: u32) {
+    for dy in 0..h {
+        for dx in 0..w {
+            let px = x + dx;
+            let py = y + dy;
+            if px < width && py < height {
+                buffer[py * width + px] = color;
+            }
+        }
+    }
+}
+
+fn draw_rect(buffer: &mut [u32], width: usize, height: usize,
+             (x, y, w, h): (usize, usize, usize, usize), color: u32) {
+    for dx in 0..w {
+        let px = x + dx;
+        if px < width {
+            if y < height {
+                buffer[y * width + px] = color;
+            }
+            if y + h - 1 < height {
+                buffer[(y + h - 1) * width + px] = color;
+            }
+        }
+    }
+    for dy in 0..h {
+        let py = y + dy;
+        if py < height {
+            if x < width {
+                buffer[py * width + x] = color;
+            }
+            if x + w - 1 < width {
+                buffer[py * width + (x + w - 1)] = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn wizard() -> Wizard {
+        Wizard::new(std::env::temp_dir().join(format!("lsystem_wizard_{}", std::process::id())))
+    }
+    
+    #[test]
+    fn target_path_is_derived_from_the_name() {
+        let mut wizard = wizard();
+        wizard.name.text = "  My Fern #2 ".to_string();
+        assert!(wizard.target_path().ends_with("my_fern__2.json"));
+    }
+    
+    #[test]
+    fn steps_build_a_rule_from_the_typed_fields() {
+        let mut wizard = wizard();
+        assert!(wizard.next().is_none());
+        assert_eq!(wizard.error.as_deref(), Some("Name must not be empty"));
+        
+        wizard.name.text = "Bush".to_string();
+        wizard.axiom.text = "X[F".to_string();
+        wizard.next();
+        assert_eq!(wizard.step, WizardStep::NameAndAxiom);
+        wizard.axiom.text = "XF".to_string();
+        wizard.next();
+        assert_eq!(wizard.step, WizardStep::Productions);
+        let symbols: Vec<char> = wizard.productions.iter().map(|(symbol, _)| *symbol).collect();
+        assert_eq!(symbols, ['F', 'X']);
+        
+        wizard.productions[1].1.text = "F[+X]".to_string();
+        wizard.next();
+        wizard.iterations.text = "12".to_string();
+        wizard.next();
+        assert_eq!(wizard.step, WizardStep::Parameters);
+        assert!(wizard.error.is_some());
+        wizard.iterations.text = "3".to_string();
+        wizard.color.text = "1 0.5 0".to_string();
+        wizard.next();
+        assert_eq!(wizard.step, WizardStep::Preview);
+        
+        let Some(WizardOutcome::Finished { rule, path }) = wizard.next() else { panic!("wizard did not finish") };
+        assert_eq!(path, wizard.target_path());
+        assert_eq!(rule.axiom, "XF");
+        assert_eq!(rule.iterations, 3);
+        assert_eq!(rule.rules.get(&'X').map(String::as_str), Some("F[+X]"));
+        assert!(!rule.rules.contains_key(&'F'));
+    }
+    
+    #[test]
+    fn back_steps_out_and_cancels_from_the_first_step() {
+        let mut wizard = wizard();
+        wizard.step = WizardStep::Parameters;
+        assert!(wizard.back().is_none());
+        assert_eq!(wizard.step, WizardStep::Productions);
+        wizard.back();
+        assert!(matches!(wizard.back(), Some(WizardOutcome::Cancelled)));
+    }
+    
+    #[test]
+    fn key_to_char_applies_shift() {
+        assert_eq!(key_to_char(Key::F, false), Some('f'));
+        assert_eq!(key_to_char(Key::F, true), Some('F'));
+        assert_eq!(key_to_char(Key::Key9, true), Some('('));
+        assert_eq!(key_to_char(Key::LeftBracket, true), Some('['));
+        assert_eq!(key_to_char(Key::Enter, false), None);
+    }
+}